@@ -24,8 +24,19 @@
 //
 use std::fmt::{Display, Write};
 use std::process::{Command, Output, CommandArgs};
+#[cfg(feature = "testing")]
+use std::os::unix::process::ExitStatusExt;
+#[cfg(feature = "testing")]
+use std::process::ExitStatus;
 use std::ffi::OsStr;
+#[cfg(feature = "testing")]
+use std::cell::RefCell;
+#[cfg(feature = "testing")]
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
 use thiserror::Error;
+use crate::defaults;
 
 pub trait CommandExtTrait {
     /// Execute command via output() using sudo and return:
@@ -39,11 +50,130 @@ pub trait CommandExtTrait {
     /// If a termination with a non 0 exit status is considered succesful
     /// this method should not be used.
     fn perform(&mut self) -> Result<std::process::Output, CommandError>;
+
+    /// Execute command via perform() and retry it up to retries
+    /// times, waiting backoff in between, as long as predicate
+    /// returns true for the error of the last try
+    ///
+    /// The predicate can prepare the next try, e.g. fix
+    /// permissions. The error of the last try is returned
+    fn perform_with_retry<F>(
+        &mut self, retries: u32, backoff: Duration, predicate: F
+    ) -> Result<std::process::Output, CommandError>
+    where
+        F: FnMut(&CommandError) -> bool;
 }
 
 impl CommandExtTrait for Command {
     fn perform(&mut self) -> Result<std::process::Output, CommandError> {
-        handle_output(self.output(), self.get_args())
+        HostRunner.perform(self)
+    }
+
+    fn perform_with_retry<F>(
+        &mut self, retries: u32, backoff: Duration, predicate: F
+    ) -> Result<std::process::Output, CommandError>
+    where
+        F: FnMut(&CommandError) -> bool
+    {
+        HostRunner.perform_with_retry(self, retries, backoff, predicate)
+    }
+}
+
+pub trait CommandRunner {
+    /// Execute the prepared command and return its [Output]
+    /// or the error as described for [CommandExtTrait::perform]
+    ///
+    /// Functions calling external tools like podman take a
+    /// runner such that they can be tested with a MockRunner
+    /// instead of the real tool
+    fn perform(&self, command: &mut Command) -> Result<Output, CommandError>;
+
+    /// Execute the prepared command as described for
+    /// [CommandExtTrait::perform_with_retry]
+    fn perform_with_retry<F>(
+        &self, command: &mut Command, retries: u32, backoff: Duration,
+        mut predicate: F
+    ) -> Result<Output, CommandError>
+    where
+        F: FnMut(&CommandError) -> bool
+    {
+        let mut retry_count = 0;
+        loop {
+            match self.perform(command) {
+                Err(error) if retry_count < retries && predicate(&error) => {
+                    retry_count += 1;
+                    thread::sleep(backoff);
+                },
+                result => return result
+            }
+        }
+    }
+}
+
+/// Runs commands on the host
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HostRunner;
+
+impl CommandRunner for HostRunner {
+    fn perform(&self, command: &mut Command) -> Result<Output, CommandError> {
+        handle_output(command.output(), command.get_args())
+    }
+}
+
+/// Answers commands with queued outputs instead of running
+/// them and records the command lines, meant for tests and
+/// only available with the testing feature
+#[cfg(feature = "testing")]
+#[derive(Debug, Default)]
+pub struct MockRunner {
+    outputs: RefCell<VecDeque<Output>>,
+    calls: RefCell<Vec<String>>
+}
+
+#[cfg(feature = "testing")]
+impl MockRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn respond(self, code: i32, stdout: &str, stderr: &str) -> Self {
+        /*!
+        Queue the output of the next command with the given
+        exit code. Outputs are used in the order they were queued
+        !*/
+        self.outputs.borrow_mut().push_back(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec()
+        });
+        self
+    }
+
+    pub fn calls(&self) -> Vec<String> {
+        /*!
+        Command lines performed so far, program and arguments
+        separated by a space
+        !*/
+        self.calls.borrow().clone()
+    }
+}
+
+#[cfg(feature = "testing")]
+impl CommandRunner for MockRunner {
+    fn perform(&self, command: &mut Command) -> Result<Output, CommandError> {
+        let call = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let output = self.outputs.borrow_mut().pop_front().ok_or_else(
+            || std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No output queued for: {}", call)
+            )
+        );
+        self.calls.borrow_mut().push(call);
+        handle_output(output, command.get_args())
     }
 }
 
@@ -81,10 +211,32 @@ pub enum ProcessError {
     IO(#[from] std::io::Error),
 
     // The Command could be called but has a non zero exit status
-    #[error("The process failed with status {}", .0.status)]
+    #[error("The process failed with status {}{}", .0.status, stderr_excerpt(&.0.stderr))]
     ExecutionError(std::process::Output),
 }
 
+pub fn stderr_excerpt(stderr: &[u8]) -> String {
+    /*!
+    Format captured stderr of a failed command for error messages.
+    Long output is cut at the front as the reason for a failure
+    is usually reported last
+    !*/
+    let stderr = String::from_utf8_lossy(stderr);
+    let stderr = stderr.trim();
+    if stderr.is_empty() {
+        return String::new()
+    }
+    let mut start = stderr.len().saturating_sub(defaults::COMMAND_STDERR_MAX);
+    while ! stderr.is_char_boundary(start) {
+        start += 1
+    }
+    if start > 0 {
+        format!(": ...{}", &stderr[start..])
+    } else {
+        format!(": {}", stderr)
+    }
+}
+
 impl From<std::process::Output> for ProcessError {
     fn from(value: std::process::Output) -> Self {
         Self::ExecutionError(value)
@@ -117,8 +269,6 @@ impl Display for CommandError {
         f.write_char('"')?;
         f.write_char(':')?;
         f.write_char(' ')?;
-        f.write_str(format!("{:?}", self.base).as_str())?;
-        f.write_char(' ')?;
         std::fmt::Display::fmt(&self.base, f)
     }
 }
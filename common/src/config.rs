@@ -22,13 +22,37 @@
 // SOFTWARE.
 //
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
 use std::path::Path;
 use lazy_static::lazy_static;
 
 use crate::defaults;
+use crate::error::FlakeError;
+use crate::lookup::Lookup;
 
 lazy_static! {
-    static ref FLAKES_CONFIG: FlakesConfig = read_flakes_config();
+    static ref FLAKES_CONFIG: Result<FlakesConfig, FlakeError> = read_flakes_config();
+}
+
+pub fn check_flakes_config() -> Result<(), FlakeError> {
+    /*!
+    Check that the systemwide flakes configuration could be read.
+    Entry points call this before using any of the getters below,
+    which expect a valid configuration
+    !*/
+    match FLAKES_CONFIG.as_ref() {
+        Ok(_) => Ok(()),
+        Err(FlakeError::ConfigError { path, message }) => {
+            Err(FlakeError::ConfigError {
+                path: path.clone(), message: message.clone()
+            })
+        },
+        Err(error) => Err(FlakeError::ConfigError {
+            path: defaults::FLAKES_CONFIG.to_string(),
+            message: error.to_string()
+        })
+    }
 }
 
 pub fn get_flakes_dir() -> String {
@@ -46,11 +70,100 @@ pub fn get_firecracker_ids_dir() -> String {
     firecracker_ids_dir.clone().unwrap_or(defaults::FIRECRACKER_IDS_DIR.to_string())
 }
 
+pub fn get_firecracker_socket_dir() -> String {
+    let GenericData { firecracker_socket_dir, .. } = &flakes_config().generic;
+    firecracker_socket_dir.clone()
+        .unwrap_or(defaults::FIRECRACKER_SOCKET_DIR.to_string())
+}
+
+pub fn get_podman_storage_driver() -> Option<String> {
+    let GenericData { podman_storage_driver, .. } = &flakes_config().generic;
+    podman_storage_driver.clone()
+}
+
+pub fn get_podman_path() -> String {
+    let GenericData { podman_path, .. } = &flakes_config().generic;
+    match podman_path {
+        Some(podman_path) if ! podman_path.contains('/') => {
+            Lookup::which_path(podman_path).unwrap_or(podman_path.clone())
+        },
+        Some(podman_path) => podman_path.clone(),
+        None => defaults::PODMAN_PATH.to_string()
+    }
+}
+
+pub fn get_provision_tmpdir() -> Option<String> {
+    let GenericData { provision_tmpdir, .. } = &flakes_config().generic;
+    provision_tmpdir.clone()
+}
+
+pub fn get_podman_permission_fixup() -> bool {
+    let GenericData { podman_permission_fixup, .. } = &flakes_config().generic;
+    podman_permission_fixup.unwrap_or(true)
+}
+
+pub fn get_registry_mirrors() -> HashMap<String, String> {
+    let GenericData { registry_mirrors, .. } = &flakes_config().generic;
+    registry_mirrors.clone().unwrap_or_default()
+}
+
+pub fn flake_yaml(
+    flakes_dir: &str, program: &str, chain: &mut Vec<String>
+) -> Result<String, String> {
+    /*!
+    Read the yaml of the given flake including its .d files. If
+    the flake sets extends: NAME, the yaml of the extended flake
+    is read the same way and put in front, such that the settings
+    of the extending flake win. The chain of visited flakes is
+    used to detect cycles
+    !*/
+    if chain.iter().any(|name| name == program) {
+        chain.push(program.to_string());
+        return Err(format!("Cyclic extends: {}", chain.join(" -> ")))
+    }
+    chain.push(program.to_string());
+
+    let base_yaml = fs::read_to_string(
+        format!("{}/{}.yaml", flakes_dir, program)
+    );
+    if base_yaml.is_err() && chain.len() > 1 {
+        return Err(format!("Extended flake not found: {}", program))
+    }
+
+    let mut extra_yamls: Vec<_> = fs::read_dir(
+        format!("{}/{}.d", flakes_dir, program)
+    )
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|x| x.path())
+        .collect();
+
+    extra_yamls.sort();
+
+    let yaml: String = base_yaml
+        .into_iter()
+        .chain(extra_yamls.into_iter().flat_map(fs::read_to_string))
+        .collect();
+
+    let extends = yaml_rust::YamlLoader::load_from_str(&yaml).ok()
+        .and_then(|docs| docs.first()
+            .and_then(|doc| doc["extends"].as_str().map(str::to_string))
+        );
+    match extends {
+        Some(parent) => {
+            let parent_yaml = flake_yaml(flakes_dir, &parent, chain)?;
+            Ok(format!("{}\n{}", parent_yaml, yaml))
+        },
+        None => Ok(yaml)
+    }
+}
+
 fn flakes_config() -> &'static FlakesConfig {
-    &FLAKES_CONFIG
+    FLAKES_CONFIG.as_ref().expect("Flakes config is validated at startup")
 }
 
-fn read_flakes_config() -> FlakesConfig {
+fn read_flakes_config() -> Result<FlakesConfig, FlakeError> {
     /*!
     Read systemwide flakes configuration file
 
@@ -58,20 +171,36 @@ fn read_flakes_config() -> FlakesConfig {
         flakes_dir: ~
         podman_ids_dir: ~
         firecracker_ids_dir: ~
+        firecracker_socket_dir: ~
+        podman_storage_driver: ~
+        podman_path: ~
+        provision_tmpdir: ~
+        podman_permission_fixup: ~
+        registry_mirrors: ~
     !*/
     if Path::new(defaults::FLAKES_CONFIG).exists() {
+        let config_error = |message: String| FlakeError::ConfigError {
+            path: defaults::FLAKES_CONFIG.to_string(),
+            message
+        };
         let flakes_file = std::fs::File::open(defaults::FLAKES_CONFIG)
-            .unwrap_or_else(|_| panic!("Failed to open {}", defaults::FLAKES_CONFIG));
+            .map_err(|error| config_error(error.to_string()))?;
         serde_yaml::from_reader(flakes_file)
-            .unwrap_or_else(|error| panic!("Failed to import {}: {}", defaults::FLAKES_CONFIG, error))
+            .map_err(|error| config_error(error.to_string()))
     } else {
-        FlakesConfig {
+        Ok(FlakesConfig {
             generic: GenericData {
                 flakes_dir: None::<String>,
                 podman_ids_dir: None::<String>,
-                firecracker_ids_dir: None::<String>
+                firecracker_ids_dir: None::<String>,
+                firecracker_socket_dir: None::<String>,
+                podman_storage_driver: None::<String>,
+                podman_path: None::<String>,
+                provision_tmpdir: None::<String>,
+                podman_permission_fixup: None::<bool>,
+                registry_mirrors: None::<HashMap<String, String>>
             }
-        }
+        })
     }
 }
 
@@ -89,5 +218,29 @@ struct GenericData {
     podman_ids_dir: Option<String>,
 
     /// ID files directory for firecracker registrations
-    firecracker_ids_dir: Option<String>
+    firecracker_ids_dir: Option<String>,
+
+    /// Directory for the vsock unix domain sockets of the
+    /// firecracker registrations
+    firecracker_socket_dir: Option<String>,
+
+    /// Storage driver used by podman for the flakes registry,
+    /// overrides the driver setting from the flakes storage.conf
+    podman_storage_driver: Option<String>,
+
+    /// Podman binary used by podman-pilot and flake-ctl,
+    /// a name without a path is looked up in PATH
+    podman_path: Option<String>,
+
+    /// Base directory for temporary data created during
+    /// provisioning, defaults to the system temp directory
+    provision_tmpdir: Option<String>,
+
+    /// Fix the ownership of the flakes registry on podman
+    /// permission errors, defaults to true
+    podman_permission_fixup: Option<bool>,
+
+    /// Mirror locations by registry, image references of a
+    /// listed registry are pulled from its mirror
+    registry_mirrors: Option<HashMap<String, String>>
 }
@@ -27,15 +27,61 @@ use crate::defaults;
 use crate::flakelog::FlakeLog;
 use crate::error::FlakeError;
 use crate::user::User;
-use crate::command::CommandExtTrait;
+use crate::lookup::Lookup;
+use crate::command::{
+    CommandExtTrait, CommandError, CommandRunner, HostRunner
+};
+use crate::config::{
+    get_podman_storage_driver, get_podman_path, get_podman_permission_fixup
+};
+use std::process::{Command, Stdio};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::time::Duration;
 use users::{get_current_uid, get_current_gid};
+use ubyte::ByteUnit;
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Container {
 }
 
 impl Container {
+    pub fn permission_fixup_enabled() -> bool {
+        /*!
+        Check if the recursive ownership fixup of the flakes
+        registry is allowed. It is disabled by the %no-perm-fix
+        pilot option or podman_permission_fixup: false in the
+        flakes config
+        !*/
+        Self::permission_fixup_allowed(&Lookup::args())
+    }
+
+    pub fn permission_fixup_allowed(args: &[String]) -> bool {
+        /*!
+        Check the given flake call arguments and the flakes
+        config for permission fixup being allowed
+        !*/
+        ! Lookup::pilot_run_options_from(args).contains_key("%no-perm-fix")
+            && get_podman_permission_fixup()
+    }
+
     pub fn podman_setup_permissions() -> Result<(), FlakeError> {
+        if ! Self::permission_fixup_enabled() {
+            FlakeLog::debug("Permission fixup of the flakes registry disabled");
+            return Ok(())
+        }
+        Self::podman_setup_permissions_with(&HostRunner)
+    }
+
+    pub fn podman_setup_permissions_with<R: CommandRunner>(
+        runner: &R
+    ) -> Result<(), FlakeError> {
+        /*!
+        Chown the flakes registry recursively to the calling
+        user, the commands are performed through runner.
+        Unlike podman_setup_permissions the flakes config and
+        pilot options are not consulted, the caller decides
+        !*/
         let root = User::from("root");
         let user_id = get_current_uid();
         let user_gid = get_current_gid();
@@ -47,14 +93,60 @@ impl Container {
             .arg(chown_param.clone())
             .arg(defaults::FLAKES_REGISTRY);
         FlakeLog::debug(&format!("{:?}", fix_storage.get_args()));
-        fix_storage.perform()?;
+        runner.perform(&mut fix_storage)?;
 
-        let _ = Self::podman_setup_run_permissions();
+        let _ = Self::podman_setup_run_permissions_with(runner);
 
         Ok(())
     }
 
+    pub fn fix_permission_error(error: &CommandError) -> bool {
+        Self::permission_fixup_enabled()
+            && Self::fix_permission_error_with(&HostRunner, error)
+    }
+
+    pub fn fix_permission_error_with<R: CommandRunner>(
+        runner: &R, error: &CommandError
+    ) -> bool {
+        /*!
+        Retry predicate for podman calls. On a permission error
+        the permissions are fixed and true is returned such that
+        the call gets retried. Whether the fixup is allowed at
+        all is up to the caller
+        !*/
+        let error = format!("{:?}", error.base);
+        if error.contains("not permitted") || error.contains("permission denied") {
+            // This is an expensive operation depending on the storage size
+            let _ = Self::podman_setup_permissions_with(runner);
+            return true
+        }
+        false
+    }
+
+    pub fn fix_permissions_on_any_error(error: &CommandError) -> bool {
+        Self::permission_fixup_enabled()
+            && Self::fix_permissions_on_any_error_with(&HostRunner, error)
+    }
+
+    pub fn fix_permissions_on_any_error_with<R: CommandRunner>(
+        runner: &R, _error: &CommandError
+    ) -> bool {
+        /*!
+        Retry predicate for podman calls which fixes the
+        permissions on any error and requests a retry. Whether
+        the fixup is allowed at all is up to the caller
+        !*/
+        let _ = Self::podman_setup_permissions_with(runner);
+        true
+    }
+
     pub fn podman_setup_run_permissions() -> Result<(), FlakeError> {
+        Self::podman_setup_run_permissions_with(&HostRunner)
+    }
+
+    pub fn podman_setup_run_permissions_with<R: CommandRunner>(
+        runner: &R
+    ) -> Result<(), FlakeError> {
         let root = User::from("root");
         let user_id = get_current_uid();
         let user_gid = get_current_gid();
@@ -66,8 +158,246 @@ impl Container {
             .arg("/run/libpod")
             .arg(defaults::FLAKES_REGISTRY_RUNROOT);
         FlakeLog::debug(&format!("{:?}", fix_run_storage.get_args()));
-        fix_run_storage.perform()?;
+        runner.perform(&mut fix_run_storage)?;
+
+        Ok(())
+    }
+
+    pub fn podman_setup_storage_driver() -> Result<(), FlakeError> {
+        /*!
+        Export the podman storage driver configured in the flakes
+        config such that it overrides the driver from storage.conf.
+        Only drivers known to support image mount, which is required
+        for the flake provisioning, are accepted
+        !*/
+        if let Some(driver) = get_podman_storage_driver() {
+            if ! defaults::PODMAN_STORAGE_DRIVERS.contains(&driver.as_str()) {
+                return Err(FlakeError::IOError {
+                    kind: "InvalidInput".to_string(),
+                    message: format!(
+                        "Storage driver {} does not support image mount, \
+                        use one of: {}",
+                        driver, defaults::PODMAN_STORAGE_DRIVERS.join(", ")
+                    )
+                })
+            }
+            FlakeLog::debug(&format!("Using storage driver: {}", driver));
+            env::set_var("STORAGE_DRIVER", driver);
+        }
+        Ok(())
+    }
+
+    pub fn known_capability(capability: &str) -> bool {
+        /*!
+        Check if the given capability is a known Linux capability.
+        The name is matched case insensitive with or without the
+        CAP_ prefix like podman does
+        !*/
+        let capability = capability.to_uppercase();
+        let name = capability.strip_prefix("CAP_").unwrap_or(&capability);
+        defaults::LINUX_CAPABILITIES.contains(&name)
+    }
+
+    pub fn check_labels(labels: &[&str]) -> Result<(), FlakeError> {
+        /*!
+        Check that all labels are in the format key=value with
+        a key made of alphanumerics and . _ - / and that no key
+        is used more than once
+        !*/
+        let mut keys: HashSet<&str> = HashSet::new();
+        for label in labels {
+            let key = match label.split_once('=') {
+                Some((key, value)) if ! key.is_empty()
+                    && key.chars().all(|c| {
+                        c.is_ascii_alphanumeric() || "._-/".contains(c)
+                    })
+                    && ! value.contains('\n') => key,
+                _ => return Err(FlakeError::IOError {
+                    kind: "InvalidInput".to_string(),
+                    message: format!(
+                        "Label {} must be in the format key=value", label
+                    )
+                })
+            };
+            if key == defaults::FLAKE_APP_LABEL {
+                return Err(FlakeError::IOError {
+                    kind: "InvalidInput".to_string(),
+                    message: format!("Label key {} is reserved", key)
+                })
+            }
+            if ! keys.insert(key) {
+                return Err(FlakeError::IOError {
+                    kind: "InvalidInput".to_string(),
+                    message: format!("Label key {} used more than once", key)
+                })
+            }
+        }
+        Ok(())
+    }
+
+    pub fn parse_size(name: &str, value: &str) -> Result<u64, String> {
+        /*!
+        Parse the value of the size setting name, e.g. 512MiB,
+        into a number of bytes. Zero is not a valid size
+        !*/
+        match value.parse::<ByteUnit>() {
+            Ok(size) if size.as_u64() > 0 => Ok(size.as_u64()),
+            _ => Err(format!(
+                "{} {} is not a valid size, e.g. 512MiB or 10GiB",
+                name, value
+            ))
+        }
+    }
+
+    pub fn parse_storage_size(
+        size: &str, driver: Option<&str>
+    ) -> Result<u64, String> {
+        /*!
+        Parse the given container storage size into a number of
+        bytes. If the storage driver is known it must support
+        size quotas. An unset driver is left for podman to check
+        !*/
+        let bytes = Self::parse_size("storage_size", size)?;
+        if let Some(driver) = driver {
+            if ! defaults::PODMAN_SIZE_DRIVERS.contains(&driver) {
+                return Err(format!(
+                    "Storage driver {} does not support a storage_size, \
+                    use one of: {}",
+                    driver, defaults::PODMAN_SIZE_DRIVERS.join(", ")
+                ))
+            }
+        }
+        Ok(bytes)
+    }
+
+    pub fn mirror_reference(
+        reference: &str, mirrors: &HashMap<String, String>
+    ) -> Option<String> {
+        /*!
+        Rewrite the given image reference to be pulled from the
+        mirror configured for its registry. References without a
+        registry belong to docker.io. References by digest are
+        not rewritten as the image could not be found under its
+        original name after the pull
+        !*/
+        if reference.contains('@') {
+            return None
+        }
+        let (registry, path) = match reference.split_once('/') {
+            Some((registry, path)) if registry.contains('.')
+                || registry.contains(':')
+                || registry == "localhost" => (registry, path.to_string()),
+            Some(_) => ("docker.io", reference.to_string()),
+            None => ("docker.io", format!("library/{}", reference))
+        };
+        mirrors.get(registry).map(|mirror| {
+            format!("{}/{}", mirror.trim_end_matches('/'), path)
+        })
+    }
+
+    pub fn exists_any(
+        names: &[&str], user: User
+    ) -> Result<HashSet<String>, FlakeError> {
+        /*!
+        Bulk check for the existence of the given container images
+        or containers. Instead of one podman call per name, a single
+        podman images and podman ps call is used to build the set
+        of known references which is then used to answer the
+        membership of all names. Returns the subset of names
+        that exists
+        !*/
+        let mut images = user.run(get_podman_path());
+        images.arg("images")
+            .arg("--format").arg("{{.Repository}}:{{.Tag}}");
+        let mut containers = user.run(get_podman_path());
+        containers.arg("ps")
+            .arg("--all")
+            .arg("--format").arg("{{.ID}} {{.Names}}");
+
+        let mut known: HashSet<String> = HashSet::new();
+        let mut container_ids: Vec<String> = Vec::new();
+        for line in Self::podman_list(&mut images)?.lines() {
+            known.insert(line.to_string());
+        }
+        for line in Self::podman_list(&mut containers)?.lines() {
+            let mut fields = line.split_whitespace();
+            if let Some(id) = fields.next() {
+                container_ids.push(id.to_string());
+            }
+            for name in fields {
+                known.insert(name.to_string());
+            }
+        }
+
+        let mut found: HashSet<String> = HashSet::new();
+        for name in names {
+            let exists = known.contains(*name)
+                || known.contains(&format!("{}:latest", name))
+                || known.contains(&format!("localhost/{}", name))
+                || known.contains(&format!("localhost/{}:latest", name))
+                || container_ids.iter().any(|id| name.starts_with(id));
+            if exists {
+                found.insert(name.to_string());
+            }
+        }
+        Ok(found)
+    }
+
+    fn podman_list(call: &mut Command) -> Result<String, FlakeError> {
+        /*!
+        Run the given podman listing call and return its output.
+        On failure the permissions of the registry are fixed
+        and the call is retried once
+        !*/
+        FlakeLog::debug(&format!("{:?}", call.get_args()));
+        let output = call.perform_with_retry(
+            1, Duration::ZERO, Self::fix_permissions_on_any_error
+        )?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    pub fn podman_mount(
+        name: &str, as_image: bool, user: User
+    ) -> Result<String, FlakeError> {
+        Self::podman_mount_with(&HostRunner, name, as_image, user)
+    }
+
+    pub fn podman_mount_with<R: CommandRunner>(
+        runner: &R, name: &str, as_image: bool, user: User
+    ) -> Result<String, FlakeError> {
+        /*!
+        Mount container or container image and return mount point
+        !*/
+        let mut call = user.run(get_podman_path());
+        if as_image {
+            call.arg("image").arg("mount").arg(name);
+        } else {
+            call.arg("mount").arg(name);
+        }
+        FlakeLog::debug(&format!("{:?}", call.get_args()));
+        let output = runner.perform(&mut call)?;
+        Ok(
+            String::from_utf8_lossy(&output.stdout)
+                .trim_end_matches('\n').to_owned()
+        )
+    }
 
+    pub fn podman_umount(
+        name: &str, as_image: bool, user: User
+    ) -> Result<(), FlakeError> {
+        /*!
+        Umount container or container image
+        !*/
+        let mut call = user.run(get_podman_path());
+        call.stderr(Stdio::null());
+        call.stdout(Stdio::null());
+        if as_image {
+            call.arg("image").arg("umount").arg(name);
+        } else {
+            call.arg("umount").arg(name);
+        }
+        FlakeLog::debug(&format!("{:?}", call.get_args()));
+        call.perform()?;
         Ok(())
     }
 }
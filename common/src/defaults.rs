@@ -25,6 +25,51 @@ pub const FLAKES_CONFIG: &str = "/etc/flakes.yml";
 pub const FLAKES_DIR: &str = "/usr/share/flakes";
 pub const PODMAN_IDS_DIR: &str = "/tmp/flakes";
 pub const FIRECRACKER_IDS_DIR: &str = "/tmp/flakes";
+pub const FIRECRACKER_SOCKET_DIR: &str = "/run";
+pub const FIRECRACKER_OVERLAY_DIR: &str = "/var/lib/firecracker/storage";
+pub const PODMAN_PATH: &str = "/usr/bin/podman";
 pub const FLAKES_STORAGE: &str = "/etc/flakes/storage.conf";
 pub const FLAKES_REGISTRY: &str = "/usr/share/flakes/storage";
 pub const FLAKES_REGISTRY_RUNROOT: &str = "/run/flakes";
+pub const GC_LOCK_FILE: &str = "gc.lock";
+pub const STATUS_LINK_SUFFIX: &str = ".status";
+pub const FLAKE_APP_LABEL: &str = "io.flake-pilot.app";
+pub const COMMAND_STDERR_MAX: usize = 1024;
+pub const CHOWN_BATCH_SIZE: usize = 1000;
+pub const PODMAN_STORAGE_DRIVERS: [&str; 4] = [
+    "overlay", "vfs", "btrfs", "zfs"
+];
+pub const PODMAN_SIZE_DRIVERS: [&str; 3] = [
+    "overlay", "btrfs", "zfs"
+];
+pub const PODMAN_RESTART_POLICIES: [&str; 3] = [
+    "no", "on-failure", "always"
+];
+pub const PODMAN_NETWORK_MODES: [&str; 8] = [
+    "bridge", "host", "none", "private", "slirp4netns", "pasta",
+    "container", "ns"
+];
+pub const PODMAN_ULIMITS: [&str; 15] = [
+    "core", "cpu", "data", "fsize", "locks", "memlock", "msgqueue", "nice",
+    "nofile", "nproc", "rss", "rtprio", "rttime", "sigpending", "stack"
+];
+pub const EXIT_ENGINE_NOT_FOUND: u8 = 127;
+pub const LOG_LEVELS: [&str; 6] = [
+    "off", "error", "warn", "info", "debug", "trace"
+];
+pub const LINUX_SIGNALS: [&str; 31] = [
+    "ABRT", "ALRM", "BUS", "CHLD", "CONT", "FPE", "HUP", "ILL", "INT",
+    "IO", "KILL", "PIPE", "POLL", "PROF", "PWR", "QUIT", "SEGV", "STKFLT",
+    "STOP", "SYS", "TERM", "TRAP", "TSTP", "TTIN", "TTOU", "URG", "USR1",
+    "USR2", "VTALRM", "WINCH", "XCPU"
+];
+pub const LINUX_CAPABILITIES: [&str; 42] = [
+    "ALL", "AUDIT_CONTROL", "AUDIT_READ", "AUDIT_WRITE", "BLOCK_SUSPEND",
+    "BPF", "CHECKPOINT_RESTORE", "CHOWN", "DAC_OVERRIDE", "DAC_READ_SEARCH",
+    "FOWNER", "FSETID", "IPC_LOCK", "IPC_OWNER", "KILL", "LEASE",
+    "LINUX_IMMUTABLE", "MAC_ADMIN", "MAC_OVERRIDE", "MKNOD", "NET_ADMIN",
+    "NET_BIND_SERVICE", "NET_BROADCAST", "NET_RAW", "PERFMON", "SETFCAP",
+    "SETGID", "SETPCAP", "SETUID", "SYS_ADMIN", "SYS_BOOT", "SYS_CHROOT",
+    "SYS_MODULE", "SYS_NICE", "SYS_PACCT", "SYS_PTRACE", "SYS_RAWIO",
+    "SYS_RESOURCE", "SYS_TIME", "SYS_TTY_CONFIG", "SYSLOG", "WAKE_ALARM"
+];
@@ -22,8 +22,10 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 //
-use std::process::{ExitCode, Output, Termination};
+use std::process::{Command, ExitCode, Output, Termination};
+use std::time::Duration;
 use crate::command::{CommandError, ProcessError};
+use crate::defaults;
 use thiserror::Error;
 
 
@@ -59,6 +61,28 @@ pub enum FlakeError {
     #[error("Datasync failed, for details recall with PILOT_DEBUG=1")]
     SyncFailed,
 
+    /// The flake configuration could not be read or parsed
+    #[error("Config error in {path}: {message}")]
+    ConfigError {
+        path: String,
+        message: String
+    },
+
+    /// Waiting for an operation failed within the given retries
+    #[error(
+        "Timeout in {operation} after {retries} retries ({:.1}s), \
+        for details recall with PILOT_DEBUG=1", .elapsed.as_secs_f64()
+    )]
+    Timeout {
+        operation: String,
+        retries: u32,
+        elapsed: Duration
+    },
+
+    /// The engine to run the flake with is not installed
+    #[error("Engine {} not found, installed ?", .0)]
+    EngineNotFound(String),
+
     /// OperationError pass through
     #[error("{}", .0)]
     OperationError(#[from] OperationError)
@@ -67,14 +91,30 @@ pub enum FlakeError {
 #[derive(Debug, Error)]
 pub enum OperationError {
     #[error("Max retries exceeded, for details recall with PILOT_DEBUG=1")]
-    MaxTriesExceeded
+    MaxTriesExceeded,
+
+    #[error("Instance is not running")]
+    NotRunning,
+
+    #[error("Removed stale socket {}, the instance is not running", .0)]
+    StaleSocket(String),
+
+    #[error("Instance not ready after {}s waiting for {}", .0, .1)]
+    NotReady(u64, String),
+
+    #[error("VM failed to boot within {}s, check the kernel and rootfs, for details recall with PILOT_DEBUG=1", .0)]
+    BootFailed(u64),
+
+    #[error("Provisioning declined")]
+    Declined
 }
 
-impl Termination for FlakeError {
+impl FlakeError {
     /// A failed sub command will forward its error code
     ///
-    /// All other errors are represented as Failure
-    fn report(self) -> std::process::ExitCode {
+    /// A missing engine is reported as EXIT_ENGINE_NOT_FOUND,
+    /// all other errors are represented as failure
+    pub fn exit_code(&self) -> u8 {
         match self {
             FlakeError::CommandError(
                 CommandError {
@@ -82,11 +122,40 @@ impl Termination for FlakeError {
                     ..
                 }
             ) => match status.code() {
-                Some(code) => (code as u8).into(),
-                None => ExitCode::FAILURE,
+                Some(code) => code as u8,
+                None => 1,
             },
-            _ => ExitCode::FAILURE,
+            FlakeError::EngineNotFound(_) => defaults::EXIT_ENGINE_NOT_FOUND,
+            _ => 1,
+        }
+    }
+
+    /// Run the given hook command on the host as the calling user
+    ///
+    /// The error message and exit code are passed to the hook via
+    /// FLAKE_FAILURE_REASON and FLAKE_FAILURE_CODE. The hook runs
+    /// best-effort, its own failure is logged but ignored
+    pub fn run_failure_hook(&self, hook: &str) {
+        let mut call = Command::new("sh");
+        call.arg("-c").arg(hook)
+            .env("FLAKE_FAILURE_REASON", self.to_string())
+            .env("FLAKE_FAILURE_CODE", self.exit_code().to_string());
+        match call.status() {
+            Ok(status) => {
+                if ! status.success() {
+                    warn!("on_failure hook {:?} failed: {}", hook, status);
+                }
+            },
+            Err(error) => {
+                warn!("on_failure hook {:?} could not be called: {}", hook, error);
+            }
         }
     }
 }
 
+impl Termination for FlakeError {
+    /// Report the exit code as provided by [FlakeError::exit_code]
+    fn report(self) -> std::process::ExitCode {
+        ExitCode::from(self.exit_code())
+    }
+}
@@ -0,0 +1,187 @@
+//
+// Copyright (c) 2023 Elektrobit Automotive GmbH
+// Copyright (c) 2023 Marcus Schäfer
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use std::fmt;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+use crate::error::FlakeError;
+
+/// Cache type of the firecracker overlay drive
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    #[default]
+    Writeback,
+    Unsafe
+}
+
+impl CacheType {
+    pub const NAMES: [&'static str; 2] = ["Writeback", "Unsafe"];
+}
+
+impl fmt::Display for CacheType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheType::Writeback => write!(f, "Writeback"),
+            CacheType::Unsafe => write!(f, "Unsafe")
+        }
+    }
+}
+
+impl FromStr for CacheType {
+    type Err = FlakeError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "Writeback" => Ok(CacheType::Writeback),
+            "Unsafe" => Ok(CacheType::Unsafe),
+            _ => Err(FlakeError::IOError {
+                kind: format!("{:?}", ErrorKind::InvalidInput),
+                message: format!(
+                    "Invalid cache type {}, use one of: {}",
+                    name, Self::NAMES.join(", ")
+                )
+            })
+        }
+    }
+}
+
+// FireCrackerConfig represents firecracker json config
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerConfig {
+    #[serde(rename = "boot-source")]
+    pub boot_source: FireCrackerBootSource,
+    pub drives: Vec<FireCrackerDrive>,
+    #[serde(rename = "network-interfaces")]
+    pub network_interfaces: Vec<FireCrackerNetworkInterface>,
+    #[serde(rename = "machine-config")]
+    pub machine_config: FireCrackerMachine,
+    pub vsock: FireCrackerVsock,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<FireCrackerMetrics>
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerBootSource {
+    pub kernel_image_path: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub initrd_path: String,
+    pub boot_args: String
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerDrive {
+    pub drive_id: String,
+    pub path_on_host: String,
+    pub is_root_device: bool,
+    pub is_read_only: bool,
+    pub cache_type: String
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerNetworkInterface {
+    pub iface_id: String,
+    pub guest_mac: String,
+    pub host_dev_name: String
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerMachine {
+    pub vcpu_count: i64,
+    pub mem_size_mib: i64
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerVsock {
+    pub guest_cid: u32,
+    pub uds_path: String
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerMetrics {
+    pub metrics_path: String
+}
+
+impl Default for FireCrackerConfig {
+    fn default() -> Self {
+        /*!
+        Default template as shipped with the firecracker-pilot.
+        Kernel, rootfs, boot args and the vsock are set by the
+        pilot from the flake config at run time
+        !*/
+        Self {
+            boot_source: FireCrackerBootSource {
+                kernel_image_path: String::new(),
+                initrd_path: String::new(),
+                boot_args: String::new()
+            },
+            drives: vec![FireCrackerDrive {
+                drive_id: "rootfs".to_string(),
+                path_on_host: String::new(),
+                is_root_device: true,
+                is_read_only: true,
+                cache_type: "Writeback".to_string()
+            }],
+            network_interfaces: vec![FireCrackerNetworkInterface {
+                iface_id: "eth0".to_string(),
+                guest_mac: "AA:FC:00:00:00:01".to_string(),
+                host_dev_name: "tap0".to_string()
+            }],
+            machine_config: FireCrackerMachine {
+                vcpu_count: 2,
+                mem_size_mib: 4096
+            },
+            vsock: FireCrackerVsock {
+                guest_cid: 3,
+                uds_path: "/run/sci_cmd.sock".to_string()
+            },
+            metrics: None
+        }
+    }
+}
+
+impl FireCrackerConfig {
+    pub fn from_template(template: &str) -> Result<Self, FlakeError> {
+        /*!
+        Read the firecracker json template. A missing or broken
+        template is reported along with how to create a new one
+        !*/
+        let file = File::open(template).map_err(|error| {
+            let message = match error.kind() {
+                ErrorKind::NotFound => format!(
+                    "firecracker template not found, expected at {}, \
+                    create it with: flake-ctl firecracker init-template",
+                    template
+                ),
+                _ => format!("failed to open firecracker template: {}", error)
+            };
+            FlakeError::ConfigError { path: template.to_string(), message }
+        })?;
+        serde_json::from_reader(file).map_err(|error| {
+            FlakeError::ConfigError {
+                path: template.to_string(),
+                message: format!(
+                    "invalid firecracker template: {}, recreate it with: \
+                    flake-ctl firecracker init-template --force",
+                    error
+                )
+            }
+        })
+    }
+}
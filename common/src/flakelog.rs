@@ -23,6 +23,10 @@
 //
 use std::env;
 
+use crate::error::FlakeError;
+use crate::lookup::Lookup;
+use crate::defaults;
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct FlakeLog {
 }
@@ -37,4 +41,42 @@ impl FlakeLog {
     pub fn is_debug() -> bool {
         env::var("PILOT_DEBUG").is_ok()
     }
+
+    pub fn level_override(
+        log_level: Option<&str>, pilot_debug: bool
+    ) -> Option<&str> {
+        /*!
+        Log level to use in place of the default logger filter.
+        pilot_debug, set if PILOT_DEBUG is present, forces trace,
+        otherwise the given log_level from the flake config is
+        used if valid
+        !*/
+        if pilot_debug {
+            return Some("trace")
+        }
+        log_level.filter(|log_level| defaults::LOG_LEVELS.contains(log_level))
+    }
+
+    pub fn is_machine_readable() -> bool {
+        /*!
+        Check for the %silent:machine pilot option which requests
+        output that is predictable when called from other programs
+        !*/
+        Lookup::get_pilot_run_options()
+            .get("%silent").is_some_and(|value| value == "machine")
+    }
+
+    pub fn error(error: &FlakeError) {
+        /*!
+        Report the given error, as a single structured line on
+        stderr if machine readable output was requested
+        !*/
+        if Self::is_machine_readable() {
+            eprintln!(
+                "code={} message={:?}", error.exit_code(), error.to_string()
+            )
+        } else {
+            error!("{}", error)
+        }
+    }
 }
@@ -25,50 +25,468 @@ use crate::flakelog::FlakeLog;
 use crate::error::FlakeError;
 use crate::user::User;
 use crate::command::CommandExtTrait;
+use crate::defaults;
+use crate::lookup::Lookup;
+use crate::config::get_provision_tmpdir;
+use crate::report::ProvisionReport;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use tempfile::TempDir;
+use users::get_effective_uid;
+use serde::Deserialize;
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct IO {
 }
 
+/// A tar or path include and its destination in the instance
+#[derive(Debug)]
+enum Include<'a> {
+    Tar {
+        archive: &'a str,
+        destination: &'a str
+    },
+    Path {
+        source: &'a str,
+        destination: &'a str
+    }
+}
+
+impl Include<'_> {
+    fn destination(&self) -> &str {
+        match self {
+            Include::Tar { destination, .. } => destination,
+            Include::Path { destination, .. } => destination
+        }
+    }
+}
+
+/// Restores the previous process umask when dropped
+#[derive(Debug)]
+pub struct UmaskGuard {
+    previous: libc::mode_t
+}
+
+impl Drop for UmaskGuard {
+    fn drop(&mut self) {
+        unsafe { libc::umask(self.previous) };
+    }
+}
+
+/// How path includes are provided to the instance
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IncludeMode {
+    /// Copy the data into the instance at provisioning time
+    #[default]
+    Copy,
+
+    /// Mount the host data read-only into the instance.
+    /// The host data must stay present at run time
+    Mount
+}
+
 impl IO {
     pub fn sync_includes(
-        target: &String, tar_includes: Vec<&str>, path_includes: Vec<&str>, user: User
+        target: &String, tar_includes: Vec<&str>, path_includes: Vec<&str>,
+        user: User, jobs: usize, chown: Option<&str>
+    ) -> Result<u64, FlakeError> {
+        /*!
+        Sync custom include data to target path. Returns the
+        number of bytes synced for path includes if provisioning
+        is reported, see ProvisionReport.
+
+        Includes are synced by up to jobs threads in parallel.
+        If include destinations overlap, all includes are synced
+        one after the other in the given order. A failed include
+        does not stop the others, all failures are reported.
+
+        If chown is set to UID:GID the synced data is owned by
+        the given ids instead of the ids from the host
+        !*/
+        let mut includes: Vec<Include> = Vec::new();
+        for tar in tar_includes {
+            let (archive, destination) = Self::include_tar(tar)?;
+            includes.push(Include::Tar {
+                archive, destination: destination.unwrap_or("/")
+            });
+        }
+        for path in path_includes {
+            let (source, destination) = Self::include_path(path)?;
+            includes.push(Include::Path { source, destination });
+        }
+        let destinations: Vec<&str> = includes.iter()
+            .map(Include::destination)
+            .collect();
+        let jobs = if Self::destinations_overlap(&destinations) {
+            1
+        } else {
+            jobs.clamp(1, includes.len().max(1))
+        };
+        FlakeLog::debug(&format!(
+            "Syncing {} include(s) with {} job(s)", includes.len(), jobs
+        ));
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let include = match includes.get(index) {
+                        Some(include) => include,
+                        None => break
+                    };
+                    let result = Self::sync_include(
+                        target, include, user, chown
+                    );
+                    results.lock().unwrap().push((index, result));
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(index, _)| *index);
+        let mut bytes_synced = 0;
+        let mut errors: Vec<FlakeError> = Vec::new();
+        for (_, result) in results {
+            match result {
+                Ok(bytes) => bytes_synced += bytes,
+                Err(error) => errors.push(error)
+            }
+        }
+        if errors.len() > 1 {
+            return Err(FlakeError::IOError {
+                kind: "Other".to_string(),
+                message: format!(
+                    "{} includes failed: {}", errors.len(),
+                    errors.iter()
+                        .map(|error| error.to_string())
+                        .collect::<Vec<String>>()
+                        .join("; ")
+                )
+            })
+        }
+        match errors.pop() {
+            Some(error) => Err(error),
+            None => Ok(bytes_synced)
+        }
+    }
+
+    fn sync_include(
+        target: &String, include: &Include, user: User, chown: Option<&str>
+    ) -> Result<u64, FlakeError> {
+        /*!
+        Sync a single tar or path include to target path
+        !*/
+        match include {
+            Include::Tar { archive, destination } => {
+                FlakeLog::debug(
+                    &format!("Provision tar archive: [{}]", archive)
+                );
+                let extract_dir = if *destination == "/" {
+                    target.to_string()
+                } else {
+                    let extract_dir = format!("{}{}", target, destination);
+                    let mut mkdir = user.run("mkdir");
+                    mkdir.arg("-p").arg(&extract_dir);
+                    FlakeLog::debug(&format!("{:?}", mkdir.get_args()));
+                    mkdir.perform()?;
+                    extract_dir
+                };
+                let mut call = user.run("tar");
+                call.arg("-C").arg(&extract_dir)
+                    .arg("-xf").arg(archive);
+                FlakeLog::debug(&format!("{:?}", call.get_args()));
+                let output = call.perform()?;
+                FlakeLog::debug(
+                    &format!("{}", &String::from_utf8_lossy(&output.stdout))
+                );
+                FlakeLog::debug(
+                    &format!("{}", &String::from_utf8_lossy(&output.stderr))
+                );
+                if let Some(chown) = chown {
+                    Self::chown_tar_members(archive, &extract_dir, chown, user)?;
+                }
+                Ok(0)
+            },
+            Include::Path { source, destination } => {
+                FlakeLog::debug(&format!("Provision path: [{}]", source));
+                let chown_option = chown.map(|chown| format!("--chown={}", chown));
+                let mut options = vec!["--mkpath"];
+                if let Some(chown_option) = &chown_option {
+                    options.push(chown_option);
+                }
+                Self::sync_data(
+                    source, &format!("{}/{}", target, destination),
+                    options, user
+                )
+            }
+        }
+    }
+
+    fn chown_tar_members(
+        archive: &str, extract_dir: &str, chown: &str, user: User
+    ) -> Result<(), FlakeError> {
+        /*!
+        Change the ownership of the files extracted from the
+        given tar archive. Only the archive members are changed,
+        data already present in extract_dir is left untouched
+        !*/
+        let mut list = user.run("tar");
+        list.arg("-tf").arg(archive);
+        FlakeLog::debug(&format!("{:?}", list.get_args()));
+        let output = list.perform()?;
+        let members: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|member| ! member.is_empty())
+            .map(|member| {
+                format!("{}/{}", extract_dir, member.trim_end_matches('/'))
+            })
+            .collect();
+        for members in members.chunks(defaults::CHOWN_BATCH_SIZE) {
+            let mut call = user.run("chown");
+            call.arg("--no-dereference").arg(chown).args(members);
+            FlakeLog::debug(&format!("chown {} for {} file(s)", chown, members.len()));
+            call.perform()?;
+        }
+        Ok(())
+    }
+
+    pub fn check_chown(chown: &str) -> Result<(), String> {
+        /*!
+        Check that the given ownership is in the format UID:GID
+        with numeric ids
+        !*/
+        let valid = match chown.split_once(':') {
+            Some((uid, gid)) => [uid, gid].iter().all(|id| {
+                ! id.is_empty()
+                    && id.chars().all(|c| c.is_ascii_digit())
+                    && id.parse::<u32>().is_ok()
+            }),
+            None => false
+        };
+        if ! valid {
+            return Err(format!(
+                "Include chown {} must be UID:GID with numeric ids", chown
+            ))
+        }
+        Ok(())
+    }
+
+    pub fn workdir() -> Result<Option<String>, FlakeError> {
+        /*!
+        Current working directory of the caller if it should be
+        passed into the instance, set by the %cwd pilot option
+        !*/
+        if ! Lookup::get_pilot_run_options().contains_key("%cwd") {
+            return Ok(None)
+        }
+        let cwd = env::current_dir()?;
+        let workdir = cwd.to_str().ok_or_else(|| FlakeError::IOError {
+            kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+            message: format!("%cwd {} is not valid UTF-8", cwd.display())
+        })?;
+        Self::check_workdir(workdir)?;
+        Ok(Some(workdir.to_string()))
+    }
+
+    pub fn check_workdir(workdir: &str) -> Result<(), FlakeError> {
+        /*!
+        Check that the given working directory can be passed into
+        the instance. The host root directory is never passed and
+        the path must not interfere with the volume and include
+        syntax
+        !*/
+        let components = workdir.split('/').filter(|part| ! part.is_empty());
+        if ! workdir.starts_with('/') || components.count() == 0 {
+            return Err(FlakeError::IOError {
+                kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+                message: format!(
+                    "%cwd {} must be an absolute path below /", workdir
+                )
+            })
+        }
+        if workdir.contains(|c: char| c == ':' || c == ',' || c.is_whitespace()) {
+            return Err(FlakeError::IOError {
+                kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+                message: format!(
+                    "%cwd {} must not contain ':', ',' or whitespace", workdir
+                )
+            })
+        }
+        Ok(())
+    }
+
+    pub fn destinations_overlap(destinations: &[&str]) -> bool {
+        /*!
+        Check if any include destination is the same as or
+        below another one, such that the includes write into
+        the same subtree of the instance
+        !*/
+        let parts: Vec<Vec<&str>> = destinations.iter()
+            .map(|destination| {
+                destination.split('/').filter(|part| ! part.is_empty())
+                    .collect()
+            })
+            .collect();
+        parts.iter().enumerate().any(|(index, first)| {
+            parts.iter().skip(index + 1).any(|second| {
+                first.starts_with(second) || second.starts_with(first)
+            })
+        })
+    }
+
+    pub fn include_jobs() -> Result<usize, FlakeError> {
+        /*!
+        Number of parallel jobs to sync includes with, set by
+        the %jobs:N pilot option. Defaults to the number of
+        available CPUs
+        !*/
+        Self::include_jobs_from(&Lookup::args())
+    }
+
+    pub fn include_jobs_from(args: &[String]) -> Result<usize, FlakeError> {
+        /*!
+        Read the include jobs from the given arguments
+        !*/
+        match Lookup::pilot_run_options_from(args).get("%jobs") {
+            Some(jobs) => match jobs.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => Ok(jobs),
+                _ => Err(FlakeError::IOError {
+                    kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+                    message: format!(
+                        "%jobs:{} must be a positive number", jobs
+                    )
+                })
+            },
+            None => Ok(
+                thread::available_parallelism()
+                    .map(|jobs| jobs.get())
+                    .unwrap_or(1)
+            )
+        }
+    }
+
+    pub fn validate_includes(
+        tar_includes: Vec<&str>, path_includes: Vec<&str>
     ) -> Result<(), FlakeError> {
         /*!
-        Sync custom include data to target path
+        Check that all include sources exist and are readable
+        such that a broken include fails before the instance
+        gets created. Sources only readable by root are accepted
+        for other users as provisioning runs through sudo
         !*/
+        let mut sources: Vec<(&str, &str)> = Vec::new();
         for tar in tar_includes {
-            FlakeLog::debug(&format!("Provision tar archive: [{}]", tar));
-            let mut call = user.run("tar");
-            call.arg("-C").arg(target)
-                .arg("-xf").arg(tar);
-            FlakeLog::debug(&format!("{:?}", call.get_args()));
-            let output = call.perform()?;
-            FlakeLog::debug(
-                &format!("{}", &String::from_utf8_lossy(&output.stdout))
-            );
-            FlakeLog::debug(
-                &format!("{}", &String::from_utf8_lossy(&output.stderr))
-            );
+            sources.push(("tar", Self::include_tar(tar)?.0));
         }
         for path in path_includes {
-            FlakeLog::debug(&format!("Provision path: [{}]", path));
-            Self::sync_data(
-                path, &format!("{}/{}", target, path),
-                ["--mkpath"].to_vec(), user
-            )?;
+            sources.push(("path", Self::include_path(path)?.0));
+        }
+        for (kind, source) in sources {
+            if let Err(error) = File::open(source) {
+                let permission_denied =
+                    error.kind() == std::io::ErrorKind::PermissionDenied;
+                if permission_denied && get_effective_uid() != 0 {
+                    continue
+                }
+                return Err(FlakeError::IOError {
+                    kind: format!("{:?}", error.kind()),
+                    message: format!(
+                        "Include {} {} is not accessible: {}",
+                        kind, source, error
+                    )
+                })
+            }
         }
         Ok(())
     }
 
+    pub fn include_tar(tar: &str) -> Result<(&str, Option<&str>), FlakeError> {
+        /*!
+        Split a tar include into the archive and the directory
+        to extract it to. A plain tar is extracted at the root
+        of the instance, a tar in the form tar:dst is extracted
+        to dst which must be an absolute path within the instance.
+        The tar is split at the last ':' followed by a '/', such
+        that archive names containing ':' can be used
+        !*/
+        match Self::split_destination(tar) {
+            Some((archive, destination)) => {
+                let within_target = destination.starts_with('/')
+                    && ! destination.split('/').any(|part| part == "..");
+                if archive.is_empty() || ! within_target {
+                    return Err(FlakeError::IOError {
+                        kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+                        message: format!(
+                            "Include tar {} must be tar:dst with absolute dst \
+                            not leaving the instance root",
+                            tar
+                        )
+                    })
+                }
+                Ok((archive, Some(destination)))
+            },
+            None => Ok((tar, None))
+        }
+    }
+
+    pub fn include_path(path: &str) -> Result<(&str, &str), FlakeError> {
+        /*!
+        Split a path include into its source and destination.
+        A plain path is synced to the same location in the
+        instance, a path in the form src:dst is synced to dst
+        which must be absolute. The path is split at the last ':'
+        followed by a '/', a source containing ':/' therefore
+        requires an explicit dst
+        !*/
+        match Self::split_destination(path) {
+            Some((source, destination)) => {
+                if source.is_empty() {
+                    return Err(FlakeError::IOError {
+                        kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+                        message: format!(
+                            "Include path {} must be src:dst with absolute dst",
+                            path
+                        )
+                    })
+                }
+                Ok((source, destination))
+            },
+            None => Ok((path, path))
+        }
+    }
+
+    fn split_destination(include: &str) -> Option<(&str, &str)> {
+        /*!
+        Split an include at the last ':' which is followed by an
+        absolute destination
+        !*/
+        include.rfind(":/").map(
+            |index| (&include[..index], &include[index + 1..])
+        )
+    }
+
     pub fn sync_data(
         source: &str, target: &str, options: Vec<&str>, user: User
-    ) -> Result<(), FlakeError> {
+    ) -> Result<u64, FlakeError> {
         /*!
-        Sync data from source path to target path
+        Sync data from source path to target path. Returns the
+        number of bytes transferred if provisioning is reported,
+        see ProvisionReport, zero otherwise
         !*/
+        let reported = ProvisionReport::is_enabled();
         let mut call = user.run("rsync");
         call.arg("-av");
+        if reported {
+            call.arg("--stats");
+        }
         for option in options {
             call.arg(option);
         }
@@ -84,6 +502,240 @@ impl IO {
         if !output.status.success() {
             return Err(FlakeError::SyncFailed)
         }
+        if reported {
+            return Ok(ProvisionReport::rsync_transferred_bytes(
+                &String::from_utf8_lossy(&output.stdout)
+            ))
+        }
+        Ok(0)
+    }
+
+    pub fn provision_tmpdir() -> PathBuf {
+        /*!
+        Base directory for temporary provisioning data. The
+        %tmpdir:PATH pilot option takes precedence over the
+        provision_tmpdir setting from the flakes config
+        !*/
+        match Lookup::get_pilot_run_options().get("%tmpdir") {
+            Some(tmpdir) if ! tmpdir.is_empty() => PathBuf::from(tmpdir),
+            _ => get_provision_tmpdir()
+                .map(PathBuf::from)
+                .unwrap_or_else(env::temp_dir)
+        }
+    }
+
+    pub fn tempdir() -> Result<TempDir, FlakeError> {
+        /*!
+        Create temporary directory below provision_tmpdir
+        !*/
+        Ok(tempfile::Builder::new().tempdir_in(Self::provision_tmpdir())?)
+    }
+
+    pub fn tempfile() -> Result<File, FlakeError> {
+        /*!
+        Create temporary file below provision_tmpdir
+        !*/
+        Ok(tempfile::tempfile_in(Self::provision_tmpdir())?)
+    }
+
+    pub fn lock(lock_file: &str) -> Result<File, FlakeError> {
+        /*!
+        Acquire an exclusive advisory lock on the given lock_file.
+        Blocks until the lock is available. The lock is held until
+        the returned file is dropped
+        !*/
+        let file = match File::open(lock_file) {
+            Ok(file) => file,
+            Err(_) => OpenOptions::new()
+                .write(true).create(true).truncate(false).open(lock_file)?
+        };
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().into())
+        }
+        Ok(file)
+    }
+
+    pub fn parse_umask(umask: &str) -> Result<u32, FlakeError> {
+        /*!
+        Parse an octal umask value like 0027
+        !*/
+        match u32::from_str_radix(umask, 8) {
+            Ok(mask) if mask <= 0o777 => Ok(mask),
+            _ => Err(FlakeError::IOError {
+                kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+                message: format!(
+                    "Invalid umask {}, expected an octal value up to 0777",
+                    umask
+                )
+            })
+        }
+    }
+
+    pub fn check_cpuset(cpuset: &str) -> Result<(), FlakeError> {
+        /*!
+        Check if the given cpuset is a comma separated list of
+        CPU numbers or ranges like 0-3,5
+        !*/
+        let valid = cpuset.split(',').all(|cpus| {
+            let (first, last) = cpus.split_once('-').unwrap_or((cpus, cpus));
+            let parse = |cpu: &str| {
+                cpu.bytes().all(|c| c.is_ascii_digit())
+                    .then(|| cpu.parse::<u32>().ok()).flatten()
+            };
+            matches!(
+                (parse(first), parse(last)), (Some(first), Some(last))
+                if first <= last
+            )
+        });
+        if ! valid {
+            return Err(FlakeError::IOError {
+                kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+                message: format!(
+                    "Invalid cpuset {}, expected CPU numbers or ranges \
+                    like 0-3,5", cpuset
+                )
+            })
+        }
         Ok(())
     }
+
+    pub fn umask(umask: Option<&str>) -> Result<Option<UmaskGuard>, FlakeError> {
+        /*!
+        Set the process umask for the lifetime of the returned
+        guard. Child processes inherit it, commands called via
+        sudo combine it with the umask configured for sudo.
+        Without a umask nothing is changed
+        !*/
+        match umask {
+            Some(umask) => {
+                let mask = Self::parse_umask(umask)?;
+                let previous = unsafe { libc::umask(mask as libc::mode_t) };
+                Ok(Some(UmaskGuard { previous }))
+            },
+            None => Ok(None)
+        }
+    }
+
+    pub fn gc_sweep<F>(
+        ids_dir: &str, threshold: usize, mut gc_file: F
+    ) -> Result<(), FlakeError> where F: FnMut(&String) {
+        /*!
+        Call gc_file for all ID files in ids_dir if there are
+        more than threshold of them. The sweep holds the gc lock
+        in ids_dir such that only one garbage collection runs
+        at a time
+        !*/
+        let lock_file = format!("{}/{}", ids_dir, defaults::GC_LOCK_FILE);
+        let _lock = Self::lock(&lock_file)?;
+        let mut id_file_names: Vec<String> = Vec::new();
+        let paths = match fs::read_dir(ids_dir) {
+            Ok(paths) => paths,
+            Err(error) => {
+                return Err(FlakeError::IOError {
+                    kind: format!("{:?}", error.kind()),
+                    message: format!("fs::read_dir failed on {}: {}",
+                        ids_dir, error
+                    )
+                })
+            }
+        };
+        for path in paths {
+            let id_file = format!("{}", path?.path().display());
+            if id_file != lock_file
+                && ! id_file.ends_with(defaults::STATUS_LINK_SUFFIX)
+            {
+                id_file_names.push(id_file);
+            }
+        }
+        if id_file_names.len() > threshold {
+            for id_file in id_file_names {
+                gc_file(&id_file);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_status(
+        status_file: &str, id_file: &str, state: &str, id: &str
+    ) -> Result<(), FlakeError> {
+        /*!
+        Write the instance state, id and start time as key=value
+        lines to the given status_file. The start time is taken
+        when the state changes to starting and kept otherwise.
+        The file is replaced atomically and linked from next to
+        the id_file such that gc can delete it together with
+        the id_file
+        !*/
+        let started = match fs::read_to_string(status_file) {
+            Ok(status) if state != "starting" => status.lines()
+                .find_map(|line| line.strip_prefix("started="))
+                .map(str::to_string),
+            _ => None
+        }.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|started| started.as_secs())
+                .unwrap_or_default()
+                .to_string()
+        });
+        let status_tmp = format!("{}.tmp", status_file);
+        fs::write(
+            &status_tmp,
+            format!("state={}\nid={}\nstarted={}\n", state, id, started)
+        )?;
+        fs::rename(&status_tmp, status_file)?;
+        let status_link = format!(
+            "{}{}", id_file, defaults::STATUS_LINK_SUFFIX
+        );
+        if Self::owned_status_link(&status_link)
+            != Some(PathBuf::from(status_file))
+        {
+            let _ = fs::remove_file(&status_link);
+            symlink(status_file, &status_link)?;
+        }
+        Ok(())
+    }
+
+    fn owned_status_link(status_link: &str) -> Option<PathBuf> {
+        /*!
+        Target of the given status link if it is a symlink owned
+        by the effective user. The ids dirs are writable for all
+        users, links created by anybody else are never followed
+        !*/
+        let metadata = fs::symlink_metadata(status_link).ok()?;
+        if metadata.file_type().is_symlink()
+            && metadata.uid() == get_effective_uid()
+        {
+            fs::read_link(status_link).ok()
+        } else {
+            None
+        }
+    }
+
+    pub fn remove_status(id_file: &str) {
+        /*!
+        Delete the status file the link next to the given id_file
+        points to if the link is owned by the effective user. The
+        id_file may belong to any flake, thus the target is only
+        deleted if it is a file of the effective user which looks
+        like a status file written by write_status. Status links
+        of other users are left alone, a link owned by the
+        effective user is deleted in any case
+        !*/
+        let status_link = format!(
+            "{}{}", id_file, defaults::STATUS_LINK_SUFFIX
+        );
+        if let Some(target) = Self::owned_status_link(&status_link) {
+            let is_status = fs::symlink_metadata(&target)
+                .is_ok_and(|metadata| metadata.is_file()
+                    && metadata.uid() == get_effective_uid()
+                )
+                && fs::read_to_string(&target)
+                    .is_ok_and(|status| status.starts_with("state="));
+            if is_status {
+                FlakeLog::debug(&format!("Deleting {}", target.display()));
+                let _ = fs::remove_file(&target);
+            }
+            let _ = fs::remove_file(&status_link);
+        }
+    }
 }
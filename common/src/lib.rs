@@ -34,3 +34,7 @@ pub mod flakelog;
 pub mod defaults;
 pub mod io;
 pub mod container;
+pub mod signals;
+pub mod report;
+#[cfg(feature = "json")]
+pub mod firecracker;
@@ -25,8 +25,12 @@ use std::backtrace::Backtrace;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::sync::OnceLock;
 
 use crate::flakelog::FlakeLog;
+use crate::error::FlakeError;
+
+static ARGS: OnceLock<Vec<String>> = OnceLock::new();
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Lookup {
@@ -43,19 +47,49 @@ impl Lookup {
         env::var("PILOT_DEBUG").is_ok()
     }
 
+    pub fn set_args(args: Vec<String>) -> bool {
+        /*!
+        Set the arguments used in place of the process arguments.
+        The first element is the program name. Arguments can only
+        be set once per process, false is returned if they were
+        already set before
+        !*/
+        ARGS.set(args).is_ok()
+    }
+
+    pub fn args() -> Vec<String> {
+        /*!
+        Arguments of the flake call, either the ones set via
+        set_args or the process arguments
+        !*/
+        match ARGS.get() {
+            Some(args) => args.clone(),
+            None => env::args().collect()
+        }
+    }
+
     pub fn get_run_cmdline(
         init: Vec<String>, quote_for_kernel_cmdline: bool
     ) -> Vec<String> {
         /*!
         setup run commandline for the command call
         !*/
-        let args: Vec<String> = env::args().collect();
+        Self::run_cmdline_from(&Self::args(), init, quote_for_kernel_cmdline)
+    }
+
+    pub fn run_cmdline_from(
+        args: &[String], init: Vec<String>, quote_for_kernel_cmdline: bool
+    ) -> Vec<String> {
+        /*!
+        setup run commandline from the given arguments, the
+        first argument is the program name and gets skipped
+        !*/
         let mut run: Vec<String> = init;
-        for arg in &args[1..] {
+        for arg in args.iter().skip(1) {
             FlakeLog::debug(&format!("Got Argument: {}", arg));
             if ! arg.starts_with('@') && ! arg.starts_with('%') {
                 if quote_for_kernel_cmdline {
-                    run.push(arg.replace('-', "\\-").to_string());
+                    run.push(Self::quote_for_kernel_cmdline(arg));
                 } else {
                     run.push(arg.to_string());
                 }
@@ -64,6 +98,28 @@ impl Lookup {
         run
     }
 
+    pub fn quote_for_kernel_cmdline(arg: &str) -> String {
+        /*!
+        Escape arg such that it is read back as a single argument
+        by a shell-like split of the run="..." kernel parameter.
+        All characters other than alphanumerics and a few safe
+        ones are escaped by a backslash. The kernel has no way
+        to escape a double quote inside of a quoted parameter,
+        thus arguments containing one can't be passed this way
+        !*/
+        if arg.is_empty() {
+            return "''".to_string()
+        }
+        let mut quoted = String::new();
+        for c in arg.chars() {
+            if ! c.is_ascii_alphanumeric() && ! "_/.:=,+".contains(c) {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted
+    }
+
     pub fn get_pilot_run_options() -> HashMap<String, String> {
         /*!
         read runtime options which are only meant to be used for the
@@ -72,9 +128,16 @@ impl Lookup {
         from the standard Unix/Linux commandline format and treat
         options passed as %name:value to be a pilot option
         !*/
-        let args: Vec<String> = env::args().collect();
+        Self::pilot_run_options_from(&Self::args())
+    }
+
+    pub fn pilot_run_options_from(args: &[String]) -> HashMap<String, String> {
+        /*!
+        read pilot options from the given arguments, the
+        first argument is the program name and gets skipped
+        !*/
         let mut pilot_options = HashMap::new();
-        for arg in &args[1..] {
+        for arg in args.iter().skip(1) {
             if arg.starts_with('%') {
                 let (name, value) = arg.rsplit_once(':').unwrap_or_default();
                 if name.is_empty() {
@@ -87,15 +150,50 @@ impl Lookup {
         pilot_options
     }
 
+    pub fn get_app_override() -> Result<Option<String>, FlakeError> {
+        /*!
+        Application path in the instance given for this call
+        by the %app:PATH pilot option. It overrides the
+        target_app_path of the flake configuration
+        !*/
+        Self::app_override_from(&Self::args())
+    }
+
+    pub fn app_override_from(
+        args: &[String]
+    ) -> Result<Option<String>, FlakeError> {
+        /*!
+        Read the application path override from the given
+        arguments, the path must be absolute
+        !*/
+        match Self::pilot_run_options_from(args).remove("%app") {
+            Some(app) if app.starts_with('/') && app.len() > 1 => Ok(Some(app)),
+            Some(app) => Err(FlakeError::IOError {
+                kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+                message: format!(
+                    "%app:{} must be an absolute path in the instance", app
+                )
+            }),
+            None => Ok(None)
+        }
+    }
+
     pub fn which(command: &str) -> bool {
+        Self::which_path(command).is_some()
+    }
+
+    pub fn which_path(command: &str) -> Option<String> {
+        /*!
+        Lookup the absolute path of command in PATH
+        !*/
         if let Ok(path) = env::var("PATH") {
             for path_entry in path.split(':') {
                 let abs_command = format!("{}/{}", path_entry, command);
-                if fs::metadata(abs_command).is_ok() {
-                    return true;
+                if fs::metadata(&abs_command).is_ok() {
+                    return Some(abs_command);
                 }
             }
         }
-        false
+        None
     }
 }
@@ -0,0 +1,93 @@
+//
+// Copyright (c) 2023 SUSE Software Solutions Germany GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use serde::Serialize;
+use crate::flakelog::FlakeLog;
+use crate::lookup::Lookup;
+
+/// Summary of what the provisioning of an instance did
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ProvisionReport {
+    /// Layers synced into the instance in the order processed
+    pub layers: Vec<String>,
+    /// Bytes transferred by rsync as reported by its stats
+    pub bytes_synced: u64,
+    /// Number of tar, path and oci includes applied
+    pub includes: usize,
+}
+
+impl ProvisionReport {
+    pub fn is_enabled() -> bool {
+        /*!
+        Provisioning is only reported in debug mode or if
+        requested via the %report pilot option. Collecting
+        the rsync stats is skipped otherwise
+        !*/
+        FlakeLog::is_debug()
+            || Lookup::get_pilot_run_options().contains_key("%report")
+    }
+
+    pub fn rsync_transferred_bytes(rsync_stats: &str) -> u64 {
+        /*!
+        Read the transferred file size from rsync --stats output
+        !*/
+        for line in rsync_stats.lines() {
+            if let Some(size) = line.strip_prefix("Total transferred file size:") {
+                let bytes: String = size.trim()
+                    .chars()
+                    .take_while(|c| c.is_ascii_digit() || *c == ',')
+                    .filter(char::is_ascii_digit)
+                    .collect();
+                return bytes.parse().unwrap_or_default()
+            }
+        }
+        0
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        /*!
+        Serialize the report as single line JSON
+        !*/
+        serde_json::to_string(self).expect("Report is serializable")
+    }
+
+    pub fn report(&self) {
+        /*!
+        Print the report as JSON line on stderr if requested
+        via %report, log it at info level in debug mode
+        !*/
+        #[cfg(feature = "json")]
+        if Lookup::get_pilot_run_options().contains_key("%report") {
+            eprintln!("{}", self.to_json());
+            return
+        }
+        if FlakeLog::is_debug() {
+            info!(
+                "Provisioned {} layer(s) [{}], {} include(s), {} bytes synced",
+                self.layers.len(), self.layers.join(", "),
+                self.includes, self.bytes_synced
+            );
+        }
+    }
+}
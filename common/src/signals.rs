@@ -0,0 +1,78 @@
+//
+// Copyright (c) 2023 SUSE Software Solutions Germany GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use signal_hook::consts::{SIGHUP, SIGTERM, SIGUSR1};
+use signal_hook::iterator::{Handle, Signals};
+use std::thread::{self, JoinHandle};
+
+use crate::error::FlakeError;
+use crate::flakelog::FlakeLog;
+
+/// Signals a supervisor sends to the pilot which are meant
+/// for the process running inside of the instance
+pub const FORWARDED_SIGNALS: [i32; 3] = [SIGTERM, SIGHUP, SIGUSR1];
+
+/// Forward [FORWARDED_SIGNALS] received by the pilot
+///
+/// Forwarding is active until the SignalForward is dropped
+pub struct SignalForward {
+    handle: Handle,
+    thread: Option<JoinHandle<()>>
+}
+
+impl SignalForward {
+    pub fn start<F>(forward: F) -> Result<SignalForward, FlakeError>
+    where F: Fn(i32) + Send + 'static {
+        /*!
+        Handle forwarded signals by calling forward with
+        the signal number
+        !*/
+        let mut signals = Signals::new(FORWARDED_SIGNALS)?;
+        let handle = signals.handle();
+        let thread = thread::spawn(move || {
+            for signal in signals.forever() {
+                FlakeLog::debug(&format!("Forwarding signal {}", signal));
+                forward(signal);
+            }
+        });
+        Ok(SignalForward { handle, thread: Some(thread) })
+    }
+
+    pub fn to_process(pid: u32) -> Result<SignalForward, FlakeError> {
+        /*!
+        Forward signals to the process with the given pid
+        !*/
+        Self::start(move |signal| {
+            unsafe { libc::kill(pid as libc::pid_t, signal) };
+        })
+    }
+}
+
+impl Drop for SignalForward {
+    fn drop(&mut self) {
+        self.handle.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
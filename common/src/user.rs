@@ -26,6 +26,7 @@ use std::path::Path;
 use std::{process::Command, ffi::OsStr};
 use serde::{Serialize, Deserialize};
 use crate::command::{CommandExtTrait, CommandError};
+use crate::error::FlakeError;
 use users::{get_current_uid, get_current_groupname};
 
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
@@ -39,9 +40,51 @@ impl<'a> From<&'a str> for User<'a> {
     }
 }
 
+impl<'a> User<'a> {
+    pub fn parse(spec: &'a str) -> Result<Self, FlakeError> {
+        /*!
+        Create user from a runas spec which is either a user name
+        or a numeric user ID prefixed with '#'. sudo accepts the
+        '#UID' form as is, thus only the syntax is validated here
+        !*/
+        let valid = match spec.strip_prefix('#') {
+            Some(uid) => {
+                ! uid.is_empty()
+                    && uid.chars().all(|c| c.is_ascii_digit())
+                    && uid.parse::<u32>().is_ok()
+            },
+            None => {
+                let name = spec.strip_suffix('$').unwrap_or(spec);
+                name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+                    && name.chars().all(|c| {
+                        c.is_ascii_lowercase() || c.is_ascii_digit()
+                            || c == '_' || c == '-'
+                    })
+            }
+        };
+        if ! valid {
+            return Err(FlakeError::IOError {
+                kind: format!("{:?}", std::io::ErrorKind::InvalidInput),
+                message: format!(
+                    "runas {} must be a user name or #UID", spec
+                )
+            })
+        }
+        Ok(Self { name: Some(spec) })
+    }
+}
+
 impl User<'_> {
     pub const ROOT: User<'static> = User { name: Some("root")};
 
+    pub fn is_root(&self) -> bool {
+        /*!
+        Check if commands of this user run as root. Without a
+        name sudo runs the command as root
+        !*/
+        matches!(self.name, None | Some("root") | Some("#0"))
+    }
+
     pub fn get_user_id(&self) -> String {
         get_current_uid().to_string()
     }
@@ -29,6 +29,7 @@ pub const PIVOT_ROOT: &str = "/sbin/pivot_root";
 pub const OVERLAY_ROOT: &str = "/overlayroot/rootfs";
 pub const OVERLAY_UPPER: &str = "/overlayroot/rootfs_upper";
 pub const OVERLAY_WORK: &str = "/overlayroot/rootfs_work";
+pub const OVERLAY_LAYER_PREFIX: &str = "/overlayroot/layer_";
 pub const PROBE_MODULE: &str = "/sbin/modprobe";
 pub const SYSTEMD_NETWORK_RESOLV_CONF: &str = "/run/systemd/resolve/resolv.conf";
 pub const VM_QUIT: &str = "sci_quit";
@@ -39,6 +40,12 @@ pub const RETRIES: u32 =
     60;
 pub const VM_WAIT_TIMEOUT_MSEC: u64 =
     1000;
+pub const SELECT_TIMEOUT_MSEC: i64 =
+    200;
+pub const SCI_EXIT_TRAILER: &str =
+    "\0sci-exit:";
+pub const SECRET_ENV_PREFIX: &str =
+    "SECRET_ENV";
 
 pub fn debug(message: &str) {
     if env::var("PILOT_DEBUG").is_ok() {
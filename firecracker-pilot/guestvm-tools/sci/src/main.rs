@@ -26,16 +26,21 @@
 extern crate log;
 extern crate shell_words;
 
+#[cfg(test)]
+pub mod tests;
+
 pub mod defaults;
 
 use std::env;
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
 use std::os::unix::fs::symlink;
 use std::path::Path;
 use std::process::Command;
-use std::os::unix::process::CommandExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use system_shutdown::force_reboot;
 use std::fs;
-use sys_mount::Mount;
+use sys_mount::{Mount, MountFlags};
 use env_logger::Env;
 use std::{thread, time};
 use vsock::{VsockListener, VsockStream};
@@ -43,6 +48,7 @@ use std::io::Read;
 use std::net::Shutdown;
 use std::os::fd::AsRawFd;
 use std::io::Write;
+use std::io;
 use pty::prelude::Fork;
 use termios::*;
 
@@ -74,15 +80,22 @@ fn main() {
     }
 
     // parse commandline from run environment variable
+    // run=@FILE references a file in the overlay providing
+    // the command, one argument per line
+    let mut run_file: Option<String> = None;
     match env::var("run").ok() {
         Some(call_cmd) => {
-            match shell_words::split(&call_cmd) {
-                Ok(call_params) => {
-                    args = call_params
-                },
-                Err(error) => {
-                    debug(&format!("Failed to parse {}: {}", call_cmd, error));
-                    do_reboot(false)
+            if let Some(file) = call_cmd.strip_prefix('@') {
+                run_file = Some(file.to_string())
+            } else {
+                match parse_run(&call_cmd) {
+                    Ok(call_params) => {
+                        args = call_params
+                    },
+                    Err(message) => {
+                        debug(&message);
+                        do_reboot(false)
+                    }
                 }
             }
         },
@@ -92,19 +105,16 @@ fn main() {
         }
     }
 
-    // sanity check on command to call
-    if args[0].is_empty() {
-        debug("No command to execute specified");
-    }
-
-    // check if given command requires process replacement
-    if args[0] == "/usr/lib/systemd/systemd" {
-        do_exec = true;
+    if run_file.is_none() {
+        do_exec = requires_exec(&args);
     }
 
     // check for resume mode
     let resume = env::var("sci_resume").ok().is_some();
 
+    // check for keep alive mode, only used for debugging
+    let keep_alive = env::var("sci_keep_alive").ok().is_some();
+
     // check for console setting
     let mut console_vsock = false;
     if resume || env::var("sci_force_vsock").ok().is_some() {
@@ -137,7 +147,13 @@ fn main() {
             {
                 Ok(_) => {
                     debug(&format!("Mounted {:?} on /overlayroot", overlay));
-                    ok = true
+                    ok = true;
+                    if let Some(run_file) = &run_file {
+                        args = read_run_file(
+                            &format!("{}{}", defaults::OVERLAY_UPPER, run_file)
+                        );
+                        do_exec = requires_exec(&args);
+                    }
                 },
                 Err(error) => {
                     debug(&format!("Failed to mount overlayroot: {}", error));
@@ -164,14 +180,32 @@ fn main() {
                     }
                 }
             }
+            // mount optional read-only layers, the last layer
+            // is the topmost lower directory of the overlay
+            let mut lowerdirs: Vec<String> = vec!["/".to_string()];
             if ok {
+                if let Ok(layers) = env::var("overlay_lowerdirs") {
+                    for (index, layer) in layers.split(':').enumerate() {
+                        let layer_dir = format!(
+                            "{}{}", defaults::OVERLAY_LAYER_PREFIX, index
+                        );
+                        ok = mount_layer(layer, &layer_dir);
+                        if ! ok {
+                            break
+                        }
+                        lowerdirs.insert(0, layer_dir);
+                    }
+                }
+            }
+            if ok {
+                let overlay_options = format!(
+                    "lowerdir={},upperdir={},workdir={}",
+                    lowerdirs.join(":"),
+                    defaults::OVERLAY_UPPER, defaults::OVERLAY_WORK
+                );
                 match Mount::builder()
                     .fstype("overlay")
-                    .data(
-                        &format!("lowerdir=/,upperdir={},workdir={}",
-                            defaults::OVERLAY_UPPER, defaults::OVERLAY_WORK
-                        )
-                    )
+                    .data(&overlay_options)
                     .mount("overlay", defaults::OVERLAY_ROOT)
                 {
                     Ok(_) => {
@@ -207,6 +241,10 @@ fn main() {
                     }
                 }
             }
+            if let Err(message) = validate_args(&args) {
+                debug(&message);
+                do_reboot(false)
+            }
             if do_exec {
                 call = Command::new(defaults::SWITCH_ROOT);
                 call.arg(".").arg(&args[0]);
@@ -232,18 +270,28 @@ fn main() {
                         }
                     }
                     mount_basic_fs();
+                    mount_extra_fs();
                     setup_resolver_link();
                 }
             }
         },
         None => {
+            if run_file.is_some() {
+                debug("run=@FILE requires an overlay_root");
+                do_reboot(false)
+            }
+            mount_extra_fs();
+            if let Err(message) = validate_args(&args) {
+                debug(&message);
+                do_reboot(false)
+            }
             // Call command in current environment
             call = Command::new(&args[0]);
         }
     };
 
     // Setup command call parameters
-    for arg in &args[1..] {
+    for arg in args.iter().skip(1) {
         call.arg(arg);
     }
 
@@ -251,150 +299,27 @@ fn main() {
     if ! ok {
         do_reboot(ok)
     }
-    if console_vsock {
-        // vsock required; check if vhost transport is loaded
-        let mut modprobe = Command::new(defaults::PROBE_MODULE);
-        modprobe.arg(defaults::VHOST_TRANSPORT);
-        debug(&format!(
-            "SCI CALL: {} -> {:?}", defaults::PROBE_MODULE, modprobe.get_args()
-        ));
-        match modprobe.status() {
-            Ok(_) => { },
-            Err(error) => {
-                debug(&format!(
-                    "Loading {} module failed: {}",
-                    defaults::VHOST_TRANSPORT, error
-                ));
-            }
-        }
-        // start vsock listener on VM_PORT, wait for command(s) in a loop
-        // A received command turns into a vsock stream process calling
-        // the command with an expected listener.
-        debug(&format!(
-            "Binding vsock CID={} on port={}",
-            defaults::GUEST_CID, defaults::VM_PORT
-        ));
-        match VsockListener::bind_with_cid_port(
-            defaults::GUEST_CID, defaults::VM_PORT
-        ) {
-            Ok(listener) => {
-                // Enter main loop
-                loop {
-                    ok = true;
-                    match listener.accept() {
-                        Ok((mut stream, addr)) => {
-                            // read command string from incoming connection
-                            debug(&format!(
-                                "Accepted incoming connection from: {}:{}",
-                                addr.cid(), addr.port()
-                            ));
-                            let mut call_str = String::new();
-                            let mut call_buf = Vec::new();
-                            match stream.read_to_end(&mut call_buf) {
-                                Ok(_) => {
-                                    call_str = String::from_utf8(
-                                        call_buf.to_vec()
-                                    ).unwrap();
-                                    let len_to_truncate = call_str
-                                        .trim_end()
-                                        .len();
-                                    call_str.truncate(len_to_truncate);
-                                },
-                                Err(error) => {
-                                    debug(&format!(
-                                        "Failed to read data {}", error
-                                    ));
-                                    ok = false
-                                }
-                            };
-                            stream.shutdown(Shutdown::Both).unwrap();
-                            if call_str.is_empty() {
-                                // Caused by handshake checks that connects
-                                // without sending data
-                                debug("No data received until connection end");
-                                continue
-                            }
-                            debug(&format!(
-                                "SCI CALL RAW BUF: {:?}", call_str
-                            ));
-                            let mut call_stack: Vec<&str> =
-                                call_str.split(' ').collect();
-                            let exec_port = call_stack.pop().unwrap();
-                            let exec_cmd = call_stack.join(" ");
-                            let mut exec_port_num = 0;
-                            match exec_port.parse::<u32>() {
-                                Ok(num) => { exec_port_num = num },
-                                Err(error) => {
-                                    debug(&format!(
-                                        "Failed to parse port: {}: {}",
-                                        exec_port, error
-                                    ));
-                                    ok = false
-                                }
-                            }
-                            debug(&format!(
-                                "CALL SCI: string:'{}' u32:{}",
-                                exec_cmd, exec_port_num
-                            ));
-
-                            // Establish a VSOCK connection with the farend
-                            let thread_handle = thread::spawn(move || {
-                                let mut retry_count = 0;
-                                loop {
-                                    if retry_count == defaults::RETRIES {
-                                        break
-                                    }
-                                    match VsockStream::connect_with_cid_port(
-                                        2, exec_port_num
-                                    ) {
-                                        Ok(vsock_stream) => {
-                                            redirect_command(
-                                                &exec_cmd, vsock_stream
-                                            );
-                                            break
-                                        },
-                                        Err(error) => {
-                                            debug(&format!(
-                                                "[{}] VSOCK-CONNECT failed with: {}",
-                                                retry_count, error
-                                            ));
-                                            let some_time = time::Duration::from_millis(
-                                                defaults::VM_WAIT_TIMEOUT_MSEC
-                                            );
-                                            thread::sleep(some_time);
-                                        }
-                                    }
-                                    retry_count += 1
-                                }
-                            });
-                            if ! resume {
-                                // Wait for the thread to finish if not in resume mode
-                                let _ = thread_handle.join();
-                            }
-                        },
-                        Err(error) => {
-                            debug(&format!(
-                                "Failed to accept incoming connection: {}",
-                                error
-                            ));
-                            ok = false
-                        }
-                    }
-
-                    // we are not in resume mode, exit after the command is done
-                    if ! resume {
-                        break
-                    }
+    // change into the caller's working directory if passed via %cwd,
+    // process replacement through switch_root always starts in /
+    if let Ok(workdir) = env::var("sci_workdir") {
+        if ! do_exec {
+            match env::set_current_dir(&workdir) {
+                Ok(_) => {
+                    debug(&format!(
+                        "Changed working directory to {}", workdir
+                    ));
+                },
+                Err(error) => {
+                    debug(&format!(
+                        "Failed to change working directory to {}: {}",
+                        workdir, error
+                    ));
                 }
-            },
-            Err(error) => {
-                debug(&format!(
-                    "Failed to bind vsock: CID: {}: {}",
-                    defaults::GUEST_CID, error
-                ));
-                ok = false
             }
         }
+    }
+    if console_vsock {
+        ok = run_vsock_listener(resume);
     } else {
         // run regular command and close vm
         if do_exec {
@@ -408,26 +333,305 @@ fn main() {
             ));
             let _ = call.status();
         }
+        if keep_alive {
+            // keep the VM running and wait for commands through
+            // the vsock for inspection of the instance
+            info!(
+                "sci_keep_alive set: VM kept alive, listening on vsock port {}",
+                defaults::VM_PORT
+            );
+            ok = run_vsock_listener(true);
+        }
     }
     
     // Close firecracker session
     do_reboot(ok)
 }
 
-fn redirect_command(command: &str, stream: vsock::VsockStream) {
+fn mount_layer(device: &str, layer_dir: &str) -> bool {
+    /*!
+    Mount the given layer device read-only to layer_dir
+    !*/
+    if let Err(error) = fs::create_dir_all(layer_dir) {
+        debug(&format!("Error creating directory {}: {}", layer_dir, error));
+        return false
+    }
+    match Mount::builder().flags(MountFlags::RDONLY).mount(device, layer_dir) {
+        Ok(_) => {
+            debug(&format!("Mounted layer {} on {}", device, layer_dir));
+            true
+        },
+        Err(error) => {
+            debug(&format!("Failed to mount layer {}: {}", device, error));
+            false
+        }
+    }
+}
+
+fn parse_run(call_cmd: &str) -> Result<Vec<String>, String> {
+    /*!
+    Split the run=... command into its arguments and validate it
+    !*/
+    let args = shell_words::split(call_cmd).map_err(
+        |error| format!("Failed to parse {}: {}", call_cmd, error)
+    )?;
+    validate_args(&args)?;
+    Ok(args)
+}
+
+fn validate_args(args: &[String]) -> Result<(), String> {
+    /*!
+    Check that args provides a command to execute
+    !*/
+    match args.first() {
+        None => Err("No command to execute specified".to_string()),
+        Some(command) if command.trim().is_empty() => {
+            Err(format!("Invalid command to execute: {:?}", command))
+        },
+        Some(_) => Ok(())
+    }
+}
+
+fn requires_exec(args: &[String]) -> bool {
+    /*!
+    Check if the given command requires process replacement
+    !*/
+    args.first()
+        .map(|command| command == "/usr/lib/systemd/systemd")
+        .unwrap_or_default()
+}
+
+fn parse_call(call_str: &str) -> (Vec<(String, OsString)>, String) {
+    /*!
+    Split the data received on the vsock into the secret env
+    variables sent as SECRET_ENV NAME HEXVALUE lines in front
+    and the command line
+    !*/
+    let mut envs = Vec::new();
+    let mut call = call_str;
+    while let Some((line, rest)) = call.split_once('\n') {
+        let secret = line.strip_prefix(defaults::SECRET_ENV_PREFIX)
+            .and_then(|secret| secret.strip_prefix(' '))
+            .and_then(|secret| secret.split_once(' '))
+            .and_then(|(name, value)| {
+                hex_decode(value).map(|value| (name.to_string(), value))
+            });
+        match secret {
+            Some(secret) => envs.push(secret),
+            None => break
+        }
+        call = rest;
+    }
+    (envs, call.to_string())
+}
+
+fn hex_decode(value: &str) -> Option<OsString> {
+    /*!
+    Decode a hex encoded value
+    !*/
+    value.as_bytes().chunks(2)
+        .map(|byte| match byte {
+            [high, low] => std::str::from_utf8(&[*high, *low]).ok()
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok()),
+            _ => None
+        })
+        .collect::<Option<Vec<u8>>>()
+        .map(OsString::from_vec)
+}
+
+fn read_run_file(run_file: &str) -> Vec<String> {
+    /*!
+    Read command from run_file, one argument per line
+    !*/
+    match fs::read_to_string(run_file) {
+        Ok(content) => {
+            content.lines().map(|arg| arg.to_string()).collect()
+        },
+        Err(error) => {
+            debug(&format!("Failed to read {}: {}", run_file, error));
+            do_reboot(false);
+            vec![]
+        }
+    }
+}
+
+fn run_vsock_listener(resume: bool) -> bool {
+    /*!
+    Listen on the vsock for commands to execute. In resume mode
+    the listener keeps waiting for further commands, otherwise it
+    returns after the first command is done
+    !*/
+    let mut ok;
+    // vsock required; check if vhost transport is loaded
+    let mut modprobe = Command::new(defaults::PROBE_MODULE);
+    modprobe.arg(defaults::VHOST_TRANSPORT);
+    debug(&format!(
+        "SCI CALL: {} -> {:?}", defaults::PROBE_MODULE, modprobe.get_args()
+    ));
+    match modprobe.status() {
+        Ok(_) => { },
+        Err(error) => {
+            debug(&format!(
+                "Loading {} module failed: {}",
+                defaults::VHOST_TRANSPORT, error
+            ));
+        }
+    }
+    // start vsock listener on VM_PORT, wait for command(s) in a loop
+    // A received command turns into a vsock stream process calling
+    // the command with an expected listener.
+    // the guest CID is allocated per VM by firecracker-pilot
+    let guest_cid = env::var("sci_guest_cid").ok()
+        .and_then(|cid| cid.parse::<u32>().ok())
+        .unwrap_or(defaults::GUEST_CID);
+    debug(&format!(
+        "Binding vsock CID={} on port={}",
+        guest_cid, defaults::VM_PORT
+    ));
+    match VsockListener::bind_with_cid_port(
+        guest_cid, defaults::VM_PORT
+    ) {
+        Ok(listener) => {
+            // Enter main loop
+            loop {
+                ok = true;
+                match listener.accept() {
+                    Ok((mut stream, addr)) => {
+                        // read command string from incoming connection
+                        debug(&format!(
+                            "Accepted incoming connection from: {}:{}",
+                            addr.cid(), addr.port()
+                        ));
+                        let mut call_str = String::new();
+                        let mut call_buf = Vec::new();
+                        match stream.read_to_end(&mut call_buf) {
+                            Ok(_) => {
+                                call_str = String::from_utf8(
+                                    call_buf.to_vec()
+                                ).unwrap();
+                                let len_to_truncate = call_str
+                                    .trim_end()
+                                    .len();
+                                call_str.truncate(len_to_truncate);
+                            },
+                            Err(error) => {
+                                debug(&format!(
+                                    "Failed to read data {}", error
+                                ));
+                                ok = false
+                            }
+                        };
+                        stream.shutdown(Shutdown::Both).unwrap();
+                        if call_str.is_empty() {
+                            // Caused by handshake checks that connects
+                            // without sending data
+                            debug("No data received until connection end");
+                            continue
+                        }
+                        let (envs, call_str) = parse_call(&call_str);
+                        debug(&format!(
+                            "SCI CALL RAW BUF: {:?}", call_str
+                        ));
+                        for (name, _) in &envs {
+                            debug(&format!("SCI CALL SECRET ENV: {}", name));
+                        }
+                        let mut call_stack: Vec<&str> =
+                            call_str.split(' ').collect();
+                        let exec_port = call_stack.pop().unwrap();
+                        let exec_cmd = call_stack.join(" ");
+                        let mut exec_port_num = 0;
+                        match exec_port.parse::<u32>() {
+                            Ok(num) => { exec_port_num = num },
+                            Err(error) => {
+                                debug(&format!(
+                                    "Failed to parse port: {}: {}",
+                                    exec_port, error
+                                ));
+                                ok = false
+                            }
+                        }
+                        debug(&format!(
+                            "CALL SCI: string:'{}' u32:{}",
+                            exec_cmd, exec_port_num
+                        ));
+
+                        // Establish a VSOCK connection with the farend
+                        let thread_handle = thread::spawn(move || {
+                            let mut retry_count = 0;
+                            loop {
+                                if retry_count == defaults::RETRIES {
+                                    break
+                                }
+                                match VsockStream::connect_with_cid_port(
+                                    2, exec_port_num
+                                ) {
+                                    Ok(vsock_stream) => {
+                                        redirect_command(
+                                            &exec_cmd, &envs, vsock_stream
+                                        );
+                                        break
+                                    },
+                                    Err(error) => {
+                                        debug(&format!(
+                                            "[{}] VSOCK-CONNECT failed with: {}",
+                                            retry_count, error
+                                        ));
+                                        let some_time = time::Duration::from_millis(
+                                            defaults::VM_WAIT_TIMEOUT_MSEC
+                                        );
+                                        thread::sleep(some_time);
+                                    }
+                                }
+                                retry_count += 1
+                            }
+                        });
+                        if ! resume {
+                            // Wait for the thread to finish if not in resume mode
+                            let _ = thread_handle.join();
+                        }
+                    },
+                    Err(error) => {
+                        debug(&format!(
+                            "Failed to accept incoming connection: {}",
+                            error
+                        ));
+                        ok = false
+                    }
+                }
+
+                // we are not in resume mode, exit after the command is done
+                if ! resume {
+                    break
+                }
+            }
+        },
+        Err(error) => {
+            debug(&format!(
+                "Failed to bind vsock: CID: {}: {}",
+                guest_cid, error
+            ));
+            ok = false
+        }
+    }
+    ok
+}
+
+fn redirect_command(
+    command: &str, envs: &[(String, OsString)], stream: vsock::VsockStream
+) {
     // start the given command as a child process in a new PTY
     // or on raw channels if no pseudo terminal can be allocated
     // connect its standard channels to the stream
     // transfer all channel data when there is data as long as the child exists
     match Fork::from_ptmx() {
         Ok(fork) => {
-            redirect_command_to_pty(command, stream, fork)
+            redirect_command_to_pty(command, envs, stream, fork)
         },
         Err(error) => {
             debug(&format!(
                 "Terminal allocation failed, using raw channels: {:?}", error
             ));
-            redirect_command_to_raw_channels(command, stream)
+            redirect_command_to_raw_channels(command, envs, stream)
         }
     }
 }
@@ -453,12 +657,13 @@ fn set_output_terminal_flags(fd: i32) {
 }
 
 fn redirect_command_to_raw_channels(
-    command: &str, mut stream: vsock::VsockStream
+    command: &str, envs: &[(String, OsString)], mut stream: vsock::VsockStream
 ) {
     let mut call_args: Vec<&str> = command.split(' ').collect();
     let program = call_args.remove(0);
     let mut call = Command::new(program);
     call
+        .envs(envs.iter().map(|(name, value)| (name, value)))
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped());
@@ -471,6 +676,7 @@ fn redirect_command_to_raw_channels(
     match call.spawn() {
         Ok(mut child) => {
             // access useful I/O and file descriptors
+            let child_pid = child.id() as libc::pid_t;
             let stdin = child.stdin.as_mut().unwrap();
             let stdout = child.stdout.as_mut().unwrap();
             let stderr = child.stderr.as_mut().unwrap();
@@ -496,17 +702,38 @@ fn redirect_command_to_raw_channels(
                 unsafe { libc::FD_SET(stream_fd, &mut fdset) };
                 max_fd = std::cmp::max(max_fd, stream_fd);
 
+                // check the child before select so that output written
+                // right before it exited is still seen by select
+                let exited = process_exited(child_pid);
+
                 // block this thread until something new happens
-                // on these file-descriptors
-                unsafe {
+                // on these file-descriptors or the timeout hits
+                let mut timeout = select_timeout();
+                let ready = unsafe {
                     libc::select(
                         max_fd + 1,
                         &mut fdset,
                         std::ptr::null_mut(),
                         std::ptr::null_mut(),
-                        std::ptr::null_mut()
+                        &mut timeout
                     )
                 };
+                if ready < 0 {
+                    if io::Error::last_os_error().kind()
+                        == io::ErrorKind::Interrupted
+                    {
+                        continue;
+                    }
+                    debug("select failure on process channels");
+                    break;
+                }
+                if ready == 0 {
+                    if exited {
+                        debug("process exited without closing its channels");
+                        break;
+                    }
+                    continue;
+                }
                 // this thread is not blocked any more,
                 // try to handle what happened on the file descriptors
                 if unsafe { libc::FD_ISSET(stdout_fd, &fdset) } {
@@ -561,7 +788,13 @@ fn redirect_command_to_raw_channels(
                     }
                 }
             }
-            let _ = child.wait();
+            let exit_code = match child.wait() {
+                Ok(status) => status.code().unwrap_or_else(
+                    || 128 + status.signal().unwrap_or_default()
+                ),
+                Err(_) => 1
+            };
+            send_exit_code(&mut stream, exit_code);
         },
         Err(error) => {
             debug(&format!(
@@ -571,12 +804,52 @@ fn redirect_command_to_raw_channels(
     }
 }
 
-fn redirect_command_to_pty(
-    command: &str, mut stream: vsock::VsockStream, pty_fork: Fork
+fn send_exit_code<S: Write>(stream: &mut S, exit_code: i32) {
+    /*!
+    Send the exit code of the command as trailer after its
+    output such that the host can return it to the caller
+    !*/
+    debug(&format!("Command exit code: {}", exit_code));
+    if stream.write_all(
+        format!("{}{}\n", defaults::SCI_EXIT_TRAILER, exit_code).as_bytes()
+    ).is_err() {
+        debug("write failure on stream");
+    }
+}
+
+fn select_timeout() -> libc::timeval {
+    libc::timeval {
+        tv_sec: 0,
+        tv_usec: defaults::SELECT_TIMEOUT_MSEC * 1000
+    }
+}
+
+fn process_exited(pid: libc::pid_t) -> bool {
+    /*!
+    Check if the given child has exited without reaping it,
+    the final wait() on the child is still up to the caller
+    !*/
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        libc::waitid(
+            libc::P_PID, pid as libc::id_t, &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT
+        )
+    };
+    // waitid failing means there is no such child to wait for
+    result != 0 || unsafe { info.si_pid() } != 0
+}
+
+fn redirect_command_to_pty<S: Read + Write + AsRawFd>(
+    command: &str, envs: &[(String, OsString)], mut stream: S, pty_fork: Fork
 ) {
     if let Ok(mut master) = pty_fork.is_parent() {
         let stdout_fd = master.as_raw_fd();
         let stream_fd = stream.as_raw_fd();
+        let child_pid = match pty_fork {
+            Fork::Parent(pid, _) => pid,
+            Fork::Child(_) => 0
+        };
 
         set_output_terminal_flags(stdout_fd);
 
@@ -593,17 +866,38 @@ fn redirect_command_to_pty(
             unsafe { libc::FD_SET(stream_fd, &mut fdset) };
             max_fd = std::cmp::max(max_fd, stream_fd);
 
+            // check the child before select so that output written
+            // right before it exited is still seen by select
+            let exited = process_exited(child_pid);
+
             // block this thread until something new happens
-            // on these file-descriptors
-            unsafe {
+            // on these file-descriptors or the timeout hits
+            let mut timeout = select_timeout();
+            let ready = unsafe {
                 libc::select(
                     max_fd + 1,
                     &mut fdset,
                     std::ptr::null_mut(),
                     std::ptr::null_mut(),
-                    std::ptr::null_mut()
+                    &mut timeout
                 )
             };
+            if ready < 0 {
+                if io::Error::last_os_error().kind()
+                    == io::ErrorKind::Interrupted
+                {
+                    continue;
+                }
+                debug("select failure on terminal channels");
+                break;
+            }
+            if ready == 0 {
+                if exited {
+                    debug("process exited without closing its terminal");
+                    break;
+                }
+                continue;
+            }
             // this thread is not blocked any more,
             // try to handle what happened on the file descriptors
             if unsafe { libc::FD_ISSET(stdout_fd, &fdset) } {
@@ -641,25 +935,35 @@ fn redirect_command_to_pty(
                 }
             }
         }
-        let _ = pty_fork.wait();
+        let mut status = 0;
+        let exit_code = if unsafe {
+            libc::waitpid(child_pid, &mut status, 0)
+        } != child_pid {
+            1
+        } else if libc::WIFSIGNALED(status) {
+            128 + libc::WTERMSIG(status)
+        } else {
+            libc::WEXITSTATUS(status)
+        };
+        send_exit_code(&mut stream, exit_code);
     } else {
         let mut call_args: Vec<&str> = command.split(' ').collect();
         let program = call_args.remove(0);
         let mut call = Command::new(program);
+        call.envs(envs.iter().map(|(name, value)| (name, value)));
         for arg in call_args {
             call.arg(arg);
         }
         debug(&format!(
             "SCI CALL: {} -> {:?}", program, call.get_args()
         ));
-        match call.status() {
-            Ok(_) => { },
-            Err(error) => {
-                debug(&format!(
-                    "SCI guest command failed with: {}", error
-                ));
-            }
-        }
+        // replace the forked sci such that the parent waits
+        // for the exit code of the command itself
+        let error = call.exec();
+        debug(&format!(
+            "SCI guest command failed with: {}", error
+        ));
+        std::process::exit(127)
     }
 }
 
@@ -749,6 +1053,56 @@ fn mount_basic_fs() {
     }
 }
 
+fn mount_extra_fs() {
+    /*!
+    Mount additional filesystems from the extra_mounts boot
+    parameter, a comma separated list of fstype:source:target.
+    Failed mounts are skipped
+    !*/
+    let extra_mounts = match env::var("extra_mounts") {
+        Ok(extra_mounts) => extra_mounts,
+        Err(_) => return
+    };
+    for extra_mount in extra_mounts.split(',') {
+        let (fstype, source, target) = match parse_extra_mount(extra_mount) {
+            Ok(extra_mount) => extra_mount,
+            Err(message) => {
+                debug(&message);
+                continue
+            }
+        };
+        if let Err(error) = fs::create_dir_all(target) {
+            debug(&format!("Error creating directory {}: {}", target, error));
+            continue
+        }
+        match Mount::builder().fstype(fstype).mount(source, target) {
+            Ok(_) => debug(&format!("Mounted {} on {}", fstype, target)),
+            Err(error) => {
+                debug(&format!("Failed to mount {}: {}", target, error));
+            }
+        }
+    }
+}
+
+fn parse_extra_mount(
+    extra_mount: &str
+) -> Result<(&str, &str, &str), String> {
+    /*!
+    Split an extra mount into fstype, source and target
+    !*/
+    match extra_mount.split(':').collect::<Vec<&str>>()[..] {
+        [fstype, source, target] if ! fstype.is_empty()
+            && ! source.is_empty() && target.starts_with('/') =>
+        {
+            Ok((fstype, source, target))
+        },
+        _ => Err(format!(
+            "Invalid extra mount {:?}, expected fstype:source:/target",
+            extra_mount
+        ))
+    }
+}
+
 fn setup_logger() {
     /*!
     Set up the logger internally
@@ -0,0 +1,127 @@
+//
+// Copyright (c) 2022 Elektrobit Automotive GmbH
+// Copyright (c) 2023 Marcus Schäfer
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use crate::{
+    parse_run, validate_args, requires_exec, process_exited, parse_extra_mount,
+    parse_call, redirect_command_to_pty
+};
+use std::ffi::OsString;
+use std::io::Read;
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+use pty::prelude::Fork;
+
+#[test]
+fn test_parse_run() {
+    assert_eq!(
+        parse_run("/usr/bin/ls -l '/some path'").unwrap(),
+        vec!["/usr/bin/ls", "-l", "/some path"]
+    );
+}
+
+#[test]
+fn test_parse_run_empty() {
+    assert!(parse_run("").is_err());
+    assert!(parse_run("   ").is_err());
+    assert!(parse_run("''").is_err());
+    assert!(parse_run("' ' -l").is_err());
+}
+
+#[test]
+fn test_parse_run_unbalanced_quotes() {
+    assert!(parse_run("/usr/bin/ls 'foo").is_err());
+}
+
+#[test]
+fn test_validate_args() {
+    assert!(validate_args(&[]).is_err());
+    assert!(validate_args(&["".to_string()]).is_err());
+    assert!(validate_args(&["vsock".to_string()]).is_ok());
+}
+
+#[test]
+fn test_requires_exec() {
+    assert!(requires_exec(&["/usr/lib/systemd/systemd".to_string()]));
+    assert!(! requires_exec(&["/usr/bin/ls".to_string()]));
+    assert!(! requires_exec(&[]));
+}
+
+#[test]
+fn test_process_exited() {
+    let mut child = Command::new("sleep").arg("1").spawn().unwrap();
+    assert!(! process_exited(child.id() as libc::pid_t));
+    let _ = child.wait();
+    // a reaped child is no longer there to be waited for
+    assert!(process_exited(child.id() as libc::pid_t));
+
+    let mut child = Command::new("true").spawn().unwrap();
+    while ! process_exited(child.id() as libc::pid_t) {}
+    // the status is left for the final wait()
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_parse_extra_mount() {
+    assert_eq!(
+        parse_extra_mount("cgroup2:none:/sys/fs/cgroup").unwrap(),
+        ("cgroup2", "none", "/sys/fs/cgroup")
+    );
+    assert!(parse_extra_mount("bpf:bpf").is_err());
+    assert!(parse_extra_mount("bpf:bpf:sys/fs/bpf").is_err());
+    assert!(parse_extra_mount(":none:/mnt").is_err());
+    assert!(parse_extra_mount("a:b:/c:d").is_err());
+}
+
+#[test]
+fn test_parse_call() {
+    let (envs, call) = parse_call(
+        "SECRET_ENV DB_KEY 730a6b\nSECRET_ENV EMPTY \n/usr/bin/app -x 1234"
+    );
+    assert_eq!(envs, vec![
+        ("DB_KEY".to_string(), OsString::from("s\nk")),
+        ("EMPTY".to_string(), OsString::from(""))
+    ]);
+    assert_eq!(call, "/usr/bin/app -x 1234");
+
+    let (envs, call) = parse_call("/usr/bin/app 1234");
+    assert!(envs.is_empty());
+    assert_eq!(call, "/usr/bin/app 1234");
+}
+
+#[test]
+fn test_redirect_command_to_pty_exit_code() {
+    for (command, trailer) in [
+        ("true", "\0sci-exit:0\n"),
+        ("false", "\0sci-exit:1\n"),
+        ("/no/such/command", "\0sci-exit:127\n")
+    ] {
+        let (stream, mut host) = UnixStream::pair().unwrap();
+        redirect_command_to_pty(
+            command, &[], stream, Fork::from_ptmx().unwrap()
+        );
+        let mut output = String::new();
+        host.read_to_string(&mut output).unwrap();
+        assert!(output.ends_with(trailer), "{}: {:?}", command, output);
+    }
+}
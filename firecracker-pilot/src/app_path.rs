@@ -24,7 +24,8 @@
 //
 extern crate yaml_rust;
 
-use std::env;
+use flakes::lookup::Lookup;
+use flakes::error::FlakeError;
 use which::which;
 use std::path::Path;
 use std::process::exit;
@@ -33,15 +34,18 @@ use yaml_rust::Yaml;
 use yaml_rust::YamlLoader;
 use flakes::config::get_flakes_dir;
 
-pub fn program_abs_path() -> String {
+pub fn program_abs_path() -> Result<String, FlakeError> {
     /*!
     Lookup absolute program path on the filesystem from
     the argv binary name of the caller
     !*/
-    let args: Vec<String> = env::args().collect();
-    let mut program_path = String::new();
-    program_path.push_str(which(&args[0]).unwrap().to_str().unwrap());
-    program_path
+    let program = Lookup::args().into_iter().next().unwrap_or_default();
+    which(&program)
+        .map(|program_path| program_path.to_string_lossy().to_string())
+        .map_err(|error| FlakeError::IOError {
+            kind: "NotFound".to_string(),
+            message: format!("Flake {:?} not found: {}", program, error)
+        })
 }
 
 pub fn basename(program_path: &String) -> String {
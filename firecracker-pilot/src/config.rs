@@ -23,26 +23,30 @@
 //
 use lazy_static::lazy_static;
 use serde::Deserialize;
-use strum::Display;
-use std::{env, fs, path::PathBuf};
-use flakes::config::get_flakes_dir;
+use flakes::config::{check_flakes_config, flake_yaml, get_flakes_dir};
+use flakes::error::FlakeError;
+use flakes::firecracker::CacheType;
+use flakes::io::IncludeMode;
+use crate::app_path::{basename, program_abs_path};
 
 lazy_static! {
-    static ref CONFIG: Config<'static> = load_config();
+    static ref CONFIG: Result<Config<'static>, FlakeError> = load_config();
 }
 
 /// Returns the config singleton
 ///
 /// Will initialize the config on first call and return the cached version afterwards
 pub fn config() -> &'static Config<'static> {
-    &CONFIG
+    try_config().expect("Config is validated at startup")
 }
 
-fn get_base_path() -> PathBuf {
-    which::which(env::args().next().expect("Arg 0 must be present")).expect("Symlink should exist")
+/// Returns the config singleton or the error from loading it
+pub fn try_config() -> Result<&'static Config<'static>, &'static FlakeError> {
+    CONFIG.as_ref()
 }
 
-fn load_config() -> Config<'static> {
+
+fn load_config() -> Result<Config<'static>, FlakeError> {
     /*!
     Read firecracker runtime configuration for given program
 
@@ -52,53 +56,45 @@ fn load_config() -> Config<'static> {
        └── program_name.yaml
 
     Config files below program_name.d are read in alpha sort order
-    and attached to the master program_name.yaml file. The result
-    is send to the Yaml parser
+    and attached to the master program_name.yaml file. If the
+    result contains extends: NAME, the config of the flake NAME
+    is read first and the config of program_name is attached to it.
+    The result is send to the Yaml parser
     !*/
-    let base_path = get_base_path();
-    let base_path = base_path.file_name().unwrap().to_str().unwrap();
-    let base_yaml = fs::read_to_string(config_file(base_path));
-
-    let mut extra_yamls: Vec<_> = fs::read_dir(config_dir(base_path))
-        .into_iter()
-        .flatten()
-        .flatten()
-        .map(|x| x.path())
-        .collect();
-
-    extra_yamls.sort();
-
-    let full_yaml: String = base_yaml
-        .into_iter()
-        .chain(extra_yamls.into_iter().flat_map(fs::read_to_string))
-        .collect();
-    config_from_str(&full_yaml)
+    check_flakes_config()?;
+    let base_path = basename(&program_abs_path()?);
+    let base_path = base_path.as_str();
+    let config_error = |message| FlakeError::ConfigError {
+        path: config_file(base_path),
+        message
+    };
+    let full_yaml = flake_yaml(&get_flakes_dir(), base_path, &mut Vec::new())
+        .map_err(config_error)?;
+    config_from_str(&full_yaml).map_err(config_error)
 }
 
-pub fn config_from_str(input: &str) -> Config<'static> {
+pub fn config_from_str(input: &str) -> Result<Config<'static>, String> {
     // Parse into a generic YAML to remove duplicate keys
 
-    let yaml = yaml_rust::YamlLoader::load_from_str(input).unwrap();
-    let yaml = yaml.first().unwrap();
+    let yaml = yaml_rust::YamlLoader::load_from_str(input)
+        .map_err(|error| error.to_string())?;
+    let yaml = yaml.first().ok_or("No configuration found")?;
     let mut buffer = String::new();
-    yaml_rust::YamlEmitter::new(&mut buffer).dump(yaml).unwrap();
+    yaml_rust::YamlEmitter::new(&mut buffer).dump(yaml)
+        .map_err(|error| format!("{:?}", error))?;
 
     // Convert to a String and leak it to make it static
     // Can not use serde_yaml::from_value because of lifetime limitations
     // Safety: This does not cause a reocurring memory leak since `load_config` is only called once
     let content = Box::leak(buffer.into_boxed_str());
 
-    serde_yaml::from_str(content).unwrap()
+    serde_yaml::from_str(content).map_err(|error| error.to_string())
 }
 
 pub fn config_file(program: &str) -> String {
     format!("{}/{}.yaml", get_flakes_dir(), program)
 }
 
-fn config_dir(program: &str) -> String {
-    format!("{}/{}.d", get_flakes_dir(), program)
-}
-
 #[derive(Deserialize)]
 pub struct Config<'a> {
     #[serde(borrow)]
@@ -119,6 +115,14 @@ impl<'a> Config<'a> {
     pub fn paths(&self) -> Vec<&'a str> {
         self.include.path.as_ref().cloned().unwrap_or_default()
     }
+
+    pub fn oci_images(&self) -> Vec<&'a str> {
+        self.include.oci.as_ref().cloned().unwrap_or_default()
+    }
+
+    pub fn include_mode(&self) -> IncludeMode {
+        self.include.mode
+    }
 }
 
 #[derive(Deserialize)]
@@ -126,6 +130,17 @@ pub struct IncludeSection<'a> {
     #[serde(borrow)]
     tar: Option<Vec<&'a str>>,
     path: Option<Vec<&'a str>>,
+
+    /// Optional container images from the local flakes registry
+    /// whose root filesystem gets synced into the VM overlay
+    #[serde(default)]
+    oci: Option<Vec<&'a str>>,
+
+    /// Only copy is supported, firecracker provides no device
+    /// to mount host data. The setting is only read to reject
+    /// mount with a clear error
+    #[serde(default)]
+    mode: IncludeMode,
 }
 
 #[derive(Deserialize)]
@@ -173,6 +188,54 @@ pub struct RuntimeSection<'a> {
     #[serde(default)]
     pub force_vsock: bool,
 
+    /// Command to run on the host as the calling user if the
+    /// provisioning or the run of the instance fails. The failure
+    /// reason is passed via the FLAKE_FAILURE_REASON environment
+    /// variable. The hook runs best-effort and does not change
+    /// the exit code of the pilot
+    ///
+    /// Default: no hook
+    #[serde(default)]
+    pub on_failure: Option<&'a str>,
+
+    /// Octal umask like "0027" applied while provisioning the
+    /// instance, e.g. for syncing includes and creating their
+    /// parent directories. Commands called via sudo combine it
+    /// with the umask configured for sudo. Modes preserved from
+    /// the sources are not changed
+    ///
+    /// Default: umask of the calling process
+    #[serde(default)]
+    pub provision_umask: Option<&'a str>,
+
+    /// Log level of the pilot for this flake, one of: off,
+    /// error, warn, info, debug or trace. Takes precedence
+    /// over the global logger setup, debug and trace also
+    /// enable the debug output as PILOT_DEBUG does. If
+    /// PILOT_DEBUG is set, the level is always trace
+    ///
+    /// Default: global logger setup
+    #[serde(default)]
+    pub log_level: Option<&'a str>,
+
+    /// Absolute path of a file on the host to which the state
+    /// (starting, running or stopped), the VM ID and the start
+    /// time of a resume VM is written. The file is deleted with
+    /// the garbage collection of the VM
+    ///
+    /// Default: no status file
+    #[serde(default)]
+    pub status_file: Option<&'a str>,
+
+    /// CPUs the firecracker process is pinned to as comma
+    /// separated list of CPU numbers or ranges like 0-3,5.
+    /// Applied via taskset, the jailer and the VM threads
+    /// inherit the CPU affinity
+    ///
+    /// Default: no pinning
+    #[serde(default)]
+    pub cpuset: Option<&'a str>,
+
     pub firecracker: EngineSection<'a>,
 }
 
@@ -183,6 +246,11 @@ pub struct EngineSection<'a> {
     /// specified size will be created and attached to the VM
     pub overlay_size: Option<&'a str>,
 
+    /// Grow the overlay of a resume VM by overlay_size before it
+    /// is started if its free space runs low
+    #[serde(default)]
+    pub overlay_autogrow: bool,
+
     pub cache_type: Option<CacheType>,
     pub mem_size_mib: Option<i64>,
     pub vcpu_count: Option<i64>,
@@ -196,17 +264,68 @@ pub struct EngineSection<'a> {
     /// Optional path to initrd image done by app registration
     pub initrd_path: Option<&'a str>,
 
-    pub boot_args: Vec<&'a str>,
-}
+    /// Optional absolute path to sci in the rootfs image
+    /// If specified it overrides the init= boot argument
+    pub init_path: Option<&'a str>,
 
-#[derive(Debug, Deserialize, Clone, Display)]
-pub enum CacheType {
-    Writeback,
-    Unsafe
-}
+    /// Optional list of read-only rootfs layer images attached
+    /// as extra drives. The layers are stacked in the given order
+    /// on top of the rootfs as lower directories of the overlay.
+    /// Requires overlay_size to be set
+    #[serde(default)]
+    pub layer_image_paths: Vec<&'a str>,
 
-impl Default for CacheType {
-    fn default() -> Self {
-        Self::Writeback
-    }
+    /// Optional additional filesystems sci mounts in the VM in
+    /// the format fstype:source:target, e.g. cgroup2:none:/sys/fs/cgroup
+    #[serde(default)]
+    pub extra_mounts: Vec<&'a str>,
+
+    /// Optional absolute path of the file firecracker writes its
+    /// metrics to. The parent directory is created if missing
+    pub metrics_path: Option<&'a str>,
+
+    /// Optional environment variables for the command in the VM
+    /// in the format NAME=file:/path or NAME=keyring:description.
+    /// The value is read on the host and passed to sci through
+    /// the vsock connection instead of the kernel cmdline.
+    /// Requires resume or force_vsock
+    #[serde(default)]
+    pub secret_env: Vec<&'a str>,
+
+    /// Optional absolute path of a file the output of firecracker
+    /// and the serial console of the VM is appended to if not
+    /// running in debug mode. The file is opened as the calling
+    /// user
+    pub console_log: Option<&'a str>,
+
+    /// Host directories to share live with the VM. Not supported,
+    /// firecracker provides no virtio-fs device. The setting is
+    /// only read to reject it with a clear error instead of
+    /// silently ignoring it
+    #[serde(default)]
+    pub shared_dirs: Vec<&'a str>,
+
+    /// Start firecracker through the jailer for chroot, cgroup
+    /// and namespace isolation instead of calling it directly
+    #[serde(default)]
+    pub use_jailer: bool,
+
+    /// User and group ID the jailer drops privileges to.
+    /// Required if use_jailer is set
+    pub jailer_uid: Option<u32>,
+    pub jailer_gid: Option<u32>,
+
+    /// Base directory the jailer creates the VM chroot in
+    pub jailer_chroot_base: Option<&'a str>,
+
+    /// Path of the firecracker binary the jailer runs
+    pub jailer_exec_file: Option<&'a str>,
+
+    /// Seconds a resume or force_vsock VM may take to boot until
+    /// sci answers on the vsock. If exceeded the VM is killed and
+    /// a boot failure is reported. Without it the connection is
+    /// retried like for the command execution
+    pub boot_timeout_sec: Option<u64>,
+
+    pub boot_args: Vec<&'a str>,
 }
@@ -24,6 +24,19 @@
 //
 pub const FIRECRACKER: &str =
     "firecracker";
+pub const JAILER: &str =
+    "jailer";
+pub const TASKSET: &str =
+    "taskset";
+pub const SETFACL: &str =
+    "setfacl";
+pub const JAILER_EXEC_FILE: &str =
+    "/usr/bin/firecracker";
+pub const JAILER_CHROOT_BASE: &str =
+    "/srv/jailer";
+pub const JAILER_CONFIG_FILE: &str =
+    "firecracker.json";
+pub const JAILER_ID_MAX: usize = 64;
 pub const IMAGE_ROOT: &str =
     "image";
 pub const IMAGE_OVERLAY: &str =
@@ -34,18 +47,44 @@ pub const OVERLAY_UPPER: &str =
     "overlayroot/rootfs_upper";
 pub const OVERLAY_WORK: &str =
     "overlayroot/rootfs_work";
-pub const FIRECRACKER_OVERLAY_DIR:&str =
-    "/var/lib/firecracker/storage";
+pub const OVERLAY_AUTOGROW_FREE_PERCENT: u64 = 10;
+pub const OVERLAY_RESIZE_FSTYPES: [&str; 3] =
+    ["ext2", "ext3", "ext4"];
+pub const FIRECRACKER_CID_DIR:&str =
+    "/var/lib/firecracker/cids";
+pub const FIRECRACKER_CID_LOCK_FILE:&str =
+    "cid.lock";
 pub const FIRECRACKER_TEMPLATE:&str =
     "/etc/flakes/firecracker.json";
 pub const FIRECRACKER_VSOCK_PREFIX: &str =
-    "/run/sci_cmd_";
+    "sci_cmd_";
 pub const FIRECRACKER_VSOCK_PORT_START: u32 = 49200;
+pub const VHOST_VSOCK_DEVICE: &str =
+    "/dev/vhost-vsock";
+pub const VHOST_VSOCK_MODULE: &str =
+    "/sys/module/vhost_vsock";
+pub const SCI_RUN_FILE: &str =
+    "/.sci_run";
+pub const KERNEL_CMDLINE_RUN_MAX: usize = 1024;
 pub const GC_THRESHOLD: usize = 20;
 pub const VM_CID: u32 = 3;
 pub const VM_PORT: u32 =
     52;
+pub const SECRET_ENV_PREFIX: &str =
+    "SECRET_ENV";
+pub const KEYCTL: &str =
+    "keyctl";
 pub const RETRIES: u32 =
     60;
 pub const VM_WAIT_TIMEOUT_MSEC: u64 =
     1000;
+pub const STALE_SOCKET_RETRIES: u32 =
+    3;
+pub const VM_STOP_RETRIES: u32 =
+    10;
+pub const VM_STOP_WAIT_MSEC: u64 =
+    200;
+pub const SELECT_TIMEOUT_MSEC: i64 =
+    200;
+pub const SCI_EXIT_TRAILER: &[u8] =
+    b"\0sci-exit:";
@@ -24,75 +24,41 @@
 //
 use std::ffi::OsStr;
 use std::{thread, time};
-use flakes::io::IO;
-use flakes::command::{CommandError, handle_output, CommandExtTrait};
+use std::time::{Duration, Instant};
+use flakes::io::{IO, IncludeMode};
+use flakes::command::{
+    CommandError, ProcessError, handle_output, CommandExtTrait, stderr_excerpt,
+    CommandRunner, HostRunner
+};
 use flakes::error::{FlakeError, OperationError};
 use flakes::user::{User, mkdir, chmod};
 use flakes::lookup::Lookup;
+use flakes::container::Container;
+use flakes::signals::SignalForward;
+use flakes::report::ProvisionReport;
+use flakes::defaults::{FLAKES_STORAGE, FIRECRACKER_OVERLAY_DIR};
 use spinoff::{Spinner, spinners, Color};
 use ubyte::ByteUnit;
 use std::path::Path;
-use std::process::{Stdio, id};
+use std::process::{Child, Command, Stdio, id};
 use std::env;
 use std::fs;
 use crate::config::{config, RuntimeSection, EngineSection};
-use tempfile::{NamedTempFile, tempdir};
+use tempfile::NamedTempFile;
 use std::io::{self, Write, SeekFrom, Seek};
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::os::unix::net::{UnixStream, UnixListener};
 use std::io::prelude::*;
 use std::net::Shutdown;
-use serde::{Serialize, Deserialize};
 use serde_json::{self};
-use flakes::config::get_firecracker_ids_dir;
+use flakes::config::{get_firecracker_ids_dir, get_firecracker_socket_dir};
+use flakes::firecracker::{
+    FireCrackerConfig, FireCrackerDrive, FireCrackerMetrics
+};
 use std::os::fd::AsRawFd;
 
 use crate::defaults;
 
-// FireCrackerConfig represents firecracker json config
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FireCrackerConfig {
-    #[serde(rename = "boot-source")]
-    pub boot_source: FireCrackerBootSource,
-    pub drives: Vec<FireCrackerDrive>,
-    #[serde(rename = "network-interfaces")]
-    pub network_interfaces: Vec<FireCrackerNetworkInterface>,
-    #[serde(rename = "machine-config")]
-    pub machine_config: FireCrackerMachine,
-    pub vsock: FireCrackerVsock
-}
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FireCrackerBootSource {
-    pub kernel_image_path: String,
-    #[serde(skip_serializing_if = "String::is_empty")]
-    pub initrd_path: String,
-    pub boot_args: String
-}
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FireCrackerDrive {
-    pub drive_id: String,
-    pub path_on_host: String,
-    pub is_root_device: bool,
-    pub is_read_only: bool,
-    pub cache_type: String
-}
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FireCrackerNetworkInterface {
-    pub iface_id: String,
-    pub guest_mac: String,
-    pub host_dev_name: String
-}
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FireCrackerMachine {
-    pub vcpu_count: i64,
-    pub mem_size_mib: i64
-}
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FireCrackerVsock {
-    pub guest_cid: u32,
-    pub uds_path: String
-}
-
 pub fn create(program_name: &String) -> Result<(String, String), FlakeError> {
     /*!
     Create VM for later execution of program_name.
@@ -137,6 +103,33 @@ pub fn create(program_name: &String) -> Result<(String, String), FlakeError> {
         # Default: false
         force_vsock: true|false
 
+        # Command to run on the host if the VM provisioning
+        # or run fails. The failure reason is passed via the
+        # FLAKE_FAILURE_REASON environment variable
+        on_failure: logger "flake failed: $FLAKE_FAILURE_REASON"
+
+        # Octal umask applied while provisioning, combined
+        # with the umask of sudo for privileged commands
+        provision_umask: "0027"
+
+        # Log level of the pilot for this flake, debug and trace
+        # also enable the debug output like PILOT_DEBUG does
+        #
+        # Default: global logger setup
+        log_level: debug
+
+        # Resume VMs only: file on the host to which the state,
+        # VM ID and start time is written as key=value lines,
+        # e.g. for monitoring
+        #
+        # Default: no status file
+        status_file: /run/myapp.status
+
+        # CPUs to pin the firecracker process to
+        #
+        # Default: no pinning
+        cpuset: 0-3,5
+
         firecracker:
           # Currently fixed settings through app registration
           boot_args:
@@ -154,6 +147,13 @@ pub fn create(program_name: &String) -> Result<(String, String), FlakeError> {
           # specified size will be created and attached to the VM
           overlay_size: 20g
 
+          # Grow the overlay of a resume VM by overlay_size before
+          # it gets started if less than 10% of it is free.
+          # Requires an ext2/3/4 overlay
+          #
+          # Default: false
+          overlay_autogrow: false
+
           # Path to rootfs image done by app registration
           rootfs_image_path: /var/lib/firecracker/images/NAME/rootfs
 
@@ -163,24 +163,103 @@ pub fn create(program_name: &String) -> Result<(String, String), FlakeError> {
           # Optional path to initrd image done by app registration
           initrd_path: /var/lib/firecracker/images/NAME/initrd
 
+          # Optional absolute path to sci in the rootfs image.
+          # Overrides the init= setting from the boot_args
+          #
+          # Default: /usr/sbin/sci
+          init_path: /usr/sbin/sci
+
+          # Optional read-only rootfs layer images stacked in
+          # the given order on top of the rootfs.
+          # Requires overlay_size to be set
+          layer_image_paths:
+            - /var/lib/firecracker/images/LAYER/rootfs
+
+          # Optional additional filesystems mounted by sci in the
+          # VM in the format fstype:source:target
+          extra_mounts:
+            - cgroup2:none:/sys/fs/cgroup
+
+          # Optional file firecracker writes its metrics to
+          metrics_path: /var/log/firecracker/NAME.metrics
+
+          # Optional environment variables for the command in the
+          # VM read on the host from a file or the user keyring
+          # and passed through the vsock instead of boot_args
+          secret_env:
+            - DB_KEY=file:/etc/myapp/db.key
+            - API_TOKEN=keyring:myapp_token
+
+          # Optional file the firecracker output and the serial
+          # console of the VM are appended to in non debug mode
+          console_log: /var/tmp/NAME.console
+
+          # Start firecracker through the jailer which runs it
+          # chrooted as the given uid/gid in its own cgroup and
+          # namespaces. The VM resources are linked into the
+          # chroot below jailer_chroot_base
+          #
+          # Default: false
+          use_jailer: true|false
+          jailer_uid: 123
+          jailer_gid: 100
+          jailer_chroot_base: /srv/jailer
+          jailer_exec_file: /usr/bin/firecracker
+
+          # Seconds a resume or force_vsock VM may take until sci
+          # answers on the vsock. The VM is killed and the call
+          # fails if it takes longer
+          boot_timeout_sec: 30
+
       include:
         tar:
           - tar-archive-file-name-to-include
         path:
           - file-or-directory-to-include
+        oci:
+          - container-image-name-to-include
 
     Calling this method returns a vector including a placeholder
     for the later VM process ID and and the name of
     the VM ID file.
     !*/
     if ! Lookup::which(defaults::FIRECRACKER) {
-        return Err(FlakeError::IOError {
-            kind: "FileNotFound".to_string(),
-            message: format!("{} not found in $PATH, installed ?",
-                defaults::FIRECRACKER
+        return Err(
+            FlakeError::EngineNotFound(defaults::FIRECRACKER.to_string())
+        )
+    }
+    if config().runtime().firecracker.use_jailer {
+        if ! Lookup::which(defaults::JAILER) {
+            return Err(
+                FlakeError::EngineNotFound(defaults::JAILER.to_string())
             )
-        })
+        }
+        if ! Lookup::which(defaults::SETFACL) {
+            return Err(FlakeError::IOError {
+                kind: "NotFound".to_string(),
+                message: format!(
+                    "{} is required for use_jailer but not installed",
+                    defaults::SETFACL
+                )
+            })
+        }
+        get_jail_ids(program_name)?;
+    }
+    if let Some(cpuset) = config().runtime().cpuset {
+        IO::check_cpuset(cpuset)?;
+        if ! Lookup::which(defaults::TASKSET) {
+            return Err(FlakeError::IOError {
+                kind: "NotFound".to_string(),
+                message: format!(
+                    "{} is required for cpuset but not installed",
+                    defaults::TASKSET
+                )
+            })
+        }
     }
+    // Fail early on an invalid %app override
+    Lookup::get_app_override()?;
+
     // provisioning needs root permissions for mount
     // make sure we have them for this session
     let root_user = User::from("root");
@@ -194,12 +273,19 @@ pub fn create(program_name: &String) -> Result<(String, String), FlakeError> {
 
     // get flake config sections
     let RuntimeSection {
-        runas, resume, firecracker: engine_section, ..
+        runas, resume, force_vsock, firecracker: engine_section, ..
     } = config().runtime();
 
-    let user = User::from(runas);
+    let user = User::parse(runas)?;
 
     // check for includes
+    if config().include_mode() == IncludeMode::Mount {
+        return Err(FlakeError::ConfigError {
+            path: crate::config::config_file(program_name),
+            message: "include mode mount is not supported for VMs, \
+                firecracker can't mount host data, use copy".to_string()
+        })
+    }
     let tar_includes = config().tars();
     let path_includes = config().paths();
     let has_includes = !tar_includes.is_empty() || !path_includes.is_empty();
@@ -207,6 +293,15 @@ pub fn create(program_name: &String) -> Result<(String, String), FlakeError> {
     // Make sure meta dirs exists
     init_meta_dirs()?;
 
+    // A resume VM keeps the working directory it was provisioned
+    // with, the caller's directory can't be passed in per call
+    if resume && IO::workdir()?.is_some() {
+        return Err(FlakeError::ConfigError {
+            path: crate::config::config_file(program_name),
+            message: "%cwd can't be used with resume".to_string()
+        })
+    }
+
     // Check early return condition
     if Path::new(&vm_id_file_path).exists() && gc_meta_files(
         &vm_id_file_path, user, program_name, resume
@@ -217,6 +312,23 @@ pub fn create(program_name: &String) -> Result<(String, String), FlakeError> {
         return Ok((vmid, vm_id_file_path));
     }
 
+    // Exec mode only runs a command in an already running VM
+    if Lookup::get_pilot_run_options().contains_key("%exec") {
+        if ! (resume || force_vsock) {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: "%exec requires resume or force_vsock".to_string()
+            })
+        }
+        if Path::new(&vm_id_file_path).exists() && gc_meta_files(
+            &vm_id_file_path, user, program_name, resume
+        )? {
+            let vmid = fs::read_to_string(&vm_id_file_path)?;
+            return Ok((vmid, vm_id_file_path));
+        }
+        return Err(FlakeError::OperationError(OperationError::NotRunning))
+    }
+
     // Garbage collect occasionally
     gc(user, program_name).ok();
 
@@ -227,6 +339,40 @@ pub fn create(program_name: &String) -> Result<(String, String), FlakeError> {
         return Err(FlakeError::AlreadyRunning)
     }
 
+    // Fail early on broken includes
+    IO::validate_includes(tar_includes, path_includes)?;
+    if let Some(provision_umask) = config().runtime().provision_umask {
+        IO::parse_umask(provision_umask)?;
+    }
+    if ! engine_section.secret_env.is_empty() {
+        if ! (resume || force_vsock) {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: "secret_env requires resume or force_vsock"
+                    .to_string()
+            })
+        }
+        for secret_env in &engine_section.secret_env {
+            parse_secret_env(secret_env)?;
+        }
+    }
+    if engine_section.boot_timeout_sec.is_some() && ! (resume || force_vsock) {
+        return Err(FlakeError::ConfigError {
+            path: crate::config::config_file(program_name),
+            message: "boot_timeout_sec requires resume or force_vsock"
+                .to_string()
+        })
+    }
+    if let Some(status_file) = config().runtime().status_file {
+        if ! resume || ! status_file.starts_with('/') {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: "status_file must be an absolute path \
+                    and requires resume".to_string()
+            })
+        }
+    }
+
     // Setup VM...
     let pilot_options = Lookup::get_pilot_run_options();
     let mut spinner = None;
@@ -272,7 +418,7 @@ fn run_creation(
 
     // Setup root overlay if configured
     let vm_overlay_file = get_meta_file_name(
-        program_name, defaults::FIRECRACKER_OVERLAY_DIR, "ext2"
+        program_name, FIRECRACKER_OVERLAY_DIR, "ext2"
     );
     if let Some(overlay_size) = engine_section.overlay_size {
         let overlay_size = overlay_size.parse::<ByteUnit>().expect(
@@ -291,13 +437,36 @@ fn run_creation(
                 debug!("sudo {:?}", mkfs.get_args());
             }
             mkfs.perform()?;
+        } else if engine_section.overlay_autogrow {
+            grow_overlay(&vm_overlay_file, overlay_size, user)?;
         }
     }
 
+    // Container images can only be provisioned into an overlay
+    let oci_includes = config().oci_images();
+    if !oci_includes.is_empty() && engine_section.overlay_size.is_none() {
+        return Err(FlakeError::ConfigError {
+            path: crate::config::config_file(program_name),
+            message: "include oci requires an overlay_size".to_string()
+        })
+    }
+
+    // The caller's working directory can only be provisioned into an overlay
+    let workdir = IO::workdir()?;
+    if workdir.is_some() && engine_section.overlay_size.is_none() {
+        return Err(FlakeError::ConfigError {
+            path: crate::config::config_file(program_name),
+            message: "%cwd requires an overlay_size".to_string()
+        })
+    }
+
     // Provision VM
+    let include_jobs = IO::include_jobs()?;
+    let mut report = ProvisionReport::default();
     if engine_section.overlay_size.is_some() {
+        let _umask = IO::umask(config().runtime().provision_umask)?;
         let vm_image_file = engine_section.rootfs_image_path;
-        let tmp_dir = tempdir()?;
+        let tmp_dir = IO::tempdir()?;
         if let Some(tmp_dir) = tmp_dir.path().to_str() {
             let vm_mount_point = mount_vm(
                 tmp_dir,
@@ -305,39 +474,261 @@ fn run_creation(
                 &vm_overlay_file,
                 User::ROOT
             )?;
-            if has_includes {
+            if has_includes || workdir.is_some() {
                 if Lookup::is_debug() {
                     debug!("Syncing includes...");
                 }
-                IO::sync_includes(
+                // sync the content of the working directory to the
+                // same location in the instance
+                let workdir_include = workdir.as_ref().map(
+                    |workdir| format!("{0}/:{0}", workdir)
+                );
+                let mut paths = config().paths();
+                if let Some(workdir_include) = &workdir_include {
+                    paths.push(workdir_include);
+                }
+                report.includes = config().tars().len() + paths.len();
+                report.bytes_synced += IO::sync_includes(
                     &vm_mount_point, config().tars(),
-                    config().paths(), User::ROOT
+                    paths, User::ROOT, include_jobs, None
                 )?;
             }
+            report.layers = oci_includes.iter()
+                .map(|oci| oci.to_string())
+                .collect();
+            report.includes += oci_includes.len();
+            let provisioned = sync_oci_includes(
+                &vm_mount_point, oci_includes, User::ROOT
+            ).and_then(|bytes_synced| {
+                report.bytes_synced += bytes_synced;
+                if run_from_file(program_name) {
+                    provision_run_file(&vm_mount_point, program_name)
+                } else {
+                    Ok(())
+                }
+            });
             umount_vm(tmp_dir, User::ROOT)?;
+            provisioned?;
         }
     }
+    if ProvisionReport::is_enabled() {
+        report.report();
+    }
     Ok(result)
 }
 
+pub fn grow_overlay(
+    overlay_path: &str, grow_size: u64, user: User
+) -> Result<(), FlakeError> {
+    /*!
+    Grow the overlay image of a resume VM by grow_size if its
+    free space dropped below OVERLAY_AUTOGROW_FREE_PERCENT.
+    Only filesystems which can be resized offline are grown
+    !*/
+    let tmp_dir = IO::tempdir()?;
+    let mount_point = tmp_dir.path().to_string_lossy().to_string();
+    let mut mount = User::ROOT.run("mount");
+    mount.arg("-o")
+        .arg("ro")
+        .arg(overlay_path)
+        .arg(&mount_point);
+    if Lookup::is_debug() {
+        debug!("{:?}", mount.get_args());
+    }
+    mount.perform()?;
+    let mut df = User::ROOT.run("df");
+    df.arg("--output=fstype,size,avail")
+        .arg("-B1")
+        .arg(&mount_point);
+    if Lookup::is_debug() {
+        debug!("{:?}", df.get_args());
+    }
+    let usage = df.perform();
+    let mut umount = User::ROOT.run("umount");
+    umount.arg(&mount_point);
+    if Lookup::is_debug() {
+        debug!("{:?}", umount.get_args());
+    }
+    umount.perform()?;
+
+    let usage = String::from_utf8_lossy(&usage?.stdout).to_string();
+    let (fstype, size, avail) = parse_df_usage(&usage).ok_or_else(
+        || FlakeError::IOError {
+            kind: "InvalidData".to_string(),
+            message: format!("Unexpected df output: {}", usage.trim())
+        }
+    )?;
+    if ! overlay_needs_growth(size, avail) {
+        return Ok(())
+    }
+    if ! defaults::OVERLAY_RESIZE_FSTYPES.contains(&fstype.as_str()) {
+        warn!(
+            "Overlay {} is low on space but {} can't be resized",
+            overlay_path, fstype
+        );
+        return Ok(())
+    }
+    let overlay_fd = fs::OpenOptions::new().write(true).open(overlay_path)?;
+    let new_size = overlay_fd.metadata()?.len() + grow_size;
+    if Lookup::is_debug() {
+        debug!("Growing overlay {} to {} bytes", overlay_path, new_size);
+    }
+    overlay_fd.set_len(new_size)?;
+
+    // resize2fs requires a freshly checked filesystem. e2fsck
+    // exits with 1 if it corrected errors which is fine here
+    let mut fsck = user.run("e2fsck");
+    fsck.arg("-f")
+        .arg("-y")
+        .arg(overlay_path);
+    if Lookup::is_debug() {
+        debug!("sudo {:?}", fsck.get_args());
+    }
+    match fsck.perform() {
+        Err(CommandError {
+            base: ProcessError::ExecutionError(output), ..
+        }) if output.status.code() == Some(1) => {},
+        result => { result?; }
+    }
+    let mut resize = user.run("resize2fs");
+    resize.arg(overlay_path);
+    if Lookup::is_debug() {
+        debug!("sudo {:?}", resize.get_args());
+    }
+    resize.perform()?;
+    Ok(())
+}
+
+pub fn parse_df_usage(output: &str) -> Option<(String, u64, u64)> {
+    /*!
+    Read fstype, size and available bytes from the output of
+    df --output=fstype,size,avail -B1
+    !*/
+    let mut fields = output.lines().nth(1)?.split_whitespace();
+    let fstype = fields.next()?.to_string();
+    let size = fields.next()?.parse::<u64>().ok()?;
+    let avail = fields.next()?.parse::<u64>().ok()?;
+    Some((fstype, size, avail))
+}
+
+pub fn overlay_needs_growth(size: u64, avail: u64) -> bool {
+    /*!
+    Check if the available space is below the autogrow threshold
+    !*/
+    avail.saturating_mul(100)
+        < size.saturating_mul(defaults::OVERLAY_AUTOGROW_FREE_PERCENT)
+}
+
+pub fn get_run_args(
+    program_name: &str, quote_for_kernel_cmdline: bool
+) -> Vec<String> {
+    /*!
+    Setup run commandline for the command call
+    !*/
+    Lookup::get_run_cmdline(
+        vec![get_target_app_path(program_name)], quote_for_kernel_cmdline
+    )
+}
+
+pub fn run_from_file(program_name: &str) -> bool {
+    /*!
+    Check if the run commandline exceeds the space available
+    on the kernel cmdline. In this case the command is passed
+    to sci through a file in the VM overlay, which requires
+    an overlay to be configured
+    !*/
+    let RuntimeSection {
+        resume, force_vsock, firecracker: engine_section, ..
+    } = config().runtime();
+    let run = get_run_args(program_name, true);
+    ! (resume || force_vsock)
+        && engine_section.overlay_size.is_some()
+        && (run.join(" ").len() > defaults::KERNEL_CMDLINE_RUN_MAX
+            || run.iter().any(|arg| arg.contains('"')))
+}
+
+pub fn provision_run_file(
+    vm_mount_point: &str, program_name: &str
+) -> Result<(), FlakeError> {
+    /*!
+    Write the run commandline, one argument per line, into
+    the mounted VM such that sci can read it from there
+    !*/
+    let mut run_file = NamedTempFile::new()?;
+    for arg in get_run_args(program_name, false) {
+        writeln!(run_file, "{}", arg)?;
+    }
+    let mut copy = User::ROOT.run("cp");
+    copy.arg(run_file.path())
+        .arg(format!("{}{}", vm_mount_point, defaults::SCI_RUN_FILE));
+    if Lookup::is_debug() {
+        debug!("{:?}", copy.get_args());
+    }
+    copy.perform()?;
+    Ok(())
+}
+
+pub fn sync_oci_includes(
+    vm_mount_point: &str, oci_includes: Vec<&str>, user: User
+) -> Result<u64, FlakeError> {
+    /*!
+    Sync the root filesystem of the given container images
+    into the mounted VM overlay. Returns the number of bytes
+    synced if provisioning is reported
+    !*/
+    let mut bytes_synced = 0;
+    if oci_includes.is_empty() {
+        return Ok(bytes_synced)
+    }
+    // container images are looked up in the flakes registry
+    env::set_var("CONTAINERS_STORAGE_CONF", FLAKES_STORAGE);
+    Container::podman_setup_storage_driver()?;
+    for oci in oci_includes {
+        if Lookup::is_debug() {
+            debug!("Syncing OCI include [{}]...", oci);
+        }
+        let oci_mount_point = Container::podman_mount(oci, true, user)?;
+        let synced = IO::sync_data(
+            &format!("{}/", oci_mount_point),
+            &format!("{}/", vm_mount_point),
+            [].to_vec(),
+            user
+        );
+        let _ = Container::podman_umount(oci, true, user);
+        bytes_synced += synced?;
+    }
+    Ok(bytes_synced)
+}
+
 pub fn start(
     program_name: &String, (vm_id, vm_id_file): (String, String)
-) -> Result<(), FlakeError> {
+) -> Result<u8, FlakeError> {
     /*!
     Start VM with the given VM ID
 
     firecracker-pilot exits with the return code from firecracker
-    after this function
+    after this function. If the app is executed through the vsock
+    the exit code of the app in the VM is returned
     !*/
-    let RuntimeSection { runas, resume, force_vsock, .. } = config().runtime();
+    let RuntimeSection {
+        runas, resume, force_vsock, firecracker: engine_section, ..
+    } = config().runtime();
 
-    let user = User::from(runas);
+    let user = User::parse(runas)?;
+
+    if resume || force_vsock {
+        check_vsock(defaults::VHOST_VSOCK_DEVICE, defaults::VHOST_VSOCK_MODULE)?;
+    }
 
     let mut is_blocking: bool = true;
+    let mut exit_code = 0;
 
     if vm_running(&vm_id, user)? {
         // 1. Execute app in running VM
-        execute_command_at_instance(program_name)?;
+        if resume {
+            set_status(&vm_id_file, "running", &vm_id);
+        }
+        exit_code = execute_command_at_instance(program_name)?;
     } else {
         let firecracker_config = NamedTempFile::new()?;
         create_firecracker_config(
@@ -346,42 +737,131 @@ pub fn start(
         if resume || force_vsock {
             // 2. Startup VM as background job and execute app through vsock
             is_blocking = false;
-            call_instance(
-                &firecracker_config, &vm_id_file, user, is_blocking
-            )?;
-            execute_command_at_instance(program_name)?;
+            if resume {
+                set_status(&vm_id_file, "starting", &vm_id);
+            }
+            let started = call_instance(
+                program_name, &firecracker_config, &vm_id_file,
+                user, is_blocking
+            );
+            if resume {
+                let vm_id = fs::read_to_string(&vm_id_file)
+                    .unwrap_or_else(|_| vm_id.clone());
+                let state = match started {
+                    Ok(_) => "running",
+                    Err(_) => "stopped"
+                };
+                set_status(&vm_id_file, state, vm_id.trim());
+            }
+            let child = started?;
+            if let Some(boot_timeout) = engine_section.boot_timeout_sec {
+                // tell a VM which fails to boot apart from a slow app
+                if ! wait_for_boot(
+                    &get_vsock_uds_path(program_name),
+                    Duration::from_secs(boot_timeout)
+                ) {
+                    if let Some(child) = child {
+                        let vm_id = child.id().to_string();
+                        kill_vm(child, user);
+                        if resume {
+                            set_status(&vm_id_file, "stopped", &vm_id);
+                        }
+                    }
+                    return Err(FlakeError::OperationError(
+                        OperationError::BootFailed(boot_timeout)
+                    ))
+                }
+            }
+            exit_code = execute_command_at_instance(program_name)?;
         } else {
             // 3. Startup VM and execute app
             call_instance(
-                &firecracker_config, &vm_id_file, user, is_blocking
+                program_name, &firecracker_config, &vm_id_file,
+                user, is_blocking
             )?;
         }
     }
-    Ok(())
+    Ok(exit_code)
+}
+
+pub fn check_vsock(device: &str, module: &str) -> Result<(), FlakeError> {
+    /*!
+    Check that the host provides vsock support before a VM that
+    is talked to through the vsock gets started. The device node
+    exists once the vhost_vsock module is loaded
+    !*/
+    if Path::new(device).exists() {
+        return Ok(())
+    }
+    let message = if Path::new(module).exists() {
+        format!(
+            "vhost_vsock is loaded but {} does not exist; check the \
+            device setup or disable resume and force_vsock", device
+        )
+    } else {
+        "vhost_vsock not available; load the module with \
+        modprobe vhost_vsock or disable resume and force_vsock".to_string()
+    };
+    Err(FlakeError::IOError {
+        kind: "NotFound".to_string(),
+        message
+    })
+}
+
+fn set_status(vm_id_file: &str, state: &str, vm_id: &str) {
+    /*!
+    Update the configured status_file of a resume VM,
+    failing to do so does not stop the app from running
+    !*/
+    if let Some(status_file) = config().runtime().status_file {
+        if let Err(error) = IO::write_status(
+            status_file, vm_id_file, state, vm_id
+        ) {
+            warn!("Failed to write status file {}: {}", status_file, error);
+        }
+    }
 }
 
 pub fn call_instance(
-    config_file: &NamedTempFile, vm_id_file: &String,
+    program_name: &String, config_file: &NamedTempFile, vm_id_file: &String,
     user: User, is_blocking: bool
-) -> Result<(), FlakeError> {
+) -> Result<Option<Child>, FlakeError> {
     /*!
-    Run firecracker with specified configuration
+    Run firecracker with specified configuration. If not
+    blocking the spawned process is returned to the caller
     !*/
-    let mut firecracker = user.run(defaults::FIRECRACKER);
+    let use_jailer = config().runtime().firecracker.use_jailer;
+    let mut firecracker = if use_jailer {
+        jailer_command(program_name, config_file)?
+    } else {
+        let mut firecracker = run_pinned(user, defaults::FIRECRACKER);
+        firecracker
+            .arg("--no-api")
+            .arg("--id")
+            .arg(id().to_string())
+            .arg("--config-file")
+            .arg(config_file.path());
+        firecracker
+    };
+    let console_log = match config().runtime().firecracker.console_log {
+        Some(console_log) if ! Lookup::is_debug() => {
+            Some(open_console_log(console_log)?)
+        },
+        _ => None
+    };
     if ! Lookup::is_debug() {
-        firecracker.stderr(Stdio::null());
+        match &console_log {
+            Some(console_log) => firecracker.stderr(console_log.try_clone()?),
+            None => firecracker.stderr(Stdio::null())
+        };
     }
     if ! Lookup::is_debug() && ! is_blocking {
-        firecracker
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped());
+        firecracker.stdin(Stdio::piped());
+        match console_log {
+            Some(console_log) => firecracker.stdout(console_log),
+            None => firecracker.stdout(Stdio::piped())
+        };
     }
-    firecracker
-        .arg("--no-api")
-        .arg("--id")
-        .arg(id().to_string())
-        .arg("--config-file")
-        .arg(config_file.path());
     if Lookup::is_debug() {
         debug!("sudo {:?}", firecracker.get_args())
     }
@@ -395,9 +875,78 @@ pub fn call_instance(
     File::create(vm_id_file)?.write_all(pid.to_string().as_bytes())?;
 
     if is_blocking {
-        handle_output(child.wait_with_output(), firecracker.get_args())?;
+        // forward supervisor signals to firecracker
+        let _signal_forward = SignalForward::to_process(pid)?;
+        let output = child.wait_with_output();
+        if use_jailer {
+            delete_jail(&get_meta_name(program_name));
+        }
+        handle_output(output, firecracker.get_args())?;
+        return Ok(None)
+    }
+    Ok(Some(child))
+}
+
+pub fn run_pinned(user: User, program: &str) -> Command {
+    /*!
+    Setup the call of program as the given user. With a cpuset
+    configured the program is started through taskset such
+    that it and all its threads run on the given CPUs only
+    !*/
+    match config().runtime().cpuset {
+        Some(cpuset) => {
+            let mut call = user.run(defaults::TASKSET);
+            call.arg("--cpu-list").arg(cpuset).arg(program);
+            call
+        },
+        None => user.run(program)
     }
-    Ok(())
+}
+
+pub fn open_console_log(console_log: &str) -> Result<File, FlakeError> {
+    /*!
+    Open the console log file for appending
+    !*/
+    OpenOptions::new().create(true).append(true).open(console_log)
+        .map_err(|error| FlakeError::IOError {
+            kind: format!("{:?}", error.kind()),
+            message: format!(
+                "Failed to open console_log {}: {}", console_log, error
+            )
+        })
+}
+
+pub fn jailer_command(
+    program_name: &String, config_file: &NamedTempFile
+) -> Result<Command, FlakeError> {
+    /*!
+    Setup the jailer call to run firecracker chrooted with
+    the given configuration
+    !*/
+    let (uid, gid) = get_jail_ids(program_name)?;
+    let meta_name = get_meta_name(program_name);
+    jail_file(
+        &config_file.path().to_string_lossy(),
+        &get_jail_root(&meta_name), defaults::JAILER_CONFIG_FILE,
+        JailAccess::Owned(uid, gid)
+    )?;
+    let mut jailer = run_pinned(User::ROOT, defaults::JAILER);
+    jailer
+        .arg("--id")
+        .arg(get_jail_id(&meta_name))
+        .arg("--exec-file")
+        .arg(get_jail_exec_file())
+        .arg("--uid")
+        .arg(uid.to_string())
+        .arg("--gid")
+        .arg(gid.to_string())
+        .arg("--chroot-base-dir")
+        .arg(get_jail_chroot_base())
+        .arg("--")
+        .arg("--no-api")
+        .arg("--config-file")
+        .arg(defaults::JAILER_CONFIG_FILE);
+    Ok(jailer)
 }
 
 pub fn get_exec_port() -> u32 {
@@ -419,42 +968,165 @@ pub fn get_exec_port() -> u32 {
     port
 }
 
+pub fn allocate_guest_cid(program_name: &String) -> Result<u32, FlakeError> {
+    /*!
+    Allocate a vsock guest CID for the VM of the given program
+
+    Each VM gets its own CID, starting at VM_CID, such that
+    multiple VMs can run at the same time. The allocation is
+    tracked in a cid file per VM similar to the vmid file.
+    An existing allocation for the same VM is re-used
+    !*/
+    let cid_file_path = get_meta_file_name(
+        program_name, defaults::FIRECRACKER_CID_DIR, "cid"
+    );
+    let _lock = IO::lock(&format!(
+        "{}/{}", defaults::FIRECRACKER_CID_DIR,
+        defaults::FIRECRACKER_CID_LOCK_FILE
+    ))?;
+    if let Ok(cid) = fs::read_to_string(&cid_file_path) {
+        if let Ok(cid) = cid.trim().parse::<u32>() {
+            return Ok(cid)
+        }
+    }
+    let mut allocated: Vec<u32> = Vec::new();
+    for entry in fs::read_dir(defaults::FIRECRACKER_CID_DIR)? {
+        let path = entry?.path();
+        if path.extension().and_then(OsStr::to_str) == Some("cid") {
+            if let Ok(cid) = fs::read_to_string(&path) {
+                if let Ok(cid) = cid.trim().parse::<u32>() {
+                    allocated.push(cid);
+                }
+            }
+        }
+    }
+    let mut cid = defaults::VM_CID;
+    while allocated.contains(&cid) {
+        cid += 1;
+    }
+    File::create(&cid_file_path)?.write_all(cid.to_string().as_bytes())?;
+    if Lookup::is_debug() {
+        debug!("Allocated guest CID {} in {}", cid, cid_file_path);
+    }
+    Ok(cid)
+}
+
+pub fn parse_secret_env(
+    secret_env: &str
+) -> Result<(&str, &str, &str), FlakeError> {
+    /*!
+    Split a secret_env entry NAME=file:/path or
+    NAME=keyring:description into name, source type and source
+    !*/
+    let invalid = || FlakeError::IOError {
+        kind: "InvalidInput".to_string(),
+        message: format!(
+            "secret_env {} must be NAME=file:/path or \
+            NAME=keyring:description", secret_env
+        )
+    };
+    let (name, source) = secret_env.split_once('=').ok_or_else(invalid)?;
+    let valid_name = name.chars().next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false)
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match source.split_once(':') {
+        Some(("file", path)) if valid_name && path.starts_with('/') => {
+            Ok((name, "file", path))
+        },
+        Some(("keyring", description))
+            if valid_name && ! description.is_empty() =>
+        {
+            Ok((name, "keyring", description))
+        },
+        _ => Err(invalid())
+    }
+}
+
+pub fn read_secret(
+    source_type: &str, source: &str
+) -> Result<Vec<u8>, FlakeError> {
+    /*!
+    Read secret from a file or the user keyring as the calling
+    user. A trailing newline is not part of the secret
+    !*/
+    let mut secret = if source_type == "keyring" {
+        let mut keyctl = Command::new(defaults::KEYCTL);
+        keyctl.arg("pipe").arg(format!("%user:{}", source));
+        let output = keyctl.output().map_err(|error| FlakeError::IOError {
+            kind: format!("{:?}", error.kind()),
+            message: format!("Failed to call {}: {}", defaults::KEYCTL, error)
+        })?;
+        if ! output.status.success() {
+            return Err(FlakeError::IOError {
+                kind: "NotFound".to_string(),
+                message: format!(
+                    "Failed to read key {} from keyring{}",
+                    source, stderr_excerpt(&output.stderr)
+                )
+            })
+        }
+        output.stdout
+    } else {
+        fs::read(source).map_err(|error| FlakeError::IOError {
+            kind: format!("{:?}", error.kind()),
+            message: format!("Failed to read secret {}: {}", source, error)
+        })?
+    };
+    if secret.last() == Some(&b'\n') {
+        secret.pop();
+    }
+    Ok(secret)
+}
+
+pub fn secret_env_preamble(secret_env: &[&str]) -> Result<String, FlakeError> {
+    /*!
+    Read all secrets and format them as lines in front of the
+    command sent to sci. Values are hex encoded such that they
+    can contain any byte including newlines
+    !*/
+    let mut preamble = String::new();
+    for secret_env in secret_env {
+        let (name, source_type, source) = parse_secret_env(secret_env)?;
+        let secret = read_secret(source_type, source)?;
+        if Lookup::is_debug() {
+            debug!("Passing secret {} from {}:{}", name, source_type, source);
+        }
+        preamble.push_str(&format!(
+            "{} {} {}\n", defaults::SECRET_ENV_PREFIX, name, hex_encode(&secret)
+        ));
+    }
+    Ok(preamble)
+}
+
+pub fn hex_encode(data: &[u8]) -> String {
+    /*!
+    Encode data as lowercase hex string
+    !*/
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
 pub fn check_connected(program_name: &String) -> Result<(), FlakeError> {
     /*!
     Check if instance connection is OK
     !*/
     let mut retry_count = 0;
-    let vsock_uds_path = format!(
-        "/run/sci_cmd_{}.sock", get_meta_name(program_name)
-    );
+    let started = Instant::now();
+    let vsock_uds_path = get_vsock_uds_path(program_name);
     chmod(&vsock_uds_path, "777", User::ROOT)?;
     loop {
         if retry_count == defaults::RETRIES {
             if Lookup::is_debug() {
                 debug!("Max retries for VM connection check exceeded")
             }
-            return Err(
-                FlakeError::OperationError(OperationError::MaxTriesExceeded)
-            )
+            return Err(FlakeError::Timeout {
+                operation: "vsock connect".to_string(),
+                retries: retry_count,
+                elapsed: started.elapsed()
+            })
         }
-        let mut buffer = [0; 14];
-        if let Ok(mut stream) = UnixStream::connect(&vsock_uds_path) {
-            let _ = stream.set_write_timeout(
-                Some(time::Duration::from_millis(200))
-            );
-            let _ = stream.set_read_timeout(
-                Some(time::Duration::from_millis(200))
-            );
-            stream.write_all(
-                format!("CONNECT {}\n", defaults::VM_PORT
-            ).as_bytes())?;
-            if stream.read_exact(&mut buffer).is_ok() {
-                let output = String::from_utf8(buffer.to_vec()).unwrap();
-                if output.starts_with("OK") {
-                    return Ok(())
-                }
-            }
-            stream.shutdown(Shutdown::Both).unwrap();
+        if vsock_handshake(&vsock_uds_path) {
+            return Ok(())
         }
         // VM not yet ready for connections
         let some_time = time::Duration::from_millis(
@@ -466,30 +1138,175 @@ pub fn check_connected(program_name: &String) -> Result<(), FlakeError> {
                 defaults::VM_WAIT_TIMEOUT_MSEC
             );
         }
-        thread::sleep(some_time);
-        retry_count += 1
+        thread::sleep(some_time);
+        retry_count += 1
+    }
+}
+
+pub fn vsock_handshake(vsock_uds_path: &str) -> bool {
+    /*!
+    Connect to the sci port of the VM through the vsock socket
+    and check that sci accepts the connection
+    !*/
+    let mut buffer = [0; 14];
+    let mut stream = match UnixStream::connect(vsock_uds_path) {
+        Ok(stream) => stream,
+        Err(_) => return false
+    };
+    let _ = stream.set_write_timeout(Some(time::Duration::from_millis(200)));
+    let _ = stream.set_read_timeout(Some(time::Duration::from_millis(200)));
+    let connected = stream.write_all(
+        format!("CONNECT {}\n", defaults::VM_PORT).as_bytes()
+    ).is_ok() && stream.read_exact(&mut buffer).is_ok()
+        && buffer.starts_with(b"OK");
+    if ! connected {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    connected
+}
+
+pub fn wait_for_boot(vsock_uds_path: &str, boot_timeout: Duration) -> bool {
+    /*!
+    Wait for a freshly started VM to create its vsock socket
+    and for sci to answer on it. Returns false if the VM does
+    not get there within boot_timeout
+    !*/
+    let started = Instant::now();
+    let mut accessible = false;
+    loop {
+        if Path::new(vsock_uds_path).exists() {
+            if ! accessible {
+                accessible = chmod(vsock_uds_path, "777", User::ROOT).is_ok();
+            }
+            if accessible && vsock_handshake(vsock_uds_path) {
+                return true
+            }
+        }
+        if started.elapsed() >= boot_timeout {
+            if Lookup::is_debug() {
+                debug!(
+                    "VM did not answer on {} within {:?}",
+                    vsock_uds_path, boot_timeout
+                );
+            }
+            return false
+        }
+        thread::sleep(
+            time::Duration::from_millis(defaults::VM_WAIT_TIMEOUT_MSEC)
+        );
+    }
+}
+
+pub fn kill_vm(mut child: Child, user: User) {
+    /*!
+    Stop the VM started as the given child process. The child
+    is not yet reaped, such that its PID can't have been reused.
+    Firecracker runs through sudo which only relays catchable
+    signals, it is terminated and killed if that does not help.
+    Failures are not fatal, the meta data is garbage collected
+    later
+    !*/
+    let user = if config().runtime().firecracker.use_jailer {
+        User::ROOT
+    } else {
+        user
+    };
+    for signal in ["-TERM", "-KILL"] {
+        if ! matches!(child.try_wait(), Ok(None)) {
+            break
+        }
+        let mut kill = user.run("kill");
+        kill.arg(signal).arg(child.id().to_string());
+        if Lookup::is_debug() {
+            debug!("{:?}", kill.get_args());
+        }
+        if let Err(error) = kill.perform() {
+            if Lookup::is_debug() {
+                debug!("Failed to stop VM {}: {}", child.id(), error);
+            }
+        }
+        for _ in 0..defaults::VM_STOP_RETRIES {
+            if ! matches!(child.try_wait(), Ok(None)) {
+                break
+            }
+            thread::sleep(
+                time::Duration::from_millis(defaults::VM_STOP_WAIT_MSEC)
+            );
+        }
+    }
+}
+
+pub fn check_stale_socket(
+    vsock_uds_path: &str, vm_id_file: &str, user: User
+) -> Result<(), FlakeError> {
+    /*!
+    Check if the vsock socket is stale
+
+    A socket is stale if it refuses connections and the VM
+    process from the vm_id_file is not alive. A stale socket
+    is deleted and reported as error instead of spinning
+    through all connection retries
+    !*/
+    if ! Path::new(vsock_uds_path).exists() {
+        return Ok(())
+    }
+    for retry in 0..defaults::STALE_SOCKET_RETRIES {
+        if UnixStream::connect(vsock_uds_path).is_ok() {
+            return Ok(())
+        }
+        if retry + 1 < defaults::STALE_SOCKET_RETRIES {
+            if Lookup::is_debug() {
+                debug!("Sleeping(check_stale_socket): 100ms");
+            }
+            thread::sleep(time::Duration::from_millis(100));
+        }
+    }
+    let vmid = fs::read_to_string(vm_id_file)
+        .unwrap_or_else(|_| "0".to_string());
+    if vm_running(&vmid.trim().to_string(), user)? {
+        // VM is alive but not yet ready for connections
+        return Ok(())
+    }
+    if Lookup::is_debug() {
+        debug!("Deleting stale socket {}", vsock_uds_path);
+    }
+    if fs::remove_file(vsock_uds_path).is_err() {
+        delete_file(&vsock_uds_path.to_string(), user);
     }
+    Err(FlakeError::OperationError(
+        OperationError::StaleSocket(vsock_uds_path.to_string())
+    ))
 }
 
-pub fn send_command_to_instance(program_name: &String, exec_port: u32) -> i32 {
+pub fn send_command_to_instance(
+    program_name: &String, exec_port: u32, secrets: &str
+) -> Result<(), FlakeError> {
     /*!
-    Send command to the VM via a vsock
+    Send command to the VM via a vsock. The given secrets
+    preamble is sent in front of the command line
     !*/
     let mut status_code;
     let mut retry_count = 0;
-    let mut run: Vec<String> = vec![get_target_app_path(program_name)];
+    let started = Instant::now();
+    let mut run: Vec<String> = Vec::new();
 
+    if ! Lookup::get_pilot_run_options().contains_key("%exec") {
+        // in exec mode the caller arguments are the command
+        run.push(get_target_app_path(program_name));
+    }
     run = Lookup::get_run_cmdline(run, false);
-    let vsock_uds_path = format!(
-        "/run/sci_cmd_{}.sock", get_meta_name(program_name)
-    );
+    let vsock_uds_path = get_vsock_uds_path(program_name);
     loop {
         status_code = 1;
         if retry_count == defaults::RETRIES {
             if Lookup::is_debug() {
                 debug!("Max retries for VM command transfer exceeded");
             }
-            return status_code
+            return Err(FlakeError::Timeout {
+                operation: "vsock command transfer".to_string(),
+                retries: retry_count,
+                elapsed: started.elapsed()
+            })
         }
         match UnixStream::connect(&vsock_uds_path) {
             Ok(mut stream) => {
@@ -505,7 +1322,8 @@ pub fn send_command_to_instance(program_name: &String, exec_port: u32) -> i32 {
                         if output.starts_with("OK") {
                             stream.write_all(
                                 format!(
-                                    "{} {}\n", run.join(" "), exec_port
+                                    "{}{} {}\n",
+                                    secrets, run.join(" "), exec_port
                                 ).as_bytes()
                             ).unwrap();
                             status_code = 0
@@ -538,20 +1356,20 @@ pub fn send_command_to_instance(program_name: &String, exec_port: u32) -> i32 {
         }
         retry_count += 1
     }
-    status_code
+    Ok(())
 }
 
 pub fn execute_command_at_instance(
     program_name: &String
-) -> Result<(), FlakeError> {
+) -> Result<u8, FlakeError> {
     /*!
     Send command to a vsock connected to a running instance
+    and return the exit code of the command. If sci does not
+    report the exit code, 0 is returned
     !*/
     let mut retry_count = 0;
-    let vsock_uds_path = format!(
-        "{}{}.sock",
-        defaults::FIRECRACKER_VSOCK_PREFIX, get_meta_name(program_name)
-    );
+    let started = Instant::now();
+    let vsock_uds_path = get_vsock_uds_path(program_name);
 
     // wait for UDS socket to appear
     loop {
@@ -559,9 +1377,11 @@ pub fn execute_command_at_instance(
             if Lookup::is_debug() {
                 debug!("Max retries for UDS socket lookup exceeded");
             }
-            return Err(
-                FlakeError::OperationError(OperationError::MaxTriesExceeded)
-            )
+            return Err(FlakeError::Timeout {
+                operation: format!("vsock socket lookup of {}", vsock_uds_path),
+                retries: retry_count,
+                elapsed: started.elapsed()
+            })
         }
         if Path::new(&vsock_uds_path).exists() {
             break
@@ -574,18 +1394,33 @@ pub fn execute_command_at_instance(
         retry_count += 1
     }
 
+    // a socket left behind by a crashed instance can't be contacted
+    let RuntimeSection { runas, .. } = config().runtime();
+    let vm_id_file = get_meta_file_name(
+        program_name, &get_firecracker_ids_dir(), "vmid"
+    );
+    check_stale_socket(&vsock_uds_path, &vm_id_file, User::parse(runas)?)?;
+
     // make sure instance can be contacted
     check_connected(program_name)?;
 
     // spawn the listener and wait for sci to run the command
     let exec_port = get_exec_port();
     let command_socket = &format!("{}_{}", vsock_uds_path, exec_port);
-    let thread_handle = stream_listener(command_socket);
+    let vmid = fs::read_to_string(&vm_id_file)
+        .unwrap_or_else(|_| "0".to_string());
+    let secrets = secret_env_preamble(
+        &config().runtime().firecracker.secret_env
+    )?;
+    let thread_handle = stream_listener(command_socket, vmid.trim());
 
-    send_command_to_instance(program_name, exec_port);
+    send_command_to_instance(program_name, exec_port, &secrets)?;
 
-    let _ = thread_handle.join();
-    Ok(())
+    let exit_code = thread_handle.join().ok().flatten();
+    if Lookup::is_debug() {
+        debug!("Command exit code reported by sci: {:?}", exit_code);
+    }
+    Ok(exit_code.unwrap_or_default())
 }
 
 pub fn create_firecracker_config(
@@ -595,13 +1430,12 @@ pub fn create_firecracker_config(
     /*!
     Create json config to call firecracker
     !*/
-    let template = File::open(defaults::FIRECRACKER_TEMPLATE)?;
-    let mut firecracker_config: FireCrackerConfig = serde_json::from_reader(
-        template
+    let mut firecracker_config = FireCrackerConfig::from_template(
+        defaults::FIRECRACKER_TEMPLATE
     )?;
     let mut boot_args: Vec<String> = Vec::new();
     let RuntimeSection {
-        resume, force_vsock, firecracker: engine_section, ..
+        runas, resume, force_vsock, firecracker: engine_section, ..
     } = config().runtime();
 
     // set kernel_image_path
@@ -615,9 +1449,7 @@ pub fn create_firecracker_config(
     }
 
     // setup run commandline for the command call
-    let mut run: Vec<String> = vec![get_target_app_path(program_name)];
-
-    run = Lookup::get_run_cmdline(run, true);
+    let run = get_run_args(program_name, true);
 
     // set boot_args
     if Lookup::is_debug() {
@@ -626,10 +1458,106 @@ pub fn create_firecracker_config(
     if engine_section.overlay_size.is_some() {
         boot_args.push("overlay_root=/dev/vdb".to_string());
     }
-    for boot_option in engine_section.boot_args
-    {
+    if ! engine_section.layer_image_paths.is_empty() {
+        if engine_section.overlay_size.is_none() {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: "layer_image_paths requires overlay_size".to_string()
+            })
+        }
+        // layer drives follow rootfs(vda) and overlay(vdb)
+        let layer_devices: Vec<String> = (0..engine_section
+            .layer_image_paths.len())
+            .map(|index| format!("/dev/vd{}", (b'c' + index as u8) as char))
+            .collect();
+        boot_args.push(
+            format!("overlay_lowerdirs={}", layer_devices.join(":"))
+        );
+    }
+    if ! engine_section.extra_mounts.is_empty() {
+        for extra_mount in &engine_section.extra_mounts {
+            check_extra_mount(extra_mount).map_err(
+                |message| FlakeError::ConfigError {
+                    path: crate::config::config_file(program_name),
+                    message
+                }
+            )?;
+        }
+        boot_args.push(
+            format!("extra_mounts={}", engine_section.extra_mounts.join(","))
+        );
+    }
+
+    // invalid settings are reported before a guest CID gets
+    // allocated, such that no allocation is left behind
+    if ! engine_section.shared_dirs.is_empty() {
+        return Err(FlakeError::ConfigError {
+            path: crate::config::config_file(program_name),
+            message: "shared_dirs requires virtio-fs which firecracker \
+                does not support, use include path instead".to_string()
+        })
+    }
+    if let Some(init_path) = engine_section.init_path {
+        if ! init_path.starts_with('/') {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: format!(
+                    "init_path must be an absolute path: {}", init_path
+                )
+            })
+        }
+    }
+    if let Some(console_log) = engine_section.console_log {
+        if ! console_log.starts_with('/') {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: format!(
+                    "console_log must be an absolute file path: {}",
+                    console_log
+                )
+            })
+        }
+    }
+    let run_on_cmdline = ! (
+        resume || force_vsock || run_from_file(program_name)
+    );
+    if run_on_cmdline && run.iter().any(|arg| arg.contains('"')) {
+        return Err(FlakeError::IOError {
+            kind: "InvalidInput".to_string(),
+            message: "Arguments with a double quote can't be passed \
+                on the kernel cmdline, configure an overlay_size to \
+                pass them through a file".to_string()
+        })
+    }
+    let workdir = IO::workdir()?;
+
+    let guest_cid = allocate_guest_cid(program_name)?;
+    boot_args.push(format!("sci_guest_cid={}", guest_cid));
+    if let Some(workdir) = workdir {
+        boot_args.push(format!("sci_workdir={}", workdir));
+    }
+    let pilot_options = Lookup::get_pilot_run_options();
+    if pilot_options.contains_key("%keep-alive") && ! (resume || force_vsock) {
+        // keep the VM running after the command for debugging
+        boot_args.push("sci_keep_alive=1".to_string());
+        info!(
+            "VM kept alive after command, connect via: {} (CONNECT {})",
+            get_vsock_uds_path(program_name), defaults::VM_PORT
+        );
+    }
+    if let Some(init_path) = engine_section.init_path {
+        boot_args.push(format!("init={}", init_path));
+    }
+    for boot_option in engine_section.boot_args {
+        if engine_section.init_path.is_some()
+            && boot_option.starts_with("init=")
+        {
+            // init_path overrides the init from the boot args
+            continue
+        }
         if (resume || force_vsock)
             && ! Lookup::is_debug()
+            && engine_section.console_log.is_none()
             && boot_option.starts_with("console=")
         {
             // in resume mode the communication is handled
@@ -639,7 +1567,14 @@ pub fn create_firecracker_config(
         } else {
             boot_args.push(boot_option.to_owned());
         }
-        }
+    }
+    // the console log of vsock based VMs needs a serial console
+    if engine_section.console_log.is_some()
+        && (resume || force_vsock)
+        && ! boot_args.iter().any(|arg| arg.starts_with("console="))
+    {
+        boot_args.push("console=ttyS0".to_string());
+    }
     if ! firecracker_config.boot_source.boot_args.is_empty() {
         firecracker_config.boot_source.boot_args.push(' ');
     }
@@ -650,7 +1585,18 @@ pub fn create_firecracker_config(
         firecracker_config.boot_source.boot_args.push_str(
             " run=vsock"
         )
+    } else if ! run_on_cmdline {
+        // command provisioned in the overlay, see run_creation
+        firecracker_config.boot_source.boot_args.push_str(
+            &format!(" run=@{}", defaults::SCI_RUN_FILE)
+        )
     } else {
+        if run.join(" ").len() > defaults::KERNEL_CMDLINE_RUN_MAX {
+            warn!(
+                "Command line might get truncated, configure an \
+                overlay_size to pass it through a file"
+            );
+        }
         firecracker_config.boot_source.boot_args.push_str(
             &format!(" run=\"{}\"", run.join(" "))
         )
@@ -665,7 +1611,7 @@ pub fn create_firecracker_config(
     if engine_section.overlay_size.is_some() {
         let vm_overlay_file = get_meta_file_name(
             program_name,
-            defaults::FIRECRACKER_OVERLAY_DIR,
+            FIRECRACKER_OVERLAY_DIR,
             "ext2"
         );
 
@@ -682,15 +1628,27 @@ pub fn create_firecracker_config(
         firecracker_config.drives.push(drive);
     }
 
+    // set drive section for read-only layers
+    for (index, layer_image_path) in
+        engine_section.layer_image_paths.iter().enumerate()
+    {
+        let drive = FireCrackerDrive {
+            drive_id: format!("layer{}", index),
+            path_on_host: layer_image_path.to_string(),
+            is_root_device: false,
+            is_read_only: true,
+            cache_type: firecracker_config.drives[0].cache_type.clone()
+        };
+        firecracker_config.drives.push(drive);
+    }
+
     // set tap device name
     firecracker_config.network_interfaces[0].host_dev_name =
         format!("tap-{}", get_meta_name(program_name));
 
     // set vsock name
-    firecracker_config.vsock.guest_cid = defaults::VM_CID;
-    firecracker_config.vsock.uds_path = format!(
-        "/run/sci_cmd_{}.sock", get_meta_name(program_name)
-    );
+    firecracker_config.vsock.guest_cid = guest_cid;
+    firecracker_config.vsock.uds_path = get_vsock_uds_path(program_name);
 
     // set mem_size_mib
     if let Some(mem_size_mib) = engine_section.mem_size_mib {
@@ -701,6 +1659,39 @@ pub fn create_firecracker_config(
     if let Some(vcpu_count) = engine_section.vcpu_count {
         firecracker_config.machine_config.vcpu_count = vcpu_count;
     }
+
+    // set metrics file
+    if let Some(metrics_path) = engine_section.metrics_path {
+        let metrics_dir = Path::new(metrics_path).parent()
+            .and_then(Path::to_str)
+            .filter(|_| metrics_path.starts_with('/'))
+            .ok_or_else(|| FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: format!(
+                    "metrics_path must be an absolute file path: {}",
+                    metrics_path
+                )
+            })?;
+        let user = User::parse(runas)?;
+        mkdir(metrics_dir, "755", user)?;
+        // firecracker opens but does not create the metrics file,
+        // an existing file or FIFO is kept as is
+        if ! Path::new(metrics_path).exists() {
+            let mut touch = user.run("touch");
+            touch.arg(metrics_path);
+            if Lookup::is_debug() {
+                debug!("{:?}", touch.get_args());
+            }
+            touch.perform()?;
+        }
+        firecracker_config.metrics = Some(FireCrackerMetrics {
+            metrics_path: metrics_path.to_string()
+        });
+    }
+    // link resources into the jailer chroot
+    if engine_section.use_jailer {
+        jail_config(program_name, &mut firecracker_config)?;
+    }
     if Lookup::is_debug() {
         debug!("{}", &serde_json::to_string(&firecracker_config)?);
     }
@@ -711,6 +1702,26 @@ pub fn create_firecracker_config(
     Ok(())
 }
 
+pub fn check_extra_mount(extra_mount: &str) -> Result<(), String> {
+    /*!
+    Check if the given extra mount is specified as
+    fstype:source:target with an absolute target. The
+    entries are passed on the kernel command line and
+    must not contain whitespace or commas
+    !*/
+    let fields: Vec<&str> = extra_mount.split(':').collect();
+    let valid = fields.len() == 3
+        && fields.iter().all(|field| ! field.is_empty())
+        && fields[2].starts_with('/')
+        && ! extra_mount.contains(|c: char| c == ',' || c.is_whitespace());
+    if ! valid {
+        return Err(format!(
+            "extra_mount {} must be fstype:source:/target", extra_mount
+        ))
+    }
+    Ok(())
+}
+
 pub fn get_target_app_path(
     program_name: &str, 
 ) -> String {
@@ -719,16 +1730,31 @@ pub fn get_target_app_path(
 
     This is either the program name specified at registration
     time or the configured target application from the flake
-    configuration file
+    configuration file. The %app:PATH pilot option, validated
+    in create(), takes precedence over both
     !*/
+    if let Ok(Some(app)) = Lookup::get_app_override() {
+        return app
+    }
     config().vm.target_app_path.unwrap_or(program_name).to_owned()
-
 }
 
 pub fn init_meta_dirs() -> Result<(), CommandError> {
-    [defaults::FIRECRACKER_OVERLAY_DIR, &get_firecracker_ids_dir()].iter()
+    [
+        FIRECRACKER_OVERLAY_DIR,
+        defaults::FIRECRACKER_CID_DIR,
+        &get_firecracker_ids_dir()
+    ].iter()
         .filter(|path| !Path::new(path).is_dir())
-        .try_for_each(|path| mkdir(path, "777", User::ROOT))
+        .try_for_each(|path| mkdir(path, "777", User::ROOT))?;
+    // sockets are created by firecracker as root and made
+    // accessible by the pilot, the directory itself is not
+    // writable for other users
+    let socket_dir = get_firecracker_socket_dir();
+    if !Path::new(&socket_dir).is_dir() {
+        mkdir(&socket_dir, "755", User::ROOT)?;
+    }
+    Ok(())
 }
 
 pub fn vm_running(vmid: &String, user: User) -> Result<bool, FlakeError> {
@@ -763,11 +1789,302 @@ pub fn get_meta_file_name(
     meta_file
 }
 
+pub fn get_vsock_uds_path(program_name: &String) -> String {
+    /*!
+    Construct vsock unix domain socket path from given program name.
+    The socket is created in the configured firecracker_socket_dir,
+    with the jailer in use inside of the chroot instead
+    !*/
+    let meta_name = get_meta_name(program_name);
+    let uds_path = format!(
+        "{}/{}{}.sock", get_firecracker_socket_dir(),
+        defaults::FIRECRACKER_VSOCK_PREFIX, meta_name
+    );
+    if config().runtime().firecracker.use_jailer {
+        return format!(
+            "{}/{}", get_jail_root(&meta_name), file_name(&uds_path)
+        )
+    }
+    uds_path
+}
+
+pub fn get_jail_id(meta_name: &str) -> String {
+    /*!
+    Construct jailer ID from given meta name. The jailer only
+    accepts alphanumeric characters and hyphens
+    !*/
+    meta_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .take(defaults::JAILER_ID_MAX)
+        .collect()
+}
+
+pub fn get_jail_chroot_base() -> String {
+    /*!
+    Base directory of the jailer chroots
+    !*/
+    config().runtime().firecracker.jailer_chroot_base
+        .unwrap_or(defaults::JAILER_CHROOT_BASE)
+        .to_string()
+}
+
+pub fn get_jail_exec_file() -> String {
+    /*!
+    Path of the firecracker binary the jailer runs
+    !*/
+    config().runtime().firecracker.jailer_exec_file
+        .unwrap_or(defaults::JAILER_EXEC_FILE)
+        .to_string()
+}
+
+pub fn get_jail_dir(meta_name: &str) -> String {
+    /*!
+    Directory the jailer creates for the VM of the given meta name
+    !*/
+    format!(
+        "{}/{}/{}",
+        get_jail_chroot_base(),
+        file_name(&get_jail_exec_file()),
+        get_jail_id(meta_name)
+    )
+}
+
+pub fn get_jail_root(meta_name: &str) -> String {
+    /*!
+    Root of the jailer chroot for the VM of the given meta name
+    !*/
+    format!("{}/root", get_jail_dir(meta_name))
+}
+
+pub fn get_jail_ids(program_name: &str) -> Result<(u32, u32), FlakeError> {
+    /*!
+    Lookup user and group ID the jailer runs firecracker as
+    !*/
+    let EngineSection {
+        jailer_uid, jailer_gid, ..
+    } = config().runtime().firecracker;
+    match (jailer_uid, jailer_gid) {
+        (Some(uid), Some(gid)) => Ok((uid, gid)),
+        _ => Err(FlakeError::ConfigError {
+            path: crate::config::config_file(program_name),
+            message: "use_jailer requires jailer_uid and jailer_gid"
+                .to_string()
+        })
+    }
+}
+
+pub fn jail_config(
+    program_name: &String, firecracker_config: &mut FireCrackerConfig
+) -> Result<(), FlakeError> {
+    /*!
+    Make the VM resources available inside of the jailer chroot
+    and reference them relative to it in the given configuration.
+    Read-only files are hard linked if possible, writable ones
+    are bind mounted such that writes to the overlay and metrics
+    end up in their original location
+    !*/
+    let (uid, _) = get_jail_ids(program_name)?;
+    let meta_name = get_meta_name(program_name);
+    let jail_root = get_jail_root(&meta_name);
+    mkdir(&jail_root, "755", User::ROOT)?;
+    // the pilot binds the command socket of the vsock next to
+    // the vsock socket in the chroot
+    for user in jail_socket_users(config().runtime().runas) {
+        grant_access_with(&HostRunner, &jail_root, &user, "rwx")?;
+    }
+
+    let boot_source = &mut firecracker_config.boot_source;
+    boot_source.kernel_image_path = jail_file(
+        &boot_source.kernel_image_path, &jail_root, "kernel",
+        JailAccess::ReadOnly
+    )?;
+    if ! boot_source.initrd_path.is_empty() {
+        boot_source.initrd_path = jail_file(
+            &boot_source.initrd_path, &jail_root, "initrd",
+            JailAccess::ReadOnly
+        )?;
+    }
+    for drive in firecracker_config.drives.iter_mut() {
+        // firecracker opens writable drives as the jailer user
+        let access = if drive.is_read_only {
+            JailAccess::ReadOnly
+        } else {
+            JailAccess::Writable(uid)
+        };
+        drive.path_on_host = jail_file(
+            &drive.path_on_host, &jail_root, &drive.drive_id, access
+        )?;
+    }
+    if let Some(metrics) = firecracker_config.metrics.as_mut() {
+        metrics.metrics_path = jail_file(
+            &metrics.metrics_path, &jail_root, "metrics",
+            JailAccess::Writable(uid)
+        )?;
+    }
+    let uds_path = &mut firecracker_config.vsock.uds_path;
+    *uds_path = format!("/{}", file_name(uds_path));
+    Ok(())
+}
+
+/// How a file is made available inside of the jailer chroot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JailAccess {
+    /// Hard linked, or copied if on another filesystem
+    ReadOnly,
+    /// Bind mounted and writable for the given jailer uid. The
+    /// owner of the original file is not changed
+    Writable(u32),
+    /// Copied and owned by the given jailer uid and gid
+    Owned(u32, u32)
+}
+
+pub fn jail_file(
+    source: &str, jail_root: &str, name: &str, access: JailAccess
+) -> Result<String, FlakeError> {
+    /*!
+    Make source available as name in the jailer chroot.
+    Returns the path of the file relative to the chroot
+    !*/
+    jail_file_with(&HostRunner, source, jail_root, name, access)
+}
+
+pub fn jail_file_with<R: CommandRunner>(
+    runner: &R, source: &str, jail_root: &str, name: &str, access: JailAccess
+) -> Result<String, FlakeError> {
+    /*!
+    Make source available as name in the jailer chroot through
+    the given runner according to access
+    !*/
+    let target = format!("{}/{}", jail_root, name);
+    let perform = |mut call: Command| -> Result<(), FlakeError> {
+        if Lookup::is_debug() {
+            debug!("{:?}", call.get_args());
+        }
+        runner.perform(&mut call)?;
+        Ok(())
+    };
+    let copy = |target: &str| {
+        let mut copy = User::ROOT.run("cp");
+        copy.arg("-f").arg(source).arg(target);
+        copy
+    };
+    match access {
+        JailAccess::ReadOnly => {
+            let mut link = User::ROOT.run("ln");
+            link.arg("-f").arg(source).arg(&target);
+            if perform(link).is_err() {
+                perform(copy(&target))?;
+            }
+        },
+        JailAccess::Writable(uid) => {
+            let mut touch = User::ROOT.run("touch");
+            touch.arg(&target);
+            perform(touch)?;
+            let mut bind = User::ROOT.run("mount");
+            bind.arg("--bind").arg(source).arg(&target);
+            perform(bind)?;
+            grant_access_with(runner, source, &uid.to_string(), "rw")?;
+        },
+        JailAccess::Owned(uid, gid) => {
+            perform(copy(&target))?;
+            let mut chown = User::ROOT.run("chown");
+            chown.arg(format!("{}:{}", uid, gid)).arg(&target);
+            perform(chown)?;
+        }
+    }
+    Ok(format!("/{}", name))
+}
+
+pub fn grant_access_with<R: CommandRunner>(
+    runner: &R, path: &str, user: &str, permissions: &str
+) -> Result<(), FlakeError> {
+    /*!
+    Grant the given user name or uid access to path by an ACL
+    entry, the owner of path stays as is
+    !*/
+    let mut setfacl = User::ROOT.run(defaults::SETFACL);
+    setfacl.arg("-m").arg(format!("u:{}:{}", user, permissions)).arg(path);
+    if Lookup::is_debug() {
+        debug!("{:?}", setfacl.get_args());
+    }
+    runner.perform(&mut setfacl)?;
+    Ok(())
+}
+
+pub fn jail_socket_users(runas: &str) -> Vec<String> {
+    /*!
+    Users which bind sockets in the jailer chroot, the calling
+    user and the runas user, root excluded
+    !*/
+    let mut users: Vec<String> = Vec::new();
+    let uid = User::default().get_user_id();
+    if uid != "0" {
+        users.push(uid);
+    }
+    if ! User::from(runas).is_root() {
+        let runas = runas.strip_prefix('#').unwrap_or(runas);
+        if ! users.iter().any(|user| user == runas) {
+            users.push(runas.to_string());
+        }
+    }
+    users
+}
+
+pub fn jail_mounts(mounts: &str, jail_dir: &str) -> Vec<String> {
+    /*!
+    Mount points below jail_dir from the given /proc/self/mounts
+    content, innermost first such that they can be unmounted in
+    this order
+    !*/
+    let mut jail_mounts: Vec<String> = mounts.lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|mount_point| mount_point.replace("\\040", " "))
+        .filter(|mount_point| Path::new(mount_point).starts_with(jail_dir))
+        .collect();
+    jail_mounts.sort();
+    jail_mounts.reverse();
+    jail_mounts
+}
+
+pub fn delete_jail(meta_name: &str) {
+    /*!
+    Delete the jailer chroot of the VM with the given meta name
+    !*/
+    let jail_dir = get_jail_dir(meta_name);
+    if Path::new(&jail_dir).exists() {
+        if Lookup::is_debug() {
+            debug!("Deleting {}", jail_dir);
+        }
+        // writable files are bind mounted, release them first
+        // such that the originals are not touched
+        let mounts = fs::read_to_string("/proc/self/mounts")
+            .unwrap_or_default();
+        for mount_point in jail_mounts(&mounts, &jail_dir) {
+            let mut umount = User::ROOT.run("umount");
+            umount.arg(&mount_point);
+            if let Err(error) = umount.perform() {
+                error!("Failed to umount {}: {:?}", mount_point, error);
+                return
+            }
+        }
+        let mut call = User::ROOT.run("rm");
+        call.arg("-rf").arg(&jail_dir);
+        if let Err(error) = call.perform() {
+            error!("Failed to remove jail {}: {:?}", jail_dir, error)
+        }
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(OsStr::to_str).unwrap_or(path)
+}
+
 pub fn get_meta_name(program_name: &String) -> String {
     /*!
     Construct meta data basename from given program name
     !*/
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = Lookup::args();
     let mut meta_file = program_name.to_string();
     for arg in &args[1..] {
         if arg.starts_with('@') {
@@ -796,30 +2113,56 @@ pub fn gc_meta_files(
                 if Lookup::is_debug() {
                     debug!("Deleting {}", vm_id_file);
                 }
+                IO::remove_status(vm_id_file);
                 match fs::remove_file(vm_id_file) {
                     Ok(_) => { },
                     Err(error) => {
                         error!("Failed to remove VMID: {:?}", error)
                     }
                 }
-                let vsock_uds_path = format!(
-                    "/run/sci_cmd_{}.sock", get_meta_name(program_name)
-                );
+                let vsock_uds_path = get_vsock_uds_path(program_name);
                 if Path::new(&vsock_uds_path).exists() {
                     if Lookup::is_debug() {
                         debug!("Deleting {}", vsock_uds_path);
                     }
                     delete_file(&vsock_uds_path, user);
                 }
+                if config().runtime().firecracker.use_jailer {
+                    if let Some(meta_name) = Path::new(&vm_id_file)
+                        .file_stem().and_then(OsStr::to_str)
+                    {
+                        delete_jail(meta_name);
+                    }
+                }
                 let vm_overlay_file = format!(
                     "{}/{}",
-                    defaults::FIRECRACKER_OVERLAY_DIR,
+                    FIRECRACKER_OVERLAY_DIR,
                     Path::new(&vm_id_file)
                         .file_name()
                         .and_then(OsStr::to_str)
                         .map(|x| x.replace(".vmid", ".ext2"))
                         .unwrap()
                 );
+                let vm_cid_file = format!(
+                    "{}/{}",
+                    defaults::FIRECRACKER_CID_DIR,
+                    Path::new(&vm_id_file)
+                        .file_name()
+                        .and_then(OsStr::to_str)
+                        .map(|x| x.replace(".vmid", ".cid"))
+                        .unwrap()
+                );
+                if Path::new(&vm_cid_file).exists() {
+                    if Lookup::is_debug() {
+                        debug!("Deleting {}", vm_cid_file);
+                    }
+                    match fs::remove_file(&vm_cid_file) {
+                        Ok(_) => { },
+                        Err(error) => {
+                            error!("Failed to remove CID: {:?}", error)
+                        }
+                    }
+                }
                 if Path::new(&vm_overlay_file).exists() && ! resume {
                     if Lookup::is_debug() {
                         debug!("Deleting {}", vm_overlay_file);
@@ -846,25 +2189,16 @@ pub fn gc(user: User, program_name: &String) -> Result<(), FlakeError> {
     /*!
     Garbage collect VMID files for which no VM exists anymore
     !*/
-    let vmid_file_names: Vec<_> = fs::read_dir(get_firecracker_ids_dir())?
-        .filter_map(|entry| entry.ok())
-        .filter_map(|x| x.path()
-            .to_str()
-            .map(ToOwned::to_owned))
-        .collect();
-
-    if vmid_file_names.len() <= defaults::GC_THRESHOLD {
-        return Ok(())
-    }
-    for vm_id_file in vmid_file_names {
-        // collective garbage collect but do not delete overlay
-        // images as they might be re-used for resume type instances.
-        // The cleanup of overlay images from resume type instances
-        // must be done by an explicit user action to avoid deleting
-        // user data in overlay images eventually preserved for later.
-        gc_meta_files(&vm_id_file, user, program_name, true).ok();
-    }
-    Ok(())
+    IO::gc_sweep(
+        &get_firecracker_ids_dir(), defaults::GC_THRESHOLD, |vm_id_file| {
+            // collective garbage collect but do not delete overlay
+            // images as they might be re-used for resume type instances.
+            // The cleanup of overlay images from resume type instances
+            // must be done by an explicit user action to avoid deleting
+            // user data in overlay images eventually preserved for later.
+            gc_meta_files(vm_id_file, user, program_name, true).ok();
+        }
+    )
 }
 
 pub fn delete_file(filename: &String, user: User) -> bool {
@@ -972,32 +2306,98 @@ pub fn umount_vm(sub_dir: &str, user: User) -> Result<(), CommandError> {
     x.into_iter().collect()
 }
 
-pub fn stream_listener(socket_path: &str) -> thread::JoinHandle<()> {
+pub fn stream_listener(
+    socket_path: &str, vmid: &str
+) -> thread::JoinHandle<Option<u8>> {
     let mut socket = String::new();
     socket.push_str(socket_path);
+    let vmid = vmid.to_string();
     let handle = move |socket: String| {
         match UnixListener::bind(socket) {
             Ok(listener) => {
-                if let Some(stream) = listener.incoming().next() {
-                    match stream {
-                        Ok(stream) => {
-                            stream_io(stream);
-                        }
-                        Err(error) => {
-                            error!("VM Connection failed: {}", error);
-                        }
+                match listener.incoming().next()? {
+                    Ok(stream) => {
+                        stream_io(stream, &vmid)
+                    }
+                    Err(error) => {
+                        error!("VM Connection failed: {}", error);
+                        None
                     }
                 }
             }
             Err(error) => {
-                error!("VM sockket listener failed: {}", error)
+                error!("VM sockket listener failed: {}", error);
+                None
             }
         }
     };
     thread::spawn(move || {handle(socket)})
 }
 
-pub fn stream_io(mut stream: UnixStream) {
+pub fn vm_process_alive(vmid: &str) -> bool {
+    /*!
+    Check if the firecracker process tracked by vmid still exists.
+    An unknown vmid(0) can't be checked and counts as alive
+    !*/
+    vmid == "0" || Path::new(&format!("/proc/{}", vmid)).exists()
+}
+
+/// Separates the exit code trailer sci sends after the command
+/// output from the output itself. The trailer is only taken into
+/// account at the end of the stream
+#[derive(Debug, Default)]
+pub struct ExitTrailer {
+    pending: Vec<u8>
+}
+
+impl ExitTrailer {
+    pub fn feed(&mut self, data: &[u8]) -> Vec<u8> {
+        /*!
+        Add data from the stream and return the part of it which
+        can't belong to the trailer and is safe to be passed on
+        !*/
+        self.pending.extend_from_slice(data);
+        let start = self.pending.len().saturating_sub(
+            defaults::SCI_EXIT_TRAILER.len() + 4
+        );
+        let keep = (start..self.pending.len())
+            .find(|index| Self::is_trailer_start(&self.pending[*index..]))
+            .unwrap_or(self.pending.len());
+        self.pending.drain(..keep).collect()
+    }
+
+    pub fn finish(&mut self) -> (Vec<u8>, Option<u8>) {
+        /*!
+        End of stream, return the remaining output and
+        the exit code from the trailer if present
+        !*/
+        let pending = std::mem::take(&mut self.pending);
+        let exit_code = pending
+            .strip_prefix(defaults::SCI_EXIT_TRAILER)
+            .and_then(|code| code.strip_suffix(b"\n"))
+            .and_then(|code| std::str::from_utf8(code).ok())
+            .and_then(|code| code.parse::<u8>().ok());
+        match exit_code {
+            Some(_) => (Vec::new(), exit_code),
+            None => (pending, None)
+        }
+    }
+
+    fn is_trailer_start(data: &[u8]) -> bool {
+        let marker = defaults::SCI_EXIT_TRAILER;
+        if data.len() <= marker.len() {
+            return marker.starts_with(data)
+        }
+        let code = data[marker.len()..].strip_suffix(b"\n").unwrap_or(
+            &data[marker.len()..]
+        );
+        data.starts_with(marker)
+            && code.len() <= 3
+            && code.iter().all(u8::is_ascii_digit)
+    }
+}
+
+pub fn stream_io(mut stream: UnixStream, vmid: &str) -> Option<u8> {
     let mut stdin = io::stdin();
     let mut stdout = io::stdout();
 
@@ -1006,6 +2406,7 @@ pub fn stream_io(mut stream: UnixStream) {
     let stdout_fd = stdout.as_raw_fd();
     // main send/recv loop
     let mut buffer = [0_u8; 100];
+    let mut trailer = ExitTrailer::default();
     loop {
         // prepare file descriptors to be watched for by select()
         let raw_fdset = std::mem::MaybeUninit::<libc::fd_set>::uninit();
@@ -1017,22 +2418,48 @@ pub fn stream_io(mut stream: UnixStream) {
         max_fd = std::cmp::max(max_fd, stdout_fd);
 
         unsafe { libc::FD_SET(stdin_fd, &mut fdset) };
-        max_fd = std::cmp::max(max_fd, stdout_fd);
+        max_fd = std::cmp::max(max_fd, stdin_fd);
 
         unsafe { libc::FD_SET(stream_fd, &mut fdset) };
         max_fd = std::cmp::max(max_fd, stream_fd);
 
+        // check the VM before select so that output sent
+        // right before it went away is still seen by select
+        let alive = vm_process_alive(vmid);
+
         // block this thread until something new happens
-        // on these file-descriptors
-        unsafe {
+        // on these file-descriptors or the timeout hits
+        let mut timeout = libc::timeval {
+            tv_sec: 0,
+            tv_usec: defaults::SELECT_TIMEOUT_MSEC * 1000
+        };
+        let ready = unsafe {
             libc::select(
                 max_fd + 1,
                 &mut fdset,
                 std::ptr::null_mut(),
                 std::ptr::null_mut(),
-                std::ptr::null_mut()
+                &mut timeout
             )
         };
+        if ready < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            if Lookup::is_debug() {
+                debug!("select failure on stream");
+            }
+            break;
+        }
+        if ready == 0 {
+            if ! alive {
+                if Lookup::is_debug() {
+                    debug!("VM {} is gone, closing stream", vmid);
+                }
+                break;
+            }
+            continue;
+        }
         // this thread is not blocked any more,
         // try to handle what happened on the file descriptors
         if unsafe { libc::FD_ISSET(stdin_fd, &fdset) } {
@@ -1068,7 +2495,7 @@ pub fn stream_io(mut stream: UnixStream) {
                     }
                     break;
                 }
-                if stdout.write_all(&buffer[0..sz_r]).is_err() {
+                if stdout.write_all(&trailer.feed(&buffer[0..sz_r])).is_err() {
                     if Lookup::is_debug() {
                         debug!("write failure on stdout");
                     }
@@ -1082,4 +2509,8 @@ pub fn stream_io(mut stream: UnixStream) {
             }
         }
     }
+    let (output, exit_code) = trailer.finish();
+    let _ = stdout.write_all(&output);
+    let _ = stdout.flush();
+    exit_code
 }
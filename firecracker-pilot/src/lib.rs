@@ -0,0 +1,142 @@
+//
+// Copyright (c) 2022 Elektrobit Automotive GmbH
+// Copyright (c) 2023 Marcus Schäfer
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+#[macro_use]
+extern crate log;
+
+#[cfg(test)]
+pub mod tests;
+
+use std::env;
+use std::process::ExitCode;
+
+use config::{config, config_file, try_config};
+use flakes::error::FlakeError;
+use flakes::flakelog::FlakeLog;
+use flakes::lookup::Lookup;
+use flakes::defaults::LOG_LEVELS;
+
+pub mod app_path;
+pub mod firecracker;
+pub mod defaults;
+pub mod config;
+
+/// Run the flake registered as program_name
+///
+/// program_name is the name or path of the flake as it would be
+/// used on the commandline, the arguments of the current process
+/// are passed along to the VM. Errors are logged and trigger
+/// the configured on_failure hook before they are returned.
+/// The flake configuration is loaded once per process, thus only
+/// one flake can be run per process
+pub fn run_flake(program_name: &str) -> Result<ExitCode, FlakeError> {
+    run_flake_with_args(program_name, env::args().skip(1).collect())
+}
+
+/// Run the flake registered as program_name with the given arguments
+///
+/// Same as run_flake but args are passed along to the VM
+/// instead of the arguments of the current process, such that
+/// the flake can be run from an embedding program. Like the
+/// flake configuration the arguments can only be set once per
+/// process
+pub fn run_flake_with_args(
+    program_name: &str, args: Vec<String>
+) -> Result<ExitCode, FlakeError> {
+    set_args(program_name, args)?;
+
+    // load config now so we can terminate early if the config is invalid
+    if let Err(error) = try_config() {
+        FlakeLog::error(error);
+        return Err(match error {
+            FlakeError::ConfigError { path, message } => FlakeError::ConfigError {
+                path: path.clone(), message: message.clone()
+            },
+            _ => FlakeError::ConfigError {
+                path: config_file(&app_path::basename(&program_name.to_string())),
+                message: error.to_string()
+            }
+        });
+    }
+    if let Some(log_level) = config().runtime().log_level {
+        if ! LOG_LEVELS.contains(&log_level) {
+            let error = FlakeError::ConfigError {
+                path: config_file(&app_path::basename(&program_name.to_string())),
+                message: format!(
+                    "Invalid log_level {}, use one of: {}",
+                    log_level, LOG_LEVELS.join(", ")
+                )
+            };
+            FlakeLog::error(&error);
+            return Err(error)
+        }
+    }
+    // past here there should be no more panics
+
+    match run() {
+        Ok(exit_code) => Ok(ExitCode::from(exit_code)),
+        Err(err) => {
+            FlakeLog::error(&err);
+            if let Some(hook) = config().runtime().on_failure {
+                err.run_failure_hook(hook);
+            }
+            Err(err)
+        },
+    }
+}
+
+/// Log level configured for the flake registered as program_name
+///
+/// Used to set up the logger prior to run_flake. Returns None
+/// if the flake config has no log_level or can't be loaded, the
+/// latter is reported by run_flake
+pub fn log_level(program_name: &str) -> Option<&'static str> {
+    set_args(program_name, env::args().skip(1).collect()).ok()?;
+    try_config().ok().and_then(|config| config.runtime().log_level)
+}
+
+fn set_args(program_name: &str, args: Vec<String>) -> Result<(), FlakeError> {
+    let args: Vec<String> = std::iter::once(program_name.to_string())
+        .chain(args)
+        .collect();
+    if ! Lookup::set_args(args.clone()) && Lookup::args() != args {
+        return Err(FlakeError::IOError {
+            kind: "InvalidInput".to_string(),
+            message: "Flake arguments can only be set once per process"
+                .to_string()
+        })
+    }
+    Ok(())
+}
+
+fn run() -> Result<u8, FlakeError> {
+    let program_path = app_path::program_abs_path()?;
+    let program_name = app_path::basename(&program_path);
+
+    let identifier = firecracker::create(&program_name)?;
+    firecracker::start(
+        &program_name,
+        identifier
+    )
+}
@@ -24,6 +24,21 @@
 //
 use crate::config::config_file;
 use crate::config::config_from_str;
+use crate::firecracker::{
+    check_stale_socket, vm_process_alive, get_jail_id, ExitTrailer,
+    parse_df_usage, overlay_needs_growth, check_extra_mount,
+    open_console_log, parse_secret_env, secret_env_preamble, check_vsock,
+    wait_for_boot, jail_file_with, jail_mounts, jail_socket_users, JailAccess
+};
+use flakes::command::MockRunner;
+use flakes::firecracker::{CacheType, FireCrackerConfig, FireCrackerMetrics};
+use flakes::error::{FlakeError, OperationError};
+use flakes::user::User;
+use flakes::lookup::Lookup;
+use std::fs;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use tempfile::tempdir;
 
 #[test]
 fn simple_config() {
@@ -34,7 +49,7 @@ fn simple_config() {
 include:
  tar: ~
 "#,
-    );
+    ).unwrap();
     assert_eq!(cfg.vm.name, "JoJo");
 }
 
@@ -50,12 +65,450 @@ vm:
  name: Dio
  host_app_path: /other
 "#,
-    );
+    ).unwrap();
     assert_eq!(cfg.vm.name, "Dio");
 }
 
+#[test]
+fn include_oci_config() {
+    let cfg = config_from_str(
+            r#"vm:
+ name: JoJo
+ host_app_path: /myapp
+include:
+ tar: ~
+ oci:
+  - registry.opensuse.org/some/app
+"#,
+    ).unwrap();
+    assert_eq!(cfg.oci_images(), vec!["registry.opensuse.org/some/app"]);
+}
+
 #[test]
 fn test_program_config_file() {
     let config_file = config_file("app");
     assert_eq!("/usr/share/flakes/app.yaml", config_file);
 }
+
+#[test]
+fn stale_socket_without_vm() {
+    let tmp = tempdir().unwrap();
+    let socket = tmp.path().join("sci_cmd_app.sock");
+    let vm_id_file = tmp.path().join("app.vmid");
+    // the socket file stays behind when the listener is gone
+    drop(UnixListener::bind(&socket).unwrap());
+    fs::write(&vm_id_file, "0").unwrap();
+
+    let result = check_stale_socket(
+        socket.to_str().unwrap(), vm_id_file.to_str().unwrap(), User::ROOT
+    );
+    assert!(matches!(
+        result,
+        Err(FlakeError::OperationError(OperationError::StaleSocket(_)))
+    ));
+    assert!(!socket.exists());
+}
+
+#[test]
+fn live_socket_is_not_stale() {
+    let tmp = tempdir().unwrap();
+    let socket = tmp.path().join("sci_cmd_app.sock");
+    let _listener = UnixListener::bind(&socket).unwrap();
+
+    let result = check_stale_socket(
+        socket.to_str().unwrap(), "/does/not/exist.vmid", User::ROOT
+    );
+    assert!(result.is_ok());
+    assert!(socket.exists());
+}
+
+#[test]
+fn default_template_matches_shipped_template() {
+    let shipped: FireCrackerConfig = serde_json::from_str(
+        include_str!("../template/firecracker.json")
+    ).unwrap();
+    assert_eq!(
+        serde_json::to_value(FireCrackerConfig::default()).unwrap(),
+        serde_json::to_value(shipped).unwrap()
+    );
+}
+
+#[test]
+fn template_errors_are_explained() {
+    let tmp = tempdir().unwrap();
+    let template = tmp.path().join("firecracker.json");
+    let template = template.to_str().unwrap();
+
+    match FireCrackerConfig::from_template(template) {
+        Err(FlakeError::ConfigError { path, message }) => {
+            assert_eq!(path, template);
+            assert!(message.contains("not found"));
+            assert!(message.contains("flake-ctl firecracker init-template"));
+        },
+        _ => panic!("missing template not detected")
+    }
+
+    fs::write(template, "{}").unwrap();
+    match FireCrackerConfig::from_template(template) {
+        Err(FlakeError::ConfigError { message, .. }) => {
+            assert!(message.contains("invalid firecracker template"));
+        },
+        _ => panic!("invalid template not detected")
+    }
+
+    fs::write(
+        template,
+        serde_json::to_string(&FireCrackerConfig::default()).unwrap()
+    ).unwrap();
+    assert!(FireCrackerConfig::from_template(template).is_ok());
+}
+
+#[test]
+fn metrics_section_only_when_set() {
+    let mut firecracker_config: FireCrackerConfig = serde_json::from_str(
+        include_str!("../template/firecracker.json")
+    ).unwrap();
+    assert!(firecracker_config.metrics.is_none());
+    let json = serde_json::to_string(&firecracker_config).unwrap();
+    assert!(!json.contains("metrics"));
+
+    firecracker_config.metrics = Some(FireCrackerMetrics {
+        metrics_path: "/var/log/firecracker/app.metrics".to_string()
+    });
+    let json = serde_json::to_string(&firecracker_config).unwrap();
+    assert!(json.contains(
+        r#""metrics":{"metrics_path":"/var/log/firecracker/app.metrics"}"#
+    ));
+}
+
+#[test]
+fn test_vm_process_alive() {
+    assert!(vm_process_alive("0"));
+    assert!(vm_process_alive(&std::process::id().to_string()));
+    assert!(! vm_process_alive("999999999"));
+}
+
+#[test]
+fn jailer_config() {
+    let cfg = config_from_str(
+            r#"vm:
+ name: JoJo
+ host_app_path: /myapp
+ runtime:
+  runas: root
+  resume: false
+  firecracker:
+   rootfs_image_path: /var/lib/firecracker/images/JoJo/rootfs
+   kernel_image_path: /var/lib/firecracker/images/JoJo/kernel
+   boot_args: []
+   use_jailer: true
+   jailer_uid: 123
+   jailer_gid: 100
+   jailer_exec_file: /opt/firecracker/bin/firecracker
+include:
+ tar: ~
+"#,
+    ).unwrap();
+    let engine_section = cfg.runtime().firecracker;
+    assert!(engine_section.use_jailer);
+    assert_eq!(engine_section.jailer_uid, Some(123));
+    assert_eq!(engine_section.jailer_gid, Some(100));
+    assert!(engine_section.jailer_chroot_base.is_none());
+    assert_eq!(
+        engine_section.jailer_exec_file,
+        Some("/opt/firecracker/bin/firecracker")
+    );
+}
+
+#[test]
+fn test_jail_file() {
+    let root = "/srv/jailer/firecracker/app/root";
+
+    // read-only files are linked, copied across filesystems
+    let runner = MockRunner::new().respond(0, "", "");
+    assert_eq!(
+        jail_file_with(&runner, "/images/kernel", root, "kernel", JailAccess::ReadOnly)
+            .unwrap(),
+        "/kernel"
+    );
+    assert_eq!(runner.calls().len(), 1);
+    assert!(runner.calls()[0].ends_with(&format!("ln -f /images/kernel {}/kernel", root)));
+    let runner = MockRunner::new()
+        .respond(1, "", "Invalid cross-device link")
+        .respond(0, "", "");
+    jail_file_with(&runner, "/images/kernel", root, "kernel", JailAccess::ReadOnly)
+        .unwrap();
+    assert!(runner.calls()[1].ends_with(&format!("cp -f /images/kernel {}/kernel", root)));
+
+    // writable files are bind mounted and keep their owner
+    let runner = MockRunner::new()
+        .respond(0, "", "")
+        .respond(0, "", "")
+        .respond(0, "", "");
+    assert_eq!(
+        jail_file_with(&runner, "/overlay/app", root, "overlay", JailAccess::Writable(123))
+            .unwrap(),
+        "/overlay"
+    );
+    let calls = runner.calls();
+    assert!(calls[0].ends_with(&format!("touch {}/overlay", root)));
+    assert!(calls[1].ends_with(&format!("mount --bind /overlay/app {}/overlay", root)));
+    assert!(calls[2].ends_with("setfacl -m u:123:rw /overlay/app"));
+    assert!(! calls.iter().any(|call| call.contains("chown")));
+
+    // a failed bind mount is an error
+    let runner = MockRunner::new()
+        .respond(0, "", "")
+        .respond(32, "", "mount failed");
+    assert!(
+        jail_file_with(&runner, "/overlay/app", root, "overlay", JailAccess::Writable(123))
+            .is_err()
+    );
+
+    // owned files are copies handed over to the jailer user
+    let runner = MockRunner::new()
+        .respond(0, "", "")
+        .respond(0, "", "");
+    jail_file_with(&runner, "/tmp/config", root, "firecracker.json", JailAccess::Owned(123, 100))
+        .unwrap();
+    let calls = runner.calls();
+    assert!(calls[0].ends_with(&format!("cp -f /tmp/config {}/firecracker.json", root)));
+    assert!(calls[1].ends_with(&format!("chown 123:100 {}/firecracker.json", root)));
+}
+
+#[test]
+fn test_jail_mounts() {
+    let jail_dir = "/srv/jailer/firecracker/app";
+    let mounts = "\
+/dev/sda1 / ext4 rw 0 0
+/dev/sda1 /srv/jailer/firecracker/app/root/overlay ext4 rw 0 0
+/dev/sda1 /srv/jailer/firecracker/app2/root/overlay ext4 rw 0 0
+/dev/sda1 /srv/jailer/firecracker/app/root/my\\040file ext4 rw 0 0
+";
+    assert_eq!(jail_mounts(mounts, jail_dir), vec![
+        "/srv/jailer/firecracker/app/root/overlay",
+        "/srv/jailer/firecracker/app/root/my file"
+    ]);
+}
+
+#[test]
+fn test_jail_socket_users() {
+    let uid = User::default().get_user_id();
+    let users = jail_socket_users("root");
+    assert!(! users.contains(&"root".to_string()));
+    assert_eq!(users.contains(&uid), uid != "0");
+    assert_eq!(jail_socket_users("#0"), jail_socket_users("root"));
+    assert!(jail_socket_users("#4711").contains(&"4711".to_string()));
+    assert!(jail_socket_users("joe").contains(&"joe".to_string()));
+}
+
+#[test]
+fn cache_type_config() {
+    let cfg = config_from_str(
+            r#"vm:
+ name: JoJo
+ host_app_path: /myapp
+ runtime:
+  runas: root
+  firecracker:
+   rootfs_image_path: /var/lib/firecracker/images/JoJo/rootfs
+   kernel_image_path: /var/lib/firecracker/images/JoJo/kernel
+   boot_args: []
+   cache_type: Unsafe
+include:
+ tar: ~
+"#,
+    ).unwrap();
+    assert_eq!(cfg.runtime().firecracker.cache_type, Some(CacheType::Unsafe));
+    assert_eq!(CacheType::default().to_string(), "Writeback");
+    for name in CacheType::NAMES {
+        assert_eq!(name.parse::<CacheType>().unwrap().to_string(), name);
+    }
+    assert!("writeback".parse::<CacheType>().is_err());
+}
+
+#[test]
+fn test_check_vsock() {
+    let tmp = tempdir().unwrap();
+    let device = tmp.path().join("vhost-vsock");
+    let module = tmp.path().join("vhost_vsock");
+    let device = device.to_str().unwrap();
+    let module = module.to_str().unwrap();
+
+    let error = check_vsock(device, module).unwrap_err().to_string();
+    assert!(error.contains("modprobe vhost_vsock"));
+
+    fs::create_dir(module).unwrap();
+    let error = check_vsock(device, module).unwrap_err().to_string();
+    assert!(error.contains(device));
+
+    fs::write(device, "").unwrap();
+    assert!(check_vsock(device, module).is_ok());
+}
+
+#[test]
+fn timeout_error_names_operation() {
+    let error = FlakeError::Timeout {
+        operation: "vsock connect".to_string(),
+        retries: 60,
+        elapsed: std::time::Duration::from_millis(61500)
+    };
+    assert_eq!(error.exit_code(), 1);
+    assert_eq!(
+        error.to_string(),
+        "Timeout in vsock connect after 60 retries (61.5s), \
+        for details recall with PILOT_DEBUG=1"
+    );
+}
+
+#[test]
+fn test_get_jail_id() {
+    assert_eq!(get_jail_id("myapp@vm_1.x"), "myapp-vm-1-x");
+    assert_eq!(get_jail_id(&"a".repeat(80)).len(), 64);
+}
+
+#[test]
+fn initrd_path_only_when_set() {
+    let mut firecracker_config: FireCrackerConfig = serde_json::from_str(
+        include_str!("../template/firecracker.json")
+    ).unwrap();
+    assert!(firecracker_config.boot_source.initrd_path.is_empty());
+    let json = serde_json::to_string(&firecracker_config).unwrap();
+    assert!(!json.contains("initrd_path"));
+
+    firecracker_config.boot_source.initrd_path =
+        "/var/lib/firecracker/images/app/initrd".to_string();
+    let json = serde_json::to_string(&firecracker_config).unwrap();
+    assert!(json.contains(
+        r#""initrd_path":"/var/lib/firecracker/images/app/initrd""#
+    ));
+}
+
+#[test]
+fn test_exit_trailer() {
+    let mut trailer = ExitTrailer::default();
+    let mut output = trailer.feed(b"hello\n\0sci-e");
+    output.extend(trailer.feed(b"xit:4"));
+    output.extend(trailer.feed(b"2\n"));
+    assert_eq!(output, b"hello\n");
+    assert_eq!(trailer.finish(), (Vec::new(), Some(42)));
+
+    // output which only looks like a trailer start is passed on
+    let mut trailer = ExitTrailer::default();
+    let mut output = trailer.feed(b"data\0sci");
+    assert_eq!(output, b"data");
+    output.extend(trailer.feed(b" more"));
+    assert_eq!(output, b"data\0sci more");
+    assert_eq!(trailer.finish(), (Vec::new(), None));
+
+    // no trailer from an older sci
+    let mut trailer = ExitTrailer::default();
+    assert_eq!(trailer.feed(b"prompt $ "), b"prompt $ ");
+    let mut trailer_cut = ExitTrailer::default();
+    trailer_cut.feed(b"\0sci-exit:");
+    assert_eq!(trailer_cut.finish(), (b"\0sci-exit:".to_vec(), None));
+    assert_eq!(trailer.finish(), (Vec::new(), None));
+}
+
+#[test]
+fn test_overlay_autogrow() {
+    let output = "Type 1B-blocks Avail\next2 1000000 50000\n";
+    let (fstype, size, avail) = parse_df_usage(output).unwrap();
+    assert_eq!(fstype, "ext2");
+    assert_eq!((size, avail), (1000000, 50000));
+    assert!(overlay_needs_growth(size, avail));
+    assert!(! overlay_needs_growth(size, 100000));
+    assert!(parse_df_usage("Type 1B-blocks Avail\n").is_none());
+}
+
+#[test]
+fn test_check_extra_mount() {
+    assert!(check_extra_mount("cgroup2:none:/sys/fs/cgroup").is_ok());
+    assert!(check_extra_mount("hugetlbfs:none:/dev/hugepages").is_ok());
+    assert!(check_extra_mount("bpf:/sys/fs/bpf").is_err());
+    assert!(check_extra_mount("bpf:bpf:sys/fs/bpf").is_err());
+    assert!(check_extra_mount("tmpfs:none:/a,b").is_err());
+    assert!(check_extra_mount("tmpfs:none:/a b").is_err());
+}
+
+#[test]
+fn console_log_is_appended() {
+    let tmp = tempdir().unwrap();
+    let console_log = tmp.path().join("app.console");
+    let console_log = console_log.to_str().unwrap();
+    open_console_log(console_log).unwrap().write_all(b"boot\n").unwrap();
+    open_console_log(console_log).unwrap().write_all(b"boot\n").unwrap();
+    assert_eq!(fs::read_to_string(console_log).unwrap(), "boot\nboot\n");
+
+    let missing_dir = tmp.path().join("missing/app.console");
+    match open_console_log(missing_dir.to_str().unwrap()) {
+        Err(FlakeError::IOError { kind, message }) => {
+            assert_eq!(kind, "NotFound");
+            assert!(message.contains("console_log"));
+        },
+        _ => panic!("missing console_log directory not detected")
+    }
+}
+
+#[test]
+fn run_args_survive_kernel_cmdline() {
+    let args: Vec<String> = [
+        "/usr/bin/app", "-l", "--name=some value", "it's", "back\\slash",
+        "", "  ", "$HOME", "a;b|c&d", "%silent", "@name", "tab\there", "ü"
+    ].iter().map(|arg| arg.to_string()).collect();
+    let run = Lookup::run_cmdline_from(
+        &args, vec!["/usr/bin/app".to_string()], true
+    );
+    let cmdline = run.join(" ");
+    // the kernel ends a quoted parameter at the next double quote
+    assert!(! cmdline.contains('"'));
+    let mut expected = vec!["/usr/bin/app"];
+    expected.extend([
+        "-l", "--name=some value", "it's", "back\\slash", "", "  ",
+        "$HOME", "a;b|c&d", "tab\there", "ü"
+    ]);
+    assert_eq!(shell_words::split(&cmdline).unwrap(), expected);
+
+    // double quotes can't be escaped for the kernel
+    assert!(Lookup::quote_for_kernel_cmdline("say \"hi\"").contains('"'));
+}
+
+#[test]
+fn test_secret_env() {
+    assert_eq!(
+        parse_secret_env("DB_KEY=file:/etc/db.key").unwrap(),
+        ("DB_KEY", "file", "/etc/db.key")
+    );
+    assert_eq!(
+        parse_secret_env("_TOKEN=keyring:app_token").unwrap(),
+        ("_TOKEN", "keyring", "app_token")
+    );
+    assert!(parse_secret_env("DB_KEY").is_err());
+    assert!(parse_secret_env("DB_KEY=file:db.key").is_err());
+    assert!(parse_secret_env("DB_KEY=env:HOME").is_err());
+    assert!(parse_secret_env("1KEY=file:/etc/db.key").is_err());
+    assert!(parse_secret_env("DB-KEY=file:/etc/db.key").is_err());
+
+    let mut secret = tempfile::NamedTempFile::new().unwrap();
+    secret.write_all(b"s\nk\n").unwrap();
+    let secret_env = format!("DB_KEY=file:{}", secret.path().display());
+    assert_eq!(
+        secret_env_preamble(&[&secret_env]).unwrap(),
+        "SECRET_ENV DB_KEY 730a6b\n"
+    );
+}
+
+#[test]
+fn test_wait_for_boot() {
+    let tmp = tempdir().unwrap();
+    let socket = tmp.path().join("sci_cmd_never");
+    let socket = socket.to_str().unwrap();
+
+    // a socket which never appears fails after the boot timeout
+    // and does not spin through the connection retries
+    let started = std::time::Instant::now();
+    assert!(!wait_for_boot(socket, std::time::Duration::from_millis(300)));
+    let elapsed = started.elapsed();
+    assert!(elapsed >= std::time::Duration::from_millis(300));
+    assert!(elapsed < std::time::Duration::from_secs(5));
+}
@@ -24,9 +24,10 @@
 //
 use crate::{app_config, defaults, firecracker, podman};
 use glob::glob;
+use std::collections::BTreeMap;
 use std::fs;
 use std::os::unix::fs::symlink;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use flakes::config::get_flakes_dir;
 
 pub fn register(app: Option<&String>, target: Option<&String>, engine: &str) -> bool {
@@ -50,6 +51,13 @@ pub fn register(app: Option<&String>, target: Option<&String>, engine: &str) ->
             return false;
         }
     }
+    if let Some(owner) = registered_by_other(host_app_path) {
+        error!(
+            "Application {} is already registered by flake {}",
+            host_app_path, owner
+        );
+        return false;
+    }
     info!("Registering application: {}", host_app_path);
 
     // host_app_path -> pointing to engine
@@ -89,20 +97,173 @@ pub fn register(app: Option<&String>, target: Option<&String>, engine: &str) ->
     true
 }
 
-#[allow(clippy::too_many_arguments)]
+pub fn host_app_paths_in(flakes_dir: &str) -> BTreeMap<String, Vec<String>> {
+    /*!
+    Map each host path registered in flakes_dir, that is the
+    host_app_path and the aliases of an app, to the names of
+    all apps claiming it
+    !*/
+    let mut host_app_paths: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for app_name in app_names_in(flakes_dir) {
+        let config_file = format!("{}/{}.yaml", flakes_dir, app_name);
+        if let Ok(app_conf) =
+            app_config::AppConfig::init_from_file(Path::new(&config_file))
+        {
+            let paths = app_conf.host_app_path().map(str::to_string)
+                .into_iter().chain(app_conf.aliases());
+            for path in paths {
+                host_app_paths.entry(path).or_default().push(app_name.clone());
+            }
+        }
+    }
+    host_app_paths
+}
+
+pub fn registered_by_other(host_app_path: &String) -> Option<String> {
+    /*!
+    Name of another app which already owns the given host path.
+    Registering a path owned by its own app name is a replace
+    of this app and no conflict
+    !*/
+    let app_basename = basename(host_app_path);
+    host_app_paths_in(&get_flakes_dir())
+        .remove(host_app_path)
+        .and_then(|owners| {
+            owners.into_iter().find(|owner| *owner != app_basename)
+        })
+}
+
+pub fn register_aliases(app: &String, aliases: &[String], engine: &str) -> bool {
+    /*!
+    Register additional host paths for an already registered app.
+
+    Each alias is an app symlink pointing to the engine launcher.
+    As the pilot reads the flake config by the name it was called
+    with, the alias config file and directory are symlinks to
+    the ones of the app. All aliases are checked before any link
+    is created. Only the aliases created by this call are recorded
+    in the app config such that a rollback via remove never
+    touches paths owned by something else.
+    !*/
+    let app_basename = basename(app);
+    let config_file = format!("{}/{}.yaml", get_flakes_dir(), &app_basename);
+    let mut app_conf = match app_config::AppConfig::init_from_file(
+        Path::new(&config_file)
+    ) {
+        Ok(app_conf) => app_conf,
+        Err(error) => {
+            error!("Failed to load flake config: {}", error);
+            return false
+        }
+    };
+    if app_conf.container.is_none() {
+        error!("Aliases are only supported for container flakes");
+        return false
+    }
+    let mut created: Vec<String> = Vec::new();
+    let mut ok = check_aliases(app, aliases);
+    if ok {
+        for alias in aliases {
+            info!("Registering alias: {} for {}", alias, app);
+            if ! create_alias(alias, &app_basename, engine) {
+                ok = false;
+                break
+            }
+            created.push(alias.to_string());
+        }
+    }
+    app_conf.container.as_mut().unwrap().aliases =
+        Some(created).filter(|created| ! created.is_empty());
+    if let Err(error) = app_conf.save(Path::new(&config_file)) {
+        error!("Failed to update flake config {}: {}", config_file, error);
+        return false
+    }
+    ok
+}
+
+fn check_aliases(app: &String, aliases: &[String]) -> bool {
+    /*!
+    Check that all aliases are absolute, distinct host paths
+    which are not in use, neither as host path nor as config
+    of another flake
+    !*/
+    let mut basenames: Vec<String> = vec![basename(app)];
+    for alias in aliases {
+        if !alias.starts_with('/') {
+            error!(
+                "Alias {:?} must be specified with an absolute path", alias
+            );
+            return false
+        }
+        if let Some(owner) = registered_by_other(alias) {
+            error!("Alias {} is already registered by flake {}", alias, owner);
+            return false
+        }
+        if app_path_state(alias) != AppPathState::Free {
+            error!("Alias path {} already exists", alias);
+            return false
+        }
+        let alias_basename = basename(alias);
+        if basenames.contains(&alias_basename) {
+            error!("Alias {} clashes with the name of another alias", alias);
+            return false
+        }
+        for config in [
+            format!("{}/{}.yaml", get_flakes_dir(), alias_basename),
+            format!("{}/{}.d", get_flakes_dir(), alias_basename)
+        ] {
+            if fs::symlink_metadata(&config).is_ok() {
+                error!("Alias {} clashes with existing {}", alias, config);
+                return false
+            }
+        }
+        basenames.push(alias_basename);
+    }
+    true
+}
+
+fn create_alias(alias: &str, app_basename: &str, engine: &str) -> bool {
+    /*!
+    Create the alias link and its config links. On failure the
+    links created so far are deleted again
+    !*/
+    let alias_dir = Path::new(alias).parent().unwrap();
+    if let Err(error) = fs::create_dir_all(alias_dir) {
+        error!("Failed creating: {:?}: {:?}", alias_dir, error);
+        return false
+    }
+    let alias_basename = basename(&alias.to_string());
+    let links = [
+        (engine.to_string(), alias.to_string()),
+        (
+            format!("{}.yaml", app_basename),
+            format!("{}/{}.yaml", get_flakes_dir(), alias_basename)
+        ),
+        (
+            format!("{}.d", app_basename),
+            format!("{}/{}.d", get_flakes_dir(), alias_basename)
+        ),
+    ];
+    for (index, (target, link_name)) in links.iter().enumerate() {
+        if let Err(error) = symlink(target, link_name) {
+            error!(
+                "Error while creating symlink \"{} -> {}\": {:?}",
+                link_name, target, error
+            );
+            for (_, link_name) in &links[..index] {
+                let _ = fs::remove_file(link_name);
+            }
+            return false
+        }
+    }
+    true
+}
+
 pub fn create_container_config(
     container: &str,
     app: Option<&String>,
     target: Option<&String>,
-    base: Option<&String>,
-    check_host_dependencies: bool,
-    layers: Option<Vec<String>>,
-    includes_tar: Option<Vec<String>>,
-    includes_path: Option<Vec<String>>,
-    resume: bool,
-    attach: bool,
-    run_as: Option<&String>,
-    opts: Option<Vec<String>>,
+    options: &app_config::ContainerOptions,
 ) -> bool {
     /*!
     Create app configuration for the container engine.
@@ -111,7 +272,7 @@ pub fn create_container_config(
     containing the required information to launch the
     application inside of the container engine.
     !*/
-    if base.is_none() && layers.is_some() {
+    if options.base.is_none() && options.layers.is_some() {
         error!("Layer(s) specified without a base");
         return false;
     }
@@ -130,15 +291,7 @@ pub fn create_container_config(
         container,
         target_app_path,
         host_app_path,
-        base,
-        check_host_dependencies,
-        layers,
-        includes_tar,
-        includes_path,
-        resume,
-        attach,
-        run_as,
-        opts,
+        options,
     ) {
         Ok(_) => true,
         Err(error) => {
@@ -158,11 +311,16 @@ pub fn create_vm_config(
     target: Option<&String>,
     run_as: Option<&String>,
     overlay_size: Option<&String>,
+    cache_type: Option<&String>,
+    initrd: Option<&String>,
+    no_initrd: bool,
     no_net: bool,
     resume: bool,
     force_vsock: bool,
+    boot_timeout: Option<u64>,
     includes_tar: Option<Vec<String>>,
     includes_path: Option<Vec<String>>,
+    includes_oci: Option<Vec<String>>,
 ) -> bool {
     /*!
     Create app configuration for the firecracker engine.
@@ -187,11 +345,16 @@ pub fn create_vm_config(
         host_app_path,
         run_as,
         overlay_size,
+        cache_type,
+        initrd,
+        no_initrd,
         no_net,
         resume,
         force_vsock,
+        boot_timeout,
         includes_tar,
         includes_path,
+        includes_oci,
     ) {
         Ok(_) => true,
         Err(error) => {
@@ -251,6 +414,14 @@ pub fn remove(app: &str, engine: &str, silent: bool) -> bool {
     let app_basename = basename(&app.to_string());
     let config_file = format!("{}/{}.yaml", get_flakes_dir(), &app_basename);
     let app_config_dir = format!("{}/{}.d", get_flakes_dir(), &app_basename);
+    // remove alias links
+    if let Ok(app_conf) =
+        app_config::AppConfig::init_from_file(Path::new(&config_file))
+    {
+        for alias in app_conf.aliases() {
+            remove_alias(&alias, &app_basename, engine, silent);
+        }
+    }
     if Path::new(&config_file).exists() {
         match fs::remove_file(&config_file) {
             Ok(_) => {}
@@ -279,6 +450,56 @@ pub fn remove(app: &str, engine: &str, silent: bool) -> bool {
     true
 }
 
+fn remove_alias(alias: &String, app_basename: &str, engine: &str, silent: bool) {
+    /*!
+    Delete alias link and its config links. The alias belongs
+    to the given app only if its config link points to the app
+    config, otherwise nothing is deleted. Only links pointing
+    to the engine and to the config of the app are deleted
+    !*/
+    let alias_basename = basename(alias);
+    let alias_config = format!("{}/{}.yaml", get_flakes_dir(), alias_basename);
+    if fs::read_link(&alias_config).ok()
+        != Some(PathBuf::from(format!("{}.yaml", app_basename)))
+    {
+        if !silent {
+            warn!("Alias {} is not registered for {}", alias, app_basename);
+        }
+        return
+    }
+    let links = [
+        (alias.to_string(), engine.to_string()),
+        (
+            format!("{}/{}.yaml", get_flakes_dir(), alias_basename),
+            format!("{}.yaml", app_basename)
+        ),
+        (
+            format!("{}/{}.d", get_flakes_dir(), alias_basename),
+            format!("{}.d", app_basename)
+        ),
+    ];
+    for (link_name, expected) in links.iter() {
+        match fs::read_link(link_name) {
+            Ok(target) => {
+                if target != Path::new(expected) {
+                    if !silent {
+                        error!("Symlink not pointing to {}: {}", expected, link_name);
+                    }
+                    continue
+                }
+                if let Err(error) = fs::remove_file(link_name) {
+                    if !silent {
+                        error!("Error removing alias link: {}: {:?}", link_name, error);
+                    }
+                }
+            },
+            Err(_) => {
+                // not registered or already removed
+            }
+        }
+    }
+}
+
 pub fn basename(program_path: &String) -> String {
     /*!
     Get basename from given program path
@@ -298,19 +519,64 @@ pub fn app_names() -> Vec<String> {
     /*!
     Read all flake config files
     !*/
+    app_names_in(&get_flakes_dir())
+}
+
+pub fn app_names_in(flakes_dir: &str) -> Vec<String> {
+    /*!
+    Names of all apps registered in flakes_dir. Only regular
+    NAME.yaml files holding a container or vm section are taken
+    into account. The sections are not validated further such
+    that configs of an older schema are still listed and can
+    be migrated. Alias configs, which are symlinks, and all
+    other files are skipped
+    !*/
     let mut flakes: Vec<String> = Vec::new();
-    let glob_pattern = format!("{}/*.yaml", get_flakes_dir());
+    let glob_pattern = format!("{}/*.yaml", flakes_dir);
     for config_file in glob(&glob_pattern).unwrap() {
         match config_file {
             Ok(filepath) => {
-                let base_config_file = basename(&filepath.into_os_string().into_string().unwrap());
-                match base_config_file.split('.').next() {
-                    Some(value) => {
-                        let mut app_name = String::new();
-                        app_name.push_str(value);
-                        flakes.push(app_name);
+                if filepath.is_symlink() {
+                    // config of an app alias or dangling link
+                    continue
+                }
+                if ! filepath.is_file() {
+                    debug!("Ignoring non file: {}", filepath.display());
+                    continue
+                }
+                let app_name = match filepath.file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_suffix(".yaml"))
+                {
+                    Some(app_name) if ! app_name.is_empty() => app_name,
+                    _ => {
+                        debug!(
+                            "Ignoring invalid config_file name: {}",
+                            filepath.display()
+                        );
+                        continue
                     }
-                    None => error!("Ignoring invalid config_file format: {}", base_config_file),
+                };
+                let config: Result<serde_yaml::Value, String> =
+                    fs::read_to_string(&filepath)
+                        .map_err(|error| error.to_string())
+                        .and_then(|data| serde_yaml::from_str(&data)
+                            .map_err(|error| error.to_string())
+                        );
+                match config {
+                    Ok(config) if ["container", "vm"].iter().any(
+                        |engine| config.get(engine)
+                            .is_some_and(|section| ! section.is_null())
+                    ) => {
+                        flakes.push(app_name.to_string());
+                    },
+                    Ok(_) => debug!(
+                        "Ignoring config_file without engine: {}",
+                        filepath.display()
+                    ),
+                    Err(error) => debug!(
+                        "Ignoring invalid config_file: {}", error
+                    )
                 }
             }
             Err(error) => error!("Error while traversing flakes folder: {:?}", error),
@@ -325,25 +591,77 @@ pub fn purge(app: &str, engine: &str) {
     registrations and its connected resources for the specified app
     !*/
     if engine == defaults::PODMAN_PILOT {
-        podman::purge_container(app)
+        podman::purge_container(app, false, false);
     }
     if engine == defaults::FIRECRACKER_PILOT {
         firecracker::purge_vm(app)
     }
 }
 
-pub fn init(app: Option<&String>) -> bool {
+#[derive(Debug, PartialEq, Eq)]
+pub enum AppPathState {
+    /// Nothing exists at the app path
+    Free,
+    /// The app path is a symlink to one of the pilots
+    Flake,
+    /// The app path is taken by a file not managed by flake-ctl
+    Occupied,
+}
+
+pub fn app_path_state(app: &str) -> AppPathState {
+    /*!
+    Lookup what exists at the given host app path
+    !*/
+    if fs::symlink_metadata(app).is_err() {
+        return AppPathState::Free
+    }
+    match fs::read_link(app) {
+        Ok(target) if [defaults::PODMAN_PILOT, defaults::FIRECRACKER_PILOT]
+            .iter().any(|pilot| target == Path::new(pilot)) =>
+        {
+            AppPathState::Flake
+        },
+        _ => AppPathState::Occupied
+    }
+}
+
+pub fn init(app: Option<&String>, force: bool) -> bool {
     /*!
     Create required directory structure.
 
     Symlink references to apps will be stored in get_flakes_dir()
     The init method makes sure to create this directory unless it
     already exists.
+
+    An existing app path is only replaced if force is set, such
+    that a registration does not clobber e.g. a system tool
     !*/
     let mut status = true;
-    if app.is_some() && Path::new(&app.unwrap()).exists() {
-        error!("App path {} already exists", app.unwrap());
-        return false;
+    if let Some(app) = app {
+        let conflict = match app_path_state(app) {
+            AppPathState::Free => None,
+            AppPathState::Flake => Some(
+                format!("App {} is already registered", app)
+            ),
+            AppPathState::Occupied => Some(format!(
+                "App path {} already exists and is not a flake", app
+            ))
+        };
+        if let Some(conflict) = conflict {
+            if let Some(owner) = registered_by_other(app) {
+                error!("{} by flake {}", conflict, owner);
+                return false;
+            }
+            if ! force {
+                error!("{}, use --force to replace it", conflict);
+                return false;
+            }
+            warn!("{}, replacing it", conflict);
+            if let Err(error) = fs::remove_file(app) {
+                error!("Failed to remove {}: {:?}", app, error);
+                return false;
+            }
+        }
     }
     let mut flake_dir = String::new();
     match fs::read_link(get_flakes_dir()) {
@@ -22,20 +22,32 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 //
+use std::fs;
 use std::io::{Error, ErrorKind};
+use std::os::unix::fs::chown;
 use std::path::Path;
+use users::get_user_by_name;
 use serde::{Serialize, Deserialize};
-use serde_yaml::{self};
+use serde_yaml::{self, Mapping};
 use crate::defaults;
+use crate::podman;
+use flakes::error::FlakeError;
+use flakes::container::Container;
+use flakes::config::get_podman_storage_driver;
+use flakes::firecracker::CacheType;
 
 type GenericError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
-// AppConfig represents application yaml configuration
+// AppConfig represents application yaml configuration. Keys
+// not modelled here are kept in the extra maps such that a
+// config written back by save keeps all settings of the flake
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub include: AppInclude,
     pub container: Option<AppContainer>,
     pub vm: Option<AppFireCracker>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -46,19 +58,67 @@ pub struct AppContainer {
     pub base_container: Option<String>,
     pub check_host_dependencies: bool,
     pub layers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_on_remove: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_volumes: Option<Vec<String>>,
     pub runtime: Option<AppContainerRuntime>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppContainerRuntime {
     pub runas: Option<String>,
     pub resume: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart_policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cap_add: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cap_drop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_size: Option<String>,
     pub attach: Option<bool>,
     pub podman: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppInclude {
     pub tar: Option<Vec<String>>,
     pub path: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oci: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// Settings of a container flake registration, unset values
+/// keep the defaults of the container template
+#[derive(Debug, Default)]
+pub struct ContainerOptions {
+    pub base: Option<String>,
+    pub check_host_dependencies: bool,
+    pub layers: Option<Vec<String>>,
+    pub platform: Option<String>,
+    pub includes_tar: Option<Vec<String>>,
+    pub includes_path: Option<Vec<String>>,
+    pub resume: bool,
+    pub restart_policy: Option<String>,
+    pub cap_add: Option<Vec<String>>,
+    pub cap_drop: Option<Vec<String>>,
+    pub attach: bool,
+    pub run_as: Option<String>,
+    pub opts: Option<Vec<String>>,
+    pub labels: Option<Vec<String>>,
+    pub storage_size: Option<String>,
+    pub stop_on_remove: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -69,6 +129,8 @@ pub struct AppFireCracker {
     pub base_vm: Option<String>,
     pub layers: Option<Vec<String>>,
     pub runtime: Option<AppFireCrackerRuntime>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppFireCrackerRuntime {
@@ -76,6 +138,8 @@ pub struct AppFireCrackerRuntime {
     pub resume: Option<bool>,
     pub force_vsock: Option<bool>,
     pub firecracker: Option<AppFireCrackerEngine>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppFireCrackerEngine {
@@ -87,28 +151,32 @@ pub struct AppFireCrackerEngine {
     pub mem_size_mib: Option<i32>,
     pub vcpu_count: Option<i32>,
     pub cache_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_timeout_sec: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 
 impl AppConfig {
-    #[allow(clippy::too_many_arguments)]
     pub fn save_container(
         config_file: &Path,
         container: &str,
         target_app_path: &str,
         host_app_path: &str,
-        base: Option<&String>,
-        check_host_dependencies: bool,
-        layers: Option<Vec<String>>,
-        includes_tar: Option<Vec<String>>,
-        includes_path: Option<Vec<String>>,
-        resume: bool,
-        attach: bool,
-        run_as: Option<&String>,
-        opts: Option<Vec<String>>,
+        options: &ContainerOptions,
     ) -> Result<(), GenericError> {
         /*!
         save stores an AppConfig to the given file
         !*/
+        let ContainerOptions {
+            base, check_host_dependencies, layers, platform,
+            includes_tar, includes_path, resume, restart_policy,
+            cap_add, cap_drop, attach, run_as, opts, labels,
+            storage_size, stop_on_remove
+        } = options;
+        let (check_host_dependencies, resume, attach, stop_on_remove) = (
+            *check_host_dependencies, *resume, *attach, *stop_on_remove
+        );
         let template = std::fs::File::open(defaults::FLAKE_TEMPLATE_CONTAINER)
             .unwrap_or_else(|_| panic!("Failed to open {}", defaults::FLAKE_TEMPLATE_CONTAINER));
         let mut yaml_config: AppConfig =
@@ -131,9 +199,19 @@ impl AppConfig {
                 layers.as_ref().unwrap().to_vec()
             );
         }
+        if let Some(platform) = platform {
+            container_config.platform = Some(platform.to_string());
+        }
+        if stop_on_remove {
+            container_config.stop_on_remove = Some(stop_on_remove);
+        }
         if resume {
             container_config.runtime.as_mut().unwrap()
                 .resume = Some(resume);
+            if let Some(restart_policy) = restart_policy {
+                container_config.runtime.as_mut().unwrap()
+                    .restart_policy = Some(restart_policy.to_string());
+            }
         } else if attach {
             container_config.runtime.as_mut().unwrap()
                 .attach = Some(attach);
@@ -142,6 +220,26 @@ impl AppConfig {
             container_config.runtime.as_mut().unwrap()
                 .runas = Some(run_as.to_string());
         }
+        for capability in cap_add.iter().chain(cap_drop.iter()).flatten() {
+            if ! Container::known_capability(capability) {
+                warn!("Unknown capability {}, stored as is", capability);
+            }
+        }
+        container_config.runtime.as_mut().unwrap().cap_add = cap_add.clone();
+        container_config.runtime.as_mut().unwrap().cap_drop = cap_drop.clone();
+        if let Some(labels) = labels {
+            Container::check_labels(
+                &labels.iter().map(String::as_str).collect::<Vec<_>>()
+            )?;
+        }
+        container_config.runtime.as_mut().unwrap().labels = labels.clone();
+        if let Some(storage_size) = storage_size {
+            Container::parse_storage_size(
+                storage_size, get_podman_storage_driver().as_deref()
+            )?;
+            container_config.runtime.as_mut().unwrap()
+                .storage_size = Some(storage_size.to_string());
+        }
         if includes_tar.is_some() {
             yaml_config.include.tar = Some(
                 includes_tar.as_ref().unwrap().to_vec()
@@ -165,15 +263,82 @@ impl AppConfig {
                 final_opts
             );
         }
+        let created_volumes = yaml_config.create_volume_dirs(run_as.as_ref())?;
+        if ! created_volumes.is_empty() {
+            yaml_config.container.as_mut().unwrap()
+                .created_volumes = Some(created_volumes);
+        }
 
-        let config = std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(config_file)
-            .unwrap_or_else(|_| panic!("Failed to open {:?}", config_file));
-        serde_yaml::to_writer(config, &yaml_config).unwrap();
-        Ok(())
+        yaml_config.save(config_file)
+    }
+
+    pub fn initrd_path(
+        image_dir: &str, initrd: Option<&String>, no_initrd: bool
+    ) -> Result<Option<String>, GenericError> {
+        /*!
+        initrd to boot the VM with. An explicit initrd must be an
+        existing file given by its absolute path, otherwise the
+        initrd of the VM image is used if present and not disabled
+        !*/
+        match initrd {
+            Some(initrd) => {
+                if ! initrd.starts_with('/') || ! Path::new(initrd).is_file() {
+                    return Err(
+                        Box::new(Error::new(
+                            ErrorKind::NotFound,
+                            format!("No initrd image found: {}", initrd)
+                        ))
+                    )
+                }
+                Ok(Some(initrd.to_string()))
+            },
+            None if no_initrd => Ok(None),
+            None => {
+                let initrd_path = format!(
+                    "{}/{}", image_dir, defaults::FIRECRACKER_INITRD_NAME
+                );
+                Ok(Path::new(&initrd_path).exists().then_some(initrd_path))
+            }
+        }
+    }
+
+    pub fn create_volume_dirs(
+        &self, run_as: Option<&String>
+    ) -> Result<Vec<String>, GenericError> {
+        /*!
+        Create the missing host directories of the writable
+        volumes, owned by the run_as user if given. The run_as
+        value "any" stands for the calling user and keeps the
+        ownership as is. The created directories are returned
+        to be recorded in the flake config, only those are
+        deleted by remove --rm-volumes
+        !*/
+        let run_as = run_as.filter(|run_as| run_as.as_str() != "any");
+        let mut created_volumes: Vec<String> = Vec::new();
+        for volume in self.volumes() {
+            if ! volume.starts_with('/') || Path::new(&volume).exists()
+                || ! podman::removable_host_dir(&volume)
+            {
+                continue
+            }
+            let owner = match run_as {
+                Some(run_as) => match get_user_by_name(run_as) {
+                    Some(user) => Some((user.uid(), user.primary_group_id())),
+                    None => return Err(Box::new(Error::new(
+                        ErrorKind::NotFound,
+                        format!("No such user: {}", run_as)
+                    )))
+                },
+                None => None
+            };
+            fs::create_dir_all(&volume)?;
+            if let Some((uid, gid)) = owner {
+                chown(&volume, Some(uid), Some(gid))?;
+            }
+            info!("Created volume directory: {}", volume);
+            created_volumes.push(volume);
+        }
+        Ok(created_volumes)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -184,11 +349,16 @@ impl AppConfig {
         host_app_path: &String,
         run_as: Option<&String>,
         overlay_size: Option<&String>,
+        cache_type: Option<&String>,
+        initrd: Option<&String>,
+        no_initrd: bool,
         no_net: bool,
         resume: bool,
         force_vsock: bool,
+        boot_timeout: Option<u64>,
         includes_tar: Option<Vec<String>>,
         includes_path: Option<Vec<String>>,
+        includes_oci: Option<Vec<String>>,
     ) -> Result<(), GenericError> {
         /*!
         save stores an AppConfig to the given file
@@ -230,11 +400,30 @@ impl AppConfig {
                 includes_path.as_ref().unwrap().to_vec()
             );
         }
+        if includes_oci.is_some() {
+            yaml_config.include.oci = includes_oci;
+        }
         if let Some(overlay_size) = overlay_size {
             vm_config.runtime.as_mut().unwrap()
                 .firecracker.as_mut().unwrap()
                 .overlay_size = Some(overlay_size.to_string());
         }
+        if let Some(cache_type) = cache_type {
+            vm_config.runtime.as_mut().unwrap()
+                .firecracker.as_mut().unwrap()
+                .cache_type = Some(cache_type.parse::<CacheType>()?.to_string());
+        }
+        if let Some(boot_timeout) = boot_timeout {
+            if ! (resume || force_vsock) {
+                return Err(Box::new(Error::new(
+                    ErrorKind::InvalidInput,
+                    "--boot-timeout requires --resume or --force-vsock"
+                )))
+            }
+            vm_config.runtime.as_mut().unwrap()
+                .firecracker.as_mut().unwrap()
+                .boot_timeout_sec = Some(boot_timeout);
+        }
         let rootfs_image_path = format!(
             "{}/{}", image_dir, defaults::FIRECRACKER_ROOTFS_NAME
         );
@@ -267,14 +456,9 @@ impl AppConfig {
             )
         }
 
-        let initrd_path = format!(
-            "{}/{}", image_dir, defaults::FIRECRACKER_INITRD_NAME
-        );
-        if Path::new(&initrd_path).exists() {
-            vm_config.runtime.as_mut().unwrap()
-                .firecracker.as_mut().unwrap()
-                .initrd_path = Some(initrd_path);
-        }
+        vm_config.runtime.as_mut().unwrap()
+            .firecracker.as_mut().unwrap()
+            .initrd_path = Self::initrd_path(&image_dir, initrd, no_initrd)?;
 
         if no_net {
             let mut boot_args: Vec<String> = Vec::new();
@@ -303,16 +487,141 @@ impl AppConfig {
             }
         }
 
+        yaml_config.save(config_file)
+    }
+
+    pub fn save(&self, config_file: &Path) -> Result<(), GenericError> {
+        /*!
+        Write this AppConfig to the given file. The yaml layout
+        is the same as the one written on app registration
+        !*/
         let config = std::fs::OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(config_file)
-            .unwrap_or_else(|_| panic!("Failed to open {:?}", config_file));
-        serde_yaml::to_writer(config, &yaml_config).unwrap();
+            .open(config_file)?;
+        serde_yaml::to_writer(config, self)?;
         Ok(())
     }
 
+    pub fn engine(&self) -> Option<&'static str> {
+        /*!
+        Name of the pilot this app is registered for
+        !*/
+        if self.container.is_some() {
+            Some(defaults::PODMAN_PILOT)
+        } else if self.vm.is_some() {
+            Some(defaults::FIRECRACKER_PILOT)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        /*!
+        Name of the container or VM this app is registered with
+        !*/
+        if let Some(container) = &self.container {
+            Some(&container.name)
+        } else {
+            self.vm.as_ref().map(|vm| vm.name.as_str())
+        }
+    }
+
+    pub fn host_app_path(&self) -> Option<&str> {
+        /*!
+        Absolute path of the app on the host
+        !*/
+        if let Some(container) = &self.container {
+            Some(&container.host_app_path)
+        } else {
+            self.vm.as_ref().map(|vm| vm.host_app_path.as_str())
+        }
+    }
+
+    pub fn aliases(&self) -> Vec<String> {
+        /*!
+        Additional host paths registered for this app
+        !*/
+        self.container.as_ref()
+            .and_then(|container| container.aliases.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn stop_on_remove(&self) -> bool {
+        /*!
+        Check if running instances of the app are stopped
+        when the app registration gets removed
+        !*/
+        self.container.as_ref()
+            .and_then(|container| container.stop_on_remove)
+            .unwrap_or_default()
+    }
+
+    pub fn created_volumes(&self) -> Vec<String> {
+        /*!
+        Host directories of the writable volumes which were
+        created on registration of the app
+        !*/
+        self.container.as_ref()
+            .and_then(|container| container.created_volumes.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn volumes(&self) -> Vec<String> {
+        /*!
+        Sources of the writable volumes passed to podman, that
+        is named volumes and host paths. Read-only volumes and
+        anonymous volumes are not included
+        !*/
+        let podman = self.container.as_ref()
+            .and_then(|container| container.runtime.as_ref())
+            .and_then(|runtime| runtime.podman.as_ref());
+        let mut volumes: Vec<String> = Vec::new();
+        for arg in podman.into_iter().flatten() {
+            let spec = match arg.split_once(['=', ' ']) {
+                Some(("--volume" | "-v", spec)) => spec.trim(),
+                _ => continue
+            };
+            let parts: Vec<&str> = spec.split(':').collect();
+            let read_only = parts.get(2)
+                .map(|options| options.split(',').any(|option| option == "ro"))
+                .unwrap_or(false);
+            if parts.len() > 1 && ! parts[0].is_empty() && ! read_only
+                && ! volumes.iter().any(|volume| volume == parts[0])
+            {
+                volumes.push(parts[0].to_string());
+            }
+        }
+        volumes
+    }
+
+    pub fn image_refs(&self) -> Vec<String> {
+        /*!
+        All container or VM images referenced by this app in
+        the order: name, base, layers
+        !*/
+        let mut image_refs: Vec<String> = Vec::new();
+        if let Some(container) = &self.container {
+            image_refs.push(container.name.clone());
+            if let Some(base) = &container.base_container {
+                image_refs.push(base.clone());
+            }
+            if let Some(layers) = &container.layers {
+                image_refs.extend(layers.iter().cloned());
+            }
+        } else if let Some(vm) = &self.vm {
+            image_refs.push(vm.name.clone());
+            if let Some(base) = &vm.base_vm {
+                image_refs.push(base.clone());
+            }
+            if let Some(layers) = &vm.layers {
+                image_refs.extend(layers.iter().cloned());
+            }
+        }
+        image_refs
+    }
+
     pub fn init_from_file(
         config_file: &Path
     ) -> Result<AppConfig, GenericError> {
@@ -320,10 +629,14 @@ impl AppConfig {
         new creates the new AppConfig class by reading and
         deserializing the data from a given yaml configuration
         !*/
+        let config_error = |message: String| FlakeError::ConfigError {
+            path: config_file.display().to_string(),
+            message
+        };
         let config = std::fs::File::open(config_file)
-            .unwrap_or_else(|_| panic!("Failed to open {:?}", config_file));
-        let yaml_config: AppConfig =
-            serde_yaml::from_reader(config).expect("Failed to import config file");
+            .map_err(|error| config_error(error.to_string()))?;
+        let yaml_config: AppConfig = serde_yaml::from_reader(config)
+            .map_err(|error| config_error(error.to_string()))?;
         Ok(yaml_config)
     }
 }
@@ -0,0 +1,470 @@
+//
+// Copyright (c) 2023 SUSE Software Solutions Germany GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use flakes::config::get_flakes_dir;
+use glob::glob;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::{tempdir, TempDir};
+
+use crate::defaults;
+use crate::{app, app_config, firecracker, podman};
+
+pub fn export(app: &String, output: &String, with_image: bool) -> bool {
+    /*!
+    Export application registration into a bundle
+
+    The bundle is a tar archive containing the app.yaml,
+    the app.d directory and optionally the container image
+    or the VM image set referenced by the application.
+    !*/
+    let app_basename = app::basename(app);
+    let config_file = format!("{}/{}.yaml", get_flakes_dir(), app_basename);
+    let app_config_dir = format!("{}/{}.d", get_flakes_dir(), app_basename);
+    let app_conf = match app_config::AppConfig::init_from_file(
+        Path::new(&config_file)
+    ) {
+        Ok(app_conf) => app_conf,
+        Err(error) => {
+            error!("Failed to load flake config: {}", error);
+            return false
+        }
+    };
+    info!("Exporting application: {}", app_basename);
+
+    let tmp_dir = match tempdir() {
+        Ok(tmp_dir) => tmp_dir,
+        Err(error) => {
+            error!("Failed to create tempdir: {}", error);
+            return false
+        }
+    };
+    let bundle_dir = tmp_dir.path().to_str().unwrap().to_string();
+    if ! copy_all(&config_file, &bundle_dir) {
+        return false
+    }
+    if Path::new(&app_config_dir).exists()
+        && ! copy_all(&app_config_dir, &bundle_dir)
+    {
+        return false
+    }
+    if with_image {
+        let image_dir = format!("{}/image", bundle_dir);
+        if let Err(error) = fs::create_dir_all(&image_dir) {
+            error!("Failed creating: {}: {:?}", image_dir, error);
+            return false
+        }
+        if app_conf.engine() == Some(defaults::PODMAN_PILOT) {
+            let images = app_conf.image_refs();
+            let container_archive = format!("{}/container.tar", image_dir);
+            info!("podman save -m -o {} {:?}", container_archive, images);
+            let mut call = podman::setup_podman_call("any");
+            call.arg("save")
+                .arg("--multi-image-archive")
+                .arg("-o")
+                .arg(&container_archive)
+                .args(&images);
+            if ! call_ok(call, "podman save") {
+                return false
+            }
+        } else if let Some(vm) = app_conf.name() {
+            let vm_image_dir = format!(
+                "{}/{}", defaults::FIRECRACKER_IMAGES_DIR, vm
+            );
+            if ! copy_all(&vm_image_dir, &image_dir) {
+                return false
+            }
+        }
+    }
+
+    info!("Writing bundle: {}", output);
+    let mut tar = Command::new("tar");
+    tar.arg("-C").arg(&bundle_dir)
+        .arg("-cf").arg(output)
+        .arg(".");
+    call_ok(tar, "tar")
+}
+
+pub fn import(bundle: &String, force: bool) -> bool {
+    /*!
+    Import application registration from a bundle
+
+    Restore app.yaml, the app.d directory and the optionally
+    included image from a bundle created by export and create
+    the application symlink to the matching pilot. An already
+    registered application is only replaced if force is set.
+    !*/
+    let tmp_dir = match tempdir() {
+        Ok(tmp_dir) => tmp_dir,
+        Err(error) => {
+            error!("Failed to create tempdir: {}", error);
+            return false
+        }
+    };
+    let bundle_dir = tmp_dir.path().to_str().unwrap().to_string();
+    info!("Unpacking bundle: {}", bundle);
+    let mut tar = Command::new("tar");
+    tar.arg("-C").arg(&bundle_dir)
+        .arg("-xf").arg(bundle);
+    if ! call_ok(tar, "tar") {
+        return false
+    }
+
+    let mut config_files: Vec<String> = Vec::new();
+    for entry in glob(&format!("{}/*.yaml", bundle_dir)).unwrap().flatten() {
+        config_files.push(entry.display().to_string());
+    }
+    if config_files.len() != 1 {
+        error!("Bundle must contain exactly one flake config: {}", bundle);
+        return false
+    }
+    let bundle_config_file = &config_files[0];
+    let app_conf = match app_config::AppConfig::init_from_file(
+        Path::new(bundle_config_file)
+    ) {
+        Ok(app_conf) => app_conf,
+        Err(error) => {
+            error!("Failed to load flake config: {}", error);
+            return false
+        }
+    };
+    let (host_app_path, engine) = match (
+        app_conf.host_app_path(), app_conf.engine()
+    ) {
+        (Some(host_app_path), Some(engine)) => {
+            (host_app_path.to_string(), engine)
+        },
+        _ => {
+            error!("No container or vm section in: {}", bundle_config_file);
+            return false
+        }
+    };
+    let vm_name = if engine == defaults::FIRECRACKER_PILOT {
+        app_conf.name().map(str::to_string)
+    } else {
+        None
+    };
+    let app_basename = app::basename(&host_app_path);
+    let config_file = format!("{}/{}.yaml", get_flakes_dir(), app_basename);
+    info!("Importing application: {}", host_app_path);
+
+    // replace existing registration only on request
+    let registered = Path::new(&config_file).exists()
+        || fs::symlink_metadata(&host_app_path).is_ok();
+    if registered && ! force {
+        error!(
+            "Application {} already registered, use --force to replace",
+            host_app_path
+        );
+        return false
+    }
+
+    // restore image, a VM image is staged next to the
+    // existing one and only swapped in on success
+    let container_archive = format!("{}/image/container.tar", bundle_dir);
+    if Path::new(&container_archive).exists()
+        && podman::load(&container_archive) != 0
+    {
+        return false
+    }
+    let mut staged_image: Option<(String, String)> = None;
+    if let Some(vm_name) = vm_name {
+        let bundle_image_dir = format!("{}/image/{}", bundle_dir, vm_name);
+        let image_dir = format!(
+            "{}/{}", defaults::FIRECRACKER_IMAGES_DIR, vm_name
+        );
+        if Path::new(&bundle_image_dir).exists() {
+            if Path::new(&image_dir).exists() && ! force {
+                info!("Keeping existing VM image: {}", image_dir);
+            } else {
+                let staged_name = format!("{}.import", vm_name);
+                let staged_dir = format!(
+                    "{}/{}", defaults::FIRECRACKER_IMAGES_DIR, staged_name
+                );
+                if ! firecracker::pull_new(&staged_name, true)
+                    || ! firecracker::mv(&bundle_image_dir, &staged_dir, "root")
+                {
+                    return false
+                }
+                staged_image = Some((staged_dir, image_dir));
+            }
+        }
+    }
+
+    // move an existing registration aside, it is put back
+    // if the registration from the bundle can't be restored
+    let mut replaced = None;
+    if registered {
+        match set_aside_registration_in(&get_flakes_dir(), &host_app_path) {
+            Ok(registration) => replaced = Some(registration),
+            Err(error) => {
+                error!("Failed to replace {}: {}", host_app_path, error);
+                if let Some((staged_dir, _)) = &staged_image {
+                    let _ = fs::remove_dir_all(staged_dir);
+                }
+                return false
+            }
+        }
+    }
+    let restored = restore_registration(
+        &bundle_dir, bundle_config_file, &host_app_path, engine,
+        &app_conf.aliases()
+    ) && match &staged_image {
+        Some((staged_dir, image_dir)) => swap_dir(staged_dir, image_dir),
+        None => true
+    };
+    if ! restored {
+        if fs::symlink_metadata(&host_app_path).is_ok() {
+            app::remove(&host_app_path, engine, true);
+        }
+        if let Some(registration) = replaced {
+            if let Err(error) = registration.put_back() {
+                error!(
+                    "Failed to restore previous registration of {}: {}",
+                    host_app_path, error
+                );
+            }
+        }
+        if let Some((staged_dir, _)) = &staged_image {
+            let _ = fs::remove_dir_all(staged_dir);
+        }
+        return false
+    }
+    true
+}
+
+fn restore_registration(
+    bundle_dir: &str, bundle_config_file: &str, host_app_path: &String,
+    engine: &str, aliases: &[String]
+) -> bool {
+    /*!
+    Register the app from the unpacked bundle. Leftovers of a
+    failed restore are cleaned up by the caller
+    !*/
+    let app_basename = app::basename(host_app_path);
+    let config_file = format!("{}/{}.yaml", get_flakes_dir(), app_basename);
+    let app_config_dir = format!("{}/{}.d", get_flakes_dir(), app_basename);
+    if ! app::init(Some(host_app_path), false)
+        || ! app::register(Some(host_app_path), None, engine)
+    {
+        return false
+    }
+    if let Err(error) = fs::copy(bundle_config_file, &config_file) {
+        error!("Failed to copy {}: {:?}", config_file, error);
+        return false
+    }
+    let bundle_config_dir = format!("{}/{}.d", bundle_dir, app_basename);
+    if Path::new(&bundle_config_dir).exists()
+        && ! copy_all(&format!("{}/.", bundle_config_dir), &app_config_dir)
+    {
+        return false
+    }
+    aliases.is_empty() || app::register_aliases(host_app_path, aliases, engine)
+}
+
+/// Registration moved aside while it gets replaced
+pub struct SetAside {
+    /// Flakes directory the registration belongs to
+    flakes_dir: String,
+    /// Basename of the app, names the config file and directory
+    app_basename: String,
+    /// Holds the config file and directory of the registration
+    backup: TempDir,
+    /// Links of the app and its aliases as (target, link name)
+    links: Vec<(PathBuf, String)>
+}
+
+impl SetAside {
+    pub fn put_back(self) -> Result<(), std::io::Error> {
+        /*!
+        Replace whatever got registered in the meantime with
+        the registration moved aside
+        !*/
+        for name in self.config_names() {
+            let saved = self.backup.path().join(&name);
+            if fs::symlink_metadata(&saved).is_ok() {
+                let path = Path::new(&self.flakes_dir).join(&name);
+                remove_path(&path)?;
+                fs::rename(&saved, &path)?;
+            }
+        }
+        for (target, link_name) in &self.links {
+            remove_path(Path::new(link_name))?;
+            symlink(target, link_name)?;
+        }
+        Ok(())
+    }
+
+    fn config_names(&self) -> [String; 2] {
+        [
+            format!("{}.yaml", self.app_basename),
+            format!("{}.d", self.app_basename)
+        ]
+    }
+}
+
+pub fn set_aside_registration_in(
+    flakes_dir: &str, host_app_path: &String
+) -> Result<SetAside, std::io::Error> {
+    /*!
+    Move the registration of host_app_path in flakes_dir aside.
+    The config file and directory are moved to a backup directory
+    in flakes_dir, the links of the app and of its aliases are
+    recorded and deleted. Nothing is changed if this fails
+    !*/
+    if app::app_path_state(host_app_path) == app::AppPathState::Occupied {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a flake", host_app_path)
+        ))
+    }
+    let app_basename = app::basename(host_app_path);
+    let registration = SetAside {
+        flakes_dir: flakes_dir.to_string(),
+        backup: tempfile::Builder::new()
+            .prefix(".import-").tempdir_in(flakes_dir)?,
+        links: Vec::new(),
+        app_basename
+    };
+    let config_file = Path::new(flakes_dir)
+        .join(format!("{}.yaml", registration.app_basename));
+    let mut link_names = vec![host_app_path.to_string()];
+    if let Ok(app_conf) = app_config::AppConfig::init_from_file(&config_file) {
+        for alias in app_conf.aliases() {
+            let alias_basename = app::basename(&alias);
+            let alias_config = format!("{}/{}.yaml", flakes_dir, alias_basename);
+            // only aliases linked to the config of this app belong to it
+            if fs::read_link(&alias_config).ok() != Some(
+                PathBuf::from(format!("{}.yaml", registration.app_basename))
+            ) {
+                continue
+            }
+            link_names.push(alias);
+            link_names.push(alias_config);
+            link_names.push(format!("{}/{}.d", flakes_dir, alias_basename));
+        }
+    }
+    let links: Vec<(PathBuf, String)> = link_names.into_iter()
+        .filter_map(|link_name| fs::read_link(&link_name).ok()
+            .map(|target| (target, link_name))
+        )
+        .collect();
+
+    let mut result = Ok(());
+    for name in registration.config_names() {
+        let path = Path::new(flakes_dir).join(&name);
+        if fs::symlink_metadata(&path).is_ok() {
+            result = fs::rename(&path, registration.backup.path().join(&name));
+            if result.is_err() {
+                break
+            }
+        }
+    }
+    let mut removed = Vec::new();
+    if result.is_ok() {
+        for (target, link_name) in links {
+            result = fs::remove_file(&link_name);
+            if result.is_err() {
+                break
+            }
+            removed.push((target, link_name));
+        }
+    }
+    let registration = SetAside { links: removed, ..registration };
+    if let Err(error) = result {
+        // undo what was moved so far
+        let _ = registration.put_back();
+        return Err(error)
+    }
+    Ok(registration)
+}
+
+fn remove_path(path: &Path) -> Result<(), std::io::Error> {
+    /*!
+    Delete file, link or directory at path if it exists
+    !*/
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(_) => Ok(())
+    }
+}
+
+fn swap_dir(source: &str, target: &str) -> bool {
+    /*!
+    Replace target directory with source. The target is
+    only deleted once the source is in place
+    !*/
+    let previous = format!("{}.old", target);
+    let has_previous = Path::new(target).exists();
+    if has_previous {
+        let _ = fs::remove_dir_all(&previous);
+        if let Err(error) = fs::rename(target, &previous) {
+            error!("Failed to move {} aside: {:?}", target, error);
+            return false
+        }
+    }
+    if let Err(error) = fs::rename(source, target) {
+        error!("Failed to move {} to {}: {:?}", source, target, error);
+        if has_previous {
+            let _ = fs::rename(&previous, target);
+        }
+        return false
+    }
+    if has_previous {
+        if let Err(error) = fs::remove_dir_all(&previous) {
+            warn!("Failed to delete {}: {:?}", previous, error);
+        }
+    }
+    true
+}
+
+fn copy_all(source: &str, target: &str) -> bool {
+    /*!
+    Copy file or directory recursively preserving attributes
+    !*/
+    let mut call = Command::new("cp");
+    call.arg("-a").arg(source).arg(target);
+    call_ok(call, "cp")
+}
+
+fn call_ok(mut call: Command, program: &str) -> bool {
+    /*!
+    Run given command and report failure
+    !*/
+    match call.status() {
+        Ok(status) => {
+            if ! status.success() {
+                error!("Failed, {} error message(s) reported", program);
+                return false
+            }
+        },
+        Err(error) => {
+            error!("Failed to execute {}: {:?}", program, error);
+            return false
+        }
+    }
+    true
+}
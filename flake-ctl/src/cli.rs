@@ -23,6 +23,8 @@
 // SOFTWARE.
 //
 use clap::{AppSettings, Parser, Subcommand, ArgGroup};
+use flakes::defaults::PODMAN_RESTART_POLICIES;
+use flakes::firecracker::CacheType;
 
 /// flake-ctl - Manage Flake Applications
 #[derive(Parser)]
@@ -48,6 +50,67 @@ pub enum Commands {
     },
     /// List registered flake applications
     List {
+    },
+    /// Export application registration to a bundle
+    Export {
+        /// Name or absolute path of the registered application
+        app: String,
+
+        /// File name of the bundle tar archive to create
+        #[clap(long)]
+        output: String,
+
+        /// Include the container image or VM image set
+        /// referenced by the application into the bundle
+        #[clap(long)]
+        with_image: bool,
+    },
+    /// Import application registration from a bundle
+    Import {
+        /// File name of a bundle tar archive created
+        /// by flake-ctl export
+        bundle: String,
+
+        /// Replace an already registered application
+        /// of the same name
+        #[clap(long)]
+        force: bool,
+    },
+    /// Upgrade application registrations to the current config schema
+    #[clap(
+        group(
+            ArgGroup::new("migrate")
+                .required(true).args(&["app", "all"])
+        )
+    )]
+    Migrate {
+        /// Name or absolute path of the registered application
+        app: Option<String>,
+
+        /// Migrate all registered applications
+        #[clap(long)]
+        all: bool,
+    },
+    /// Print version information of flake-ctl and the engines
+    Version {
+    },
+    /// Check the application registrations for problems
+    Doctor {
+    },
+    /// Run a throwaway flake end-to-end to check the host setup
+    Selftest {
+        /// Engine to test
+        #[clap(
+            long, default_value = "podman",
+            possible_values = &["podman", "firecracker"]
+        )]
+        engine: String,
+
+        /// Image to use for the test flake. For podman this is
+        /// a container image and defaults to busybox, for
+        /// firecracker the name of a pulled VM image is required
+        #[clap(long)]
+        image: Option<String>,
     }
 }
 
@@ -149,11 +212,37 @@ pub enum Firecracker {
         #[clap(long)]
         force_vsock: bool,
 
+        /// Seconds the VM gets to boot until it answers on the
+        /// vsock. If exceeded the VM is killed and the app call
+        /// fails. Requires --resume or --force-vsock
+        #[clap(long, value_name = "SECONDS")]
+        boot_timeout: Option<u64>,
+
         /// Size of overlay write space in bytes.
         /// Optional suffixes: KiB/MiB/GiB/TiB (1024) or KB/MB/GB/TB (1000)
         #[clap(long)]
         overlay_size: Option<String>,
 
+        /// Cache type of the overlay drive, Writeback by default.
+        /// Unsafe skips flushing the overlay data to the host,
+        /// which is faster but loses data on a host crash
+        #[clap(
+            long, requires = "overlay-size",
+            possible_values = CacheType::NAMES
+        )]
+        cache_type: Option<String>,
+
+        /// Absolute path of an initrd image to boot the VM with.
+        /// By default the initrd of the VM from the local
+        /// firecracker registry is used if present
+        #[clap(long, conflicts_with = "no-initrd")]
+        initrd: Option<String>,
+
+        /// Boot the VM without an initrd, even if the VM in the
+        /// local firecracker registry provides one
+        #[clap(long)]
+        no_initrd: bool,
+
         /// Disable networking
         #[clap(long)]
         no_net: bool,
@@ -169,6 +258,19 @@ pub enum Firecracker {
         /// specified multiple times.
         #[clap(long, multiple = true, requires = "overlay-size")]
         include_path: Option<Vec<String>>,
+
+        /// Name of a container image from the local podman
+        /// registry whose root filesystem should be included
+        /// on top of the VM instance. This option can be
+        /// specified multiple times.
+        #[clap(long, multiple = true, requires = "overlay-size")]
+        include_oci: Option<Vec<String>>,
+
+        /// Replace an existing file at the application path.
+        /// Without this option the registration is refused if
+        /// the path is already taken
+        #[clap(long)]
+        force: bool,
     },
     /// Remove application registration or entire VM
     #[clap(group(
@@ -185,15 +287,78 @@ pub enum Firecracker {
         #[clap(long)]
         app: Option<String>,
     },
+    /// Run a command in the running VM of a registered application.
+    /// Only supported for resume or force_vsock applications
+    Exec {
+        /// Name or absolute path of the registered application
+        app: String,
+
+        /// Command and its arguments to run in the VM
+        #[clap(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Export the changes stored in the overlay of a resume VM
+    /// as tar archive. The VM must not be running
+    Snapshot {
+        /// Name or absolute path of the registered application
+        app: String,
+
+        /// Output tar file name
+        #[clap(long)]
+        output: String,
+    },
+    /// Write the default firecracker json template used
+    /// by firecracker-pilot to start VMs
+    InitTemplate {
+        /// Overwrite an existing template
+        #[clap(long)]
+        force: bool,
+    },
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Podman {
     /// Pull container
     Pull {
         /// OCI image to pull from remote registry into local podman registry
         #[clap(long)]
         uri: String,
+
+        /// Platform of the image to pull in the format
+        /// OS/ARCH[/VARIANT], e.g. linux/arm64
+        #[clap(long)]
+        platform: Option<String>,
+    },
+    /// Build container image from a Containerfile
+    Build {
+        /// Containerfile to build the image from
+        #[clap(long)]
+        file: String,
+
+        /// Name of the resulting image in the local podman registry
+        #[clap(long)]
+        tag: String,
+
+        /// Build time argument in the format KEY=VALUE passed
+        /// to the Containerfile. This option can be
+        /// specified multiple times.
+        #[clap(long, multiple = true)]
+        build_arg: Option<Vec<String>>,
+
+        /// Build context directory, defaults to the
+        /// directory of the Containerfile
+        #[clap(long)]
+        context: Option<String>,
+
+        /// An absolute path to register the built image as
+        /// application on the host
+        #[clap(long)]
+        app: Option<String>,
+
+        /// Force registration even if the app is already registered
+        #[clap(long, requires = "app")]
+        force: bool,
     },
     /// Load container
     Load {
@@ -204,6 +369,24 @@ pub enum Podman {
         #[clap(long)]
         oci: String,
     },
+    /// Show container output of a registered application
+    Logs {
+        /// Name or absolute path of the registered application
+        app: String,
+
+        /// Follow the log output
+        #[clap(long)]
+        follow: bool,
+    },
+    /// Show registration and container instance state of an application
+    Inspect {
+        /// Name or absolute path of the registered application
+        app: String,
+
+        /// Print the report in JSON format
+        #[clap(long)]
+        json: bool,
+    },
     /// Remove application registration or entire container
     #[clap(group(
         ArgGroup::new("remove").required(true).args(&["container", "app"]),
@@ -218,6 +401,18 @@ pub enum Podman {
         /// Application absolute path to be removed from host
         #[clap(long)]
         app: Option<String>,
+
+        /// Also remove the named volumes and the host directories
+        /// created on registration for the writable volumes of the
+        /// application(s). Volumes used by other flakes are kept.
+        /// Asks for confirmation if called from a terminal
+        #[clap(long)]
+        rm_volumes: bool,
+
+        /// Confirm the removal of the volumes in advance, required
+        /// with --rm-volumes if not called from a terminal
+        #[clap(long, requires = "rm-volumes")]
+        yes: bool,
     },
     /// Register container application
     #[clap(
@@ -233,8 +428,22 @@ pub enum Podman {
     Register {
         /// A container name. The name must match with a
         /// name in the local podman registry
-        #[clap(long)]
-        container: String,
+        #[clap(
+            long, required_unless_present = "from-compose",
+            conflicts_with = "from-compose"
+        )]
+        container: Option<String>,
+
+        /// Path of a docker-compose file to import the service
+        /// given by the service option from. The service image
+        /// is used as container, its command as target and its
+        /// supported settings as container runtime options
+        #[clap(long, requires = "service")]
+        from_compose: Option<String>,
+
+        /// Name of the service to import from the compose file
+        #[clap(long, requires = "from-compose")]
+        service: Option<String>,
 
         /// An absolute path to the application on the host.
         /// If not specified via the target option, the
@@ -271,6 +480,17 @@ pub enum Podman {
         #[clap(long, multiple = true)]
         layer: Option<Vec<String>>,
 
+        /// Platform in the format OS/ARCH[/VARIANT] used by
+        /// podman-pilot to pull the base container and layers
+        #[clap(long)]
+        platform: Option<String>,
+
+        /// An additional absolute path on the host to call the
+        /// same application. This option can be specified
+        /// multiple times.
+        #[clap(long, multiple = true)]
+        alias: Option<Vec<String>>,
+
         /// Name of a tar file to be included on top of
         /// the container instance. This option can be
         /// specified multiple times.
@@ -289,6 +509,27 @@ pub enum Podman {
         #[clap(long)]
         resume: bool,
 
+        /// Restart policy of the resume container, applied as
+        /// podman --restart on container creation. The policy
+        /// always requires the podman service/socket to be active
+        #[clap(
+            long, requires = "resume",
+            possible_values = PODMAN_RESTART_POLICIES
+        )]
+        restart_policy: Option<String>,
+
+        /// Capability to add to the container, applied as
+        /// podman --cap-add. This option can be specified
+        /// multiple times.
+        #[clap(long, multiple = true)]
+        cap_add: Option<Vec<String>>,
+
+        /// Capability to drop from the container, applied as
+        /// podman --cap-drop. Use ALL to drop all capabilities.
+        /// This option can be specified multiple times.
+        #[clap(long, multiple = true)]
+        cap_drop: Option<Vec<String>>,
+
         /// Attach to the container if still running, rather than
         /// executing the app again. Only makes sense for interactive
         /// sessions like a shell application.
@@ -301,9 +542,35 @@ pub enum Podman {
         #[clap(long, multiple = true)]
         opt: Option<Vec<String>>,
 
+        /// Label in the format key=value set on the container,
+        /// applied as podman --label. Unlike OCI annotations
+        /// passed via --opt, labels are stored with the container
+        /// and can be used in podman ps --filter. This option can
+        /// be specified multiple times.
+        #[clap(long, multiple = true)]
+        label: Option<Vec<String>>,
+
+        /// Size of the container root filesystem, e.g. 10GiB,
+        /// applied as podman --storage-opt size=. Requires a
+        /// storage driver with quota support
+        #[clap(long)]
+        storage_size: Option<String>,
+
+        /// Stop and remove running instances of the application
+        /// when its registration is removed. By default instances
+        /// keep running and a warning is printed
+        #[clap(long)]
+        stop_on_remove: bool,
+
         /// Print registration information from container if provided
         #[clap(long)]
         info: bool,
+
+        /// Replace an existing file at the application path.
+        /// Without this option the registration is refused if
+        /// the path is already taken
+        #[clap(long)]
+        force: bool,
     },
 }
 
@@ -0,0 +1,281 @@
+//
+// Copyright (c) 2023 SUSE Software Solutions Germany GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use serde_yaml::Value;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// compose service settings imported into the flake registration
+const SUPPORTED_KEYS: [&str; 14] = [
+    "image", "command", "entrypoint", "environment", "volumes",
+    "ports", "cap_add", "cap_drop", "hostname", "working_dir", "user",
+    "tty", "stdin_open", "restart"
+];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ComposeService {
+    pub image: String,
+    pub target: Option<String>,
+    pub cap_add: Vec<String>,
+    pub cap_drop: Vec<String>,
+    pub restart_policy: Option<String>,
+    pub opts: Vec<String>,
+}
+
+pub fn load_service(
+    compose_file: &String, service_name: &String
+) -> Option<ComposeService> {
+    /*!
+    Read the given service from a docker-compose file and map it
+    to the flake registration. Settings which have no equivalent
+    are dropped with a warning
+    !*/
+    let compose: Value = match fs::read_to_string(compose_file)
+        .map_err(|error| error.to_string())
+        .and_then(|data| serde_yaml::from_str(&data)
+            .map_err(|error| error.to_string())
+        )
+    {
+        Ok(compose) => compose,
+        Err(error) => {
+            error!("Failed to load compose file {}: {}", compose_file, error);
+            return None
+        }
+    };
+    let service = match compose.get("services")
+        .and_then(|services| services.get(service_name.as_str()))
+    {
+        Some(service) => service,
+        None => {
+            error!(
+                "No service {} found in {}", service_name, compose_file
+            );
+            return None
+        }
+    };
+    // volumes are bind mounted by podman which needs absolute paths
+    let compose_dir = fs::canonicalize(compose_file).ok()
+        .and_then(|file| file.parent().map(Path::to_path_buf))
+        .or_else(|| env::current_dir().ok())
+        .unwrap_or_else(|| PathBuf::from("/"));
+    match service_to_flake(service, &compose_dir) {
+        Ok((service, dropped)) => {
+            if ! dropped.is_empty() {
+                warn!(
+                    "Dropped unsupported compose settings of service {}: {}",
+                    service_name, dropped.join(", ")
+                );
+            }
+            Some(service)
+        },
+        Err(error) => {
+            error!("Failed to import service {}: {}", service_name, error);
+            None
+        }
+    }
+}
+
+pub fn service_to_flake(
+    service: &Value, compose_dir: &Path
+) -> Result<(ComposeService, Vec<String>), String> {
+    /*!
+    Map a compose service definition to the flake registration
+    and return it together with the names of dropped settings.
+    Relative host paths of volumes are taken relative to the
+    compose_dir like compose does
+    !*/
+    let service = service.as_mapping()
+        .ok_or("service definition must be a mapping")?;
+    let mut flake = ComposeService::default();
+    let mut dropped: Vec<String> = Vec::new();
+
+    for (key, _) in service {
+        let key = key.as_str().unwrap_or_default();
+        if ! SUPPORTED_KEYS.contains(&key) {
+            dropped.push(key.to_string());
+        }
+    }
+    flake.image = service.get(&Value::from("image"))
+        .and_then(scalar)
+        .ok_or("image is required, build only services are unsupported")?;
+
+    // The flake target is called with the arguments of the
+    // app call, fixed arguments of the service can't be kept
+    let mut command = words(service.get(&Value::from("entrypoint")));
+    if command.is_empty() {
+        command = words(service.get(&Value::from("command")));
+    }
+    if ! command.is_empty() {
+        flake.target = Some(command.remove(0));
+        if ! command.is_empty() {
+            dropped.push(format!("arguments '{}'", command.join(" ")));
+        }
+    }
+
+    match service.get(&Value::from("environment")) {
+        Some(Value::Mapping(environment)) => {
+            for (name, value) in environment {
+                let name = scalar(name).unwrap_or_default();
+                match scalar(value) {
+                    Some(value) => flake.opts.push(
+                        format!("--env {}={}", name, value)
+                    ),
+                    // taken from the host environment
+                    None => flake.opts.push(format!("--env {}", name))
+                }
+            }
+        },
+        Some(environment) => {
+            for variable in list(Some(environment)) {
+                flake.opts.push(format!("--env {}", variable));
+            }
+        },
+        None => { }
+    }
+
+    for volume in service.get(&Value::from("volumes"))
+        .and_then(Value::as_sequence).into_iter().flatten()
+    {
+        match volume.as_str() {
+            Some(volume) => flake.opts.push(
+                format!("--volume {}", host_volume(volume, compose_dir))
+            ),
+            None => dropped.push("volumes in long syntax".to_string())
+        }
+    }
+    for port in service.get(&Value::from("ports"))
+        .and_then(Value::as_sequence).into_iter().flatten()
+    {
+        match scalar(port) {
+            Some(port) => flake.opts.push(format!("--publish {}", port)),
+            None => dropped.push("ports in long syntax".to_string())
+        }
+    }
+    for (key, option) in [
+        ("hostname", "--hostname"),
+        ("working_dir", "--workdir"),
+        ("user", "--user")
+    ] {
+        if let Some(value) = service.get(&Value::from(key)).and_then(scalar) {
+            flake.opts.push(format!("{} {}", option, value));
+        }
+    }
+    for (key, option) in [
+        ("tty", "--tty"),
+        ("stdin_open", "--interactive")
+    ] {
+        if let Some(Value::Bool(true)) = service.get(&Value::from(key)) {
+            flake.opts.push(option.to_string());
+        }
+    }
+    flake.cap_add = list(service.get(&Value::from("cap_add")));
+    flake.cap_drop = list(service.get(&Value::from("cap_drop")));
+
+    // podman has no unless-stopped policy for flakes and the
+    // retry count of on-failure:N is not part of the flake
+    if let Some(restart) = service.get(&Value::from("restart")).and_then(scalar) {
+        match restart.as_str() {
+            "no" | "always" | "on-failure" => {
+                flake.restart_policy = Some(restart)
+            },
+            _ if restart.starts_with("on-failure:") => {
+                flake.restart_policy = Some("on-failure".to_string());
+                dropped.push(format!("restart count of '{}'", restart));
+            },
+            _ => dropped.push(format!("restart '{}'", restart))
+        }
+    }
+
+    Ok((flake, dropped))
+}
+
+pub fn merge(
+    compose: &[String], cli: &Option<Vec<String>>
+) -> Option<Vec<String>> {
+    /*!
+    Combine imported compose values with the ones given on
+    the command line, the command line values come last
+    !*/
+    let merged: Vec<String> = compose.iter()
+        .chain(cli.iter().flatten())
+        .cloned()
+        .collect();
+    if merged.is_empty() {
+        None
+    } else {
+        Some(merged)
+    }
+}
+
+fn host_volume(volume: &str, compose_dir: &Path) -> String {
+    // named volumes and absolute paths are used as is, relative
+    // paths start with a dot like in compose
+    let (source, target) = match volume.split_once(':') {
+        Some((source, target)) => (source, Some(target)),
+        None => (volume, None)
+    };
+    if source != "." && source != ".."
+        && ! source.starts_with("./") && ! source.starts_with("../")
+    {
+        return volume.to_string()
+    }
+    let mut host_path = PathBuf::new();
+    for component in compose_dir.join(source).components() {
+        match component {
+            Component::CurDir => { },
+            Component::ParentDir => { host_path.pop(); },
+            component => host_path.push(component)
+        }
+    }
+    match target {
+        Some(target) => format!("{}:{}", host_path.display(), target),
+        None => format!("{}", host_path.display())
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.to_string()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None
+    }
+}
+
+fn list(value: Option<&Value>) -> Vec<String> {
+    value.and_then(Value::as_sequence).into_iter().flatten()
+        .filter_map(scalar)
+        .collect()
+}
+
+fn words(value: Option<&Value>) -> Vec<String> {
+    // compose splits the string form like a shell, quoting
+    // is not supported here, use the list form instead
+    match value {
+        Some(Value::String(value)) => value.split_whitespace()
+            .map(ToOwned::to_owned)
+            .collect(),
+        value => list(value)
+    }
+}
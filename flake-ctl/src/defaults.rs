@@ -24,18 +24,22 @@
 //
 pub const PODMAN_PILOT: &str =
     "/usr/bin/podman-pilot";
+pub const FIRECRACKER: &str =
+    "firecracker";
 pub const FIRECRACKER_PILOT: &str =
     "/usr/bin/firecracker-pilot";
-pub const PODMAN_PATH:&str =
-    "/usr/bin/podman";
 pub const FLAKE_TEMPLATE_CONTAINER:&str =
     "/etc/flakes/container-flake.yaml";
 pub const FLAKE_TEMPLATE_FIRECRACKER:&str =
     "/etc/flakes/firecracker-flake.yaml";
+pub const FIRECRACKER_TEMPLATE:&str =
+    "/etc/flakes/firecracker.json";
 pub const FIRECRACKER_REGISTRY_DIR:&str =
     "/var/lib/firecracker";
 pub const FIRECRACKER_IMAGES_DIR:&str =
     "/var/lib/firecracker/images";
+pub const OVERLAY_UPPER_NAME:&str =
+    "rootfs_upper";
 pub const FIRECRACKER_INITRD_NAME:&str =
     "initrd";
 pub const FIRECRACKER_KERNEL_NAME:&str =
@@ -48,3 +52,11 @@ pub const FLAKES_STORAGE:&str =
     "/etc/flakes/storage.conf";
 pub const FLAKES_REGISTRY_RUNROOT: &str =
     "/run/flakes";
+pub const SELFTEST_IMAGE: &str =
+    "docker.io/library/busybox";
+pub const SELFTEST_TARGET: &str =
+    "/bin/true";
+pub const PROTECTED_HOST_DIRS: [&str; 11] = [
+    "/bin", "/boot", "/dev", "/etc", "/lib", "/lib64", "/proc",
+    "/run", "/sbin", "/sys", "/usr"
+];
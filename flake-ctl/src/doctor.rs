@@ -0,0 +1,54 @@
+//
+// Copyright (c) 2023 SUSE Software Solutions Germany GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use flakes::config::get_flakes_dir;
+
+use crate::app;
+
+pub fn duplicate_host_app_paths(flakes_dir: &str) -> Vec<String> {
+    /*!
+    Report host paths claimed by more than one registration
+    !*/
+    app::host_app_paths_in(flakes_dir).into_iter()
+        .filter(|(_, owners)| owners.len() > 1)
+        .map(|(path, owners)| format!(
+            "Host path {} is registered by multiple flakes: {}",
+            path, owners.join(", ")
+        ))
+        .collect()
+}
+
+pub fn check() -> bool {
+    /*!
+    Check the flake registrations for problems and report them.
+    Returns true if no problem was found
+    !*/
+    let problems = duplicate_host_app_paths(&get_flakes_dir());
+    for problem in &problems {
+        error!("{}", problem);
+    }
+    if problems.is_empty() {
+        info!("No problems found");
+    }
+    problems.is_empty()
+}
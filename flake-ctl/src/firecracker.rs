@@ -22,7 +22,9 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 //
-use flakes::config::get_flakes_dir;
+use flakes::config::{get_flakes_dir, get_firecracker_ids_dir};
+use flakes::firecracker::FireCrackerConfig;
+use flakes::defaults::FIRECRACKER_OVERLAY_DIR;
 use std::ffi::OsStr;
 use std::os::unix::fs::PermissionsExt;
 use std::process::Command;
@@ -490,12 +492,12 @@ pub fn purge_vm(vm: &str) {
             "{}/{}.yaml", get_flakes_dir(), app_name
         );
         match app_config::AppConfig::init_from_file(Path::new(&config_file)) {
-            Ok(mut app_conf) => {
-                if app_conf.vm.is_some() &&
-                    vm == app_conf.vm.as_mut().unwrap().name
+            Ok(app_conf) => {
+                if app_conf.engine() == Some(defaults::FIRECRACKER_PILOT)
+                    && app_conf.name() == Some(vm)
                 {
                     app::remove(
-                        &app_conf.vm.as_mut().unwrap().host_app_path,
+                        app_conf.host_app_path().unwrap(),
                         defaults::FIRECRACKER_PILOT, false
                     );
                 }
@@ -516,3 +518,235 @@ pub fn purge_vm(vm: &str) {
         }
     }
 }
+
+pub fn exec(app: &String, command: &[String]) -> i32 {
+    /*!
+    Run the given command in the running VM of the given app
+
+    The command is passed through the app's firecracker-pilot
+    in %exec mode, which uses the vsock connection of the VM.
+    This requires a resume or force_vsock app and a running VM
+    !*/
+    let app_basename = app::basename(app);
+    let config_file = format!("{}/{}.yaml", get_flakes_dir(), app_basename);
+    let app_conf = match app_config::AppConfig::init_from_file(
+        Path::new(&config_file)
+    ) {
+        Ok(app_conf) => app_conf,
+        Err(error) => {
+            error!("Failed to load flake config: {}", error);
+            return 1
+        }
+    };
+    let vsock_enabled = app_conf.vm.as_ref()
+        .and_then(|vm| vm.runtime.as_ref())
+        .map(|runtime| {
+            runtime.resume.unwrap_or_default()
+                || runtime.force_vsock.unwrap_or_default()
+        });
+    match vsock_enabled {
+        Some(true) => { },
+        Some(false) => {
+            error!("App {} is neither resume nor force_vsock", app_basename);
+            return 1
+        },
+        None => {
+            error!("App {} is not registered for firecracker", app_basename);
+            return 1
+        }
+    }
+    if ! vm_running(&app_basename) {
+        error!("No running VM found for app: {}", app_basename);
+        return 1
+    }
+    let mut call = Command::new(app_conf.host_app_path().unwrap());
+    call.arg("%exec").args(command);
+    match call.status() {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(error) => {
+            error!("Failed to execute {}: {:?}", app_basename, error);
+            1
+        }
+    }
+}
+
+pub fn vm_running(app_basename: &str) -> bool {
+    /*!
+    Check if the VM of the given app is running by looking
+    up the process ID stored in the app's vmid file
+    !*/
+    let vm_id_file = format!(
+        "{}/{}.vmid", get_firecracker_ids_dir(), app_basename
+    );
+    fs::read_to_string(vm_id_file)
+        .map(|vmid| {
+            let vmid = vmid.trim();
+            vmid != "0" && Path::new(&format!("/proc/{}", vmid)).exists()
+        })
+        .unwrap_or_default()
+}
+
+pub fn snapshot(app: &String, output: &String) -> i32 {
+    /*!
+    Export the upper layer of the overlay of a resume VM as
+    tar archive. The overlay image is loop mounted read-only
+    and only the changes made on top of the VM rootfs are
+    packed. The VM must not be running for a consistent read
+    !*/
+    let app_basename = app::basename(app);
+    let config_file = format!("{}/{}.yaml", get_flakes_dir(), app_basename);
+    let app_conf = match app_config::AppConfig::init_from_file(
+        Path::new(&config_file)
+    ) {
+        Ok(app_conf) => app_conf,
+        Err(error) => {
+            error!("Failed to load flake config: {}", error);
+            return 1
+        }
+    };
+    let has_overlay = app_conf.vm.as_ref()
+        .and_then(|vm| vm.runtime.as_ref())
+        .and_then(|runtime| runtime.firecracker.as_ref())
+        .map(|firecracker| firecracker.overlay_size.is_some());
+    match has_overlay {
+        Some(true) => { },
+        Some(false) => {
+            error!("App {} has no overlay_size configured", app_basename);
+            return 1
+        },
+        None => {
+            error!("App {} is not registered for firecracker", app_basename);
+            return 1
+        }
+    }
+    if vm_running(&app_basename) {
+        error!(
+            "VM for app {} is running, stop it before taking a snapshot",
+            app_basename
+        );
+        return 1
+    }
+    let overlay_file = format!(
+        "{}/{}.ext2", FIRECRACKER_OVERLAY_DIR, app_basename
+    );
+    if ! Path::new(&overlay_file).exists() {
+        error!("No overlay found for app {}: {}", app_basename, overlay_file);
+        return 1
+    }
+    let tmp_dir = match tempdir() {
+        Ok(tmp_dir) => tmp_dir,
+        Err(error) => {
+            error!("Failed to create tempdir: {}", error);
+            return 1
+        }
+    };
+    let mount_point = tmp_dir.path().to_string_lossy().to_string();
+    let mut call = Command::new("sudo");
+    call.arg("mount").arg("-o").arg("loop,ro")
+        .arg(&overlay_file).arg(&mount_point);
+    debug!("{:?}", call.get_args());
+    match call.status() {
+        Ok(status) if status.success() => { },
+        Ok(status) => {
+            error!("Failed to mount {}: {}", overlay_file, status);
+            return 1
+        },
+        Err(error) => {
+            error!("Failed to execute mount: {:?}", error);
+            return 1
+        }
+    }
+    let result = export_upper(&mount_point, output);
+    umount(&mount_point, "root");
+    if result {
+        info!("Written overlay snapshot of {}: {}", app_basename, output);
+        return 0
+    }
+    1
+}
+
+fn export_upper(mount_point: &str, output: &String) -> bool {
+    /*!
+    Pack the upper overlay tree below the given mount point of
+    an overlay image into the output tar file. The archive is
+    created by root and written by the calling user
+    !*/
+    let upper = format!("{}/{}", mount_point, defaults::OVERLAY_UPPER_NAME);
+    let archive = match fs::File::create(output) {
+        Ok(archive) => archive,
+        Err(error) => {
+            error!("Failed to create {}: {}", output, error);
+            return false
+        }
+    };
+    let mut call = Command::new("sudo");
+    call.arg("tar").arg("-C").arg(&upper)
+        .arg("--numeric-owner").arg("-cf").arg("-").arg(".")
+        .stdout(archive);
+    debug!("{:?}", call.get_args());
+    match call.status() {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            error!("Failed to pack {}: {}", upper, status);
+            let _ = fs::remove_file(output);
+            false
+        },
+        Err(error) => {
+            error!("Failed to execute tar: {:?}", error);
+            let _ = fs::remove_file(output);
+            false
+        }
+    }
+}
+
+pub fn init_template(template: &str, force: bool) -> bool {
+    /*!
+    Write the default firecracker json template. The written
+    template is read back to make sure firecracker-pilot can
+    use it. An existing template is only replaced with force
+    !*/
+    if Path::new(template).exists() && ! force {
+        error!("Template {} already exists, use --force to replace", template);
+        return false
+    }
+    let json = match serde_json::to_string_pretty(
+        &FireCrackerConfig::default()
+    ) {
+        Ok(json) => json,
+        Err(error) => {
+            error!("Failed to serialize firecracker template: {}", error);
+            return false
+        }
+    };
+    let tmp_dir = match tempdir() {
+        Ok(tmp_dir) => tmp_dir,
+        Err(error) => {
+            error!("Failed to create tempdir: {}", error);
+            return false
+        }
+    };
+    let tmp_template = tmp_dir.path().join("firecracker.json")
+        .into_os_string().into_string().unwrap();
+    if let Err(error) = fs::write(&tmp_template, json + "\n") {
+        error!("Failed to write {}: {}", tmp_template, error);
+        return false
+    }
+    if let Some(template_dir) = Path::new(template).parent() {
+        if ! mkdir(&template_dir.to_string_lossy().to_string(), "root") {
+            return false
+        }
+    }
+    if ! copy(&tmp_template, &template.to_string(), "root") {
+        return false
+    }
+    match FireCrackerConfig::from_template(template) {
+        Ok(_) => {
+            info!("Written firecracker template: {}", template);
+            true
+        },
+        Err(error) => {
+            error!("{}", error);
+            false
+        }
+    }
+}
@@ -25,6 +25,9 @@
 #[macro_use]
 extern crate log;
 
+#[cfg(test)]
+pub mod tests;
+
 use env_logger::Env;
 use std::process::{exit, ExitCode};
 
@@ -35,9 +38,16 @@ pub mod app;
 pub mod app_config;
 pub mod defaults;
 pub mod fetch;
+pub mod bundle;
+pub mod migrate;
+pub mod compose;
+pub mod version;
+pub mod doctor;
+pub mod selftest;
 
-use flakes::config::get_flakes_dir;
+use flakes::config::{check_flakes_config, get_flakes_dir};
 use flakes::user::{User, mkdir};
+use flakes::container::Container;
 
 #[tokio::main]
 async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
@@ -45,6 +55,17 @@ async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
 
     let args = cli::parse_args();
 
+    // version information must be available on incomplete setups
+    if let cli::Commands::Version { } = &args.command {
+        version::print_version();
+        return Ok(ExitCode::SUCCESS)
+    }
+
+    if let Err(error) = check_flakes_config() {
+        error!("{}", error);
+        return Ok(ExitCode::FAILURE)
+    }
+
     mkdir(&get_flakes_dir(), "777", User::ROOT)?;
 
     match &args.command {
@@ -55,11 +76,53 @@ async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
             if app_names.is_empty() {
                 println!("No application(s) registered");
             } else {
+                let missing = podman::missing_images(&app_names);
                 for app in app_names {
-                    println!("- {}", app);
+                    if missing.contains(&app) {
+                        println!("- {} (container image missing)", app);
+                    } else {
+                        println!("- {}", app);
+                    }
                 }
             }
         },
+        // export
+        cli::Commands::Export { app, output, with_image } => {
+            if ! bundle::export(app, output, *with_image) {
+                return Ok(ExitCode::FAILURE)
+            }
+        },
+        // import
+        cli::Commands::Import { bundle: bundle_file, force } => {
+            if ! bundle::import(bundle_file, *force) {
+                return Ok(ExitCode::FAILURE)
+            }
+        },
+        // migrate
+        cli::Commands::Migrate { app, all } => {
+            let ok = if *all {
+                migrate::migrate_all()
+            } else {
+                migrate::migrate(app.as_ref().unwrap())
+            };
+            if ! ok {
+                return Ok(ExitCode::FAILURE)
+            }
+        },
+        // version, handled before the setup
+        cli::Commands::Version { } => { },
+        // doctor
+        cli::Commands::Doctor { } => {
+            if ! doctor::check() {
+                return Ok(ExitCode::FAILURE)
+            }
+        },
+        // selftest
+        cli::Commands::Selftest { engine, image } => {
+            if ! selftest::run(engine, image.as_ref()) {
+                return Ok(ExitCode::FAILURE)
+            }
+        },
         // firecracker engine
         cli::Commands::Firecracker { command } => {
             match &command {
@@ -84,10 +147,12 @@ async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
                 },
                 // register
                 cli::Firecracker::Register {
-                    vm, app, target, run_as, overlay_size, no_net, resume,
-                    force_vsock, include_tar, include_path
+                    vm, app, target, run_as, overlay_size, cache_type, initrd,
+                    no_initrd,
+                    no_net, resume, force_vsock, boot_timeout, include_tar,
+                    include_path, include_oci, force
                 } => {
-                    if app::init(Some(app)) {
+                    if app::init(Some(app), *force) {
                         let mut ok = app::register(
                             Some(app), target.as_ref(),
                             defaults::FIRECRACKER_PILOT
@@ -99,11 +164,16 @@ async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
                                 target.as_ref(),
                                 run_as.as_ref(),
                                 overlay_size.as_ref(),
+                                cache_type.as_ref(),
+                                initrd.as_ref(),
+                                *no_initrd,
                                 *no_net,
                                 *resume,
                                 *force_vsock,
+                                *boot_timeout,
                                 include_tar.as_ref().cloned(),
                                 include_path.as_ref().cloned(),
+                                include_oci.as_ref().cloned(),
                             );
                         }
                         if ! ok {
@@ -130,49 +200,153 @@ async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
                             defaults::FIRECRACKER_PILOT
                         );
                     }
+                },
+                // exec
+                cli::Firecracker::Exec { app, command } => {
+                    exit(firecracker::exec(app, command));
+                },
+                // snapshot
+                cli::Firecracker::Snapshot { app, output } => {
+                    exit(firecracker::snapshot(app, output));
+                },
+                // init-template
+                cli::Firecracker::InitTemplate { force } => {
+                    if ! firecracker::init_template(
+                        defaults::FIRECRACKER_TEMPLATE, *force
+                    ) {
+                        return Ok(ExitCode::FAILURE)
+                    }
                 }
             }
         },
         // podman engine
         cli::Commands::Podman { command } => {
+            Container::podman_setup_storage_driver()?;
             match &command {
                 // pull
-                cli::Podman::Pull { uri } => {
-                    exit(podman::pull(uri));
+                cli::Podman::Pull { uri, platform } => {
+                    exit(podman::pull(uri, platform.as_ref()));
+                },
+                // build
+                cli::Podman::Build {
+                    file, tag, build_arg, context, app, force
+                } => {
+                    let status = podman::build(
+                        file, tag, build_arg.as_ref(), context.as_ref()
+                    );
+                    if status != 0 {
+                        exit(status);
+                    }
+                    if app.is_some() {
+                        if ! app::init(app.as_ref(), *force) {
+                            return Ok(ExitCode::FAILURE)
+                        }
+                        let ok = app::register(
+                            app.as_ref(), None, defaults::PODMAN_PILOT
+                        ) && app::create_container_config(
+                            tag, app.as_ref(), None,
+                            &app_config::ContainerOptions {
+                                run_as: Some("any".to_string()),
+                                ..Default::default()
+                            }
+                        );
+                        if ! ok {
+                            app::remove(
+                                app.as_ref().map(String::as_str).unwrap(),
+                                defaults::PODMAN_PILOT, true
+                            );
+                            return Ok(ExitCode::FAILURE)
+                        }
+                    }
                 },
                 // load
                 cli::Podman::Load { oci } => {
                     exit(podman::load(oci));
                 },
+                // logs
+                cli::Podman::Logs { app, follow } => {
+                    exit(podman::logs(app, *follow));
+                },
+                // inspect
+                cli::Podman::Inspect { app, json } => {
+                    exit(podman::inspect(app, *json));
+                },
                 // register
                 cli::Podman::Register {
-                    container, app, target, base, check_host_dependencies,
-                    layer, include_tar, include_path, resume, attach,
-                    opt, info
+                    container, from_compose, service, app, target, base,
+                    check_host_dependencies, layer, platform, alias,
+                    include_tar, include_path, resume, restart_policy,
+                    cap_add, cap_drop, attach, opt, label, storage_size,
+                    stop_on_remove, info, force
                 } => {
+                    let compose = match (from_compose, service) {
+                        (Some(compose_file), Some(service)) => {
+                            match compose::load_service(compose_file, service) {
+                                Some(compose) => compose,
+                                None => return Ok(ExitCode::FAILURE)
+                            }
+                        },
+                        _ => compose::ComposeService::default()
+                    };
+                    let container = container.as_ref()
+                        .unwrap_or(&compose.image);
+                    let target = target.as_ref()
+                        .or(compose.target.as_ref());
+                    let cap_add = compose::merge(&compose.cap_add, cap_add);
+                    let cap_drop = compose::merge(&compose.cap_drop, cap_drop);
+                    let opt = compose::merge(&compose.opts, opt);
+                    let restart_policy = match &compose.restart_policy {
+                        Some(policy) if restart_policy.is_none() => {
+                            if ! *resume {
+                                warn!(
+                                    "Dropped compose restart {}, \
+                                    requires --resume", policy
+                                );
+                            }
+                            resume.then(|| policy.clone())
+                        },
+                        _ => restart_policy.clone()
+                    };
                     if *info {
                         podman::print_container_info(container);
-                    } else if app::init(app.as_ref()) {
+                    } else if app::init(app.as_ref(), *force) {
                         let mut ok = app::register(
-                            app.as_ref(), target.as_ref(),
-                            defaults::PODMAN_PILOT
+                            app.as_ref(), target, defaults::PODMAN_PILOT
                         );
                         if ok {
                             ok = app::create_container_config(
                                 container,
                                 app.as_ref(),
-                                target.as_ref(),
-                                base.as_ref(),
-                                *check_host_dependencies,
-                                layer.as_ref().cloned(),
-                                include_tar.as_ref().cloned(),
-                                include_path.as_ref().cloned(),
-                                *resume,
-                                *attach,
-                                Some(&"any".to_string()),
-                                opt.as_ref().cloned()
+                                target,
+                                &app_config::ContainerOptions {
+                                    base: base.clone(),
+                                    check_host_dependencies:
+                                        *check_host_dependencies,
+                                    layers: layer.clone(),
+                                    platform: platform.clone(),
+                                    includes_tar: include_tar.clone(),
+                                    includes_path: include_path.clone(),
+                                    resume: *resume,
+                                    restart_policy,
+                                    cap_add,
+                                    cap_drop,
+                                    attach: *attach,
+                                    run_as: Some("any".to_string()),
+                                    opts: opt,
+                                    labels: label.clone(),
+                                    storage_size: storage_size.clone(),
+                                    stop_on_remove: *stop_on_remove
+                                }
                             );
                         }
+                        if ok {
+                            if let Some(alias) = alias {
+                                ok = app::register_aliases(
+                                    app.as_ref().unwrap(), alias,
+                                    defaults::PODMAN_PILOT
+                                );
+                            }
+                        }
                         if ! ok {
                             app::remove(
                                 app.as_ref().map(String::as_str).unwrap(),
@@ -185,18 +359,18 @@ async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
                     }
                 },
                 // remove
-                cli::Podman::Remove { container, app } => {
-                    if ! app.is_none() && ! app::remove(
+                cli::Podman::Remove { container, app, rm_volumes, yes } => {
+                    if ! app.is_none() && ! podman::remove_app(
                         app.as_ref().map(String::as_str).unwrap(),
-                        defaults::PODMAN_PILOT, false
+                        *rm_volumes, *yes
                     ) {
                         return Ok(ExitCode::FAILURE)
                     }
-                    if ! container.is_none() {
-                        app::purge(
-                            container.as_ref().map(String::as_str).unwrap(),
-                            defaults::PODMAN_PILOT
-                        );
+                    if ! container.is_none() && ! podman::purge_container(
+                        container.as_ref().map(String::as_str).unwrap(),
+                        *rm_volumes, *yes
+                    ) {
+                        return Ok(ExitCode::FAILURE)
                     }
                 }
             }
@@ -0,0 +1,179 @@
+//
+// Copyright (c) 2023 SUSE Software Solutions Germany GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use flakes::config::get_flakes_dir;
+use serde_yaml::{Mapping, Value};
+use std::fs;
+
+use crate::app;
+
+// runtime settings which older registrations kept at the engine level
+const CONTAINER_RUNTIME_KEYS: [&str; 4] = [
+    "runas", "resume", "attach", "podman"
+];
+const VM_RUNTIME_KEYS: [&str; 4] = [
+    "runas", "resume", "force_vsock", "firecracker"
+];
+const INCLUDE_KEYS: [&str; 3] = [
+    "tar", "path", "oci"
+];
+
+pub fn migrate_all() -> bool {
+    /*!
+    Migrate the flake configs of all registered applications
+    !*/
+    migrate_all_in(&get_flakes_dir())
+}
+
+pub fn migrate_all_in(flakes_dir: &str) -> bool {
+    /*!
+    Migrate the flake configs of all applications registered
+    in flakes_dir
+    !*/
+    let mut ok = true;
+    for app_name in app::app_names_in(flakes_dir) {
+        if ! migrate_in(flakes_dir, &app_name) {
+            ok = false
+        }
+    }
+    ok
+}
+
+pub fn migrate(app: &String) -> bool {
+    /*!
+    Upgrade the flake config of the given application to the
+    current schema, see migrate_in
+    !*/
+    migrate_in(&get_flakes_dir(), app)
+}
+
+pub fn migrate_in(flakes_dir: &str, app: &String) -> bool {
+    /*!
+    Upgrade the flake config of the given application in
+    flakes_dir to the current schema. The original config is
+    kept as app.yaml.bak and only written if a schema upgrade
+    was applied, such that migrating an up to date config is
+    a noop
+    !*/
+    let app_basename = app::basename(app);
+    let config_file = format!("{}/{}.yaml", flakes_dir, app_basename);
+    let mut config: Value = match fs::read_to_string(&config_file)
+        .map_err(|error| error.to_string())
+        .and_then(|data| serde_yaml::from_str(&data)
+            .map_err(|error| error.to_string())
+        )
+    {
+        Ok(config) => config,
+        Err(error) => {
+            error!("Failed to load flake config {}: {}", config_file, error);
+            return false
+        }
+    };
+    let changes = migrate_config(&mut config);
+    if changes.is_empty() {
+        info!("{}: up to date", app_basename);
+        return true
+    }
+    let backup_file = format!("{}.bak", config_file);
+    if let Err(error) = fs::copy(&config_file, &backup_file) {
+        error!("Failed to backup {} to {}: {}", config_file, backup_file, error);
+        return false
+    }
+    let data = match serde_yaml::to_string(&config) {
+        Ok(data) => data,
+        Err(error) => {
+            error!("Failed to serialize flake config: {}", error);
+            return false
+        }
+    };
+    if let Err(error) = fs::write(&config_file, data) {
+        error!("Failed to write {}: {}", config_file, error);
+        return false
+    }
+    info!("{}: migrated, original kept as {}", app_basename, backup_file);
+    for change in changes {
+        info!("- {}", change);
+    }
+    true
+}
+
+pub fn migrate_config(config: &mut Value) -> Vec<String> {
+    /*!
+    Apply all schema upgrades to the given flake config and
+    return a description of each applied change
+    !*/
+    let mut changes: Vec<String> = Vec::new();
+    let config = match config.as_mapping_mut() {
+        Some(config) => config,
+        None => return changes
+    };
+
+    // move engine level runtime settings into the runtime section
+    for (section, keys) in [
+        ("container", CONTAINER_RUNTIME_KEYS), ("vm", VM_RUNTIME_KEYS)
+    ] {
+        let engine = match config.get_mut(&Value::from(section)) {
+            Some(Value::Mapping(engine)) => engine,
+            _ => continue
+        };
+        for key in keys {
+            let value = match engine.remove(&Value::from(key)) {
+                Some(value) => value,
+                None => continue
+            };
+            let runtime = engine.entry(Value::from("runtime"))
+                .or_insert(Value::Null);
+            if runtime.is_null() {
+                *runtime = Value::Mapping(Mapping::new());
+            }
+            if let Value::Mapping(runtime) = runtime {
+                // an existing runtime setting takes precedence
+                if ! runtime.contains_key(&Value::from(key)) {
+                    runtime.insert(Value::from(key), value);
+                }
+            }
+            changes.push(
+                format!("moved {}.{} into {}.runtime", section, key, section)
+            );
+        }
+    }
+
+    // the include section is mandatory and holds lists only
+    let include = config.entry(Value::from("include"))
+        .or_insert(Value::Null);
+    if include.is_null() {
+        *include = Value::Mapping(Mapping::new());
+        changes.push("added empty include section".to_string());
+    }
+    if let Value::Mapping(include) = include {
+        for key in INCLUDE_KEYS {
+            if let Some(value) = include.get_mut(&Value::from(key)) {
+                if value.is_string() {
+                    *value = Value::Sequence(vec![value.clone()]);
+                    changes.push(format!("converted include.{} to a list", key));
+                }
+            }
+        }
+    }
+    changes
+}
@@ -24,25 +24,42 @@
 //
 use std::fs;
 use std::env;
+use std::io::{self, IsTerminal, Write};
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
 use glob::glob;
 use crate::defaults;
 use crate::{app, app_config};
 use flakes::container::Container;
-use flakes::config::get_flakes_dir;
-use users::{get_current_username};
+use flakes::command::{
+    stderr_excerpt, CommandRunner, CommandError, HostRunner, ProcessError
+};
+use flakes::user::User;
+use flakes::defaults::FLAKE_APP_LABEL;
+use flakes::config::{
+    get_flakes_dir, get_podman_ids_dir, get_podman_path, get_registry_mirrors
+};
+use users::{get_current_username, get_user_by_name};
+use serde::Serialize;
+use serde_json::{json, Value};
 
-pub fn pull(uri: &String) -> i32 {
+pub fn pull(uri: &str, platform: Option<&String>) -> i32 {
     /*!
-    Call podman pull and prune with the provided uri
+    Call podman pull and prune with the provided uri. If a
+    registry mirror is configured for the uri, the image is
+    pulled from the mirror and renamed to the uri
     !*/
+    let mirror = Container::mirror_reference(uri, &get_registry_mirrors());
+    let source = mirror.as_deref().unwrap_or(uri);
     info!("Fetching from registry...");
-    info!("podman pull {}", uri);
+    info!("podman pull {}", source);
 
     let mut call = setup_podman_call("any");
-    call.arg("pull")
-        .arg(uri);
+    call.arg("pull");
+    if let Some(platform) = platform {
+        call.arg("--platform").arg(platform);
+    }
+    call.arg(source);
     let status = match call.status() {
         Ok(status) => {
             if status.success() {
@@ -60,6 +77,8 @@ pub fn pull(uri: &String) -> i32 {
     let status_code = status.code().unwrap();
     if ! status.success() {
         error!("Failed, error message(s) reported");
+    } else if mirror.is_some() && ! rename_mirrored(source, uri) {
+        return 1
     } else {
         info!("podman prune");
         let mut prune = setup_podman_call("any");
@@ -71,6 +90,97 @@ pub fn pull(uri: &String) -> i32 {
     status_code
 }
 
+fn rename_mirrored(mirror: &str, uri: &str) -> bool {
+    /*!
+    Rename an image pulled from a registry mirror to the
+    reference it was requested as
+    !*/
+    info!("podman tag {} {}", mirror, uri);
+    for args in [["tag", mirror, uri], ["untag", uri, mirror]] {
+        let mut call = setup_podman_call("any");
+        call.args(args);
+        match call.output() {
+            Ok(output) if output.status.success() => {},
+            Ok(output) => {
+                error!(
+                    "podman {} failed: {}", args[0],
+                    stderr_excerpt(&output.stderr)
+                );
+                return false
+            },
+            Err(error) => {
+                error!("Failed to call podman {}: {:?}", args[0], error);
+                return false
+            }
+        }
+    }
+    true
+}
+
+pub fn build(
+    file: &String, tag: &String, build_args: Option<&Vec<String>>,
+    context: Option<&String>
+) -> i32 {
+    /*!
+    Call podman build for the given Containerfile and tag the
+    resulting image. Without an explicit context the directory
+    of the Containerfile is used as build context
+    !*/
+    if ! Path::new(file).is_file() {
+        error!("Containerfile {} not found", file);
+        return 1
+    }
+    let build_args = build_args.cloned().unwrap_or_default();
+    for build_arg in &build_args {
+        if ! valid_build_arg(build_arg) {
+            error!("Build arg {} must be in the format KEY=VALUE", build_arg);
+            return 1
+        }
+    }
+    let context = match context {
+        Some(context) => context.to_string(),
+        None => match Path::new(file).parent() {
+            Some(parent) if ! parent.as_os_str().is_empty() => {
+                parent.to_string_lossy().to_string()
+            },
+            _ => ".".to_string()
+        }
+    };
+    info!("Building image...");
+    info!("podman build --file {} --tag {} {}", file, tag, context);
+
+    let mut call = setup_podman_call("any");
+    call.arg("build")
+        .arg("--file").arg(file)
+        .arg("--tag").arg(tag);
+    for build_arg in &build_args {
+        call.arg("--build-arg").arg(build_arg);
+    }
+    call.arg(&context).stderr(Stdio::piped());
+    let output = match call.output() {
+        Ok(output) => output,
+        Err(error) => {
+            error!("Failed to execute podman build: {:?}", error);
+            return 1
+        }
+    };
+    if ! output.status.success() {
+        error!("podman build failed{}", stderr_excerpt(&output.stderr));
+        return output.status.code().unwrap_or(1)
+    }
+    0
+}
+
+pub fn valid_build_arg(build_arg: &str) -> bool {
+    /*!
+    Check that a build arg is in the format KEY=VALUE
+    !*/
+    matches!(
+        build_arg.split_once('='),
+        Some((key, _)) if ! key.is_empty() && ! key.contains(char::is_whitespace)
+    )
+}
+
 pub fn load(oci: &String) -> i32 {
     /*!
     Call podman load with the provided oci tar file
@@ -114,6 +224,220 @@ pub fn load(oci: &String) -> i32 {
     status_code
 }
 
+pub fn logs(app: &String, follow: bool) -> i32 {
+    /*!
+    Call podman logs for the container instance of the given app
+
+    The container ID is read from the app's CID file as
+    written by podman-pilot for the calling user
+    !*/
+    let username = get_current_username().unwrap();
+    let container_cid_file = format!(
+        "{}/{}_{}.cid",
+        get_podman_ids_dir(), app::basename(app),
+        username.to_str().unwrap()
+    );
+    let cid = match fs::read_to_string(&container_cid_file) {
+        Ok(cid) => cid,
+        Err(_) => {
+            error!("No container instance found for app: {}", app);
+            return 1
+        }
+    };
+    let mut exists = setup_podman_call("any");
+    exists.arg("container")
+        .arg("exists")
+        .arg(&cid);
+    match exists.output() {
+        Ok(output) => {
+            if ! output.status.success() {
+                error!("Container {} for app {} does not exist", cid, app);
+                return 1
+            }
+        },
+        Err(error) => {
+            error!("Failed to execute podman: {:?}", error);
+            return 1
+        }
+    }
+    info!("podman logs {}", cid);
+    let mut call = setup_podman_call("any");
+    call.arg("logs");
+    if follow {
+        call.arg("--follow");
+    }
+    call.arg(&cid);
+    match call.status() {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(error) => {
+            error!("Failed to execute podman: {:?}", error);
+            1
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct InspectReport {
+    pub app: String,
+    pub config_file: String,
+    pub config: serde_yaml::Value,
+    pub cid_file: String,
+    pub cid: Option<String>,
+    pub running: bool,
+    pub state: Option<Value>,
+    pub image: Option<String>,
+    pub mounts: Vec<Value>,
+}
+
+pub fn inspect(app: &String, json: bool) -> i32 {
+    /*!
+    Print the registration of the given app together with the
+    state of its container instance as one report
+
+    The container ID is read from the app's CID file as
+    written by podman-pilot for the calling user
+    !*/
+    let app_basename = app::basename(app);
+    let username = get_current_username().unwrap();
+    let mut report = InspectReport {
+        app: app_basename.clone(),
+        config_file: format!("{}/{}.yaml", get_flakes_dir(), app_basename),
+        cid_file: format!(
+            "{}/{}_{}.cid",
+            get_podman_ids_dir(), app_basename, username.to_str().unwrap()
+        ),
+        ..Default::default()
+    };
+    report.config = match fs::read_to_string(&report.config_file)
+        .map_err(|error| error.to_string())
+        .and_then(|data| serde_yaml::from_str(&data)
+            .map_err(|error| error.to_string())
+        )
+    {
+        Ok(config) => config,
+        Err(error) => {
+            error!(
+                "Failed to load flake config {}: {}", report.config_file, error
+            );
+            return 1
+        }
+    };
+    report.cid = fs::read_to_string(&report.cid_file).ok()
+        .map(|cid| cid.trim().to_string())
+        .filter(|cid| ! cid.is_empty());
+    if let Some(cid) = &report.cid {
+        let mut call = setup_podman_call("any");
+        call.arg("container")
+            .arg("inspect")
+            .arg(cid);
+        match call.output() {
+            Ok(output) if output.status.success() => {
+                match serde_json::from_slice::<Value>(&output.stdout) {
+                    Ok(inspect) => inspect_subset(&mut report, &inspect),
+                    Err(error) => {
+                        error!("Failed to parse podman inspect: {}", error);
+                        return 1
+                    }
+                }
+            },
+            // a stale CID file references no container
+            Ok(_) => { },
+            Err(error) => {
+                error!("Failed to execute podman: {:?}", error);
+                return 1
+            }
+        }
+    }
+    if json {
+        match serde_json::to_string_pretty(&report) {
+            Ok(data) => println!("{}", data),
+            Err(error) => {
+                error!("Failed to format report: {}", error);
+                return 1
+            }
+        }
+    } else {
+        print_inspect_report(&report);
+    }
+    0
+}
+
+pub fn inspect_subset(report: &mut InspectReport, inspect: &Value) {
+    /*!
+    Take the instance state, image and mounts from the
+    output of podman container inspect
+    !*/
+    let container = match inspect.get(0) {
+        Some(container) => container,
+        None => return
+    };
+    let state = &container["State"];
+    report.running = state["Running"].as_bool().unwrap_or_default();
+    report.state = Some(json!({
+        "Status": state["Status"],
+        "Running": state["Running"],
+        "Pid": state["Pid"],
+        "ExitCode": state["ExitCode"],
+        "StartedAt": state["StartedAt"]
+    }));
+    report.image = container["ImageName"].as_str().map(ToOwned::to_owned);
+    report.mounts = container["Mounts"].as_array().into_iter().flatten()
+        .map(|mount| json!({
+            "Type": mount["Type"],
+            "Source": mount["Source"],
+            "Destination": mount["Destination"],
+            "RW": mount["RW"]
+        }))
+        .collect();
+}
+
+fn print_inspect_report(report: &InspectReport) {
+    println!("Registration: {}", report.config_file);
+    match serde_yaml::to_string(&report.config) {
+        Ok(config) => println!("{}", config.trim_start_matches("---\n")),
+        Err(error) => error!("Failed to format config: {}", error)
+    }
+    println!("Instance: {}", report.cid_file);
+    let cid = match &report.cid {
+        Some(cid) => cid,
+        None => {
+            println!("  no container instance");
+            return
+        }
+    };
+    println!("  cid: {}", cid);
+    let state = match &report.state {
+        Some(state) => state,
+        None => {
+            println!("  container does not exist (stale cid file)");
+            return
+        }
+    };
+    println!("  running: {}", report.running);
+    println!("  status: {}", state["Status"].as_str().unwrap_or("unknown"));
+    if report.running {
+        println!("  pid: {}", state["Pid"]);
+        println!("  started: {}", state["StartedAt"].as_str().unwrap_or(""));
+    } else {
+        println!("  exit code: {}", state["ExitCode"]);
+    }
+    if let Some(image) = &report.image {
+        println!("  image: {}", image);
+    }
+    if ! report.mounts.is_empty() {
+        println!("  mounts:");
+        for mount in &report.mounts {
+            println!(
+                "    {} -> {} ({}{})",
+                mount["Source"].as_str().unwrap_or(""),
+                mount["Destination"].as_str().unwrap_or(""),
+                mount["Type"].as_str().unwrap_or(""),
+                if mount["RW"].as_bool() == Some(false) { ", ro" } else { "" }
+            );
+        }
+    }
+}
+
 pub fn rm(container: &String) {
     /*!
     Call podman image rm with force option to remove all running containers
@@ -194,26 +518,25 @@ pub fn umount_container(container_name: &str) -> i32 {
     output.status.code().unwrap()
 }
 
-pub fn purge_container(container: &str) {
+pub fn purge_container(container: &str, rm_volumes: bool, yes: bool) -> bool {
     /*!
     Iterate over all yaml config files and find those connected
     to the container. Delete all app registrations for this
     container and also delete the container from the local
-    registry
+    registry. With rm_volumes the volumes of the apps are
+    deleted too
     !*/
+    let mut apps: Vec<app_config::AppConfig> = Vec::new();
     for app_name in app::app_names() {
         let config_file = format!(
             "{}/{}.yaml", get_flakes_dir(), app_name
         );
         match app_config::AppConfig::init_from_file(Path::new(&config_file)) {
-            Ok(mut app_conf) => {
-                if app_conf.container.is_some() &&
-                    container == app_conf.container.as_mut().unwrap().name
+            Ok(app_conf) => {
+                if app_conf.engine() == Some(defaults::PODMAN_PILOT)
+                    && app_conf.name() == Some(container)
                 {
-                    app::remove(
-                        &app_conf.container.as_mut().unwrap().host_app_path,
-                        defaults::PODMAN_PILOT, false
-                    );
+                    apps.push(app_conf);
                 }
             },
             Err(error) => {
@@ -224,7 +547,316 @@ pub fn purge_container(container: &str) {
             }
         };
     }
-    rm(&container.to_string());
+    let mut volumes: Vec<String> = Vec::new();
+    if rm_volumes {
+        volumes = removable_volumes(&apps);
+        if ! confirm_volume_removal(&volumes, yes) {
+            return false
+        }
+    }
+    let mut kept = 0;
+    for app_conf in apps {
+        let host_app_path = app_conf.host_app_path().unwrap();
+        let app_name = app::basename(&host_app_path.to_string());
+        let cid_files = instance_cid_files(&get_podman_ids_dir(), &app_name);
+        match handle_instances_with(
+            &HostRunner, &app_name, &cid_files, app_conf.stop_on_remove()
+        ) {
+            Some(running) => kept += running,
+            None => return false
+        }
+        app::remove(host_app_path, defaults::PODMAN_PILOT, false);
+    }
+    if kept > 0 {
+        // removing the image would force remove the instances
+        warn!("Keeping image {} used by running instance(s)", container);
+    } else {
+        rm(&container.to_string());
+    }
+    remove_volumes(&volumes)
+}
+
+pub fn remove_app(app: &str, rm_volumes: bool, yes: bool) -> bool {
+    /*!
+    Delete the app registration. With rm_volumes the volumes
+    of the app are deleted too. Running instances of the app
+    are stopped if the app was registered with stop_on_remove
+    !*/
+    let app_name = app::basename(&app.to_string());
+    let config_file = format!("{}/{}.yaml", get_flakes_dir(), app_name);
+    let app_conf = app_config::AppConfig::init_from_file(
+        Path::new(&config_file)
+    ).ok();
+    let mut volumes: Vec<String> = Vec::new();
+    if rm_volumes {
+        if let Some(app_conf) = &app_conf {
+            volumes = removable_volumes(std::slice::from_ref(app_conf));
+        }
+        if ! confirm_volume_removal(&volumes, yes) {
+            return false
+        }
+    }
+    let stop_on_remove = app_conf.as_ref()
+        .map(|app_conf| app_conf.stop_on_remove())
+        .unwrap_or_default();
+    let cid_files = instance_cid_files(&get_podman_ids_dir(), &app_name);
+    if handle_instances_with(
+        &HostRunner, &app_name, &cid_files, stop_on_remove
+    ).is_none() {
+        return false
+    }
+    app::remove(app, defaults::PODMAN_PILOT, false) && remove_volumes(&volumes)
+}
+
+pub fn instance_cid_files(ids_dir: &str, app: &str) -> Vec<String> {
+    /*!
+    Find the container ID files podman-pilot wrote for the
+    instances of app. The files are named APP[@NAME]_USER.cid,
+    the user part must name an existing user such that apps
+    sharing a name prefix are told apart
+    !*/
+    let mut cid_files: Vec<String> = Vec::new();
+    let pattern = format!("{}/{}[@_]*.cid", ids_dir, glob::Pattern::escape(app));
+    for cid_file in glob(&pattern).into_iter().flatten().flatten() {
+        let instance = cid_file.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.strip_prefix(app))
+            .and_then(|instance| instance.rsplit_once('_'));
+        let owned = match instance {
+            Some((name, user)) => {
+                (name.is_empty() || name.starts_with('@'))
+                    && get_user_by_name(user).is_some()
+            },
+            None => false
+        };
+        if owned {
+            cid_files.push(cid_file.to_string_lossy().to_string());
+        }
+    }
+    cid_files
+}
+
+pub fn handle_instances_with<R: CommandRunner>(
+    runner: &R, app: &str, cid_files: &[String], stop: bool
+) -> Option<usize> {
+    /*!
+    Check the instances of the given container ID files. The ID
+    files live in a directory writable for all users, only
+    containers carrying the app label of the given app are
+    considered. With stop the instances are force removed,
+    otherwise they are kept and a warning is printed for the
+    running ones. Returns the number of running instances kept
+    or None if handling an instance failed
+    !*/
+    let mut instances: Vec<(&String, String, bool)> = Vec::new();
+    for cid_file in cid_files {
+        let cid = match fs::read_to_string(cid_file) {
+            Ok(cid) => cid.trim().to_string(),
+            Err(_) => continue
+        };
+        let mut call = setup_podman_call("any");
+        call.arg("container").arg("inspect")
+            .arg("--format")
+            .arg(format!(
+                "{{{{index .Config.Labels \"{}\"}}}} {{{{.State.Running}}}}",
+                FLAKE_APP_LABEL
+            ))
+            .arg(&cid);
+        match runner.perform(&mut call) {
+            Ok(output) => {
+                let output = String::from_utf8_lossy(&output.stdout);
+                match output.trim().rsplit_once(' ') {
+                    Some((label, running)) if label == app => {
+                        instances.push((cid_file, cid, running == "true"))
+                    },
+                    _ => debug!(
+                        "Ignoring {}, container {} is not an instance of {}",
+                        cid_file, cid, app
+                    )
+                }
+            },
+            Err(CommandError { base: ProcessError::IO(error), .. }) => {
+                error!("Failed to call podman container inspect: {:?}", error);
+                return None
+            },
+            Err(_) => continue
+        }
+    }
+    let running = instances.iter().filter(|(_, _, running)| *running).count();
+    if ! stop {
+        if running > 0 {
+            warn!(
+                "Keeping {} running instance(s) of {}, register \
+                with --stop-on-remove to stop them on removal",
+                running, app
+            );
+        }
+        return Some(running)
+    }
+    let mut ok = true;
+    for (cid_file, cid, _) in instances {
+        let mut call = setup_podman_call("any");
+        call.arg("rm").arg("--force").arg(&cid);
+        match runner.perform(&mut call) {
+            Ok(_) => {
+                info!("Removed instance: {}", cid);
+                let _ = fs::remove_file(cid_file);
+            },
+            Err(error) => {
+                error!("Failed to remove instance {}: {}", cid, error);
+                ok = false
+            }
+        }
+    }
+    if ok { Some(0) } else { None }
+}
+
+fn removable_volumes(apps: &[app_config::AppConfig]) -> Vec<String> {
+    /*!
+    Volumes of the given apps which may be deleted together
+    with them. These are the named volumes and the host
+    directories recorded as created on registration, minus
+    the volumes still used by any other registered flake
+    !*/
+    let mut volumes: Vec<String> = Vec::new();
+    for app_conf in apps {
+        let created_volumes = app_conf.created_volumes();
+        for volume in app_conf.volumes() {
+            if (! volume.starts_with('/') || created_volumes.contains(&volume))
+                && ! volumes.contains(&volume)
+            {
+                volumes.push(volume);
+            }
+        }
+    }
+    let removed: Vec<&str> = apps.iter()
+        .filter_map(|app_conf| app_conf.host_app_path())
+        .collect();
+    let mut used: Vec<String> = Vec::new();
+    for app_name in app::app_names() {
+        let config_file = format!("{}/{}.yaml", get_flakes_dir(), app_name);
+        if let Ok(app_conf) = app_config::AppConfig::init_from_file(
+            Path::new(&config_file)
+        ) {
+            if ! app_conf.host_app_path()
+                .is_some_and(|path| removed.contains(&path))
+            {
+                used.append(&mut app_conf.volumes());
+            }
+        }
+    }
+    unshared_volumes(volumes, &used)
+}
+
+pub fn unshared_volumes(volumes: Vec<String>, used: &[String]) -> Vec<String> {
+    /*!
+    Drop the volumes which are used by other flakes. A host
+    directory is also kept if a used volume lives below it
+    or if it lives below a used volume
+    !*/
+    volumes.into_iter().filter(|volume| {
+        let shared = used.iter().any(|used| {
+            used == volume || (volume.starts_with('/') && used.starts_with('/')
+                && (Path::new(used).starts_with(volume)
+                    || Path::new(volume).starts_with(used)))
+        });
+        if shared {
+            warn!("Keeping volume used by another flake: {}", volume);
+        }
+        ! shared
+    }).collect()
+}
+
+fn confirm_volume_removal(volumes: &[String], yes: bool) -> bool {
+    /*!
+    List the volumes about to be deleted and ask for
+    confirmation. Without a terminal the removal must be
+    confirmed in advance via yes
+    !*/
+    if volumes.is_empty() || yes {
+        return true
+    }
+    if ! io::stdin().is_terminal() {
+        error!(
+            "Not removing {} volume(s) without confirmation, use --yes",
+            volumes.len()
+        );
+        return false
+    }
+    eprintln!("Volumes to delete:");
+    for volume in volumes {
+        eprintln!("  {}", volume);
+    }
+    eprint!("Continue? [y/N] ");
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err()
+        || ! matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+    {
+        info!("Removal declined");
+        return false
+    }
+    true
+}
+
+pub fn removable_host_dir(path: &str) -> bool {
+    /*!
+    Check if the given volume host path may be deleted. System
+    locations and directories directly below the root are never
+    deleted, even if used as volume
+    !*/
+    let components: Vec<&str> = path.split('/')
+        .filter(|part| ! part.is_empty())
+        .collect();
+    path.starts_with('/')
+        && components.len() > 1
+        && ! components.iter().any(|part| *part == "." || *part == "..")
+        && ! defaults::PROTECTED_HOST_DIRS.iter().any(|protected| {
+            Path::new(path).starts_with(protected)
+        })
+}
+
+pub fn remove_volumes(volumes: &[String]) -> bool {
+    /*!
+    Delete the given named volumes and host directories and
+    report what was removed
+    !*/
+    let mut ok = true;
+    for volume in volumes {
+        if ! volume.starts_with('/') {
+            let mut call = setup_podman_call("any");
+            call.arg("volume").arg("rm").arg(volume);
+            match call.output() {
+                Ok(output) if output.status.success() => {
+                    info!("Removed volume: {}", volume);
+                },
+                Ok(output) => {
+                    error!(
+                        "Failed to remove volume {}: {}", volume,
+                        stderr_excerpt(&output.stderr)
+                    );
+                    ok = false
+                },
+                Err(error) => {
+                    error!("Failed to call podman volume rm: {:?}", error);
+                    ok = false
+                }
+            }
+        } else if ! Path::new(volume).is_dir() {
+            warn!("Keeping volume host path, not a directory: {}", volume);
+        } else if ! removable_host_dir(volume) {
+            warn!("Keeping protected volume host directory: {}", volume);
+        } else {
+            match fs::remove_dir_all(volume) {
+                Ok(_) => info!("Removed volume directory: {}", volume),
+                Err(error) => {
+                    error!("Failed to remove {}: {:?}", volume, error);
+                    ok = false
+                }
+            }
+        }
+    }
+    ok
 }
 
 pub fn print_container_info(container: &str) {
@@ -264,7 +896,56 @@ pub fn print_container_info(container: &str) {
     umount_container(container);
 }
 
+pub fn missing_images(apps: &[String]) -> Vec<String> {
+    /*!
+    Return the names of all given apps registered for podman
+    whose container image is not present in the local registry.
+    The existence of all images is checked with one bulk lookup
+    !*/
+    let mut app_images: Vec<(String, String)> = Vec::new();
+    for app_name in apps {
+        let config_file = format!(
+            "{}/{}.yaml", get_flakes_dir(), app_name
+        );
+        if let Ok(app_conf) =
+            app_config::AppConfig::init_from_file(Path::new(&config_file))
+        {
+            if app_conf.engine() == Some(defaults::PODMAN_PILOT) {
+                app_images.push(
+                    (app_name.to_string(), app_conf.image_refs().remove(0))
+                );
+            }
+        }
+    }
+    if app_images.is_empty() {
+        return Vec::new()
+    }
+    setup_podman_env("any");
+    let names: Vec<&str> = app_images.iter()
+        .map(|(_, image)| image.as_str()).collect();
+    match Container::exists_any(&names, User::default()) {
+        Ok(present) => {
+            app_images.into_iter()
+                .filter(|(_, image)| ! present.contains(image))
+                .map(|(app_name, _)| app_name)
+                .collect()
+        },
+        Err(error) => {
+            error!("Failed to lookup container images: {:?}", error);
+            Vec::new()
+        }
+    }
+}
+
 pub fn setup_podman_call(user: &str) -> Command {
+    setup_podman_env(user);
+    let mut call = Command::new("sudo");
+    call.arg("--preserve-env")
+        .arg(get_podman_path());
+    call
+}
+
+pub fn setup_podman_env(user: &str) {
     let mut current_user = String::new();
     if user == "any" {
         let username = get_current_username().unwrap();
@@ -277,8 +958,4 @@ pub fn setup_podman_call(user: &str) -> Command {
     );
     env::set_var("CONTAINERS_STORAGE_CONF", defaults::FLAKES_STORAGE);
     env::set_var("XDG_RUNTIME_DIR", &container_runroot);
-    let mut call = Command::new("sudo");
-    call.arg("--preserve-env")
-        .arg(defaults::PODMAN_PATH);
-    call
 }
@@ -0,0 +1,153 @@
+//
+// Copyright (c) 2023 SUSE Software Solutions Germany GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use std::env;
+use std::path::Path;
+use std::process::{self, Command};
+use flakes::command::stderr_excerpt;
+
+use crate::podman::{pull, setup_podman_call};
+use crate::{app, app_config, defaults};
+
+pub fn selftest_app_path(dir: &Path) -> String {
+    /*!
+    Host path of the throwaway selftest flake, unique per process
+    !*/
+    format!(
+        "{}/flake-selftest-{}", dir.to_string_lossy(), process::id()
+    )
+}
+
+pub fn run(engine: &str, image: Option<&String>) -> bool {
+    /*!
+    Register a throwaway flake for the given engine, call the
+    selftest target through the pilot and clean up afterwards.
+    Returns true if the target succeeded
+    !*/
+    let app = selftest_app_path(&env::temp_dir());
+    let pilot = match engine {
+        "firecracker" => defaults::FIRECRACKER_PILOT,
+        _ => defaults::PODMAN_PILOT
+    };
+    info!("Running {} selftest as {}", engine, app);
+    let mut ok = match engine {
+        "firecracker" => setup_vm(&app, image),
+        _ => setup_container(&app, image)
+    };
+    if ok {
+        ok = call(&app);
+    }
+    app::remove(&app, pilot, true);
+    if ok {
+        info!("Selftest passed");
+    } else {
+        error!("Selftest failed");
+    }
+    ok
+}
+
+fn setup_container(app: &String, image: Option<&String>) -> bool {
+    /*!
+    Register the selftest flake against a minimal container image,
+    the image is pulled if not present
+    !*/
+    let image = image.cloned()
+        .unwrap_or_else(|| defaults::SELFTEST_IMAGE.to_string());
+    let present = setup_podman_call("any")
+        .arg("image").arg("exists").arg(&image)
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+    if ! present && pull(&image, None) != 0 {
+        error!("Failed to fetch selftest image {}", image);
+        return false
+    }
+    let target = defaults::SELFTEST_TARGET.to_string();
+    app::init(Some(app), false) && app::register(
+        Some(app), Some(&target), defaults::PODMAN_PILOT
+    ) && app::create_container_config(
+        &image, Some(app), Some(&target), &app_config::ContainerOptions {
+            run_as: Some("any".to_string()),
+            ..Default::default()
+        }
+    )
+}
+
+fn setup_vm(app: &String, vm: Option<&String>) -> bool {
+    /*!
+    Register the selftest flake against a pulled VM image. The
+    vsock connection is forced such that the handshake with the
+    sci in the guest is part of the test
+    !*/
+    let vm = match vm {
+        Some(vm) => vm,
+        None => {
+            error!(
+                "No VM image specified, use --image with the name \
+                of an image from flake-ctl firecracker pull"
+            );
+            return false
+        }
+    };
+    let rootfs = format!(
+        "{}/{}/{}", defaults::FIRECRACKER_IMAGES_DIR, vm,
+        defaults::FIRECRACKER_ROOTFS_NAME
+    );
+    if ! Path::new(&rootfs).exists() {
+        error!("VM image {} not found: {} does not exist", vm, rootfs);
+        return false
+    }
+    let target = defaults::SELFTEST_TARGET.to_string();
+    app::init(Some(app), false) && app::register(
+        Some(app), Some(&target), defaults::FIRECRACKER_PILOT
+    ) && app::create_vm_config(
+        vm, Some(app), Some(&target), None, None, None, None, false, true,
+        false, true, None, None, None, None
+    )
+}
+
+fn call(app: &String) -> bool {
+    /*!
+    Call the selftest flake and report its output on failure
+    !*/
+    match Command::new(app).output() {
+        Ok(output) => {
+            if output.status.success() {
+                return true
+            }
+            error!(
+                "{} exited with {}: {}", app, output.status,
+                stderr_excerpt(&output.stderr)
+            );
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if ! stdout.trim().is_empty() {
+                error!("{}", stdout.trim());
+            }
+            false
+        },
+        Err(error) => {
+            error!("Failed to call {}: {:?}", app, error);
+            false
+        }
+    }
+}
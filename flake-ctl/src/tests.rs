@@ -0,0 +1,646 @@
+//
+// Copyright (c) 2022 Elektrobit Automotive GmbH
+// Copyright (c) 2023 Marcus Schäfer
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use crate::app_config::AppConfig;
+use crate::defaults;
+use crate::migrate::{migrate_config, migrate_all_in};
+use crate::compose::{service_to_flake, ComposeService};
+use crate::version::engine_version;
+use crate::podman::{
+    inspect_subset, removable_host_dir, unshared_volumes, valid_build_arg,
+    InspectReport,
+    instance_cid_files, handle_instances_with
+};
+use crate::firecracker::{init_template, snapshot, vm_running};
+use crate::app::{app_path_state, app_names_in, host_app_paths_in, AppPathState};
+use crate::doctor::duplicate_host_app_paths;
+use crate::selftest::{run, selftest_app_path};
+use crate::bundle::set_aside_registration_in;
+use flakes::command::MockRunner;
+use tempfile::{tempdir, NamedTempFile};
+
+fn load_template(template: &str) -> AppConfig {
+    serde_yaml::from_str(template).unwrap()
+}
+
+#[test]
+fn container_config_round_trip() {
+    let mut app_conf = load_template(
+        include_str!("../template/container-flake.yaml")
+    );
+    assert_eq!(app_conf.engine(), Some(defaults::PODMAN_PILOT));
+    let container = app_conf.container.as_mut().unwrap();
+    container.name = "registry.opensuse.org/some/app".to_string();
+    container.host_app_path = "/usr/bin/app".to_string();
+    container.base_container = Some("some/base".to_string());
+    container.layers = Some(vec!["some/layer".to_string()]);
+    container.aliases = Some(vec!["/usr/bin/app2".to_string()]);
+
+    let config_file = NamedTempFile::new().unwrap();
+    app_conf.save(config_file.path()).unwrap();
+    let app_conf = AppConfig::init_from_file(config_file.path()).unwrap();
+
+    assert_eq!(app_conf.engine(), Some(defaults::PODMAN_PILOT));
+    assert_eq!(app_conf.name(), Some("registry.opensuse.org/some/app"));
+    assert_eq!(app_conf.host_app_path(), Some("/usr/bin/app"));
+    assert_eq!(app_conf.aliases(), vec!["/usr/bin/app2"]);
+    assert_eq!(
+        app_conf.image_refs(),
+        vec!["registry.opensuse.org/some/app", "some/base", "some/layer"]
+    );
+    assert_eq!(
+        app_conf.container.unwrap().runtime.unwrap().podman,
+        Some(vec!["-ti".to_string()])
+    );
+}
+
+#[test]
+fn config_save_keeps_unknown_keys() {
+    let mut template = include_str!("../template/container-flake.yaml")
+        .replace(
+            "  runtime:\n",
+            "  runtime:\n    hostname: app\n    status_file: /run/app.status\n"
+        );
+    template.push_str("extra_section:\n  key: value\n");
+    let app_conf = load_template(&template);
+
+    let config_file = NamedTempFile::new().unwrap();
+    app_conf.save(config_file.path()).unwrap();
+    let saved: serde_yaml::Value = serde_yaml::from_str(
+        &std::fs::read_to_string(config_file.path()).unwrap()
+    ).unwrap();
+    assert_eq!(saved["container"]["runtime"]["hostname"], "app");
+    assert_eq!(saved["container"]["runtime"]["status_file"], "/run/app.status");
+    assert_eq!(saved["extra_section"]["key"], "value");
+}
+
+#[test]
+fn vm_config_round_trip() {
+    let mut app_conf = load_template(
+        include_str!("../template/firecracker-flake.yaml")
+    );
+    assert_eq!(app_conf.engine(), Some(defaults::FIRECRACKER_PILOT));
+    let vm = app_conf.vm.as_mut().unwrap();
+    vm.name = "leap".to_string();
+    vm.host_app_path = "/usr/bin/app".to_string();
+    vm.runtime.as_mut().unwrap()
+        .firecracker.as_mut().unwrap()
+        .overlay_size = Some("20GiB".to_string());
+    app_conf.include.oci = Some(vec!["some/app".to_string()]);
+
+    let config_file = NamedTempFile::new().unwrap();
+    app_conf.save(config_file.path()).unwrap();
+    let app_conf = AppConfig::init_from_file(config_file.path()).unwrap();
+
+    assert_eq!(app_conf.engine(), Some(defaults::FIRECRACKER_PILOT));
+    assert_eq!(app_conf.name(), Some("leap"));
+    assert_eq!(app_conf.host_app_path(), Some("/usr/bin/app"));
+    assert_eq!(app_conf.image_refs(), vec!["leap"]);
+    assert_eq!(app_conf.include.oci, Some(vec!["some/app".to_string()]));
+    let firecracker = app_conf.vm.unwrap().runtime.unwrap()
+        .firecracker.unwrap();
+    assert_eq!(firecracker.overlay_size, Some("20GiB".to_string()));
+    assert_eq!(firecracker.mem_size_mib, Some(4096));
+}
+
+#[test]
+fn migrate_old_container_config() {
+    let mut config: serde_yaml::Value = serde_yaml::from_str(
+r#"container:
+  name: app
+  target_app_path: /usr/bin/app
+  host_app_path: /usr/bin/app
+  runas: root
+  podman:
+    - "-ti"
+  runtime:
+    runas: any
+include:
+  tar: some.tar
+"#
+    ).unwrap();
+    let changes = migrate_config(&mut config);
+    assert_eq!(changes.len(), 3);
+    let container = &config["container"];
+    assert!(container.get("runas").is_none());
+    assert_eq!(container["runtime"]["runas"].as_str(), Some("any"));
+    assert_eq!(container["runtime"]["podman"][0].as_str(), Some("-ti"));
+    assert_eq!(config["include"]["tar"][0].as_str(), Some("some.tar"));
+
+    // migrating again is a noop
+    assert!(migrate_config(&mut config).is_empty());
+}
+
+#[test]
+fn migrate_adds_include_section() {
+    let mut config: serde_yaml::Value = serde_yaml::from_str(
+        "vm:\n  name: app\n  resume: true\n"
+    ).unwrap();
+    let changes = migrate_config(&mut config);
+    assert_eq!(changes.len(), 2);
+    assert_eq!(config["vm"]["runtime"]["resume"].as_bool(), Some(true));
+    assert!(config["include"].is_mapping());
+}
+
+#[test]
+fn migrate_all_upgrades_legacy_config() {
+    let tmp = tempdir().unwrap();
+    let flakes_dir = tmp.path().to_str().unwrap();
+    let config_file = tmp.path().join("app.yaml");
+    std::fs::write(&config_file, "vm:\n  name: app\n  resume: true\n")
+        .unwrap();
+    // legacy configs are registered apps even if they don't
+    // match the current schema
+    assert_eq!(app_names_in(flakes_dir), vec!["app"]);
+    assert!(migrate_all_in(flakes_dir));
+    let config: serde_yaml::Value = serde_yaml::from_str(
+        &std::fs::read_to_string(&config_file).unwrap()
+    ).unwrap();
+    assert_eq!(config["vm"]["runtime"]["resume"].as_bool(), Some(true));
+    assert!(config["include"].is_mapping());
+    assert!(tmp.path().join("app.yaml.bak").exists());
+}
+
+#[test]
+fn migrate_current_template_is_noop() {
+    let mut config: serde_yaml::Value = serde_yaml::from_str(
+        include_str!("../template/container-flake.yaml")
+    ).unwrap();
+    assert!(migrate_config(&mut config).is_empty());
+}
+
+#[test]
+fn app_path_state_lookup() {
+    let tmp = tempfile::tempdir().unwrap();
+    let app_path = |name: &str| tmp.path().join(name)
+        .to_str().unwrap().to_string();
+
+    assert_eq!(app_path_state(&app_path("missing")), AppPathState::Free);
+
+    std::os::unix::fs::symlink(defaults::PODMAN_PILOT, app_path("flake"))
+        .unwrap();
+    assert_eq!(app_path_state(&app_path("flake")), AppPathState::Flake);
+
+    std::fs::write(app_path("tool"), "#!/bin/sh\n").unwrap();
+    assert_eq!(app_path_state(&app_path("tool")), AppPathState::Occupied);
+
+    std::os::unix::fs::symlink(app_path("tool"), app_path("link")).unwrap();
+    assert_eq!(app_path_state(&app_path("link")), AppPathState::Occupied);
+}
+
+#[test]
+fn compose_service_to_flake() {
+    let service: serde_yaml::Value = serde_yaml::from_str(r#"
+image: registry.opensuse.org/web
+command: ["/usr/bin/web", "--port", "80"]
+environment:
+  MODE: prod
+  TOKEN:
+volumes:
+  - ./data:/data:ro
+  - cache:/cache
+  - .:/src
+  - ../shared/./lib/..:/shared
+  - type: bind
+    source: /srv
+    target: /srv
+ports:
+  - 8080:80
+cap_drop:
+  - ALL
+tty: true
+restart: always
+"#).unwrap();
+    let (flake, dropped) = service_to_flake(
+        &service, std::path::Path::new("/srv/compose")
+    ).unwrap();
+    assert_eq!(flake, ComposeService {
+        image: "registry.opensuse.org/web".to_string(),
+        target: Some("/usr/bin/web".to_string()),
+        cap_add: vec![],
+        cap_drop: vec!["ALL".to_string()],
+        restart_policy: Some("always".to_string()),
+        opts: vec![
+            "--env MODE=prod".to_string(),
+            "--env TOKEN".to_string(),
+            "--volume /srv/compose/data:/data:ro".to_string(),
+            "--volume cache:/cache".to_string(),
+            "--volume /srv/compose:/src".to_string(),
+            "--volume /srv/shared:/shared".to_string(),
+            "--publish 8080:80".to_string(),
+            "--tty".to_string()
+        ]
+    });
+    assert_eq!(dropped, vec![
+        "arguments '--port 80'", "volumes in long syntax"
+    ]);
+
+    // restart policies without a flake equivalent are dropped
+    for (restart, policy, dropped_setting) in [
+        ("on-failure:3", Some("on-failure"), "restart count of 'on-failure:3'"),
+        ("unless-stopped", None, "restart 'unless-stopped'")
+    ] {
+        let service: serde_yaml::Value = serde_yaml::from_str(&format!(
+            "image: web\nrestart: {}", restart
+        )).unwrap();
+        let (flake, dropped) = service_to_flake(
+            &service, std::path::Path::new("/srv/compose")
+        ).unwrap();
+        assert_eq!(flake.restart_policy.as_deref(), policy);
+        assert_eq!(dropped, vec![dropped_setting]);
+    }
+
+    let build_only: serde_yaml::Value = serde_yaml::from_str(
+        "build: ."
+    ).unwrap();
+    assert!(service_to_flake(
+        &build_only, std::path::Path::new("/compose")
+    ).is_err());
+}
+
+#[test]
+fn engine_version_not_found() {
+    assert!(engine_version("/does/not/exist/podman").is_none());
+    assert!(engine_version("false").is_none());
+}
+
+#[test]
+fn inspect_report_subset() {
+    let inspect: serde_json::Value = serde_json::from_str(r#"[{
+        "Id": "abc",
+        "ImageName": "registry.opensuse.org/aws:latest",
+        "State": {
+            "Status": "running", "Running": true, "Pid": 42,
+            "ExitCode": 0, "StartedAt": "2023-10-01T10:00:00Z",
+            "OOMKilled": false
+        },
+        "Mounts": [{
+            "Type": "bind", "Source": "/data", "Destination": "/srv",
+            "RW": false, "Propagation": "rprivate"
+        }]
+    }]"#).unwrap();
+    let mut report = InspectReport::default();
+    inspect_subset(&mut report, &inspect);
+    assert!(report.running);
+    assert_eq!(report.image.as_deref(), Some("registry.opensuse.org/aws:latest"));
+    let state = report.state.unwrap();
+    assert_eq!(state["Pid"], 42);
+    assert!(state.get("OOMKilled").is_none());
+    assert_eq!(report.mounts.len(), 1);
+    assert_eq!(report.mounts[0]["Destination"], "/srv");
+    assert!(report.mounts[0].get("Propagation").is_none());
+
+    let mut report = InspectReport::default();
+    inspect_subset(&mut report, &serde_json::json!([]));
+    assert!(! report.running);
+    assert!(report.state.is_none());
+}
+
+#[test]
+fn init_template_keeps_existing_template() {
+    let template = NamedTempFile::new().unwrap();
+    let template = template.path().to_str().unwrap();
+    assert!(! init_template(template, false));
+    assert_eq!(std::fs::read_to_string(template).unwrap(), "");
+}
+
+#[test]
+fn app_names_skip_invalid_files() {
+    let tmp = tempdir().unwrap();
+    let dir = tmp.path();
+    let container = include_str!("../template/container-flake.yaml");
+    let vm = include_str!("../template/firecracker-flake.yaml");
+    std::fs::write(dir.join("app.yaml"), container).unwrap();
+    std::fs::write(dir.join("python3.11.yaml"), container).unwrap();
+    std::fs::write(dir.join("vm.yaml"), vm).unwrap();
+    std::fs::write(dir.join("notes.txt"), container).unwrap();
+    std::fs::write(dir.join("broken.yaml"), "container: [").unwrap();
+    std::fs::write(dir.join("other.yaml"), "key: value").unwrap();
+    std::fs::create_dir(dir.join("dir.yaml")).unwrap();
+    std::os::unix::fs::symlink(
+        dir.join("app.yaml"), dir.join("alias.yaml")
+    ).unwrap();
+    std::os::unix::fs::symlink(
+        dir.join("gone.yaml"), dir.join("dangling.yaml")
+    ).unwrap();
+
+    let mut names = app_names_in(dir.to_str().unwrap());
+    names.sort();
+    assert_eq!(names, vec!["app", "python3.11", "vm"]);
+}
+
+#[test]
+fn snapshot_requires_registered_app() {
+    let tmp = tempdir().unwrap();
+    let output = tmp.path().join("changes.tar")
+        .to_string_lossy().to_string();
+    assert!(! vm_running("no-such-app"));
+    assert_eq!(snapshot(&"no-such-app".to_string(), &output), 1);
+    assert!(! std::path::Path::new(&output).exists());
+}
+
+#[test]
+fn build_arg_format() {
+    assert!(valid_build_arg("VERSION=1.2"));
+    assert!(valid_build_arg("EMPTY="));
+    assert!(! valid_build_arg("VERSION"));
+    assert!(! valid_build_arg("=1.2"));
+    assert!(! valid_build_arg("MY VERSION=1.2"));
+}
+
+#[test]
+fn duplicate_host_app_paths_are_reported() {
+    let tmp = tempdir().unwrap();
+    let dir = tmp.path();
+    let container = include_str!("../template/container-flake.yaml");
+    let write_config = |name: &str, host_app_path: &str| {
+        let config = container.replace(
+            "host_app_path: path/to/program/on/host",
+            &format!("host_app_path: {}", host_app_path)
+        );
+        std::fs::write(dir.join(format!("{}.yaml", name)), config).unwrap();
+    };
+    write_config("tool", "/usr/bin/tool");
+    write_config("other", "/usr/bin/tool");
+    write_config("single", "/usr/bin/single");
+
+    let paths = host_app_paths_in(dir.to_str().unwrap());
+    let mut owners = paths.get("/usr/bin/tool").unwrap().clone();
+    owners.sort();
+    assert_eq!(owners, vec!["other", "tool"]);
+    assert_eq!(paths.get("/usr/bin/single").unwrap(), &vec!["single"]);
+
+    let problems = duplicate_host_app_paths(dir.to_str().unwrap());
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("/usr/bin/tool"));
+}
+
+#[test]
+fn selftest_requires_vm_image() {
+    let tmp = tempdir().unwrap();
+    let app = selftest_app_path(tmp.path());
+    assert!(app.starts_with(tmp.path().to_str().unwrap()));
+    assert!(app.ends_with(&format!("flake-selftest-{}", std::process::id())));
+    assert!(! run("firecracker", None));
+    assert!(! run("firecracker", Some(&"no-such-vm".to_string())));
+}
+
+#[test]
+fn volumes_to_remove() {
+    let mut app_conf = load_template(
+        include_str!("../template/container-flake.yaml")
+    );
+    app_conf.container.as_mut().unwrap()
+        .runtime.as_mut().unwrap().podman = Some(vec![
+            "--volume appdata:/var/lib/app".to_string(),
+            "-v /srv/app/cache:/cache:rw,z".to_string(),
+            "--volume=/srv/app/config:/etc/app:ro".to_string(),
+            "--volume /data".to_string(),
+            "-v appdata:/var/lib/app2".to_string(),
+            "-ti".to_string(),
+        ]);
+    assert_eq!(app_conf.volumes(), vec!["appdata", "/srv/app/cache"]);
+
+    assert!(removable_host_dir("/srv/app/cache"));
+    assert!(removable_host_dir("/home/user/data"));
+    assert!(! removable_host_dir("/srv"));
+    assert!(! removable_host_dir("/"));
+    assert!(! removable_host_dir("/etc/app"));
+    assert!(! removable_host_dir("/usr/share/app"));
+    assert!(! removable_host_dir("/srv/app/../../etc"));
+    assert!(! removable_host_dir("srv/app"));
+
+    // only volumes not used by other flakes are removed
+    assert_eq!(
+        unshared_volumes(
+            vec![
+                "appdata".to_string(), "shared".to_string(),
+                "/srv/app/cache".to_string(), "/srv/app/data".to_string()
+            ],
+            &["shared".to_string(), "/srv/app/data/other".to_string()]
+        ),
+        vec!["appdata", "/srv/app/cache"]
+    );
+
+    // missing host directories are created and recorded
+    let tmp = tempdir().unwrap();
+    let created = tmp.path().join("app/cache").to_string_lossy().to_string();
+    let existing = tmp.path().to_string_lossy().to_string();
+    app_conf.container.as_mut().unwrap()
+        .runtime.as_mut().unwrap().podman = Some(vec![
+            format!("--volume {}:/cache", created),
+            format!("--volume {}:/data", existing),
+            "--volume appdata:/var/lib/app".to_string(),
+        ]);
+    assert_eq!(app_conf.create_volume_dirs(None).unwrap(), vec![created.clone()]);
+    assert!(std::path::Path::new(&created).is_dir());
+    assert!(app_conf.create_volume_dirs(None).unwrap().is_empty());
+
+    // the owner is only looked up when a directory gets created
+    let no_user = "no-such-user".to_string();
+    assert!(app_conf.create_volume_dirs(Some(&no_user)).unwrap().is_empty());
+    std::fs::remove_dir(&created).unwrap();
+    assert!(app_conf.create_volume_dirs(Some(&no_user)).is_err());
+
+    // the run_as placeholder any keeps the ownership as is
+    let any = "any".to_string();
+    assert_eq!(
+        app_conf.create_volume_dirs(Some(&any)).unwrap(), vec![created.clone()]
+    );
+}
+
+#[test]
+fn stop_on_remove_instances() {
+    let mut app_conf = load_template(
+        include_str!("../template/container-flake.yaml")
+    );
+    assert!(! app_conf.stop_on_remove());
+    app_conf.container.as_mut().unwrap().stop_on_remove = Some(true);
+    assert!(app_conf.stop_on_remove());
+
+    let ids_dir = tempdir().unwrap();
+    let ids = ids_dir.path().to_str().unwrap();
+    let user = users::get_current_username().unwrap();
+    let user = user.to_str().unwrap();
+    let create_cid_files = || {
+        for (name, cid) in [
+            ("app", "abc"), ("app@web", "def"), ("app2", "ghi"),
+            ("app_other", "jkl")
+        ] {
+            std::fs::write(format!("{}/{}_{}.cid", ids, name, user), cid)
+                .unwrap();
+        }
+    };
+    create_cid_files();
+    let mut cid_files = instance_cid_files(ids, "app");
+    cid_files.sort();
+    assert_eq!(cid_files, vec![
+        format!("{}/app@web_{}.cid", ids, user),
+        format!("{}/app_{}.cid", ids, user)
+    ]);
+
+    // running instances are kept by default, stale ones ignored
+    let runner = MockRunner::new()
+        .respond(0, "app true\n", "")
+        .respond(1, "", "");
+    assert_eq!(handle_instances_with(&runner, "app", &cid_files, false), Some(1));
+    assert_eq!(runner.calls().len(), 2);
+    assert!(cid_files.iter().all(|cid_file| std::path::Path::new(cid_file).exists()));
+
+    // stopped instances are not reported as running
+    let runner = MockRunner::new()
+        .respond(0, "app false\n", "")
+        .respond(0, "app false\n", "");
+    assert_eq!(handle_instances_with(&runner, "app", &cid_files, false), Some(0));
+
+    // ID files pointing to containers of something else are ignored
+    let runner = MockRunner::new()
+        .respond(0, "other true\n", "")
+        .respond(0, " true\n", "");
+    assert_eq!(handle_instances_with(&runner, "app", &cid_files, true), Some(0));
+    assert_eq!(runner.calls().len(), 2);
+    assert!(cid_files.iter().all(|cid_file| std::path::Path::new(cid_file).exists()));
+
+    // with stop_on_remove the instances of the app are removed
+    let runner = MockRunner::new()
+        .respond(0, "app true\n", "")
+        .respond(0, "app false\n", "")
+        .respond(0, "", "")
+        .respond(0, "", "");
+    assert_eq!(handle_instances_with(&runner, "app", &cid_files, true), Some(0));
+    let calls = runner.calls();
+    assert!(calls[0].contains("container inspect --format"));
+    assert!(calls[2].ends_with("rm --force def"));
+    assert!(calls[3].ends_with("rm --force abc"));
+    assert!(cid_files.iter().all(|cid_file| ! std::path::Path::new(cid_file).exists()));
+    assert_eq!(instance_cid_files(ids, "app2").len(), 1);
+}
+
+#[test]
+fn force_import_sets_aside_and_puts_back_registration() {
+    let tmp = tempdir().unwrap();
+    let flakes_dir = tmp.path().join("flakes");
+    let bin_dir = tmp.path().join("bin");
+    std::fs::create_dir_all(flakes_dir.join("app.d")).unwrap();
+    std::fs::create_dir_all(&bin_dir).unwrap();
+    let flakes = flakes_dir.to_str().unwrap();
+    let app = bin_dir.join("app").to_str().unwrap().to_string();
+    let alias = bin_dir.join("app2").to_str().unwrap().to_string();
+    let foreign = bin_dir.join("other").to_str().unwrap().to_string();
+
+    let mut app_conf = load_template(
+        include_str!("../template/container-flake.yaml")
+    );
+    app_conf.container.as_mut().unwrap().aliases = Some(
+        vec![alias.clone(), foreign.clone()]
+    );
+    app_conf.save(&flakes_dir.join("app.yaml")).unwrap();
+    std::fs::write(flakes_dir.join("app.d/extra.yaml"), "old").unwrap();
+    let link = |target: &str, link_name: &std::path::Path| {
+        std::os::unix::fs::symlink(target, link_name).unwrap()
+    };
+    link(defaults::PODMAN_PILOT, std::path::Path::new(&app));
+    link(defaults::PODMAN_PILOT, std::path::Path::new(&alias));
+    link("app.yaml", &flakes_dir.join("app2.yaml"));
+    link("app.d", &flakes_dir.join("app2.d"));
+    // alias path of another flake is not touched
+    link(defaults::PODMAN_PILOT, std::path::Path::new(&foreign));
+    link("x.yaml", &flakes_dir.join("other.yaml"));
+
+    let registration = set_aside_registration_in(flakes, &app).unwrap();
+    for path in [
+        flakes_dir.join("app.yaml"), flakes_dir.join("app.d"),
+        flakes_dir.join("app2.yaml"), flakes_dir.join("app2.d"),
+        bin_dir.join("app"), bin_dir.join("app2")
+    ] {
+        assert!(std::fs::symlink_metadata(&path).is_err(), "{:?}", path);
+    }
+    assert!(std::fs::symlink_metadata(&foreign).is_ok());
+    assert!(std::fs::symlink_metadata(flakes_dir.join("other.yaml")).is_ok());
+
+    // a failed import leaves a partial registration behind
+    link(defaults::PODMAN_PILOT, std::path::Path::new(&app));
+    std::fs::write(flakes_dir.join("app.yaml"), "new").unwrap();
+
+    registration.put_back().unwrap();
+    let restored = AppConfig::init_from_file(&flakes_dir.join("app.yaml")).unwrap();
+    assert_eq!(restored.aliases(), vec![alias.clone(), foreign]);
+    assert_eq!(
+        std::fs::read_to_string(flakes_dir.join("app.d/extra.yaml")).unwrap(),
+        "old"
+    );
+    assert_eq!(
+        std::fs::read_link(&alias).unwrap(),
+        std::path::Path::new(defaults::PODMAN_PILOT)
+    );
+    assert_eq!(
+        std::fs::read_link(flakes_dir.join("app2.yaml")).unwrap(),
+        std::path::Path::new("app.yaml")
+    );
+    assert!(std::fs::read_link(&app).is_ok());
+    // the backup directory is gone
+    assert_eq!(std::fs::read_dir(&flakes_dir).unwrap().count(), 5);
+}
+
+#[test]
+fn force_import_refuses_to_replace_non_flake() {
+    let tmp = tempdir().unwrap();
+    let app = tmp.path().join("tool");
+    std::fs::write(&app, "binary").unwrap();
+    assert!(set_aside_registration_in(
+        tmp.path().to_str().unwrap(), &app.to_str().unwrap().to_string()
+    ).is_err());
+    assert!(app.exists());
+    assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn vm_initrd_path() {
+    let tmp = tempdir().unwrap();
+    let image_dir = tmp.path().to_str().unwrap();
+    // no initrd in the VM image
+    assert_eq!(AppConfig::initrd_path(image_dir, None, false).unwrap(), None);
+
+    // the initrd of the VM image is used unless disabled
+    let image_initrd = tmp.path().join(defaults::FIRECRACKER_INITRD_NAME);
+    std::fs::write(&image_initrd, "initrd").unwrap();
+    assert_eq!(
+        AppConfig::initrd_path(image_dir, None, false).unwrap().as_deref(),
+        image_initrd.to_str()
+    );
+    assert_eq!(AppConfig::initrd_path(image_dir, None, true).unwrap(), None);
+
+    // an explicit initrd must exist and be absolute
+    let custom = tmp.path().join("custom-initrd");
+    let custom_path = custom.to_str().unwrap().to_string();
+    assert!(AppConfig::initrd_path(image_dir, Some(&custom_path), false).is_err());
+    std::fs::write(&custom, "initrd").unwrap();
+    assert_eq!(
+        AppConfig::initrd_path(image_dir, Some(&custom_path), false).unwrap(),
+        Some(custom_path.clone())
+    );
+    assert!(AppConfig::initrd_path(
+        image_dir, Some(&"custom-initrd".to_string()), false
+    ).is_err());
+}
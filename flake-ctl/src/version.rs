@@ -0,0 +1,81 @@
+//
+// Copyright (c) 2023 SUSE Software Solutions Germany GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use std::path::Path;
+use std::process::{Command, Stdio};
+use flakes::config::{check_flakes_config, get_flakes_dir, get_podman_path};
+use flakes::defaults::FLAKES_CONFIG;
+
+use crate::defaults;
+
+pub fn print_version() {
+    /*!
+    Print the flake-ctl version together with the versions of
+    the installed engines and the flakes config in use
+    !*/
+    let not_found = || "not found".to_string();
+    println!("flake-ctl: {}", env!("CARGO_PKG_VERSION"));
+    let flakes_config = check_flakes_config();
+    let podman_path = match flakes_config {
+        Ok(_) => get_podman_path(),
+        Err(_) => flakes::defaults::PODMAN_PATH.to_string()
+    };
+    println!(
+        "podman: {}",
+        engine_version(&podman_path).unwrap_or_else(not_found)
+    );
+    println!(
+        "firecracker: {}",
+        engine_version(defaults::FIRECRACKER).unwrap_or_else(not_found)
+    );
+    if let Err(error) = flakes_config {
+        println!("flakes config: {} (invalid: {})", FLAKES_CONFIG, error);
+        return
+    }
+    if Path::new(FLAKES_CONFIG).exists() {
+        println!("flakes config: {}", FLAKES_CONFIG);
+    } else {
+        println!(
+            "flakes config: {} (not present, using defaults)", FLAKES_CONFIG
+        );
+    }
+    println!("flakes dir: {}", get_flakes_dir());
+}
+
+pub fn engine_version(engine: &str) -> Option<String> {
+    /*!
+    Return the first line of engine --version or None
+    if the engine can't be called
+    !*/
+    let output = Command::new(engine)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    if ! output.status.success() {
+        return None
+    }
+    String::from_utf8_lossy(&output.stdout).lines().next()
+        .map(|line| line.trim().to_string())
+        .filter(|line| ! line.is_empty())
+}
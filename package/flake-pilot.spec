@@ -163,14 +163,23 @@ install -m 644 flakes.yml %{buildroot}/etc/flakes.yml
 /usr/share/bash-completion/completions/flake-ctl
 %doc /usr/share/man/man8/flake-pilot.8.gz
 %doc /usr/share/man/man8/flake-ctl.8.gz
+%doc /usr/share/man/man8/flake-ctl-doctor.8.gz
+%doc /usr/share/man/man8/flake-ctl-selftest.8.gz
 %doc /usr/share/man/man8/flake-ctl-list.8.gz
+%doc /usr/share/man/man8/flake-ctl-export.8.gz
+%doc /usr/share/man/man8/flake-ctl-import.8.gz
+%doc /usr/share/man/man8/flake-ctl-migrate.8.gz
+%doc /usr/share/man/man8/flake-ctl-version.8.gz
 
 %files -n flake-pilot-podman
 %config /etc/flakes/container-flake.yaml
 %config /etc/flakes/storage.conf
 /usr/bin/podman-pilot
 /usr/sbin/flake-registry
+%doc /usr/share/man/man8/flake-ctl-podman-build.8.gz
 %doc /usr/share/man/man8/flake-ctl-podman-load.8.gz
+%doc /usr/share/man/man8/flake-ctl-podman-inspect.8.gz
+%doc /usr/share/man/man8/flake-ctl-podman-logs.8.gz
 %doc /usr/share/man/man8/flake-ctl-podman-pull.8.gz
 %doc /usr/share/man/man8/flake-ctl-podman-register.8.gz
 %doc /usr/share/man/man8/flake-ctl-podman-remove.8.gz
@@ -183,9 +192,12 @@ install -m 644 flakes.yml %{buildroot}/etc/flakes.yml
 %dir /usr/lib/flake-pilot
 %config /etc/flakes/firecracker-flake.yaml
 %config /etc/flakes/firecracker.json
+%doc /usr/share/man/man8/flake-ctl-firecracker-exec.8.gz
+%doc /usr/share/man/man8/flake-ctl-firecracker-init-template.8.gz
 %doc /usr/share/man/man8/flake-ctl-firecracker-pull.8.gz
 %doc /usr/share/man/man8/flake-ctl-firecracker-remove.8.gz
 %doc /usr/share/man/man8/flake-ctl-firecracker-register.8.gz
+%doc /usr/share/man/man8/flake-ctl-firecracker-snapshot.8.gz
 /usr/bin/firecracker-pilot
 %doc /usr/share/man/man8/firecracker-pilot.8.gz
 /usr/lib/flake-pilot/sci
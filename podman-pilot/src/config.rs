@@ -23,25 +23,28 @@
 //
 use lazy_static::lazy_static;
 use serde::Deserialize;
-use std::{env, path::PathBuf, fs};
-use flakes::config::get_flakes_dir;
+use flakes::config::{check_flakes_config, flake_yaml, get_flakes_dir};
+use flakes::error::FlakeError;
+use flakes::io::IncludeMode;
+use crate::app_path::{basename, program_abs_path};
 
 lazy_static! {
-    static ref CONFIG: Config<'static> = load_config();
+    static ref CONFIG: Result<Config<'static>, FlakeError> = load_config();
 }
 
 /// Returns the config singleton
 /// 
 /// Will initialize the config on first call and return the cached version afterwards
 pub fn config() -> &'static Config<'static> {
-    &CONFIG
+    try_config().expect("Config is validated at startup")
 }
 
-fn get_base_path() -> PathBuf {
-    which::which(env::args().next().expect("Arg 0 must be present")).expect("Symlink should exist")
+pub fn try_config() -> Result<&'static Config<'static>, &'static FlakeError> {
+    CONFIG.as_ref()
 }
 
-fn load_config() -> Config<'static> {
+
+fn load_config() -> Result<Config<'static>, FlakeError> {
     /*!
     Read container runtime configuration for given program
 
@@ -51,51 +54,45 @@ fn load_config() -> Config<'static> {
        └── program_name.yaml
 
     Config files below program_name.d are read in alpha sort order
-    and attached to the master program_name.yaml file. The result
-    is send to the Yaml parser
+    and attached to the master program_name.yaml file. If the
+    result contains extends: NAME, the config of the flake NAME
+    is read first and the config of program_name is attached to it.
+    The result is send to the Yaml parser
     !*/
-    let base_path = get_base_path();
-    let base_path  = base_path.file_name().unwrap().to_str().unwrap();
-    let base_yaml = fs::read_to_string(config_file(base_path));
-
-    let mut extra_yamls: Vec<_> = fs::read_dir(config_dir(base_path))
-        .into_iter()
-        .flatten()
-        .flatten()
-        .map(|x| x.path()).collect();
-
-    extra_yamls.sort();
-        
-
-    let full_yaml: String = base_yaml.into_iter().chain(extra_yamls.into_iter().flat_map(fs::read_to_string)).collect();
-    config_from_str(&full_yaml)
-
+    check_flakes_config()?;
+    let base_path = basename(&program_abs_path()?);
+    let base_path = base_path.as_str();
+    let config_error = |message| FlakeError::ConfigError {
+        path: config_file(base_path),
+        message
+    };
+    let full_yaml = flake_yaml(&get_flakes_dir(), base_path, &mut Vec::new())
+        .map_err(config_error)?;
+    config_from_str(&full_yaml).map_err(config_error)
 }
 
-pub fn config_from_str(input: &str) -> Config<'static> {
+pub fn config_from_str(input: &str) -> Result<Config<'static>, String> {
     // Parse into a generic YAML to remove duplicate keys
 
-    let yaml = yaml_rust::YamlLoader::load_from_str(input).unwrap();
-    let yaml = yaml.first().unwrap();
+    let yaml = yaml_rust::YamlLoader::load_from_str(input)
+        .map_err(|error| error.to_string())?;
+    let yaml = yaml.first().ok_or("No configuration found")?;
     let mut buffer = String::new();
-    yaml_rust::YamlEmitter::new(&mut buffer).dump(yaml).unwrap();
+    yaml_rust::YamlEmitter::new(&mut buffer).dump(yaml)
+        .map_err(|error| format!("{:?}", error))?;
 
     // Convert to a String and leak it to make it static
     // Can not use serde_yaml::from_value because of lifetime limitations
     // Safety: This does not cause a reocurring memory leak since `load_config` is only called once
     let content = Box::leak(buffer.into_boxed_str());
-    
-    serde_yaml::from_str(content).unwrap()
+
+    serde_yaml::from_str(content).map_err(|error| error.to_string())
 }
 
 pub fn config_file(program: &str) -> String {
     format!("{}/{}.yaml", get_flakes_dir(), program)
 }
 
-fn config_dir(program: &str) -> String {
-    format!("{}/{}.d", get_flakes_dir(), program)
-}
-
 #[derive(Deserialize)]
 pub struct Config<'a> {
     #[serde(borrow)]
@@ -124,6 +121,27 @@ impl<'a> Config<'a> {
     pub fn paths(&self) -> Vec<&'a str> {
         self.include.path.as_ref().cloned().unwrap_or_default()
     }
+
+    /// Path includes to copy into the instance at provisioning time
+    pub fn copy_paths(&self) -> Vec<&'a str> {
+        match self.include.mode {
+            IncludeMode::Copy => self.paths(),
+            IncludeMode::Mount => Vec::new()
+        }
+    }
+
+    /// Owner of the synced includes, see IncludeSection
+    pub fn include_chown(&self) -> Option<&'a str> {
+        self.include.chown
+    }
+
+    /// Path includes to mount read-only into the instance
+    pub fn mount_paths(&self) -> Vec<&'a str> {
+        match self.include.mode {
+            IncludeMode::Copy => Vec::new(),
+            IncludeMode::Mount => self.paths()
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -131,6 +149,19 @@ pub struct IncludeSection<'a> {
     #[serde(borrow)]
     tar: Option<Vec<&'a str>>,
     path: Option<Vec<&'a str>>,
+
+    /// Copy path includes into the instance or mount them
+    /// read-only. Tar includes are always extracted
+    ///
+    /// Default: copy
+    #[serde(default)]
+    mode: IncludeMode,
+
+    /// Owner of the synced includes in the format UID:GID,
+    /// only applied if the container runs rootless
+    ///
+    /// Default: keep the host ownership
+    chown: Option<&'a str>,
 }
 
 #[derive(Deserialize)]
@@ -145,6 +176,11 @@ pub struct ContainerSection<'a> {
     /// Path of the program to register on the host
     pub host_app_path: &'a str,
 
+    /// Optional entry point of the container. Overrides the
+    /// entry point inferred from target_app_path. The value
+    /// 'default' keeps the entry point of the image
+    pub entrypoint: Option<&'a str>,
+
     /// Optional base container to use with a delta 'container: name'
     ///
     /// If specified the given 'container: name' is expected to be
@@ -163,6 +199,13 @@ pub struct ContainerSection<'a> {
     #[serde(default)]
     layers: Option<Vec<&'a str>>,
 
+    /// Optional platform in the format OS/ARCH[/VARIANT]
+    /// used when pulling the base container and layers
+    ///
+    /// Default: platform of the host
+    #[serde(default)]
+    pub platform: Option<&'a str>,
+
     /// Optional registration setup
     /// Container runtime parameters
     #[serde(default)]
@@ -197,6 +240,161 @@ pub struct RuntimeSection<'a> {
     #[serde(default)]
     pub attach: bool,
 
+    /// Restart policy of a resume container, one of:
+    /// no, on-failure or always. Applied as podman --restart
+    /// option on container creation. The policy always requires
+    /// the podman service/socket to be active
+    ///
+    /// Default: no restart policy
+    #[serde(default)]
+    pub restart_policy: Option<&'a str>,
+
+    /// Hostname of the container, must be a valid DNS label.
+    /// For resume containers the hostname is set on creation
+    /// and persists for all subsequent app calls
+    ///
+    /// Default: hostname assigned by podman
+    #[serde(default)]
+    pub hostname: Option<&'a str>,
+
+    /// Timezone of the container, either a tz database name
+    /// like Europe/Berlin or local to use the host timezone.
+    /// Passed to podman --tz
+    ///
+    /// Default: timezone of the image, usually UTC
+    #[serde(default)]
+    pub timezone: Option<&'a str>,
+
+    /// User to run the app as inside of the container in the
+    /// format name, uid or uid:gid. Applied as podman --user on
+    /// create and exec. Unlike runas, which selects the host
+    /// user calling podman, this selects the container user
+    ///
+    /// Default: default user of the container
+    #[serde(default)]
+    pub exec_user: Option<&'a str>,
+
+    /// Network mode of the container, one of bridge, host, none,
+    /// private, slirp4netns[:OPTIONS], pasta[:OPTIONS],
+    /// container:ID, ns:PATH or the name of a podman network.
+    /// Applied as podman --network on creation
+    ///
+    /// Default: podman default network mode
+    #[serde(default)]
+    pub network: Option<&'a str>,
+
+    /// Name of an existing podman pod the container joins.
+    /// The container shares the namespaces of the pod, e.g.
+    /// its network, thus network and hostname can't be set.
+    /// Applied as podman --pod on creation
+    ///
+    /// Default: no pod
+    #[serde(default)]
+    pub pod: Option<&'a str>,
+
+    /// Limit of memory plus swap the container may use in the
+    /// podman --memory-swap format, e.g. 2g or -1 for unlimited.
+    /// Must not be lower than a memory limit set through the
+    /// podman --memory runtime argument
+    ///
+    /// Default: podman default
+    #[serde(default)]
+    pub memory_swap: Option<&'a str>,
+
+    /// OOM killer score adjustment of the container in the
+    /// range -1000 (never kill) to 1000 (kill first)
+    ///
+    /// Default: podman default
+    #[serde(default)]
+    pub oom_score_adj: Option<i32>,
+
+    /// Size of /dev/shm in the container, e.g. 512MiB or 2GB.
+    /// The size is passed as number of bytes to podman --shm-size
+    ///
+    /// Default: podman default
+    #[serde(default)]
+    pub shm_size: Option<&'a str>,
+
+    /// Size of the container root filesystem, e.g. 10GiB. The size
+    /// is passed as number of bytes to podman --storage-opt size=
+    /// and requires a storage driver with quota support
+    ///
+    /// Default: no size limit
+    #[serde(default)]
+    pub storage_size: Option<&'a str>,
+
+    /// Maximum number of processes in the container, -1 for
+    /// unlimited. Passed to podman --pids-limit
+    ///
+    /// Default: podman default
+    #[serde(default)]
+    pub pids_limit: Option<i64>,
+
+    /// Resource limits of the container in the format:
+    /// - NAME=SOFT[:HARD]
+    ///
+    /// Each entry is translated into a podman --ulimit option
+    ///
+    /// Default: podman default
+    #[serde(default)]
+    pub ulimits: Option<Vec<&'a str>>,
+
+    /// Parent cgroup of the container, either a systemd slice
+    /// name like flakes.slice or an absolute cgroup path.
+    /// Passed to podman --cgroup-parent
+    ///
+    /// Default: podman default
+    #[serde(default)]
+    pub cgroup_parent: Option<&'a str>,
+
+    /// CPUs the container is pinned to as comma separated list
+    /// of CPU numbers or ranges like 0-3,5. Passed to podman
+    /// --cpuset-cpus
+    ///
+    /// Default: no pinning
+    #[serde(default)]
+    pub cpuset: Option<&'a str>,
+
+    /// Signal to stop the container with, either a name like
+    /// SIGTERM or a number. Passed to podman --stop-signal
+    ///
+    /// Default: podman default
+    #[serde(default)]
+    pub stop_signal: Option<&'a str>,
+
+    /// Seconds to wait for the container to stop after the
+    /// stop signal before it gets killed. Passed to podman
+    /// --stop-timeout
+    ///
+    /// Default: podman default
+    #[serde(default)]
+    pub stop_timeout: Option<u32>,
+
+    /// Run a minimal init as PID 1 of the container which
+    /// forwards signals and reaps zombie processes. Passed
+    /// as podman --init on creation
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub init: bool,
+
+    /// Names of secrets from the podman secret store to
+    /// provide to the container. Each entry is translated
+    /// into a podman --secret option. All secrets must exist
+    /// at the time the container gets created
+    ///
+    /// Default: no secrets
+    #[serde(default)]
+    pub secrets: Option<Vec<&'a str>>,
+
+    /// Container labels in the format key=value. Each entry is
+    /// translated into a podman --label option such that the
+    /// container can be found via podman ps --filter label=
+    ///
+    /// Default: no labels
+    #[serde(default)]
+    pub labels: Option<Vec<&'a str>>,
+
     /// Caller arguments for the podman engine in the format:
     /// - PODMAN_OPTION_NAME_AND_OPTIONAL_VALUE
     ///
@@ -204,4 +402,95 @@ pub struct RuntimeSection<'a> {
     /// podman documentation.
     #[serde(default)]
     pub podman: Option<Vec<&'a str>>,
+
+    /// Host devices to pass into the container in the format:
+    /// - HOST_DEVICE[:CONTAINER_DEVICE][:PERMISSIONS]
+    ///
+    /// Each entry is translated into a podman --device option
+    ///
+    /// Default: no devices
+    #[serde(default)]
+    pub devices: Option<Vec<&'a str>>,
+
+    /// Capabilities to add to the container, each entry is
+    /// translated into a podman --cap-add option
+    ///
+    /// Default: podman default capabilities
+    #[serde(default)]
+    pub cap_add: Option<Vec<&'a str>>,
+
+    /// Capabilities to drop from the container, each entry is
+    /// translated into a podman --cap-drop option. Use ALL to
+    /// drop all capabilities and add back the required ones
+    /// via cap_add
+    ///
+    /// Default: podman default capabilities
+    #[serde(default)]
+    pub cap_drop: Option<Vec<&'a str>>,
+
+    /// Command to run on the host as the calling user if the
+    /// provisioning or the run of the instance fails. The failure
+    /// reason is passed via the FLAKE_FAILURE_REASON environment
+    /// variable. The hook runs best-effort and does not change
+    /// the exit code of the pilot
+    ///
+    /// Default: no hook
+    #[serde(default)]
+    pub on_failure: Option<&'a str>,
+
+    /// Octal umask like "0027" applied while provisioning the
+    /// instance, e.g. for syncing includes and creating their
+    /// parent directories. Commands called via sudo combine it
+    /// with the umask configured for sudo. Modes preserved from
+    /// the sources are not changed
+    ///
+    /// Default: umask of the calling process
+    #[serde(default)]
+    pub provision_umask: Option<&'a str>,
+
+    /// Log level of the pilot for this flake, one of: off,
+    /// error, warn, info, debug or trace. Takes precedence
+    /// over the global logger setup, debug and trace also
+    /// enable the debug output as PILOT_DEBUG does. If
+    /// PILOT_DEBUG is set, the level is always trace
+    ///
+    /// Default: global logger setup
+    #[serde(default)]
+    pub log_level: Option<&'a str>,
+
+    /// Command to run once through sh inside of the container
+    /// after it was started and before the app runs. For resume
+    /// containers it is called via podman exec right after the
+    /// container start, for all other containers it is called
+    /// from a wrapper in front of the app. A failing hook aborts
+    /// the launch with the exit code of the hook
+    ///
+    /// Default: no hook
+    #[serde(default)]
+    pub post_start: Option<&'a str>,
+
+    /// Readiness condition of a resume container in the format
+    /// port:N or file:/path. After the container start the app
+    /// call waits until a socket listens on the TCP port N or
+    /// the file exists inside of the container
+    ///
+    /// Default: no wait
+    #[serde(default)]
+    pub ready_wait: Option<&'a str>,
+
+    /// Seconds to wait for the ready_wait condition before the
+    /// launch fails
+    ///
+    /// Default: 30
+    #[serde(default)]
+    pub ready_timeout: Option<u64>,
+
+    /// Absolute path of a file on the host to which the state
+    /// (starting, running or stopped), the container ID and the
+    /// start time of a resume container is written. The file
+    /// is deleted with the garbage collection of the container
+    ///
+    /// Default: no status file
+    #[serde(default)]
+    pub status_file: Option<&'a str>,
 }
@@ -22,10 +22,21 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 //
-pub const GC_THRESHOLD: i32 = 20;
+pub const GC_THRESHOLD: usize = 20;
+pub const RSYNC_VANISHED_SOURCE_FILES: i32 = 24;
 pub const HOST_DEPENDENCIES: &str = "removed";
 pub const SYSTEM_HOST_DEPENDENCIES: &str = "systemfiles";
-pub const PODMAN_PATH: &str = "/usr/bin/podman";
+pub const SYSTEM_HOST_DEPENDENCIES_LIBS: &str = "systemfiles.libs";
 pub const FLAKES_STORAGE: &str = "/etc/flakes/storage.conf";
 pub const FLAKES_REGISTRY: &str = "/usr/share/flakes/storage";
 pub const FLAKES_REGISTRY_RUNROOT: &str = "/run/flakes";
+pub const ENTRYPOINT_IMAGE_DEFAULT: &str = "default";
+pub const STOP_TIMEOUT: u32 = 10;
+pub const READY_TIMEOUT: u64 = 30;
+pub const READY_POLL_MSEC: u64 = 250;
+pub const IMAGE_MOUNT_UNSUPPORTED: [&str; 3] = [
+    "podman unshare", "rootless mode", "operation not supported"
+];
+pub const LAYER_EXPORT_ROOT: &str = "rootfs";
+pub const LAYER_EXPORT_ARCHIVE: &str = "layer.tar";
+pub const EXEC_ID_ENV: &str = "FLAKE_EXEC_ID";
@@ -22,60 +22,40 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 //
-#[macro_use]
-extern crate log;
-
-#[cfg(test)]
-pub mod tests;
-
+use std::env;
 use std::process::{ExitCode, Termination};
 
-use config::config;
-use env_logger::Env;
-use flakes::error::FlakeError;
-
-pub mod app_path;
-pub mod podman;
-pub mod defaults;
-pub mod config;
+use env_logger::{Builder, Env};
+use flakes::flakelog::FlakeLog;
 
 fn main() -> ExitCode {
-    setup_logger();
-    // load config now so we can terminate early if the config is invalid
-    config();
-    // past here there should be no more panics
-
-    let result = run();
-
-    // TODO: implement cleanup function 
-    // cleanup()
+    let program = env::args().next().expect("Arg 0 must be present");
+    setup_logger(&program);
 
-    match result {
-        Ok(()) => ExitCode::SUCCESS,
-        Err(err) => {
-            error!("{err}");
-            err.report()
-        },
+    match podman_pilot::run_flake(&program) {
+        Ok(code) => code,
+        Err(err) => err.report()
     }
 }
 
-fn run() -> Result<(), FlakeError> {
-
-    let program_path = app_path::program_abs_path();
-    let program_name = app_path::basename(&program_path);
-
-    let container = podman::create(&program_name)?;
-    let cid = &container.0;
-    podman::start(
-        &program_name,
-        cid
-    )
-}
-
-fn setup_logger() {
+fn setup_logger(program: &str) {
+    // suppress info level chatter for machine readable output
+    let level = if FlakeLog::is_machine_readable() { "warn" } else { "debug" };
     let env = Env::default()
-        .filter_or("MY_LOG_LEVEL", "debug")
+        .filter_or("MY_LOG_LEVEL", level)
         .write_style_or("MY_LOG_STYLE", "always");
 
-    env_logger::init_from_env(env);
+    // the log_level of the flake config takes precedence
+    let mut builder = Builder::from_env(env);
+    let log_level = podman_pilot::log_level(program);
+    if let Some(level) = FlakeLog::level_override(
+        log_level, FlakeLog::is_debug()
+    ) {
+        // a debug or trace log_level also enables the pilot debug output
+        if matches!(level, "debug" | "trace") {
+            env::set_var("PILOT_DEBUG", "1");
+        }
+        builder.parse_filters(level);
+    }
+    builder.init();
 }
@@ -30,26 +30,41 @@ use atty::Stream;
 use flakes::user::{User, mkdir};
 use flakes::lookup::Lookup;
 use flakes::io::IO;
-use flakes::error::FlakeError;
-use flakes::command::{CommandError, CommandExtTrait};
+use flakes::error::{FlakeError, OperationError};
+use flakes::command::{
+    CommandError, CommandExtTrait, CommandRunner, HostRunner, ProcessError
+};
 use flakes::container::Container;
-use flakes::config::get_podman_ids_dir;
+use flakes::flakelog::FlakeLog;
+use flakes::report::ProvisionReport;
+use flakes::signals::SignalForward;
+use flakes::config::{
+    get_podman_ids_dir, get_podman_path, get_podman_storage_driver,
+    get_registry_mirrors
+};
+use flakes::defaults::{
+    PODMAN_RESTART_POLICIES, PODMAN_NETWORK_MODES, PODMAN_ULIMITS,
+    LINUX_SIGNALS, FLAKE_APP_LABEL
+};
 
 use std::io;
+use std::collections::HashMap;
 use std::path::Path;
 use std::process::{Command, Output, Stdio};
 use std::env;
+use std::time::{Duration, Instant};
+use std::thread;
 use std::fs;
 use std::io::{Write, Read};
 use std::fs::File;
 use std::io::Seek;
 use std::io::SeekFrom;
+use tempfile::TempDir;
 
 use spinoff::{Spinner, spinners, Color};
-use tempfile::tempfile;
-use regex::Regex;
+use regex::{Captures, Regex};
 
-use users::{get_current_username};
+use users::{get_current_username, get_current_uid};
 
 pub fn create(
     program_name: &String
@@ -73,6 +88,16 @@ pub fn create(
       target_app_path: path/to/program/in/container
       host_app_path: path/to/program/on/host
 
+      # Optional entry point of the container, overrides the
+      # target_app_path as the command to start non resume
+      # containers with. Use 'default' to keep the entry point
+      # of the image while still passing the arguments to it.
+      # Can't be combined with resume
+      #
+      # Default: target_app_path, or the image entry point
+      # if target_app_path is /
+      entrypoint: path/to/entrypoint|default
+
       # Optional base container to use with a delta 'container: name'
       # If specified the given 'container: name' is expected to be
       # an overlay for the specified base_container. podman-pilot
@@ -88,6 +113,10 @@ pub fn create(
         - name_A
         - name_B
 
+      # Optional platform used when pulling the base container
+      # and layers
+      platform: linux/arm64
+
       runtime:
         # Resume the container from previous execution.
         # If the container is still running, the app will be
@@ -96,6 +125,132 @@ pub fn create(
         # Default: false
         resume: true|false
 
+        # Restart policy of a resume container, applied
+        # as podman --restart option. The policy always
+        # requires the podman service/socket to be active
+        #
+        # Default: no restart policy
+        restart_policy: no|on-failure|always
+
+        # Hostname of the container, must be a valid DNS label
+        #
+        # Default: hostname assigned by podman
+        hostname: name
+
+        # Timezone of the container, a tz database name or local
+        #
+        # Default: timezone of the image
+        timezone: Europe/Berlin
+
+        # User to run the app as inside of the container
+        # in the format name, uid or uid:gid
+        #
+        # Default: default user of the container
+        exec_user: name|uid|uid:gid
+
+        # Network mode of the container or name of a podman network
+        #
+        # Default: podman default network mode
+        network: bridge|host|none|NAME
+
+        # Existing podman pod to join, conflicts with
+        # network and hostname
+        #
+        # Default: no pod
+        pod: name
+
+        # Memory plus swap limit, must not be lower than
+        # a --memory limit from the podman arguments
+        #
+        # Default: podman default
+        memory_swap: 2g
+
+        # OOM killer score adjustment from -1000 to 1000
+        #
+        # Default: podman default
+        oom_score_adj: -500
+
+        # Size of /dev/shm in the container
+        #
+        # Default: podman default
+        shm_size: 2GiB
+
+        # Size of the container root filesystem
+        #
+        # Default: no size limit
+        storage_size: 10GiB
+
+        # Process limit and resource limits of the container
+        #
+        # Default: podman default
+        pids_limit: 512
+        ulimits:
+          - nofile=1024:4096
+
+        # Parent cgroup of the container, a systemd slice name
+        # or an absolute cgroup path
+        #
+        # Default: podman default
+        cgroup_parent: flakes.slice
+
+        # CPUs to pin the container to
+        #
+        # Default: no pinning
+        cpuset: 0-3,5
+
+        # Resume containers only: condition port:N or file:/path
+        # to wait for inside of the container after its start and
+        # before the app is called, and the seconds to wait for it
+        #
+        # Default: no wait, ready_timeout: 30
+        ready_wait: port:8080
+        ready_timeout: 60
+
+        # Resume containers only: file on the host to which the
+        # state, container ID and start time is written as
+        # key=value lines, e.g. for monitoring
+        #
+        # Default: no status file
+        status_file: /run/myapp.status
+
+        # Signal to stop the container with and seconds to wait
+        # for it to stop before it gets killed. If set, the
+        # container is stopped this way before it gets removed
+        #
+        # Default: podman default, removal kills the container
+        stop_signal: SIGTERM
+        stop_timeout: 30
+
+        # Run a minimal init as PID 1 which reaps zombies
+        #
+        # Default: false
+        init: true|false
+
+        # Secrets from the podman secret store to provide to
+        # the container, they must exist prior the container start
+        #
+        # Default: no secrets
+        secrets:
+          - aws_credentials
+
+        # Labels set on the container in the format key=value.
+        # Unlike OCI annotations, which are only passed to the
+        # OCI runtime, labels are stored with the container and
+        # can be used to filter podman ps and podman inspect.
+        # The key io.flake-pilot.app is reserved for the app name
+        #
+        # Default: no labels
+        labels:
+          - com.example.team=infra
+
+        # Capabilities to drop from and add to the container
+        #
+        # Default: podman default capabilities
+        cap_drop:
+          - ALL
+        cap_add:
+          - NET_BIND_SERVICE
+
         # Attach to the container if still running, rather than
         # executing the app again. Only makes sense for interactive
         # sessions like a shell running as app in the container.
@@ -107,19 +262,58 @@ pub fn create(
           - --storage-opt size=10G
           - -ti
 
+        devices:
+          - /dev/ttyUSB0
+          - /dev/dri/renderD128:/dev/dri/renderD128:rw
+
+        on_failure: logger "flake failed: $FLAKE_FAILURE_REASON"
+
+        # Octal umask applied while provisioning, combined
+        # with the umask of sudo for privileged commands
+        provision_umask: "0027"
+
+        # Log level of the pilot for this flake, debug and trace
+        # also enable the debug output like PILOT_DEBUG does
+        #
+        # Default: global logger setup
+        log_level: debug
+
+        # Command to run once inside of the container after its
+        # start and before the app is called
+        post_start: /usr/bin/migrate-db
+
     include:
       tar:
         - tar-archive-file-name-to-include
       path:
         - file-or-directory-to-include
+      # copy path includes at provisioning time or mount
+      # them read-only into the container
+      mode: copy|mount
+      # owner of the synced includes for rootless containers
+      chown: 1000:1000
 
     Calling this method returns a vector including the
     container ID and and the name of the container ID
     file.
     !*/
+    // Fail early if podman is not installed
+    let podman_path = get_podman_path();
+    let podman_installed = if podman_path.contains('/') {
+        Path::new(&podman_path).is_file()
+    } else {
+        Lookup::which(&podman_path)
+    };
+    if ! podman_installed {
+        return Err(FlakeError::EngineNotFound(podman_path))
+    }
+
+    // Fail early on an invalid %app override
+    Lookup::get_app_override()?;
+
     // Read optional @NAME pilot argument to differentiate
     // simultaneous instances of the same container application
-    let (name, _): (Vec<_>, Vec<_>) = env::args().skip(1).partition(|arg| arg.starts_with('@'));
+    let (name, _): (Vec<_>, Vec<_>) = Lookup::args().into_iter().skip(1).partition(|arg| arg.starts_with('@'));
 
     // setup container ID file name
     let suffix = name.first().map(String::as_str).unwrap_or("");
@@ -128,7 +322,15 @@ pub fn create(
     let target_app_path = get_target_app_path(program_name);
 
     // get runtime section
-    let RuntimeSection { resume, attach, podman, .. } = config().runtime();
+    let RuntimeSection {
+        resume, attach, podman, devices, restart_policy, hostname, timezone,
+        exec_user,
+        network, pod,
+        memory_swap, oom_score_adj, shm_size, storage_size, cap_add, cap_drop,
+        post_start,
+        pids_limit, ulimits, cgroup_parent, cpuset, stop_signal, stop_timeout,
+        init, ready_wait, secrets, labels, status_file, ..
+    } = config().runtime();
 
     // provisioning needs root permissions for mount
     // make sure we have them for this session
@@ -153,7 +355,7 @@ pub fn create(
 
     mkdir(&container_runroot, "777", User::ROOT)?;
 
-    let mut app = user.run("podman");
+    let mut app = user.run(get_podman_path());
     app.arg("create")
         .arg("--cidfile").arg(&container_cid_file);
 
@@ -162,9 +364,19 @@ pub fn create(
 
     env::set_var("CONTAINERS_STORAGE_CONF", defaults::FLAKES_STORAGE);
     env::set_var("XDG_RUNTIME_DIR", &container_runroot);
+    Container::podman_setup_storage_driver()?;
 
     let _ = Container::podman_setup_run_permissions();
 
+    // A reused container keeps the working directory it was created
+    // with, the caller's directory can't be passed in per call
+    if (resume || attach) && IO::workdir()?.is_some() {
+        return Err(FlakeError::ConfigError {
+            path: crate::config::config_file(program_name),
+            message: "%cwd can't be used with resume or attach".to_string()
+        })
+    }
+
     // Check early return condition in resume mode
     if Path::new(&container_cid_file).exists() && gc_cid_file(&container_cid_file, user)? && (resume || attach) {
         // resume or attach mode is active and container exists
@@ -181,25 +393,230 @@ pub fn create(
         return Err(FlakeError::AlreadyRunning);
     }
 
+    // Fail early on broken includes
+    IO::validate_includes(config().tars(), config().paths())?;
+    if let Some(chown) = config().include_chown() {
+        IO::check_chown(chown).map_err(config_error(program_name))?;
+    }
+    if let Some(provision_umask) = config().runtime().provision_umask {
+        IO::parse_umask(provision_umask)?;
+    }
+
     // create the container with configured runtime arguments
     for arg in podman.iter().flatten().flat_map(|x| x.splitn(2, ' ')) {
-        let mut arg_value = arg.to_string();
-        let var_pattern = Regex::new(r"%([A-Z]+)").unwrap();
-        while var_pattern.captures(&arg_value.clone()).is_some() {
-            for capture in var_pattern.captures_iter(&arg_value.clone()) {
-                // replace %VAR placeholder(s) with the respective
-                // environment variable value if possible.
-                // If not possible replace by the variable name
-                let var_name = capture.get(1).unwrap().as_str();
-                let var_value = env::var(var_name)
-                    .unwrap_or(format!("${}", var_name));
-                arg_value = arg_value.replace(
-                    &format!("%{}", var_name), &var_value
-                );
+        app.arg(expand_vars(arg));
+    };
+
+    // mount path includes read-only instead of copying them
+    for path in config().mount_paths() {
+        let (source, destination) = IO::include_path(path)?;
+        if ! Path::new(source).exists() {
+            return Err(FlakeError::IOError {
+                kind: "NotFound".to_string(),
+                message: format!("Include path {} does not exist", source)
+            })
+        }
+        app.arg("--volume").arg(format!("{}:{}:ro", source, destination));
+    }
+
+    // pass the caller's working directory if requested via %cwd
+    if let Some(workdir) = IO::workdir()? {
+        app.arg("--volume").arg(format!("{0}:{0}", workdir))
+            .arg("--workdir").arg(&workdir);
+        let userns = podman.iter().flatten().any(
+            |arg| arg.starts_with("--userns")
+        );
+        if get_current_uid() != 0 && ! userns {
+            // keep the caller's ids such that data in the
+            // working directory is owned by the calling user
+            app.arg("--userns=keep-id");
+        }
+    }
+
+    // pass through configured host devices
+    for device in devices.iter().flatten() {
+        check_device(device)?;
+        app.arg("--device").arg(device);
+    }
+
+    // apply restart policy for resume containers
+    if let Some(restart_policy) = restart_policy {
+        if ! PODMAN_RESTART_POLICIES.contains(&restart_policy) {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: format!(
+                    "Invalid restart_policy {}, use one of: {}",
+                    restart_policy, PODMAN_RESTART_POLICIES.join(", ")
+                )
+            })
+        }
+        if resume {
+            app.arg("--restart").arg(restart_policy);
+        }
+    }
+
+    // set container hostname
+    if let Some(hostname) = hostname {
+        check_hostname(hostname).map_err(config_error(program_name))?;
+        app.arg("--hostname").arg(hostname);
+    }
+
+    // set container timezone
+    if let Some(timezone) = timezone {
+        check_timezone(timezone).map_err(config_error(program_name))?;
+        app.arg("--tz").arg(timezone);
+    }
+
+    // set container network mode
+    if let Some(network) = network {
+        check_network(network).map_err(config_error(program_name))?;
+        if network == "host" && get_current_uid() != 0 {
+            warn!(
+                "Host network in rootless mode can't bind privileged ports"
+            );
+        }
+        app.arg("--network").arg(network);
+    }
+
+    // join an existing pod
+    if let Some(pod) = pod {
+        if network.is_some() || hostname.is_some() {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: "pod can't be used with network or hostname"
+                    .to_string()
+            })
+        }
+        add_pod_with(&HostRunner, &mut app, pod, user)?;
+    }
+
+    // set the user to run the app inside of the container
+    if let Some(exec_user) = exec_user {
+        check_exec_user(exec_user).map_err(config_error(program_name))?;
+        app.arg("--user").arg(exec_user);
+    }
+
+    // set memory swap limit and OOM score
+    if let Some(memory_swap) = memory_swap {
+        check_memory_swap(
+            memory_swap, podman.as_deref().unwrap_or_default()
+        ).map_err(config_error(program_name))?;
+        app.arg("--memory-swap").arg(memory_swap);
+    }
+    if let Some(oom_score_adj) = oom_score_adj {
+        if ! (-1000..=1000).contains(&oom_score_adj) {
+            return Err(config_error(program_name)(format!(
+                "oom_score_adj {} not in range -1000..1000", oom_score_adj
+            )))
+        }
+        app.arg("--oom-score-adj").arg(oom_score_adj.to_string());
+    }
+
+    // set size of /dev/shm
+    if let Some(shm_size) = shm_size {
+        let size = parse_shm_size(shm_size)
+            .map_err(config_error(program_name))?;
+        app.arg("--shm-size").arg(size.to_string());
+    }
+
+    // set size of the container root filesystem
+    if let Some(storage_size) = storage_size {
+        let size = Container::parse_storage_size(
+            storage_size, get_podman_storage_driver().as_deref()
+        ).map_err(config_error(program_name))?;
+        app.arg("--storage-opt").arg(format!("size={}", size));
+    }
+
+    // set process and resource limits
+    if let Some(pids_limit) = pids_limit {
+        if pids_limit < -1 {
+            return Err(config_error(program_name)(format!(
+                "pids_limit {} must be -1 or a positive number", pids_limit
+            )))
+        }
+        app.arg("--pids-limit").arg(pids_limit.to_string());
+    }
+    for ulimit in ulimits.iter().flatten() {
+        check_ulimit(ulimit).map_err(config_error(program_name))?;
+        app.arg("--ulimit").arg(ulimit);
+    }
+
+    // place the container below the given cgroup
+    if let Some(cgroup_parent) = cgroup_parent {
+        check_cgroup_parent(cgroup_parent).map_err(config_error(program_name))?;
+        app.arg("--cgroup-parent").arg(cgroup_parent);
+    }
+
+    // pin the container to the given CPUs
+    if let Some(cpuset) = cpuset {
+        IO::check_cpuset(cpuset)?;
+        app.arg("--cpuset-cpus").arg(cpuset);
+    }
+
+    // readiness is only waited for on start of resume containers
+    if let Some(ready_wait) = ready_wait {
+        if ! resume {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: "ready_wait requires resume".to_string()
+            })
+        }
+        ready_check(ready_wait).map_err(config_error(program_name))?;
+    }
+
+    // instance state reporting for monitors
+    if let Some(status_file) = status_file {
+        if ! resume || ! status_file.starts_with('/') {
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: "status_file must be an absolute path \
+                    and requires resume".to_string()
+            })
+        }
+    }
+
+    // set graceful stop of the container
+    if let Some(stop_signal) = stop_signal {
+        check_stop_signal(stop_signal).map_err(config_error(program_name))?;
+        app.arg("--stop-signal").arg(stop_signal);
+    }
+    if let Some(stop_timeout) = stop_timeout {
+        app.arg("--stop-timeout").arg(stop_timeout.to_string());
+    }
+
+    // run an init process reaping zombies as PID 1
+    if init {
+        app.arg("--init");
+    }
+
+    // set container labels, the app label lets flake-ctl tell
+    // the instances of the app apart from other containers
+    app.arg("--label").arg(
+        format!("{}={}", FLAKE_APP_LABEL, program_name)
+    );
+    if let Some(labels) = labels {
+        Container::check_labels(&labels)?;
+        for label in labels {
+            app.arg("--label").arg(label);
+        }
+    }
+
+    // provide secrets from the podman secret store
+    if let Some(secrets) = secrets {
+        add_secrets_with(&HostRunner, &mut app, &secrets, user)?;
+    }
+
+    // adjust container capabilities
+    for (option, capabilities) in [
+        ("--cap-drop", &cap_drop), ("--cap-add", &cap_add)
+    ] {
+        for capability in capabilities.iter().flatten() {
+            if ! Container::known_capability(capability) {
+                warn!("Unknown capability {}, passed as is", capability);
             }
+            app.arg(option).arg(capability);
         }
-        app.arg(arg_value);
-    };
+    }
 
     // set default runtime arguments if none configured
     let has_runtime_args = podman
@@ -208,12 +625,36 @@ pub fn create(
         app.arg("--tty").arg("--interactive");
     }
 
-    if target_app_path != "/" {
-        if resume {
+    let entrypoint = get_entrypoint(
+        config().container.entrypoint, &target_app_path
+    );
+    if resume {
+        if config().container.entrypoint.is_some() {
+            // the sleep entry point keeps the container running
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message: "entrypoint can't be used with resume".to_string()
+            })
+        }
+        if target_app_path != "/" {
             app.arg("--entrypoint").arg("sleep");
-        } else {
-            app.arg("--entrypoint").arg(target_app_path.clone());
         }
+    } else if post_start.is_some() {
+        if entrypoint.is_none() {
+            // the container configured entry point is unknown
+            // and can't be called after the hook
+            return Err(FlakeError::ConfigError {
+                path: crate::config::config_file(program_name),
+                message:
+                    "post_start requires a target_app_path or entrypoint"
+                    .to_string()
+            })
+        }
+        // the post_start hook is called from a wrapper
+        // in front of the app
+        app.arg("--entrypoint").arg("sh");
+    } else if let Some(entrypoint) = &entrypoint {
+        app.arg("--entrypoint").arg(entrypoint);
     }
 
     // setup container name to use
@@ -237,6 +678,13 @@ pub fn create(
             return Err(FlakeError::UnknownCommand)
         }
     } else {
+        if let (Some(post_start), Some(entrypoint)) =
+            (post_start, &entrypoint)
+        {
+            app.arg("-c")
+                .arg(post_start_wrapper(post_start))
+                .arg(entrypoint);
+        }
         for arg in Lookup::get_run_cmdline(Vec::new(), false) {
             app.arg(arg);
         }
@@ -247,8 +695,17 @@ pub fn create(
         debug!("{:?}", app.get_args());
     }
     let pilot_options = Lookup::get_pilot_run_options();
+    if pilot_options.contains_key("%confirm") && ! atty::is(Stream::Stdin) {
+        return Err(FlakeError::IOError {
+            kind: "InvalidInput".to_string(),
+            message: "%confirm requires a terminal".to_string()
+        })
+    }
     let mut spinner = None;
-    if ! pilot_options.contains_key("%silent") {
+    // the spinner would overwrite the confirmation prompt
+    if ! pilot_options.contains_key("%silent")
+        && ! pilot_options.contains_key("%confirm")
+    {
         spinner = Some(
             Spinner::new_with_stream(
                 spinners::Line, "Launching flake...",
@@ -261,8 +718,9 @@ pub fn create(
     if pilot_options.contains_key("%ignore_sync_error") {
         ignore_sync_error = true
     }
+    let no_provision = pilot_options.contains_key("%no-provision");
 
-    match run_podman_creation(app, ignore_sync_error) {
+    match run_podman_creation(app, ignore_sync_error, no_provision) {
         Ok(cid) => {
             if let Some(spinner) = spinner {
                 spinner.success("Launching flake");
@@ -279,27 +737,32 @@ pub fn create(
 }
 
 fn run_podman_creation(
-    mut app: Command, ignore_sync_error: bool
+    mut app: Command, ignore_sync_error: bool, no_provision: bool
 ) -> Result<String, FlakeError> {
     /*!
     Create and provision container prior start
     !*/
-    let RuntimeSection { resume, .. } = config().runtime();
+    let RuntimeSection { resume, runas, .. } = config().runtime();
 
     let root_user = User::from("root");
+    let include_jobs = IO::include_jobs()?;
+
+    // includes keep their host ownership unless the container is rootless
+    let include_chown = if User::parse(runas)?.is_root() {
+        None
+    } else {
+        config().include_chown()
+    };
 
-    let output: Output = match app.perform() {
+    // On permission error, fix permissions and try again
+    let output: Output = match app.perform_with_retry(
+        1, Duration::ZERO, Container::fix_permission_error
+    ) {
         Ok(output) => {
             output
         }
         Err(error) => {
-            let error_pattern = Regex::new(r".*(not permitted|permission denied).*").unwrap();
-            if error_pattern.captures(&format!("{:?}", error.base)).is_some() {
-                // On permission error, fix permissions and try again
-                // This is an expensive operation depending on the storage size
-                let _ = Container::podman_setup_permissions();
-                app.perform()?
-            } else if resume {
+            if resume {
                 // Cleanup potentially left over container instance from an
                 // inconsistent state, e.g powerfail
                 if Lookup::is_debug() {
@@ -321,11 +784,22 @@ fn run_podman_creation(
 
     let is_delta_container = config().container.base_container.is_some();
     let check_host_dependencies = config().container.check_host_dependencies;
-    let has_includes = !config().tars().is_empty() || !config().paths().is_empty();
+    let has_includes = !config().tars().is_empty() || !config().copy_paths().is_empty();
 
     let mut provisioning_failed = None;
+    let mut report = ProvisionReport::default();
+
+    if no_provision && is_delta_container {
+        warn!(
+            "Provisioning skipped, the delta container is not \
+            merged with its base and layers"
+        );
+    }
 
-    if is_delta_container || check_host_dependencies {
+    if provisioning_required(
+        is_delta_container, check_host_dependencies, no_provision
+    ) {
+        let _umask = IO::umask(config().runtime().provision_umask)?;
         if Lookup::is_debug() {
             debug!("Mounting instance for provisioning workload");
         }
@@ -341,11 +815,14 @@ fn run_podman_creation(
 
         // lookup and sync host dependencies from systemfiles script
         let mut ignore_missing = false;
-        let system_files = tempfile()?;
+        let system_files = IO::tempfile()?;
         match build_system_dependencies(
             &instance_mount_point, defaults::SYSTEM_HOST_DEPENDENCIES,
             &system_files, root_user
-        ) {
+        ).and_then(|_| build_system_library_dependencies(
+            &instance_mount_point, defaults::SYSTEM_HOST_DEPENDENCIES_LIBS,
+            &system_files, root_user
+        )) {
             Ok(_) => {
                 if Lookup::is_debug() {
                     debug!("Syncing system dependencies...");
@@ -355,9 +832,13 @@ fn run_podman_creation(
                     root_user, ignore_missing,
                     defaults::SYSTEM_HOST_DEPENDENCIES
                 ) {
-                    Ok(_) => { },
+                    Ok(bytes_synced) => report.bytes_synced += bytes_synced,
                     Err(error) => {
-                        if ! ignore_sync_error {
+                        let declined = matches!(
+                            error,
+                            FlakeError::OperationError(OperationError::Declined)
+                        );
+                        if ! ignore_sync_error || declined {
                             provisioning_failed = Some(error)
                         }
                     }
@@ -373,18 +854,21 @@ fn run_podman_creation(
         // lookup and sync host dependencies from removed data
         if provisioning_failed.is_none() {
             ignore_missing = true;
-            let removed_files = tempfile()?;
+            let removed_files = IO::tempfile()?;
             update_removed_files(&instance_mount_point, &removed_files)?;
-            sync_host(
+            match sync_host(
                 &instance_mount_point, &removed_files,
                 root_user, ignore_missing,
                 defaults::HOST_DEPENDENCIES
-            )?;
+            ) {
+                Ok(bytes_synced) => report.bytes_synced += bytes_synced,
+                Err(error) => provisioning_failed = Some(error)
+            }
         }
 
         if is_delta_container && provisioning_failed.is_none() {
             // Create tmpfile to hold accumulated removed data from layers
-            let removed_files = tempfile()?;
+            let removed_files = IO::tempfile()?;
             if Lookup::is_debug() {
                 debug!("Provisioning delta container...");
             }
@@ -404,25 +888,30 @@ fn run_podman_creation(
                 if Lookup::is_debug() {
                     debug!("Syncing delta dependencies [{layer}]...");
                 }
-                let app_mount_point = mount_container(layer, true)?;
+                let layer_root = provide_layer(layer)?;
+                let app_mount_point = layer_root.path();
                 update_removed_files(&app_mount_point, &removed_files)?;
-                IO::sync_data(
+                report.layers.push(layer.to_string());
+                report.bytes_synced += IO::sync_data(
                     &format!("{}/", app_mount_point),
                     &format!("{}/", instance_mount_point),
                     [].to_vec(),
                     root_user
                 )?;
 
-                let _ = umount_container(layer, true);
+                release_layer(layer, layer_root);
             }
             if Lookup::is_debug() {
                 debug!("Syncing layer host dependencies...");
             }
-            sync_host(
+            match sync_host(
                 &instance_mount_point, &removed_files,
                 root_user, ignore_missing,
                 defaults::HOST_DEPENDENCIES
-            )?;
+            ) {
+                Ok(bytes_synced) => report.bytes_synced += bytes_synced,
+                Err(error) => provisioning_failed = Some(error)
+            }
         }
 
         if has_includes && provisioning_failed.is_none() {
@@ -431,9 +920,14 @@ fn run_podman_creation(
             }
             match IO::sync_includes(
                 &instance_mount_point, config().tars(),
-                config().paths(), root_user
+                config().copy_paths(), root_user, include_jobs,
+                include_chown
             ) {
-                Ok(_) => { },
+                Ok(bytes_synced) => {
+                    report.bytes_synced += bytes_synced;
+                    report.includes =
+                        config().tars().len() + config().copy_paths().len();
+                },
                 Err(error) => {
                     provisioning_failed = Some(error);
                 }
@@ -448,14 +942,56 @@ fn run_podman_creation(
         return Err(provisioning_failed);
     }
 
+    if ProvisionReport::is_enabled() {
+        report.report();
+    }
+
     Ok(cid)
 }
 
-pub fn start(program_name: &str, cid: &str) -> Result<(), FlakeError> {
+pub fn expand_vars(arg: &str) -> String {
+    /*!
+    Replace %VAR placeholder(s) with the respective environment
+    variable value if possible. If not possible use the default
+    given as %VAR:-default or replace by the variable name $VAR
+    !*/
+    let var_pattern = Regex::new(r"%([A-Z]+)(?::-([A-Za-z0-9_./-]*))?").unwrap();
+    let mut arg_value = arg.to_string();
+    while var_pattern.is_match(&arg_value) {
+        arg_value = var_pattern.replace_all(&arg_value, |capture: &Captures| {
+            let var_name = &capture[1];
+            env::var(var_name).unwrap_or_else(|_| {
+                match capture.get(2) {
+                    Some(default) => default.as_str().to_string(),
+                    None => format!("${}", var_name)
+                }
+            })
+        }).to_string();
+    }
+    arg_value
+}
+
+pub fn provisioning_required(
+    is_delta_container: bool, check_host_dependencies: bool,
+    no_provision: bool
+) -> bool {
+    /*!
+    Check if the container instance needs to be provisioned
+    with host dependencies, delta layers and includes prior
+    start. The %no-provision pilot option skips provisioning
+    !*/
+    ! no_provision && (is_delta_container || check_host_dependencies)
+}
+
+pub fn start(
+    program_name: &str, (cid, cid_file): (&str, &str)
+) -> Result<(), FlakeError> {
     /*!
     Start container with the given container ID
     !*/
-    let RuntimeSection { resume, attach, .. } = config().runtime();
+    let RuntimeSection {
+        resume, attach, post_start, ready_wait, ready_timeout, ..
+    } = config().runtime();
     
     let current_user = get_current_username().unwrap();
     let user = User::from(current_user.to_str().unwrap());
@@ -463,6 +999,9 @@ pub fn start(program_name: &str, cid: &str) -> Result<(), FlakeError> {
     let is_running = container_running(cid, user)?;
 
     if is_running {
+        if resume {
+            set_status(cid_file, "running", cid);
+        }
         if attach {
             // 1. Attach to running container
             call_instance("attach", cid, program_name, user)?;
@@ -471,8 +1010,33 @@ pub fn start(program_name: &str, cid: &str) -> Result<(), FlakeError> {
             call_instance("exec", cid, program_name, user)?;
         }
     } else if resume {
-        // 3. Startup resume type container and execute app
-        call_instance("start", cid, program_name, user)?;
+        // 3. Startup resume type container, run the post_start
+        // hook and execute app
+        set_status(cid_file, "starting", cid);
+        let started = call_instance("start", cid, program_name, user)
+            .and_then(|_| match post_start {
+                Some(post_start) => {
+                    call_post_start(cid, post_start, program_name, user)
+                },
+                None => Ok(())
+            })
+            .and_then(|_| match ready_wait {
+                Some(ready_wait) => {
+                    wait_ready(
+                        cid, ready_wait, ready_timeout, program_name, user
+                    )
+                },
+                None => Ok(())
+            });
+        if let Err(error) = started {
+            let state = match container_running(cid, user) {
+                Ok(true) => "running",
+                _ => "stopped"
+            };
+            set_status(cid_file, state, cid);
+            return Err(error)
+        }
+        set_status(cid_file, "running", cid);
         call_instance("exec", cid, program_name, user)?;
     } else {
         // 4. Startup container
@@ -482,134 +1046,834 @@ pub fn start(program_name: &str, cid: &str) -> Result<(), FlakeError> {
     Ok(())
 }
 
-pub fn get_target_app_path(program_name: &str) -> String {
+fn set_status(cid_file: &str, state: &str, cid: &str) {
     /*!
-    setup application command path name
+    Update the configured status_file of a resume container,
+    failing to do so does not stop the app from running
+    !*/
+    if let Some(status_file) = config().runtime().status_file {
+        if let Err(error) = IO::write_status(status_file, cid_file, state, cid) {
+            warn!("Failed to write status file {}: {}", status_file, error);
+        }
+    }
+}
 
-    This is either the program name specified at registration
-    time or the configured target application from the flake
-    configuration file
+pub fn ready_check(ready_wait: &str) -> Result<String, String> {
+    /*!
+    Shell command which succeeds inside of the container if
+    the given ready_wait condition port:N or file:/path is met.
+    A listening port is looked up in /proc/net/tcp(6) as no
+    network tools can be expected in the container
     !*/
-    config().container.target_app_path.unwrap_or(program_name).to_owned()
+    match ready_wait.split_once(':') {
+        Some(("port", port)) => {
+            if let Ok(port @ 1..=65535) = port.parse::<u32>() {
+                return Ok(format!(
+                    "grep -qE ':{:04X} [0-9A-F]+:0000 0A' \
+                    /proc/net/tcp /proc/net/tcp6 2>/dev/null",
+                    port
+                ))
+            }
+        },
+        Some(("file", path)) if path.starts_with('/') => {
+            return Ok(format!("test -e '{}'", path.replace('\'', "'\\''")))
+        },
+        _ => { }
+    }
+    Err(format!("ready_wait {} must be port:N or file:/path", ready_wait))
 }
 
-pub fn call_instance(
-    action: &str, cid: &str, program_name: &str, user: User
+pub fn wait_ready(
+    cid: &str, ready_wait: &str, ready_timeout: Option<u64>,
+    program_name: &str, user: User
 ) -> Result<(), FlakeError> {
     /*!
-    Call container ID based podman commands
+    Poll the ready_wait condition inside of the container until
+    it is met or ready_timeout seconds have passed
     !*/
-    let args: Vec<String> = env::args().collect();
-
-    let RuntimeSection { resume, .. } = config().runtime();
-
-    let pilot_options = Lookup::get_pilot_run_options();
-    let mut interactive = false;
-    if pilot_options.contains_key("%interactive") {
-        interactive = true;
+    let check = ready_check(ready_wait).map_err(config_error(program_name))?;
+    let timeout = ready_timeout.unwrap_or(defaults::READY_TIMEOUT);
+    let start = Instant::now();
+    loop {
+        let mut call = user.run(get_podman_path());
+        call.arg("exec").arg(cid).arg("sh").arg("-c").arg(&check);
+        if Lookup::is_debug() {
+            debug!("{:?}", call.get_args());
+        }
+        if call.perform().is_ok() {
+            return Ok(())
+        }
+        if start.elapsed() >= Duration::from_secs(timeout) {
+            return Err(FlakeError::OperationError(
+                OperationError::NotReady(timeout, ready_wait.to_string())
+            ))
+        }
+        thread::sleep(Duration::from_millis(defaults::READY_POLL_MSEC));
     }
+}
 
-    let mut call = user.run("podman");
-    if action == "rm" || action == "rm_force" {
-        call.stdout(Stdio::null());
-        call.arg("rm").arg("--force");
-    } else {
-        call.arg(action);
-    }
-    if action == "exec" {
-        call.arg("--interactive");
-        call.arg("--tty");
-    }
-    if action == "start" && ! resume {
-        call.arg("--attach");
-    } else if action == "start" {
-        // start detached, we are not interested in the
-        // start output in this case
-        call.stdout(Stdio::null());
-    }
-    call.arg(cid);
-    if action == "exec" {
-        call.arg(
-            get_target_app_path(program_name)
-        );
-        for arg in &args[1..] {
-            if ! arg.starts_with('@') {
-                call.arg(arg);
-            }
-        }
+pub fn post_start_wrapper(post_start: &str) -> String {
+    /*!
+    Shell script which runs the post_start hook and replaces
+    itself with the app passed as $0 and its arguments.
+    A failing hook exits the script with the hook exit code
+    !*/
+    format!("{{ {}\n}} || exit $?\nexec \"$0\" \"$@\"", post_start)
+}
+
+pub fn call_post_start(
+    cid: &str, post_start: &str, program_name: &str, user: User
+) -> Result<(), FlakeError> {
+    /*!
+    Run the post_start hook in the running container
+    !*/
+    let RuntimeSection { exec_user, .. } = config().runtime();
+    let mut call = user.run(get_podman_path());
+    call.arg("exec");
+    if let Some(exec_user) = exec_user {
+        check_exec_user(exec_user).map_err(config_error(program_name))?;
+        call.arg("--user").arg(exec_user);
     }
+    call.arg(cid).arg("sh").arg("-c").arg(post_start);
     if Lookup::is_debug() {
         debug!("{:?}", call.get_args());
     }
-    if interactive || atty::is(Stream::Stdout) {
-        call.status()?;
-    } else {
-        match call.output() {
-            Ok(output) => {
-                let _ = io::stdout().write_all(&output.stdout);
-                let _ = io::stderr().write_all(&output.stderr);
-            },
-            Err(_) => {
-                let _ = Container::podman_setup_permissions();
-                call.output()?;
-            }
-        };
+    let output = call.perform()?;
+    if Lookup::is_debug() {
+        debug!("{}", String::from_utf8_lossy(&output.stdout));
     }
     Ok(())
 }
 
-pub fn mount_container(
-    container_name: &str, as_image: bool
-) -> Result<String, FlakeError> {
+pub fn check_device(device: &str) -> Result<(), FlakeError> {
     /*!
-    Mount container and return mount point
+    Check if the host device of the given device specification
+    exists. In rootless mode device passthrough might be
+    restricted, thus only warn about it
     !*/
-    let root_user = User::from("root");
-    if as_image && ! container_image_exists(container_name, root_user)? {
-        pull(container_name, root_user)?;
+    let host_device = device.split(':').next().unwrap_or_default();
+    if get_current_uid() != 0 {
+        warn!(
+            "Device passthrough of {} in rootless mode might be restricted",
+            host_device
+        );
+    } else if ! host_device.starts_with("/dev/")
+        || ! Path::new(host_device).exists()
+    {
+        return Err(FlakeError::IOError {
+            kind: "NotFound".to_string(),
+            message: format!("Device {} does not exist", host_device)
+        })
     }
-    let mut call = root_user.run("podman");
-    if as_image {
-        call.arg("image").arg("mount").arg(container_name);
-    } else {
-        call.arg("mount").arg(container_name);
+    Ok(())
+}
+
+pub fn check_memory_swap(
+    memory_swap: &str, podman_args: &[&str]
+) -> Result<(), String> {
+    /*!
+    Check that memory_swap is a valid podman memory value which
+    is not lower than the memory limit from the podman runtime
+    arguments, if any
+    !*/
+    if memory_swap == "-1" {
+        return Ok(())
+    }
+    let swap_bytes = parse_memory(memory_swap).ok_or_else(
+        || format!("Invalid memory_swap value: {}", memory_swap)
+    )?;
+    let args: Vec<&str> = podman_args.iter()
+        .flat_map(|arg| arg.splitn(2, [' ', '=']))
+        .collect();
+    for (index, arg) in args.iter().enumerate() {
+        if *arg == "--memory" || *arg == "-m" {
+            let limit = args.get(index + 1).copied().unwrap_or_default();
+            if let Some(limit_bytes) = parse_memory(limit) {
+                if swap_bytes < limit_bytes {
+                    return Err(format!(
+                        "memory_swap {} is lower than memory limit {}",
+                        memory_swap, limit
+                    ))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn parse_memory(value: &str) -> Option<u64> {
+    /*!
+    Parse podman memory value NUMBER[b|k|m|g] into bytes.
+    Values not representable as u64 are rejected
+    !*/
+    let value = value.trim().to_lowercase();
+    let (number, factor) = match value.chars().last()? {
+        'b' => (&value[..value.len() - 1], 1),
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (&value[..], 1)
+    };
+    number.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+pub fn check_ulimit(ulimit: &str) -> Result<(), String> {
+    /*!
+    Check if the given ulimit is specified as NAME=SOFT[:HARD]
+    with a known limit name and a soft limit not above the
+    hard limit. A limit of -1 means unlimited
+    !*/
+    let invalid = |reason: &str| format!(
+        "ulimit {} must be NAME=SOFT[:HARD], {}", ulimit, reason
+    );
+    let (name, limits) = ulimit.split_once('=')
+        .ok_or_else(|| invalid("missing limits"))?;
+    if ! PODMAN_ULIMITS.contains(&name) {
+        return Err(invalid(&format!(
+            "use one of: {}", PODMAN_ULIMITS.join(", ")
+        )))
+    }
+    let (soft, hard) = limits.split_once(':').unwrap_or((limits, limits));
+    let parse = |limit: &str| match limit.parse::<i64>() {
+        Ok(-1) => Ok(i64::MAX),
+        Ok(limit) if limit >= 0 => Ok(limit),
+        Ok(_) => Err(invalid("limits must be -1 or positive")),
+        Err(_) => Err(invalid("limits must be numbers"))
+    };
+    if parse(soft)? > parse(hard)? {
+        return Err(invalid("soft limit is above hard limit"))
+    }
+    Ok(())
+}
+
+pub fn check_stop_signal(stop_signal: &str) -> Result<(), String> {
+    /*!
+    Check if the given stop signal is a known signal name,
+    with or without SIG prefix, or a signal number
+    !*/
+    let name = stop_signal.strip_prefix("SIG").unwrap_or(stop_signal);
+    let known = match name.parse::<i32>() {
+        Ok(number) => (1..=64).contains(&number),
+        Err(_) => LINUX_SIGNALS.contains(&name)
+    };
+    if ! known {
+        return Err(format!(
+            "stop_signal {} must be a signal name or number", stop_signal
+        ))
+    }
+    Ok(())
+}
+
+pub fn parse_shm_size(shm_size: &str) -> Result<u64, String> {
+    /*!
+    Parse the given /dev/shm size into a number of bytes
+    !*/
+    Container::parse_size("shm_size", shm_size)
+}
+
+pub fn check_hostname(hostname: &str) -> Result<(), String> {
+    /*!
+    Check if the given hostname is a valid DNS label
+    !*/
+    let valid = ! hostname.is_empty()
+        && hostname.len() <= 63
+        && ! hostname.starts_with('-')
+        && ! hostname.ends_with('-')
+        && hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if ! valid {
+        return Err(format!("Hostname {} is not a valid DNS label", hostname))
+    }
+    Ok(())
+}
+
+pub fn check_timezone(timezone: &str) -> Result<(), String> {
+    /*!
+    Check if the given timezone is local or looks like a tz
+    database name such as UTC, Europe/Berlin or Etc/GMT+1
+    !*/
+    let valid = timezone == "local" || timezone.split('/').all(|part| {
+        part.chars().next().map(|c| c.is_ascii_alphabetic()).unwrap_or(false)
+            && part.chars().all(|c| {
+                c.is_ascii_alphanumeric() || "_+-".contains(c)
+            })
+    });
+    if ! valid {
+        return Err(format!(
+            "Timezone {} must be local or a tz database name \
+            like Europe/Berlin", timezone
+        ))
+    }
+    Ok(())
+}
+
+pub fn check_cgroup_parent(cgroup_parent: &str) -> Result<(), String> {
+    /*!
+    Check if the given cgroup parent is a systemd slice name
+    or an absolute cgroup path
+    !*/
+    let valid_name = |name: &str| ! name.is_empty()
+        && name != "." && name != ".."
+        && name.chars().all(
+            |c| c.is_ascii_alphanumeric() || "-_.:@".contains(c)
+        );
+    let valid = match cgroup_parent.strip_prefix('/') {
+        Some(path) => path.split('/').all(valid_name),
+        None => cgroup_parent.len() > ".slice".len()
+            && cgroup_parent.ends_with(".slice")
+            && valid_name(cgroup_parent)
+    };
+    if ! valid {
+        return Err(format!(
+            "cgroup_parent {} must be a slice name or absolute cgroup path",
+            cgroup_parent
+        ))
+    }
+    Ok(())
+}
+
+pub fn check_network(network: &str) -> Result<(), String> {
+    /*!
+    Check if the given network is a known podman network mode
+    or a valid podman network name
+    !*/
+    let (mode, options) = match network.split_once(':') {
+        Some((mode, options)) => (mode, Some(options)),
+        None => (network, None)
+    };
+    let valid = if PODMAN_NETWORK_MODES.contains(&mode) {
+        match mode {
+            // these modes reference another namespace
+            "container" | "ns" => options.is_some_and(|o| ! o.is_empty()),
+            "slirp4netns" | "pasta" => true,
+            _ => options.is_none()
+        }
+    } else {
+        options.is_none() && Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9_.-]*$")
+            .unwrap().is_match(network)
+    };
+    if ! valid {
+        return Err(format!(
+            "network {} is neither a podman network mode nor a \
+            valid network name", network
+        ))
+    }
+    Ok(())
+}
+
+pub fn check_exec_user(exec_user: &str) -> Result<(), String> {
+    /*!
+    Check if the given container user is specified as
+    name, uid or uid:gid
+    !*/
+    let user_pattern = Regex::new(
+        r"^([a-z_][a-z0-9_-]*\$?|[0-9]+|[0-9]+:[0-9]+)$"
+    ).unwrap();
+    if ! user_pattern.is_match(exec_user) {
+        return Err(format!(
+            "exec_user {} must be one of name, uid or uid:gid", exec_user
+        ))
+    }
+    Ok(())
+}
+
+fn config_error(program_name: &str) -> impl Fn(String) -> FlakeError + '_ {
+    /*!
+    Report a failed check of a value from the flake
+    configuration of program_name as ConfigError
+    !*/
+    move |message| FlakeError::ConfigError {
+        path: crate::config::config_file(program_name),
+        message
+    }
+}
+
+pub fn get_target_app_path(program_name: &str) -> String {
+    /*!
+    setup application command path name
+
+    This is either the program name specified at registration
+    time or the configured target application from the flake
+    configuration file. The %app:PATH pilot option, validated
+    in create(), takes precedence over both
+    !*/
+    if let Ok(Some(app)) = Lookup::get_app_override() {
+        return app
+    }
+    config().container.target_app_path.unwrap_or(program_name).to_owned()
+}
+
+pub fn get_entrypoint(
+    entrypoint: Option<&str>, target_app_path: &str
+) -> Option<String> {
+    /*!
+    Entry point to start a non resume container with.
+    An explicitly configured entrypoint takes precedence over
+    the target_app_path. None means the entry point configured
+    in the image is used
+    !*/
+    match entrypoint {
+        Some(defaults::ENTRYPOINT_IMAGE_DEFAULT) => None,
+        Some(entrypoint) => Some(entrypoint.to_owned()),
+        None if target_app_path != "/" => Some(target_app_path.to_owned()),
+        None => None
+    }
+}
+
+pub fn call_instance(
+    action: &str, cid: &str, program_name: &str, user: User
+) -> Result<(), FlakeError> {
+    /*!
+    Call container ID based podman commands
+    !*/
+    let args: Vec<String> = Lookup::args();
+
+    let RuntimeSection {
+        resume, exec_user, stop_signal, stop_timeout, ..
+    } = config().runtime();
+
+    let graceful_stop = stop_signal.is_some() || stop_timeout.is_some();
+    if action == "rm_force" && graceful_stop {
+        // give the app the chance to shut down gracefully
+        stop_instance(cid, stop_timeout, user);
+    }
+
+    let pilot_options = Lookup::get_pilot_run_options();
+    let mut interactive = false;
+    if pilot_options.contains_key("%interactive") {
+        interactive = true;
+    }
+
+    let mut call = user.run(get_podman_path());
+    if action == "rm" || action == "rm_force" {
+        call.stdout(Stdio::null());
+        call.arg("rm").arg("--force");
+    } else {
+        call.arg(action);
+    }
+    // tag the exec'd process such that signals can be forwarded to it,
+    // the pilot PID is unique among all concurrent calls of the instance
+    let exec_id = std::process::id().to_string();
+    if action == "exec" {
+        call.arg("--interactive");
+        call.arg("--tty");
+        if let Some(exec_user) = exec_user {
+            check_exec_user(exec_user).map_err(config_error(program_name))?;
+            call.arg("--user").arg(exec_user);
+        }
+        call.arg("--env").arg(
+            format!("{}={}", defaults::EXEC_ID_ENV, exec_id)
+        );
+    }
+    if action == "start" && ! resume {
+        call.arg("--attach");
+    } else if action == "start" {
+        // start detached, we are not interested in the
+        // start output in this case
+        call.stdout(Stdio::null());
+    }
+    call.arg(cid);
+    let mut exec_cmdline: Vec<String> = Vec::new();
+    if action == "exec" {
+        exec_cmdline.push(get_target_app_path(program_name));
+        for arg in &args[1..] {
+            if ! arg.starts_with('@') {
+                exec_cmdline.push(arg.to_string());
+            }
+        }
+        call.args(&exec_cmdline);
     }
     if Lookup::is_debug() {
         debug!("{:?}", call.get_args());
     }
-    let output = call.perform()?;
-    Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches('\n').to_owned())
+
+    // forward supervisor signals to the process in the instance
+    let _signal_forward = match action {
+        "exec" => Some(forward_signals(cid, Some(exec_id), user)?),
+        "attach" => Some(forward_signals(cid, None, user)?),
+        "start" if ! resume => Some(forward_signals(cid, None, user)?),
+        _ => None
+    };
+
+    if interactive || atty::is(Stream::Stdout) {
+        call.status()?;
+    } else {
+        match call.output() {
+            Ok(output) => {
+                let _ = io::stdout().write_all(&output.stdout);
+                let _ = io::stderr().write_all(&output.stderr);
+            },
+            Err(error) => {
+                if ! Container::permission_fixup_enabled() {
+                    return Err(error.into())
+                }
+                let _ = Container::podman_setup_permissions();
+                call.output()?;
+            }
+        };
+    }
+    Ok(())
 }
 
-pub fn umount_container(
-    mount_point: &str, as_image: bool
+pub fn stop_instance(cid: &str, stop_timeout: Option<u32>, user: User) {
+    /*!
+    Stop the container with its configured stop signal and
+    wait up to stop_timeout seconds before it gets killed.
+    Failures are not fatal as the container gets force
+    removed afterwards
+    !*/
+    let mut call = user.run(get_podman_path());
+    call.stdout(Stdio::null());
+    call.arg("stop")
+        .arg("--time")
+        .arg(stop_timeout.unwrap_or(defaults::STOP_TIMEOUT).to_string())
+        .arg(cid);
+    if Lookup::is_debug() {
+        debug!("{:?}", call.get_args());
+    }
+    if let Err(error) = call.perform() {
+        if Lookup::is_debug() {
+            debug!("Graceful stop failed: {}", error);
+        }
+    }
+}
+
+pub fn forward_signals(
+    cid: &str, exec_id: Option<String>, user: User
+) -> Result<SignalForward, FlakeError> {
+    /*!
+    Forward signals received by the pilot to the container
+    main process or, if exec_id is given, to the process
+    started via podman exec tagged with this id
+    !*/
+    let cid = cid.to_string();
+    let user_name = user.get_name();
+    SignalForward::start(move |signal| {
+        let user = if user_name.is_empty() {
+            User::default()
+        } else {
+            User::from(user_name.as_str())
+        };
+        if let Err(error) = kill_instance(
+            &cid, signal, exec_id.as_deref(), user
+        ) {
+            warn!("Failed to forward signal {}: {}", signal, error);
+        }
+    })
+}
+
+pub fn kill_instance(
+    cid: &str, signal: i32, exec_id: Option<&str>, user: User
+) -> Result<(), FlakeError> {
+    kill_instance_with(&HostRunner, cid, signal, exec_id, user)
+}
+
+pub fn kill_instance_with<R: CommandRunner>(
+    runner: &R, cid: &str, signal: i32, exec_id: Option<&str>, user: User
 ) -> Result<(), FlakeError> {
     /*!
-    Umount container image
+    Send signal to the container main process via podman kill,
+    or to the process started via podman exec tagged with the
+    given exec_id
+    !*/
+    let exec_id = match exec_id {
+        Some(exec_id) => exec_id,
+        None => {
+            let mut kill = user.run(get_podman_path());
+            kill.arg("kill")
+                .arg("--signal").arg(signal.to_string())
+                .arg(cid);
+            FlakeLog::debug(&format!("{:?}", kill.get_args()));
+            runner.perform(&mut kill)?;
+            return Ok(())
+        }
+    };
+    match exec_pid_with(runner, cid, exec_id, user)? {
+        Some(hpid) => {
+            let mut kill = User::ROOT.run("kill");
+            kill.arg(format!("-{}", signal)).arg(hpid.to_string());
+            FlakeLog::debug(&format!("{:?}", kill.get_args()));
+            runner.perform(&mut kill)?;
+        },
+        None => FlakeLog::debug(&format!(
+            "No process of exec {} left in {}", exec_id, cid
+        ))
+    }
+    Ok(())
+}
+
+pub fn exec_pid_with<R: CommandRunner>(
+    runner: &R, cid: &str, exec_id: &str, user: User
+) -> Result<Option<u32>, FlakeError> {
+    /*!
+    Host PID of the process started via podman exec tagged with
+    the given exec_id. The tag is part of the initial environment
+    of the process and all of its children, the process whose
+    parent is not tagged is the one started by podman exec
+    !*/
+    let mut top = user.run(get_podman_path());
+    top.arg("top").arg(cid).arg("hpid");
+    FlakeLog::debug(&format!("{:?}", top.get_args()));
+    let output = runner.perform(&mut top)?;
+    let hpids: Vec<u32> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.trim().parse::<u32>().ok())
+        .collect();
+    if hpids.is_empty() {
+        return Ok(None)
+    }
+
+    // the environment of processes in rootful containers can only
+    // be read by root. Processes may exit in between, such that
+    // grep and ps report errors together with the valid results
+    let mut grep = User::ROOT.run("grep");
+    grep.arg("--null-data").arg("--files-with-matches")
+        .arg("--line-regexp").arg("--fixed-strings")
+        .arg(format!("{}={}", defaults::EXEC_ID_ENV, exec_id))
+        .args(hpids.iter().map(|hpid| format!("/proc/{}/environ", hpid)));
+    FlakeLog::debug(&format!("{:?}", grep.get_args()));
+    let tagged: Vec<u32> = String::from_utf8_lossy(&partial_output(
+        runner.perform(&mut grep)
+    )?.stdout)
+        .lines()
+        .filter_map(|file| file.strip_prefix("/proc/")?
+            .strip_suffix("/environ")?
+            .parse::<u32>().ok()
+        )
+        .collect();
+    if tagged.is_empty() {
+        return Ok(None)
+    }
+
+    let mut ps = Command::new("ps");
+    ps.arg("-o").arg("pid=,ppid=").arg("-p").arg(
+        tagged.iter().map(u32::to_string).collect::<Vec<_>>().join(",")
+    );
+    FlakeLog::debug(&format!("{:?}", ps.get_args()));
+    let exec_pid = String::from_utf8_lossy(&partial_output(
+        runner.perform(&mut ps)
+    )?.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut ids = line.split_whitespace()
+                .filter_map(|id| id.parse::<u32>().ok());
+            Some((ids.next()?, ids.next()?))
+        })
+        .find(|(_, ppid)| ! tagged.contains(ppid))
+        .map(|(pid, _)| pid);
+    Ok(exec_pid)
+}
+
+fn partial_output(
+    result: Result<Output, CommandError>
+) -> Result<Output, CommandError> {
+    // the output of a command which failed for some of its arguments
+    match result {
+        Err(CommandError { base: ProcessError::ExecutionError(output), .. }) => {
+            Ok(output)
+        },
+        result => result
+    }
+}
+
+pub fn mount_container(
+    container_name: &str, as_image: bool
+) -> Result<String, FlakeError> {
+    /*!
+    Mount container and return mount point
+    !*/
+    mount_container_with(
+        &HostRunner, container_name, as_image, &PullOptions::from_config()
+    )
+}
+
+pub fn mount_container_with<R: CommandRunner>(
+    runner: &R, container_name: &str, as_image: bool, options: &PullOptions
+) -> Result<String, FlakeError> {
+    /*!
+    Mount container through the given command runner, an image
+    missing in the local registry is pulled first
     !*/
     let root_user = User::from("root");
-    let mut call = root_user.run("podman");
-    call.stderr(Stdio::null());
-    call.stdout(Stdio::null());
-    if as_image {
-        call.arg("image").arg("umount").arg(mount_point);
-    } else {
-        call.arg("umount").arg(mount_point);
+    if as_image && ! container_image_exists_with(
+        runner, container_name, root_user, options.permission_fixup
+    )? {
+        pull_with(runner, container_name, options, root_user)?;
     }
+    Container::podman_mount_with(runner, container_name, as_image, root_user)
+}
+
+/// Content of a layer image provided for the delta provisioning
+#[derive(Debug)]
+pub enum LayerRoot {
+    /// The image is mounted via podman image mount
+    Mounted(String),
+
+    /// The image content is exported to a temporary directory
+    /// because podman image mount is not supported
+    Exported(TempDir)
+}
+
+impl LayerRoot {
+    pub fn path(&self) -> String {
+        match self {
+            LayerRoot::Mounted(mount_point) => mount_point.to_string(),
+            LayerRoot::Exported(dir) => format!(
+                "{}/{}", dir.path().display(), defaults::LAYER_EXPORT_ROOT
+            )
+        }
+    }
+}
+
+impl Drop for LayerRoot {
+    fn drop(&mut self) {
+        /*!
+        Delete exported layer content. The exported data is
+        owned by root and can't be removed by TempDir itself
+        !*/
+        if let LayerRoot::Exported(dir) = self {
+            let mut call = User::from("root").run("rm");
+            call.arg("-rf").arg(dir.path());
+            if Lookup::is_debug() {
+                debug!("{:?}", call.get_args());
+            }
+            let _ = call.perform();
+        }
+    }
+}
+
+pub fn provide_layer(layer: &str) -> Result<LayerRoot, FlakeError> {
+    provide_layer_with(&HostRunner, layer, &PullOptions::from_config())
+}
+
+pub fn provide_layer_with<R: CommandRunner>(
+    runner: &R, layer: &str, options: &PullOptions
+) -> Result<LayerRoot, FlakeError> {
+    /*!
+    Provide the content of the given layer image. The image is
+    mounted if possible. If podman image mount is not supported,
+    e.g. for rootless podman with fuse-overlayfs, the image is
+    exported to a temporary directory instead
+    !*/
+    match mount_container_with(runner, layer, true, options) {
+        Ok(mount_point) => {
+            if Lookup::is_debug() {
+                debug!("Layer [{layer}] provided via image mount");
+            }
+            Ok(LayerRoot::Mounted(mount_point))
+        },
+        Err(error) if image_mount_unsupported(&error) => {
+            if Lookup::is_debug() {
+                debug!(
+                    "Layer [{layer}] provided via image export, \
+                    image mount failed: {error}"
+                );
+            }
+            let dir = IO::tempdir()?;
+            let target = dir.path().to_string_lossy().to_string();
+            // dropping layer_root on error deletes the partial export
+            let layer_root = LayerRoot::Exported(dir);
+            export_image_with(runner, layer, &target)?;
+            Ok(layer_root)
+        },
+        Err(error) => Err(error)
+    }
+}
+
+pub fn release_layer(layer: &str, layer_root: LayerRoot) {
+    /*!
+    Umount the provided layer content, exported content is
+    deleted when layer_root is dropped
+    !*/
+    if let LayerRoot::Mounted(_) = layer_root {
+        let _ = umount_container(layer, true);
+    }
+}
+
+pub fn image_mount_unsupported(error: &FlakeError) -> bool {
+    /*!
+    Check if podman failed because image mount is not
+    supported in the current setup
+    !*/
+    match error {
+        FlakeError::CommandError(CommandError {
+            base: ProcessError::ExecutionError(output), ..
+        }) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            defaults::IMAGE_MOUNT_UNSUPPORTED.iter()
+                .any(|marker| stderr.contains(marker))
+        },
+        _ => false
+    }
+}
+
+pub fn export_image_with<R: CommandRunner>(
+    runner: &R, image: &str, target: &str
+) -> Result<(), FlakeError> {
+    /*!
+    Export the filesystem of the given image to the layer
+    export root below target. The export is done through a
+    container created from the image which is never started
+    !*/
+    let root_user = User::from("root");
+    let mut create = root_user.run(get_podman_path());
+    create.arg("create").arg("--entrypoint").arg("true").arg(image);
     if Lookup::is_debug() {
-        debug!("{:?}", call.get_args());
+        debug!("{:?}", create.get_args());
+    }
+    let output = runner.perform(&mut create)?;
+    let cid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    let archive = format!("{}/{}", target, defaults::LAYER_EXPORT_ARCHIVE);
+    let mut export = root_user.run(get_podman_path());
+    export.arg("export").arg("--output").arg(&archive).arg(&cid);
+    if Lookup::is_debug() {
+        debug!("{:?}", export.get_args());
+    }
+    let exported = runner.perform(&mut export);
+
+    let mut rm = root_user.run(get_podman_path());
+    rm.arg("rm").arg("--force").arg(&cid);
+    if Lookup::is_debug() {
+        debug!("{:?}", rm.get_args());
+    }
+    let _ = runner.perform(&mut rm);
+    exported?;
+
+    let export_root = format!("{}/{}", target, defaults::LAYER_EXPORT_ROOT);
+    fs::create_dir_all(&export_root)?;
+    let mut extract = root_user.run("tar");
+    extract.arg("-C").arg(&export_root).arg("-xf").arg(&archive);
+    if Lookup::is_debug() {
+        debug!("{:?}", extract.get_args());
     }
-    call.perform()?;
+    let extracted = runner.perform(&mut extract);
+
+    let mut rm_archive = root_user.run("rm");
+    rm_archive.arg("-f").arg(&archive);
+    let _ = runner.perform(&mut rm_archive);
+    extracted?;
     Ok(())
 }
 
+pub fn umount_container(
+    mount_point: &str, as_image: bool
+) -> Result<(), FlakeError> {
+    /*!
+    Umount container image
+    !*/
+    let root_user = User::from("root");
+    Container::podman_umount(mount_point, as_image, root_user)
+}
+
 pub fn sync_host(
     target: &String, mut removed_files: &File, user: User,
     ignore_missing: bool, from: &str
-) -> Result<(), FlakeError> {
+) -> Result<u64, FlakeError> {
     /*!
     Sync files/dirs specified in target/from, from the running
-    host to the target path
+    host to the target path. Returns the number of bytes
+    transferred if provisioning is reported
     !*/
     let mut removed_files_contents = String::new();
     let files_from = format!("{}/{}", &target, from);
@@ -620,13 +1884,27 @@ pub fn sync_host(
         if Lookup::is_debug() {
             debug!("There are no host dependencies to resolve");
         }
-        return Ok(())
+        return Ok(0)
     }
 
     File::create(&files_from)?.write_all(removed_files_contents.as_bytes())?;
 
+    if Lookup::get_pilot_run_options().contains_key("%confirm") {
+        confirm_sync(&removed_files_contents)?;
+    }
+
+    if ! Lookup::which("rsync") {
+        return Err(FlakeError::IOError {
+            kind: "NotFound".to_string(),
+            message: "rsync not found in $PATH, installed ?".to_string()
+        })
+    }
+    let reported = ProvisionReport::is_enabled();
     let mut call = user.run("rsync");
     call.arg("-av");
+    if reported {
+        call.arg("--stats");
+    }
     if ignore_missing {
         call.arg("--ignore-missing-args");
     }
@@ -636,18 +1914,39 @@ pub fn sync_host(
     if Lookup::is_debug() {
         debug!("{:?}", call.get_args());
     }
+    let mut bytes_synced = 0;
     match call.output() {
         Ok(output) => {
             if Lookup::is_debug() {
                 debug!("{}", String::from_utf8_lossy(&output.stdout));
                 debug!("{}", String::from_utf8_lossy(&output.stderr));
             }
-            if ! output.status.success() && ! ignore_missing {
-                return Err(
-                    FlakeError::IOError {
-                        kind: "rsync transfer incomplete".to_string(),
-                        message: "Please run with PILOT_DEBUG=1 for details".to_string()
-                    }
+            match output.status.code() {
+                Some(0) => { },
+                Some(defaults::RSYNC_VANISHED_SOURCE_FILES) => {
+                    // files vanished on the host between listing and
+                    // transfer, this is not considered a failure
+                    warn!("rsync: some source files vanished during sync");
+                },
+                code if ! ignore_missing => {
+                    let code = code.map(|code| code.to_string())
+                        .unwrap_or_else(|| "signal".to_string());
+                    return Err(
+                        FlakeError::IOError {
+                            kind: "rsync transfer incomplete".to_string(),
+                            message: format!(
+                                "rsync exited with {}: {}", code,
+                                String::from_utf8_lossy(&output.stderr)
+                                    .lines().last().unwrap_or_default()
+                            )
+                        }
+                    );
+                },
+                _ => { }
+            }
+            if reported {
+                bytes_synced = ProvisionReport::rsync_transferred_bytes(
+                    &String::from_utf8_lossy(&output.stdout)
                 );
             }
         }
@@ -655,9 +1954,35 @@ pub fn sync_host(
             return Err(flakes::error::FlakeError::IO(error))
         }
     }
+    Ok(bytes_synced)
+}
+
+fn confirm_sync(files: &str) -> Result<(), FlakeError> {
+    /*!
+    Show the host files about to be synced into the instance
+    and ask for confirmation on the terminal
+    !*/
+    eprintln!("Host files to sync into the instance:");
+    for file in files.lines() {
+        eprintln!("  {}", file);
+    }
+    eprint!("Continue? [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if ! confirmed(&answer) {
+        return Err(FlakeError::OperationError(OperationError::Declined))
+    }
     Ok(())
 }
 
+pub fn confirmed(answer: &str) -> bool {
+    /*!
+    Check if answer to a confirmation prompt is a yes
+    !*/
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
 pub fn init_cid_dir() -> Result<(), FlakeError> {
     /*!
     Create meta data directory structure
@@ -673,28 +1998,16 @@ pub fn container_running(cid: &str, user: User) -> Result<bool, CommandError> {
     Check if container with specified cid is running
     !*/
     let mut running_status = false;
-    let mut running = user.run("podman");
+    let mut running = user.run(get_podman_path());
     running.arg("ps")
         .arg("--format").arg("{{.ID}}");
     if Lookup::is_debug() {
         debug!("{:?}", running.get_args());
     }
-    let output: Output = match running.perform() {
-        Ok(output) => {
-            output
-        }
-        Err(error) => {
-            let error_pattern = Regex::new(r".*(not permitted|permission denied).*").unwrap();
-            if error_pattern.captures(&format!("{:?}", error.base)).is_some() {
-                // On permission error, fix permissions and try again
-                // This is an expensive operation depending on the storage size
-                let _ = Container::podman_setup_permissions();
-                running.perform()?
-            } else {
-                return Err(error)
-            }
-        }
-    };
+    // On permission error, fix permissions and try again
+    let output: Output = running.perform_with_retry(
+        1, Duration::ZERO, Container::fix_permission_error
+    )?;
     let mut running_cids = String::new();
     running_cids.push_str(
         &String::from_utf8_lossy(&output.stdout)
@@ -709,70 +2022,187 @@ pub fn container_running(cid: &str, user: User) -> Result<bool, CommandError> {
 }
 
 pub fn container_image_exists(name: &str, user: User) -> Result<bool, std::io::Error> {
+    container_image_exists_with(
+        &HostRunner, name, user, Container::permission_fixup_enabled()
+    )
+}
+
+pub fn container_image_exists_with<R: CommandRunner>(
+    runner: &R, name: &str, user: User, permission_fixup: bool
+) -> Result<bool, std::io::Error> {
     /*!
-    Check if container image is present in local registry
+    Check if container image is present in local registry.
+    With permission_fixup a failed check is retried after
+    fixing the registry permissions
     !*/
-    let mut exists = user.run("podman");
+    let mut exists = user.run(get_podman_path());
     exists.arg("image").arg("exists").arg(name);
     if Lookup::is_debug() {
         debug!("{:?}", exists.get_args());
     }
-    match exists.status() {
-        Ok(status) => {
-            if status.success() {
-                return Ok(status.success())
-            } else {
-                let _ = Container::podman_setup_permissions();
-                exists.status()?;
-            }
-        }
-        Err(error) => {
-            return Err(error)
+    // a missing image might be caused by wrong permissions,
+    // fix them and check again
+    match runner.perform_with_retry(&mut exists, 1, Duration::ZERO, |error| {
+        permission_fixup
+            && matches!(error.base, ProcessError::ExecutionError(_))
+            && Container::fix_permissions_on_any_error_with(runner, error)
+    }) {
+        Ok(_) => Ok(true),
+        Err(CommandError { base: ProcessError::IO(error), .. }) => Err(error),
+        Err(_) => Ok(false)
+    }
+}
+
+pub fn podman_object_exists_with<R: CommandRunner>(
+    runner: &R, object: &str, name: &str, user: User
+) -> Result<bool, std::io::Error> {
+    /*!
+    Check if the podman object, e.g. a secret or pod, of
+    the given name exists
+    !*/
+    let mut exists = user.run(get_podman_path());
+    exists.arg(object).arg("exists").arg(name);
+    if Lookup::is_debug() {
+        debug!("{:?}", exists.get_args());
+    }
+    match runner.perform(&mut exists) {
+        Ok(_) => Ok(true),
+        Err(CommandError { base: ProcessError::IO(error), .. }) => Err(error),
+        Err(_) => Ok(false)
+    }
+}
+
+pub fn add_pod_with<R: CommandRunner>(
+    runner: &R, app: &mut Command, pod: &str, user: User
+) -> Result<(), FlakeError> {
+    /*!
+    Let the container created by app join the given pod
+    which must exist
+    !*/
+    if ! podman_object_exists_with(runner, "pod", pod, user)? {
+        return Err(FlakeError::IOError {
+            kind: "NotFound".to_string(),
+            message: format!("podman pod {} does not exist", pod)
+        })
+    }
+    app.arg("--pod").arg(pod);
+    Ok(())
+}
+
+pub fn add_secrets_with<R: CommandRunner>(
+    runner: &R, app: &mut Command, secrets: &[&str], user: User
+) -> Result<(), FlakeError> {
+    /*!
+    Provide the given secrets from the podman secret store to
+    the container created by app. All secrets must exist
+    !*/
+    for secret in secrets {
+        if ! podman_object_exists_with(runner, "secret", secret, user)? {
+            return Err(FlakeError::IOError {
+                kind: "NotFound".to_string(),
+                message: format!("podman secret {} does not exist", secret)
+            })
         }
+        app.arg("--secret").arg(secret);
     }
-    Ok(exists.status()?.success())
+    Ok(())
 }
 
 pub fn pull(uri: &str, user: User) -> Result<(), FlakeError> {
+    pull_with(&HostRunner, uri, &PullOptions::from_config(), user)
+}
+
+/// Settings applied when pulling a missing image
+pub struct PullOptions<'a> {
+    /// Platform to pull the image for
+    pub platform: Option<&'a str>,
+    /// Mirror locations by registry
+    pub mirrors: HashMap<String, String>,
+    /// Fix the registry permissions on permission errors
+    pub permission_fixup: bool
+}
+
+impl PullOptions<'static> {
+    pub fn from_config() -> Self {
+        /*!
+        Pull settings from the flake config, the flakes config
+        and the pilot options of the current call
+        !*/
+        PullOptions {
+            platform: config().container.platform,
+            mirrors: get_registry_mirrors(),
+            permission_fixup: Container::permission_fixup_enabled()
+        }
+    }
+}
+
+pub fn pull_with<R: CommandRunner>(
+    runner: &R, uri: &str, options: &PullOptions, user: User
+) -> Result<(), FlakeError> {
     /*!
-    Call podman pull and prune with the provided uri
+    Call podman pull and prune with the provided uri. If a
+    registry mirror is configured for the uri, the image is
+    pulled from the mirror and renamed to the uri
     !*/
-    let mut pull = user.run("podman");
-    pull.arg("pull").arg(uri);
+    let mirror = Container::mirror_reference(uri, &options.mirrors);
+    let mut pull = user.run(get_podman_path());
+    pull.arg("pull");
+    if let Some(platform) = options.platform {
+        pull.arg("--platform").arg(platform);
+    }
+    pull.arg(mirror.as_deref().unwrap_or(uri));
     if Lookup::is_debug() {
         debug!("{:?}", pull.get_args());
     }
-    match pull.perform() {
-        Ok(output) => {
-            output
+    runner.perform_with_retry(
+        &mut pull, 1, Duration::ZERO,
+        |error| options.permission_fixup
+            && Container::fix_permission_error_with(runner, error)
+    )?;
+    if let Some(mirror) = mirror {
+        let mut tag = user.run(get_podman_path());
+        tag.arg("tag").arg(&mirror).arg(uri);
+        if Lookup::is_debug() {
+            debug!("{:?}", tag.get_args());
         }
-        Err(error) => {
-            let error_pattern = Regex::new(r".*(not permitted|permission denied).*").unwrap();
-            if error_pattern.captures(&format!("{:?}", error.base)).is_some() {
-                let _ = Container::podman_setup_permissions();
-                pull.perform()?
-            } else {
-                return Err(FlakeError::CommandError(error))
-            }
+        runner.perform(&mut tag)?;
+        let mut untag = user.run(get_podman_path());
+        untag.arg("untag").arg(uri).arg(&mirror);
+        if Lookup::is_debug() {
+            debug!("{:?}", untag.get_args());
         }
-    };
-    let mut prune = user.run("podman");
+        runner.perform(&mut untag)?;
+    }
+    let mut prune = user.run(get_podman_path());
     prune.arg("image").arg("prune").arg("--force");
-    match prune.status() {
-        Ok(status) => { if Lookup::is_debug() { debug!("{:?}", status) }},
+    match runner.perform(&mut prune) {
+        Ok(output) => { if Lookup::is_debug() { debug!("{:?}", output.status) }},
         Err(error) => { if Lookup::is_debug() { debug!("{:?}", error) }}
     }
     Ok(())
 }
 
 pub fn build_system_dependencies(
-    target: &String, dependency_file: &str, mut file: &File, user: User
+    target: &String, dependency_file: &str, file: &File, user: User
 ) -> Result<bool, FlakeError> {
     /*!
     Check if container provides a /systemfiles script which
     contains code to build up a list of files that needs
     to be provisioned from the host
     !*/
+    build_system_dependencies_with(
+        &HostRunner, target, dependency_file, file, user
+    )
+}
+
+pub fn build_system_dependencies_with(
+    runner: &impl CommandRunner, target: &String, dependency_file: &str,
+    mut file: &File, user: User
+) -> Result<bool, FlakeError> {
+    /*!
+    Build the list of host dependencies as described for
+    [build_system_dependencies] using the given runner
+    !*/
     let system_deps = format!("{}/{}", &target, dependency_file);
     if Path::new(&system_deps).exists() {
         if Lookup::is_debug() {
@@ -783,29 +2213,33 @@ pub fn build_system_dependencies(
         if Lookup::is_debug() {
             debug!("{:?}", call.get_args());
         }
-        match call.output() {
+        match runner.perform(&mut call) {
             Ok(output) => {
-                if output.status.success() {
-                    file.write_all(&output.stdout)?;
-                    return Ok(true);
-                } else {
-                    if Lookup::is_debug() {
-                        debug!("{}", String::from_utf8_lossy(&output.stdout));
-                        debug!("{}", String::from_utf8_lossy(&output.stderr));
-                    }
-                    return Err(
-                        FlakeError::IOError {
-                            kind: "system deps generator failed".to_string(),
-                            message: "Please run with PILOT_DEBUG=1 for details".to_string()
-                        }
-                    );
+                file.write_all(&output.stdout)?;
+                // keep the list line based for entries appended
+                // by the library dependencies
+                if output.stdout.last().is_some_and(|last| *last != b'\n') {
+                    file.write_all(b"\n")?;
+                }
+                return Ok(true);
+            },
+            Err(CommandError { base: ProcessError::ExecutionError(output), .. }) => {
+                if Lookup::is_debug() {
+                    debug!("{}", String::from_utf8_lossy(&output.stdout));
+                    debug!("{}", String::from_utf8_lossy(&output.stderr));
                 }
+                return Err(
+                    FlakeError::IOError {
+                        kind: "system deps generator failed".to_string(),
+                        message: "Please run with PILOT_DEBUG=1 for details".to_string()
+                    }
+                );
             },
             Err(error) => {
                 return Err(
                     FlakeError::IOError {
                         kind: "call failed".to_string(),
-                        message: format!("{:?}", error)
+                        message: format!("{:?}", error.base)
                     }
                 );
             }
@@ -814,6 +2248,103 @@ pub fn build_system_dependencies(
     Ok(false)
 }
 
+pub fn build_system_library_dependencies(
+    target: &String, dependency_file: &str, file: &File, user: User
+) -> Result<bool, FlakeError> {
+    /*!
+    Check if container provides a /systemfiles.libs script which
+    contains code to build up a list of host binaries. The shared
+    library dependencies of these binaries are resolved via ldd
+    and added to the list of files that needs to be provisioned
+    from the host
+    !*/
+    build_system_library_dependencies_with(
+        &HostRunner, target, dependency_file, file, user
+    )
+}
+
+pub fn build_system_library_dependencies_with(
+    runner: &impl CommandRunner, target: &String, dependency_file: &str,
+    mut file: &File, user: User
+) -> Result<bool, FlakeError> {
+    /*!
+    Resolve the host library dependencies as described for
+    [build_system_library_dependencies] using the given runner
+    !*/
+    let system_libs = format!("{}/{}", &target, dependency_file);
+    if ! Path::new(&system_libs).exists() {
+        return Ok(false)
+    }
+    if Lookup::is_debug() {
+        debug!("Calling system libs generator: {}", system_libs);
+    }
+    let mut call = user.run("sh");
+    call.arg(system_libs);
+    if Lookup::is_debug() {
+        debug!("{:?}", call.get_args());
+    }
+    let output = runner.perform(&mut call)?;
+    let mut libraries: Vec<String> = Vec::new();
+    for binary in String::from_utf8_lossy(&output.stdout).lines() {
+        let binary = binary.trim();
+        if binary.is_empty() {
+            continue
+        }
+        let mut ldd = user.run("ldd");
+        ldd.arg(binary);
+        if Lookup::is_debug() {
+            debug!("{:?}", ldd.get_args());
+        }
+        match runner.perform(&mut ldd) {
+            Ok(output) => {
+                for library in ldd_libraries(
+                    &String::from_utf8_lossy(&output.stdout)
+                ) {
+                    if ! libraries.contains(&library) {
+                        libraries.push(library);
+                    }
+                }
+            },
+            _ => {
+                if Lookup::is_debug() {
+                    debug!("Skipping {}: no shared library dependencies", binary);
+                }
+            }
+        }
+    }
+    for library in libraries {
+        file.write_all(format!("{}\n", library).as_bytes())?;
+    }
+    Ok(true)
+}
+
+pub fn ldd_libraries(ldd_output: &str) -> Vec<String> {
+    /*!
+    Read the absolute library paths from the given ldd output.
+    For symlinked libraries the link target is added too
+    !*/
+    let mut libraries: Vec<String> = Vec::new();
+    for line in ldd_output.lines() {
+        let library = match line.split_once("=>") {
+            Some((_, resolved)) => resolved.split_whitespace().next(),
+            None => line.split_whitespace().next()
+        };
+        if let Some(library) = library {
+            if ! library.starts_with('/') {
+                continue
+            }
+            libraries.push(library.to_string());
+            if let Ok(real_library) = fs::canonicalize(library) {
+                let real_library = real_library.display().to_string();
+                if real_library != library {
+                    libraries.push(real_library);
+                }
+            }
+        }
+    }
+    libraries
+}
+
 pub fn update_removed_files(
     target: &String, mut accumulated_file: &File
 ) -> Result<(), std::io::Error> {
@@ -849,32 +2380,25 @@ pub fn gc_cid_file(
     !*/
     let cid = fs::read_to_string(container_cid_file)?;
 
-    let mut exists = user.run("podman");
+    let mut exists = user.run(get_podman_path());
     exists.arg("container")
         .arg("exists")
         .arg(&cid);
     if Lookup::is_debug() {
         debug!("{:?}", exists.get_args());
     }
-    let status = match exists.output() {
-        Ok(output) => {
-            if output.status.success() {
-                output.status
-            } else {
-                let _ = Container::podman_setup_permissions();
-                exists.output()?.status
-            }
-        }
+    match exists.perform_with_retry(
+        1, Duration::ZERO, Container::fix_permissions_on_any_error
+    ) {
+        Ok(_) => Ok(true),
+        Err(CommandError { base: ProcessError::IO(error), .. }) => {
+            Err(FlakeError::IO(error))
+        },
         Err(_) => {
-            let _ = Container::podman_setup_permissions();
-            exists.output()?.status
+            IO::remove_status(container_cid_file);
+            fs::remove_file(container_cid_file)?;
+            Ok(false)
         }
-    };
-    if status.success() {
-        Ok(true)
-    } else {
-        fs::remove_file(container_cid_file)?;
-        Ok(false)
     }
 }
 
@@ -882,28 +2406,9 @@ pub fn gc(user: User) -> Result<(), FlakeError> {
     /*!
     Garbage collect CID files for which no container exists anymore
     !*/
-    let mut cid_file_names: Vec<String> = Vec::new();
-    let mut cid_file_count: i32 = 0;
-    let paths;
-    match fs::read_dir(get_podman_ids_dir()) {
-        Ok(result) => { paths = result },
-        Err(error) => {
-            return Err(FlakeError::IOError {
-                kind: format!("{:?}", error.kind()),
-                message: format!("fs::read_dir failed on {}: {}",
-                    get_podman_ids_dir(), error
-                )
-            })
-        }
-    };
-    for path in paths {
-        cid_file_names.push(format!("{}", path?.path().display()));
-        cid_file_count += 1;
-    }
-    if cid_file_count > defaults::GC_THRESHOLD {
-        for container_cid_file in cid_file_names {
-            let _ = gc_cid_file(&container_cid_file, user);
+    IO::gc_sweep(
+        &get_podman_ids_dir(), defaults::GC_THRESHOLD, |container_cid_file| {
+            let _ = gc_cid_file(container_cid_file, user);
         }
-    }
-    Ok(())
+    )
 }
@@ -26,10 +26,36 @@ use crate::app_path::program_abs_path;
 use crate::app_path::basename;
 use crate::config::config_file;
 use crate::config::config_from_str;
+use flakes::config::flake_yaml;
+use crate::defaults;
+use crate::podman::{
+    build_system_library_dependencies, build_system_library_dependencies_with,
+    build_system_dependencies_with,
+    ldd_libraries, check_hostname,
+    check_exec_user, check_network, parse_shm_size, provisioning_required,
+    check_memory_swap, parse_memory, post_start_wrapper, check_ulimit,
+    get_entrypoint, check_stop_signal, check_cgroup_parent, ready_check,
+    confirmed, expand_vars, check_timezone, pull_with, mount_container_with,
+    container_image_exists_with, provide_layer_with, LayerRoot, PullOptions,
+    add_pod_with, add_secrets_with, kill_instance_with
+};
+use flakes::user::User;
+use flakes::container::Container;
+use flakes::lookup::Lookup;
+use flakes::io::IO;
+use flakes::error::FlakeError;
+use flakes::command::{CommandExtTrait, MockRunner, stderr_excerpt};
+use flakes::report::ProvisionReport;
+use flakes::flakelog::FlakeLog;
+use std::fs;
+use std::process::Command;
+use std::time::Duration;
+use std::thread;
+use tempfile::tempdir;
 
 #[test]
 fn test_program_abs_path() {
-    let program_path = program_abs_path();
+    let program_path = program_abs_path().unwrap();
     assert!(program_path.starts_with('/'));
 }
 
@@ -39,6 +65,20 @@ fn test_basename() {
     assert_eq!("name", base_name);
 }
 
+#[test]
+fn test_pilot_args_from() {
+    let args: Vec<String> = ["app", "%interactive", "@inst", "-l", "%exec:ls"]
+        .iter().map(|arg| arg.to_string()).collect();
+    assert_eq!(
+        Lookup::run_cmdline_from(&args, vec!["init".to_string()], true),
+        vec!["init", "\\-l"]
+    );
+    let options = Lookup::pilot_run_options_from(&args);
+    assert_eq!(options.len(), 2);
+    assert_eq!(options["%interactive"], "");
+    assert_eq!(options["%exec"], "ls");
+}
+
 #[test]
 fn simple_config() {
     let cfg = config_from_str(
@@ -48,7 +88,7 @@ r#"container:
  check_host_dependencies: false
 include:
  tar: ~
-"#);
+"#).unwrap();
     assert_eq!(cfg.container.name, "JoJo");
 }
 
@@ -65,12 +105,1047 @@ container:
  name: Dio
  host_app_path: /other
  check_host_dependencies: false
-"#);
+"#).unwrap();
     assert_eq!(cfg.container.name, "Dio");
 }
 
+#[test]
+fn invalid_config() {
+    let error = config_from_str(
+r#"container:
+ host_app_path: /myapp
+ check_host_dependencies: false
+include:
+ tar: ~
+"#).err().unwrap();
+    assert!(error.contains("missing field `name`"));
+}
+
 #[test]
 fn test_program_config_file() {
     let config_file = config_file(&"app".to_string());
     assert_eq!("/usr/share/flakes/app.yaml", config_file);
 }
+
+#[test]
+fn concurrent_gc() {
+    let ids_dir = tempdir().unwrap();
+    let ids_dir = ids_dir.path().to_str().unwrap().to_string();
+    for count in 0..defaults::GC_THRESHOLD + 5 {
+        fs::write(format!("{}/app{}.cid", ids_dir, count), "cid").unwrap();
+    }
+    let sweeps: Vec<_> = (0..2).map(|_| {
+        let ids_dir = ids_dir.clone();
+        thread::spawn(move || {
+            IO::gc_sweep(&ids_dir, defaults::GC_THRESHOLD, |cid_file| {
+                fs::remove_file(cid_file).unwrap();
+            })
+        })
+    }).collect();
+    for sweep in sweeps {
+        assert!(sweep.join().unwrap().is_ok());
+    }
+    let left: Vec<_> = fs::read_dir(&ids_dir).unwrap().collect();
+    assert_eq!(left.len(), 1);
+}
+
+#[test]
+fn test_ldd_libraries() {
+    let libraries = ldd_libraries(
+r#"	linux-vdso.so.1 (0x00007ffd6b1f4000)
+	libfoo.so.1 => /flakes-test/libfoo.so.1 (0x00007f2b1c200000)
+	libbar.so => not found
+	/flakes-test/ld-linux-x86-64.so.2 (0x00007f2b1c5d8000)
+"#);
+    assert_eq!(libraries, vec![
+        "/flakes-test/libfoo.so.1", "/flakes-test/ld-linux-x86-64.so.2"
+    ]);
+}
+
+#[test]
+fn test_system_library_dependencies_without_script() {
+    let target = tempdir().unwrap();
+    let system_files = tempfile::tempfile().unwrap();
+    let provided = build_system_library_dependencies(
+        &target.path().to_str().unwrap().to_string(),
+        defaults::SYSTEM_HOST_DEPENDENCIES_LIBS, &system_files, User::ROOT
+    ).unwrap();
+    assert!(! provided);
+}
+
+#[test]
+fn test_system_library_dependencies_from_script() {
+    let target = tempdir().unwrap();
+    let target_path = target.path().to_str().unwrap().to_string();
+    let script = format!(
+        "{}/{}", target_path, defaults::SYSTEM_HOST_DEPENDENCIES_LIBS
+    );
+    fs::write(&script, "echo /usr/bin/tool\necho /usr/bin/static\n").unwrap();
+    fs::write(
+        format!("{}/{}", target_path, defaults::SYSTEM_HOST_DEPENDENCIES),
+        "echo -n /etc/hosts\n"
+    ).unwrap();
+    let system_files = tempfile::NamedTempFile::new().unwrap();
+    let runner = MockRunner::new()
+        .respond(0, "/etc/hosts", "")
+        .respond(0, "/usr/bin/tool\n\n/usr/bin/static\n", "")
+        .respond(0, "\tlibfoo.so.1 => /flakes-test/libfoo.so.1 (0x1)\n", "")
+        .respond(1, "", "not a dynamic executable");
+    assert!(build_system_dependencies_with(
+        &runner, &target_path, defaults::SYSTEM_HOST_DEPENDENCIES,
+        system_files.as_file(), User::ROOT
+    ).unwrap());
+    let provided = build_system_library_dependencies_with(
+        &runner, &target_path, defaults::SYSTEM_HOST_DEPENDENCIES_LIBS,
+        system_files.as_file(), User::ROOT
+    ).unwrap();
+    assert!(provided);
+    assert_eq!(runner.calls(), vec![
+        format!(
+            "sudo --preserve-env --user root sh {}/{}",
+            target_path, defaults::SYSTEM_HOST_DEPENDENCIES
+        ),
+        format!("sudo --preserve-env --user root sh {}", script),
+        "sudo --preserve-env --user root ldd /usr/bin/tool".to_string(),
+        "sudo --preserve-env --user root ldd /usr/bin/static".to_string()
+    ]);
+    assert_eq!(
+        fs::read_to_string(system_files.path()).unwrap(),
+        "/etc/hosts\n/flakes-test/libfoo.so.1\n"
+    );
+}
+
+#[test]
+fn test_check_hostname() {
+    assert!(check_hostname("my-app01").is_ok());
+    assert!(check_hostname("").is_err());
+    assert!(check_hostname("-app").is_err());
+    assert!(check_hostname("app.example.com").is_err());
+    assert!(check_hostname(&"a".repeat(64)).is_err());
+}
+
+#[test]
+fn test_check_network() {
+    for network in [
+        "bridge", "host", "none", "pasta", "slirp4netns:port_handler=slirp4netns",
+        "container:web", "ns:/run/netns/app", "my-net", "podman1"
+    ] {
+        assert!(check_network(network).is_ok(), "{}", network);
+    }
+    for network in ["", "host:x", "container:", "ns", "bad net", "-net"] {
+        assert!(check_network(network).is_err(), "{}", network);
+    }
+}
+
+#[test]
+fn test_check_exec_user() {
+    assert!(check_exec_user("app_user").is_ok());
+    assert!(check_exec_user("1000").is_ok());
+    assert!(check_exec_user("1000:100").is_ok());
+    assert!(check_exec_user("").is_err());
+    assert!(check_exec_user("1000:").is_err());
+    assert!(check_exec_user("user:group").is_err());
+    assert!(check_exec_user("Bad User").is_err());
+}
+
+#[test]
+fn extends_config() {
+    let flakes_dir = tempdir().unwrap();
+    let flakes_dir = flakes_dir.path().to_str().unwrap();
+    fs::write(
+        format!("{}/base.yaml", flakes_dir),
+        "container:\n name: base\n host_app_path: /base\n\
+        \x20check_host_dependencies: false\n\
+        include:\n tar:\n  - base.tar\n"
+    ).unwrap();
+    fs::write(
+        format!("{}/app.yaml", flakes_dir),
+        "extends: base\ncontainer:\n name: app\n host_app_path: /app\n\
+        \x20check_host_dependencies: false\n"
+    ).unwrap();
+    let cfg = config_from_str(
+        &flake_yaml(flakes_dir, "app", &mut Vec::new()).unwrap()
+    ).unwrap();
+    assert_eq!(cfg.container.name, "app");
+    assert_eq!(cfg.tars(), vec!["base.tar"]);
+}
+
+#[test]
+fn extends_config_cycle() {
+    let flakes_dir = tempdir().unwrap();
+    let flakes_dir = flakes_dir.path().to_str().unwrap();
+    fs::write(format!("{}/a.yaml", flakes_dir), "extends: b\n").unwrap();
+    fs::write(format!("{}/b.yaml", flakes_dir), "extends: a\n").unwrap();
+    let error = flake_yaml(flakes_dir, "a", &mut Vec::new()).unwrap_err();
+    assert_eq!(error, "Cyclic extends: a -> b -> a");
+}
+
+#[test]
+fn extends_config_missing() {
+    let flakes_dir = tempdir().unwrap();
+    let flakes_dir = flakes_dir.path().to_str().unwrap();
+    fs::write(format!("{}/a.yaml", flakes_dir), "extends: b\n").unwrap();
+    assert!(flake_yaml(flakes_dir, "a", &mut Vec::new()).is_err());
+}
+
+#[test]
+fn test_parse_memory() {
+    assert_eq!(parse_memory("512"), Some(512));
+    assert_eq!(parse_memory("2k"), Some(2048));
+    assert_eq!(parse_memory("1G"), Some(1024 * 1024 * 1024));
+    assert_eq!(parse_memory("lots"), None);
+    assert_eq!(parse_memory(&format!("{}", u64::MAX)), Some(u64::MAX));
+    assert_eq!(parse_memory(&format!("{}k", u64::MAX / 1024 + 1)), None);
+    assert_eq!(parse_memory("18446744073709551616"), None);
+}
+
+#[test]
+fn test_check_memory_swap() {
+    assert!(check_memory_swap("99999999999999999999g", &[]).is_err());
+    assert!(check_memory_swap("2g", &["--memory 1g"]).is_ok());
+    assert!(check_memory_swap("512m", &["--memory=1g"]).is_err());
+    assert!(check_memory_swap("512m", &["-m 1g"]).is_err());
+    assert!(check_memory_swap("-1", &["--memory 1g"]).is_ok());
+    assert!(check_memory_swap("512m", &["--rm"]).is_ok());
+    assert!(check_memory_swap("none", &[]).is_err());
+}
+
+#[test]
+fn test_include_path_mirror() {
+    assert_eq!(
+        IO::include_path("/host/config").unwrap(),
+        ("/host/config", "/host/config")
+    );
+}
+
+#[test]
+fn test_include_path_with_target() {
+    assert_eq!(
+        IO::include_path("/host/config:/etc/app/config").unwrap(),
+        ("/host/config", "/etc/app/config")
+    );
+    assert!(matches!(
+        IO::include_path(":/etc/app/config"),
+        Err(FlakeError::IOError { .. })
+    ));
+}
+
+#[test]
+fn test_include_path_with_colon() {
+    // only a ':' followed by an absolute path separates the target
+    assert_eq!(
+        IO::include_path("/host/10:30-backup").unwrap(),
+        ("/host/10:30-backup", "/host/10:30-backup")
+    );
+    assert_eq!(
+        IO::include_path("/host/10:30-backup:/srv/backup").unwrap(),
+        ("/host/10:30-backup", "/srv/backup")
+    );
+    assert_eq!(
+        IO::include_path("/host/a:/b:/data").unwrap(),
+        ("/host/a:/b", "/data")
+    );
+}
+
+#[test]
+fn test_include_tar() {
+    assert_eq!(IO::include_tar("/host/data.tar").unwrap(), ("/host/data.tar", None));
+    assert_eq!(
+        IO::include_tar("/host/data.tar:/srv/www/assets").unwrap(),
+        ("/host/data.tar", Some("/srv/www/assets"))
+    );
+    assert_eq!(
+        IO::include_tar("/host/data-10:30.tar").unwrap(),
+        ("/host/data-10:30.tar", None)
+    );
+    assert_eq!(
+        IO::include_tar("/host/data-10:30.tar:/srv").unwrap(),
+        ("/host/data-10:30.tar", Some("/srv"))
+    );
+    assert!(IO::include_tar("/host/data.tar:/srv/../../etc").is_err());
+    assert!(IO::include_tar(":/srv").is_err());
+}
+
+#[test]
+#[ignore = "extracts the tar through sudo, run with --ignored as root"]
+fn test_sync_includes_tar_destination() {
+    let tmp = tempdir().unwrap();
+    let source = tmp.path().join("source");
+    let target = tmp.path().join("target");
+    fs::create_dir_all(&source).unwrap();
+    fs::create_dir_all(&target).unwrap();
+    fs::write(source.join("file"), "data").unwrap();
+    let tar = tmp.path().join("data.tar");
+    let status = std::process::Command::new("tar")
+        .arg("-C").arg(&source).arg("-cf").arg(&tar).arg("file")
+        .status().unwrap();
+    assert!(status.success());
+    let tar = tar.to_str().unwrap();
+    let tar_to = format!("{}:/srv/www/assets", tar);
+
+    IO::sync_includes(
+        &target.to_str().unwrap().to_string(),
+        vec![tar, &tar_to], vec![], User::from("root"), 2, None
+    ).unwrap();
+    assert!(target.join("file").exists());
+    assert!(target.join("srv/www/assets/file").exists());
+}
+
+#[test]
+fn test_destinations_overlap() {
+    assert!(! IO::destinations_overlap(&["/srv/www", "/srv/data", "/etc/app"]));
+    assert!(! IO::destinations_overlap(&["/srv/www", "/srv/www2"]));
+    assert!(IO::destinations_overlap(&["/srv/www", "/srv/www/assets"]));
+    assert!(IO::destinations_overlap(&["/srv/www/", "/srv/www"]));
+    assert!(IO::destinations_overlap(&["/", "/etc/app"]));
+}
+
+#[test]
+fn test_check_chown() {
+    assert!(IO::check_chown("1000:1000").is_ok());
+    assert!(IO::check_chown("0:0").is_ok());
+    assert!(IO::check_chown("1000").is_err());
+    assert!(IO::check_chown("user:group").is_err());
+    assert!(IO::check_chown("1000:").is_err());
+    assert!(IO::check_chown("-1:100").is_err());
+    assert!(User::from("root").is_root());
+    assert!(User::parse("#0").unwrap().is_root());
+    assert!(! User::parse("#1000").unwrap().is_root());
+    assert!(! User::from("joe").is_root());
+}
+
+#[test]
+fn test_check_workdir() {
+    assert!(IO::check_workdir("/home/joe/project").is_ok());
+    assert!(IO::check_workdir("/tmp").is_ok());
+    assert!(IO::check_workdir("/").is_err());
+    assert!(IO::check_workdir("//").is_err());
+    assert!(IO::check_workdir("project").is_err());
+    assert!(IO::check_workdir("/home/joe/a:b").is_err());
+    assert!(IO::check_workdir("/home/joe/a,b").is_err());
+    assert!(IO::check_workdir("/home/joe/my project").is_err());
+}
+
+#[test]
+fn test_app_override() {
+    let args = |args: &[&str]| -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    };
+    assert_eq!(
+        Lookup::app_override_from(&args(&["app", "%app:/usr/bin/git", "-v"]))
+            .unwrap(),
+        Some("/usr/bin/git".to_string())
+    );
+    assert_eq!(Lookup::app_override_from(&args(&["app", "-v"])).unwrap(), None);
+    assert!(Lookup::app_override_from(&args(&["app", "%app:git"])).is_err());
+    assert!(Lookup::app_override_from(&args(&["app", "%app:/"])).is_err());
+    assert!(Lookup::app_override_from(&args(&["app", "%app"])).is_err());
+}
+
+#[test]
+fn test_include_jobs() {
+    let args = |args: &[&str]| -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    };
+    assert_eq!(IO::include_jobs_from(&args(&["app", "%jobs:4"])).unwrap(), 4);
+    assert!(IO::include_jobs_from(&args(&["app"])).unwrap() >= 1);
+    assert!(IO::include_jobs_from(&args(&["app", "%jobs:0"])).is_err());
+    assert!(IO::include_jobs_from(&args(&["app", "%jobs:many"])).is_err());
+}
+
+#[test]
+fn test_known_capability() {
+    assert!(Container::known_capability("ALL"));
+    assert!(Container::known_capability("NET_BIND_SERVICE"));
+    assert!(Container::known_capability("cap_sys_admin"));
+    assert!(!Container::known_capability("NET_FOO"));
+}
+
+#[test]
+fn test_parse_shm_size() {
+    assert_eq!(parse_shm_size("512MiB").unwrap(), 512 * 1024 * 1024);
+    assert_eq!(parse_shm_size("2GB").unwrap(), 2_000_000_000);
+    assert_eq!(parse_shm_size("1024").unwrap(), 1024);
+    assert!(parse_shm_size("0").is_err());
+    assert!(parse_shm_size("big").is_err());
+}
+
+#[test]
+fn test_no_provision_bypasses_provisioning() {
+    assert!(provisioning_required(true, false, false));
+    assert!(provisioning_required(false, true, false));
+    assert!(!provisioning_required(false, false, false));
+    assert!(!provisioning_required(true, true, true));
+    assert!(!provisioning_required(true, false, true));
+
+    // the option is taken by the pilot and not passed to the app
+    let args: Vec<String> = ["/usr/bin/app", "%no-provision", "--version"]
+        .iter().map(|arg| arg.to_string()).collect();
+    assert!(
+        Lookup::pilot_run_options_from(&args).contains_key("%no-provision")
+    );
+    assert_eq!(
+        Lookup::run_cmdline_from(&args, Vec::new(), false), ["--version"]
+    );
+}
+
+#[test]
+fn test_provision_report() {
+    let stats = "Number of files: 12 (reg: 10, dir: 2)\n\
+        Total file size: 2,345,678 bytes\n\
+        Total transferred file size: 1,234,567 bytes\n";
+    assert_eq!(ProvisionReport::rsync_transferred_bytes(stats), 1234567);
+    assert_eq!(ProvisionReport::rsync_transferred_bytes("sent 10 bytes"), 0);
+
+    let report = ProvisionReport {
+        layers: vec!["base".to_string(), "app".to_string()],
+        bytes_synced: 1234567,
+        includes: 2
+    };
+    assert_eq!(
+        report.to_json(),
+        r#"{"layers":["base","app"],"bytes_synced":1234567,"includes":2}"#
+    );
+}
+
+#[test]
+fn test_user_parse() {
+    assert_eq!(User::parse("root").unwrap().get_name(), "root");
+    assert_eq!(User::parse("build_user-1").unwrap().get_name(), "build_user-1");
+    assert_eq!(User::parse("#0").unwrap().get_name(), "#0");
+    assert_eq!(User::parse("#1000").unwrap().get_name(), "#1000");
+    assert!(User::parse("").is_err());
+    assert!(User::parse("#").is_err());
+    assert!(User::parse("#abc").is_err());
+    assert!(User::parse("#-1").is_err());
+    assert!(User::parse("#99999999999").is_err());
+    assert!(User::parse("Bad User").is_err());
+    assert!(User::parse("1000").is_err());
+}
+
+#[test]
+fn test_post_start_wrapper() {
+    let run = |hook: &str| Command::new("sh")
+        .arg("-c").arg(post_start_wrapper(hook))
+        .arg("echo").arg("app").arg("called")
+        .output().unwrap();
+
+    let output = run("echo hook # comment");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hook\napp called\n");
+
+    let output = run("echo hook; exit 3");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hook\n");
+}
+
+#[test]
+fn test_command_error_stderr() {
+    let error = Command::new("sh")
+        .arg("-c").arg("echo 'no such image' >&2; exit 2")
+        .perform().unwrap_err();
+    assert!(error.to_string().ends_with("exit status: 2: no such image"));
+
+    assert_eq!(stderr_excerpt(b" \n"), "");
+    let long_stderr = format!("{}reason", "x".repeat(2000));
+    let excerpt = stderr_excerpt(long_stderr.as_bytes());
+    assert!(excerpt.starts_with(": ..."));
+    assert!(excerpt.ends_with("reason"));
+    assert_eq!(excerpt.len(), ": ...".len() + 1024);
+}
+
+#[test]
+fn test_include_mode() {
+    let cfg = config_from_str(
+        r#"container:
+ name: JoJo
+ host_app_path: /myapp
+ check_host_dependencies: false
+include:
+ tar:
+  - base.tar
+ path:
+  - /data:/srv/data
+ mode: mount
+"#,
+    ).unwrap();
+    assert_eq!(cfg.tars(), vec!["base.tar"]);
+    assert!(cfg.copy_paths().is_empty());
+    assert_eq!(cfg.mount_paths(), vec!["/data:/srv/data"]);
+
+    let cfg = config_from_str(
+        r#"container:
+ name: JoJo
+ host_app_path: /myapp
+ check_host_dependencies: false
+include:
+ path:
+  - /data
+"#,
+    ).unwrap();
+    assert_eq!(cfg.copy_paths(), vec!["/data"]);
+    assert!(cfg.mount_paths().is_empty());
+}
+
+#[test]
+fn test_check_ulimit() {
+    assert!(check_ulimit("nofile=1024").is_ok());
+    assert!(check_ulimit("nofile=1024:4096").is_ok());
+    assert!(check_ulimit("core=-1").is_ok());
+    assert!(check_ulimit("nproc=100:-1").is_ok());
+    assert!(check_ulimit("nofile").is_err());
+    assert!(check_ulimit("files=1024").is_err());
+    assert!(check_ulimit("nofile=many").is_err());
+    assert!(check_ulimit("nofile=4096:1024").is_err());
+    assert!(check_ulimit("nofile=-1:1024").is_err());
+    assert!(check_ulimit("nofile=-2").is_err());
+}
+
+#[test]
+fn test_perform_with_retry() {
+    let tmp = tempdir().unwrap();
+    let marker = tmp.path().join("marker");
+    let script = format!(
+        "test -e {0} || {{ touch {0}; echo 'permission denied' >&2; exit 1; }}",
+        marker.display()
+    );
+
+    // first try fails, the retry succeeds
+    let mut tries = 0;
+    assert!(Command::new("sh").arg("-c").arg(&script).perform_with_retry(
+        2, Duration::ZERO, |_| { tries += 1; true }
+    ).is_ok());
+    assert_eq!(tries, 1);
+
+    // no retry if the predicate rejects the error
+    fs::remove_file(&marker).unwrap();
+    let mut tries = 0;
+    assert!(Command::new("sh").arg("-c").arg(&script).perform_with_retry(
+        2, Duration::ZERO, |_| { tries += 1; false }
+    ).is_err());
+    assert_eq!(tries, 1);
+
+    // retries are limited
+    let mut tries = 0;
+    assert!(Command::new("false").perform_with_retry(
+        2, Duration::ZERO, |_| { tries += 1; true }
+    ).is_err());
+    assert_eq!(tries, 2);
+}
+
+fn pull_options(
+    platform: Option<&str>, permission_fixup: bool
+) -> PullOptions<'_> {
+    PullOptions {
+        platform, mirrors: std::collections::HashMap::new(), permission_fixup
+    }
+}
+
+#[test]
+fn test_pull_with_permission_fixup() {
+    // a permission error fixes the registry ownership and retries
+    let runner = MockRunner::new()
+        .respond(125, "", "Error: permission denied")
+        .respond(0, "", "")
+        .respond(0, "", "")
+        .respond(0, "", "")
+        .respond(0, "", "");
+    assert!(pull_with(
+        &runner, "busybox", &pull_options(Some("linux/arm64"), true), User::ROOT
+    ).is_ok());
+    let calls = runner.calls();
+    assert_eq!(calls.len(), 5);
+    assert!(calls[0].ends_with("pull --platform linux/arm64 busybox"));
+    assert!(calls[1].contains("chown -R"));
+    assert!(calls[2].contains("chown -R"));
+    assert_eq!(calls[3], calls[0]);
+    assert!(calls[4].ends_with("image prune --force"));
+
+    // other errors are not retried
+    let runner = MockRunner::new()
+        .respond(125, "", "Error: manifest unknown");
+    assert!(pull_with(
+        &runner, "busybox", &pull_options(None, true), User::ROOT
+    ).is_err());
+    assert_eq!(runner.calls().len(), 1);
+
+    // without permission fixup a permission error is not retried
+    let runner = MockRunner::new()
+        .respond(125, "", "Error: permission denied");
+    assert!(pull_with(
+        &runner, "busybox", &pull_options(None, false), User::ROOT
+    ).is_err());
+    assert_eq!(runner.calls().len(), 1);
+
+    // a configured mirror is pulled from and tagged as the original
+    let mut options = pull_options(None, false);
+    options.mirrors.insert(
+        String::from("docker.io"), String::from("mirror.example.com")
+    );
+    let runner = MockRunner::new()
+        .respond(0, "", "")
+        .respond(0, "", "")
+        .respond(0, "", "");
+    assert!(pull_with(
+        &runner, "docker.io/library/busybox", &options, User::ROOT
+    ).is_ok());
+    let calls = runner.calls();
+    assert!(calls[0].ends_with("pull mirror.example.com/library/busybox"));
+}
+
+#[test]
+fn test_mount_container_with() {
+    // a missing image is pulled before it gets mounted
+    let runner = MockRunner::new()
+        .respond(1, "", "")
+        .respond(0, "", "")
+        .respond(0, "", "")
+        .respond(1, "", "")
+        .respond(0, "", "")
+        .respond(0, "", "")
+        .respond(0, "/var/lib/containers/storage/overlay/1/merged\n", "");
+    assert_eq!(
+        mount_container_with(
+            &runner, "busybox", true, &pull_options(None, true)
+        ).unwrap(),
+        "/var/lib/containers/storage/overlay/1/merged"
+    );
+    let calls = runner.calls();
+    assert!(calls[0].ends_with("image exists busybox"));
+    assert!(calls[4].ends_with("pull busybox"));
+    assert!(calls[6].ends_with("image mount busybox"));
+
+    // a failure to run podman at all is reported as such
+    let runner = MockRunner::new();
+    assert!(container_image_exists_with(
+        &runner, "busybox", User::ROOT, true
+    ).is_err());
+}
+
+#[test]
+fn test_provide_layer_with() {
+    // the image is mounted if possible
+    let runner = MockRunner::new()
+        .respond(0, "", "")
+        .respond(0, "/var/lib/containers/storage/overlay/1/merged\n", "");
+    let layer_root = provide_layer_with(&runner, "layer", &pull_options(None, true)).unwrap();
+    assert!(matches!(layer_root, LayerRoot::Mounted(_)));
+
+    // unsupported image mount falls back to an image export
+    let runner = MockRunner::new()
+        .respond(0, "", "")
+        .respond(125, "", "Error: cannot run command \"podman image mount\" \
+            in rootless mode, must execute `podman unshare` first")
+        .respond(0, "0123456789ab\n", "")
+        .respond(0, "", "")
+        .respond(0, "", "")
+        .respond(0, "", "")
+        .respond(0, "", "");
+    let layer_root = provide_layer_with(&runner, "layer", &pull_options(None, true)).unwrap();
+    assert!(matches!(layer_root, LayerRoot::Exported(_)));
+    assert!(layer_root.path().ends_with("/rootfs"));
+    let calls = runner.calls();
+    assert!(calls[2].ends_with("create --entrypoint true layer"));
+    assert!(calls[3].contains("export --output"));
+    assert!(calls[4].ends_with("rm --force 0123456789ab"));
+    assert!(calls[5].contains("tar -C"));
+
+    // other mount failures are reported
+    let runner = MockRunner::new()
+        .respond(0, "", "")
+        .respond(125, "", "Error: layer not known");
+    assert!(provide_layer_with(&runner, "layer", &pull_options(None, true)).is_err());
+    assert_eq!(runner.calls().len(), 2);
+}
+
+#[test]
+fn test_get_entrypoint() {
+    assert_eq!(get_entrypoint(None, "/usr/bin/app"), Some("/usr/bin/app".to_string()));
+    assert_eq!(get_entrypoint(None, "/"), None);
+    assert_eq!(
+        get_entrypoint(Some("/usr/bin/run"), "/usr/bin/app"),
+        Some("/usr/bin/run".to_string())
+    );
+    assert_eq!(get_entrypoint(Some("/usr/bin/run"), "/"), Some("/usr/bin/run".to_string()));
+    assert_eq!(get_entrypoint(Some("default"), "/usr/bin/app"), None);
+}
+
+#[test]
+fn test_check_stop_signal() {
+    assert!(check_stop_signal("SIGTERM").is_ok());
+    assert!(check_stop_signal("INT").is_ok());
+    assert!(check_stop_signal("15").is_ok());
+    assert!(check_stop_signal("SIGFOO").is_err());
+    assert!(check_stop_signal("0").is_err());
+    assert!(check_stop_signal("sigterm").is_err());
+}
+
+#[test]
+fn test_validate_includes() {
+    let tmp = tempdir().unwrap();
+    let tar = tmp.path().join("data.tar");
+    fs::write(&tar, "").unwrap();
+    let tar = tar.to_str().unwrap();
+    let dir = tmp.path().to_str().unwrap();
+    let dir_to = format!("{}:/data", dir);
+
+    assert!(IO::validate_includes(vec![tar], vec![dir, &dir_to]).is_ok());
+
+    // missing tar
+    let result = IO::validate_includes(vec!["/does/not/exist.tar"], vec![]);
+    match result {
+        Err(FlakeError::IOError { kind, message }) => {
+            assert_eq!(kind, "NotFound");
+            assert!(message.contains("tar /does/not/exist.tar"));
+        },
+        _ => panic!("missing tar include not detected")
+    }
+
+    // missing path, also in src:dst form
+    for path in ["/does/not/exist", "/does/not/exist:/data"] {
+        let result = IO::validate_includes(vec![tar], vec![path]);
+        match result {
+            Err(FlakeError::IOError { kind, message }) => {
+                assert_eq!(kind, "NotFound");
+                assert!(message.contains("path /does/not/exist "));
+            },
+            _ => panic!("missing path include not detected")
+        }
+    }
+}
+
+#[test]
+fn test_parse_umask() {
+    assert_eq!(IO::parse_umask("0027").unwrap(), 0o027);
+    assert_eq!(IO::parse_umask("077").unwrap(), 0o077);
+    assert_eq!(IO::parse_umask("0777").unwrap(), 0o777);
+    assert!(IO::parse_umask("1000").is_err());
+    assert!(IO::parse_umask("0028").is_err());
+    assert!(IO::parse_umask("").is_err());
+    assert!(IO::umask(None).unwrap().is_none());
+}
+
+#[test]
+fn test_check_cgroup_parent() {
+    assert!(check_cgroup_parent("flakes.slice").is_ok());
+    assert!(check_cgroup_parent("user-1000.slice").is_ok());
+    assert!(check_cgroup_parent("/flakes/app").is_ok());
+    assert!(check_cgroup_parent("flakes").is_err());
+    assert!(check_cgroup_parent(".slice").is_err());
+    assert!(check_cgroup_parent("/flakes/../app").is_err());
+    assert!(check_cgroup_parent("/flakes//app").is_err());
+    assert!(check_cgroup_parent("/flakes app").is_err());
+}
+
+#[test]
+fn test_ready_check() {
+    assert_eq!(
+        ready_check("port:8080").unwrap(),
+        "grep -qE ':1F90 [0-9A-F]+:0000 0A' /proc/net/tcp /proc/net/tcp6 2>/dev/null"
+    );
+    assert_eq!(ready_check("file:/run/app.ready").unwrap(), "test -e '/run/app.ready'");
+    assert_eq!(ready_check("file:/run/it's").unwrap(), "test -e '/run/it'\\''s'");
+    assert!(ready_check("port:0").is_err());
+    assert!(ready_check("port:http").is_err());
+    assert!(ready_check("file:run/app.ready").is_err());
+    assert!(ready_check("8080").is_err());
+}
+
+#[test]
+fn test_secrets_and_pod_config() {
+    let cfg = config_from_str(
+        r#"container:
+ name: JoJo
+ host_app_path: /myapp
+ check_host_dependencies: false
+ runtime:
+  runas: root
+  pod: sidecars
+  secrets:
+   - aws_credentials
+   - db_password
+include:
+ tar: ~
+"#,
+    ).unwrap();
+    assert_eq!(
+        cfg.runtime().secrets,
+        Some(vec!["aws_credentials", "db_password"])
+    );
+    assert_eq!(cfg.runtime().pod, Some("sidecars"));
+}
+
+#[test]
+fn test_add_secrets_and_pod_with() {
+    // existing secrets and pods are added to the creation call
+    let runner = MockRunner::new()
+        .respond(0, "", "")
+        .respond(0, "", "")
+        .respond(0, "", "");
+    let mut app = Command::new("podman");
+    add_secrets_with(
+        &runner, &mut app, &["aws_credentials", "db_password"], User::ROOT
+    ).unwrap();
+    add_pod_with(&runner, &mut app, "sidecars", User::ROOT).unwrap();
+    assert_eq!(
+        app.get_args().collect::<Vec<_>>(), [
+            "--secret", "aws_credentials", "--secret", "db_password",
+            "--pod", "sidecars"
+        ]
+    );
+    let calls = runner.calls();
+    assert!(calls[0].ends_with("secret exists aws_credentials"));
+    assert!(calls[1].ends_with("secret exists db_password"));
+    assert!(calls[2].ends_with("pod exists sidecars"));
+
+    // missing secrets and pods are reported
+    let runner = MockRunner::new().respond(1, "", "");
+    let mut app = Command::new("podman");
+    assert!(add_secrets_with(
+        &runner, &mut app, &["db_password"], User::ROOT
+    ).is_err());
+    let runner = MockRunner::new().respond(1, "", "");
+    assert!(add_pod_with(&runner, &mut app, "sidecars", User::ROOT).is_err());
+    assert_eq!(app.get_args().count(), 0);
+}
+
+#[test]
+fn test_init_config() {
+    let flake = |runtime: &str| format!(
+        r#"container:
+ name: JoJo
+ host_app_path: /myapp
+ check_host_dependencies: false
+ runtime:
+  runas: root
+{}include:
+ tar: ~
+"#,
+        runtime
+    );
+    assert!(config_from_str(&flake("  init: true\n")).unwrap().runtime().init);
+    assert!(! config_from_str(&flake("")).unwrap().runtime().init);
+}
+
+#[test]
+fn test_check_labels() {
+    assert!(
+        Container::check_labels(&["com.example.team=infra", "tier="]).is_ok()
+    );
+    assert!(Container::check_labels(&["novalue"]).is_err());
+    assert!(Container::check_labels(&["=value"]).is_err());
+    assert!(Container::check_labels(&["bad key=value"]).is_err());
+    assert!(
+        Container::check_labels(&["team=infra", "team=web"]).is_err()
+    );
+}
+
+#[test]
+fn test_parse_storage_size() {
+    assert_eq!(
+        Container::parse_storage_size("10GiB", None).unwrap(),
+        10 * 1024 * 1024 * 1024
+    );
+    assert_eq!(
+        Container::parse_storage_size("1GB", Some("overlay")).unwrap(),
+        1_000_000_000
+    );
+    assert!(Container::parse_storage_size("10GG", None).is_err());
+    assert!(Container::parse_storage_size("0", None).is_err());
+    assert!(Container::parse_storage_size("10GiB", Some("vfs")).is_err());
+}
+
+#[test]
+fn test_mirror_reference() {
+    let mirrors: std::collections::HashMap<String, String> = [
+        ("docker.io", "mirror.example.com:5000/dockerhub/"),
+        ("registry.opensuse.org", "mirror.example.com:5000/opensuse")
+    ].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    assert_eq!(
+        Container::mirror_reference("busybox", &mirrors).unwrap(),
+        "mirror.example.com:5000/dockerhub/library/busybox"
+    );
+    assert_eq!(
+        Container::mirror_reference("someone/app:1.0", &mirrors).unwrap(),
+        "mirror.example.com:5000/dockerhub/someone/app:1.0"
+    );
+    assert_eq!(
+        Container::mirror_reference(
+            "registry.opensuse.org/opensuse/leap:15.5", &mirrors
+        ).unwrap(),
+        "mirror.example.com:5000/opensuse/opensuse/leap:15.5"
+    );
+    assert!(
+        Container::mirror_reference("quay.io/some/app", &mirrors).is_none()
+    );
+    assert!(
+        Container::mirror_reference("localhost:5000/app", &mirrors).is_none()
+    );
+    assert!(
+        Container::mirror_reference("busybox@sha256:abc", &mirrors).is_none()
+    );
+}
+
+#[test]
+fn test_log_level_config() {
+    let cfg = config_from_str(
+        r#"container:
+ name: JoJo
+ host_app_path: /myapp
+ check_host_dependencies: false
+ runtime:
+  runas: root
+  log_level: debug
+include:
+ tar: ~
+"#,
+    ).unwrap();
+    assert_eq!(cfg.runtime().log_level, Some("debug"));
+}
+
+#[test]
+fn test_log_level_override() {
+    assert_eq!(FlakeLog::level_override(Some("warn"), false), Some("warn"));
+    assert_eq!(FlakeLog::level_override(Some("loud"), false), None);
+    assert_eq!(FlakeLog::level_override(None, false), None);
+    // PILOT_DEBUG wins over the flake config
+    assert_eq!(FlakeLog::level_override(Some("warn"), true), Some("trace"));
+    assert_eq!(FlakeLog::level_override(None, true), Some("trace"));
+}
+
+#[test]
+fn test_confirmed() {
+    assert!(confirmed("y\n"));
+    assert!(confirmed("Yes\n"));
+    assert!(! confirmed("\n"));
+    assert!(! confirmed("n\n"));
+    assert!(! confirmed("yep\n"));
+}
+
+#[test]
+fn test_engine_not_found_exit_code() {
+    let error = FlakeError::EngineNotFound("/usr/bin/podman".to_string());
+    assert_eq!(error.exit_code(), 127);
+    assert_eq!(
+        error.to_string(), "Engine /usr/bin/podman not found, installed ?"
+    );
+}
+
+#[test]
+fn test_expand_vars() {
+    std::env::set_var("FLAKEVARSET", "/data");
+    std::env::remove_var("FLAKEVARUNSET");
+    assert_eq!(expand_vars("%FLAKEVARSET:/data"), "/data:/data");
+    assert_eq!(expand_vars("%FLAKEVARSET:-/tmp:/data"), "/data:/data");
+    assert_eq!(expand_vars("%FLAKEVARUNSET:-/tmp:/data"), "/tmp:/data");
+    assert_eq!(expand_vars("%FLAKEVARUNSET:-"), "");
+    assert_eq!(expand_vars("%FLAKEVARUNSET:/data"), "$FLAKEVARUNSET:/data");
+}
+
+#[test]
+fn test_status_file() {
+    let tmp = tempdir().unwrap();
+    let id_file = tmp.path().join("app_user.cid").to_string_lossy().to_string();
+    let status_dir = tempdir().unwrap();
+    let status_file = status_dir.path().join("app.status")
+        .to_string_lossy().to_string();
+    fs::write(&id_file, "0123abc").unwrap();
+
+    IO::write_status(&status_file, &id_file, "starting", "0123abc").unwrap();
+    let starting = fs::read_to_string(&status_file).unwrap();
+    assert!(starting.starts_with("state=starting\nid=0123abc\nstarted="));
+    let started = starting.lines().last().unwrap().to_string();
+
+    IO::write_status(&status_file, &id_file, "running", "0123abc").unwrap();
+    let running = fs::read_to_string(&status_file).unwrap();
+    assert_eq!(running, format!("state=running\nid=0123abc\n{}\n", started));
+
+    // status links are not garbage collected as ID files
+    let mut swept = Vec::new();
+    IO::gc_sweep(&tmp.path().to_string_lossy(), 0, |file| {
+        swept.push(file.to_string())
+    }).unwrap();
+    assert_eq!(swept, vec![id_file.clone()]);
+
+    // a link to a file which is no status file keeps that file
+    let other = status_dir.path().join("other").to_string_lossy().to_string();
+    fs::write(&other, "keep").unwrap();
+    let link = format!("{}.status", id_file);
+    fs::remove_file(&link).unwrap();
+    std::os::unix::fs::symlink(&other, &link).unwrap();
+    IO::remove_status(&id_file);
+    assert!(std::path::Path::new(&other).exists());
+    assert!(! std::path::Path::new(&link).exists());
+
+    // the status file is removed independent of the flake config
+    // of the caller, gc sweeps the id files of all flakes
+    IO::write_status(&status_file, &id_file, "running", "0123abc").unwrap();
+    IO::remove_status(&id_file);
+    assert!(! std::path::Path::new(&status_file).exists());
+    assert!(! std::path::Path::new(&format!("{}.status", id_file)).exists());
+}
+
+#[test]
+fn test_check_cpuset() {
+    assert!(IO::check_cpuset("0").is_ok());
+    assert!(IO::check_cpuset("0-3,5").is_ok());
+    assert!(IO::check_cpuset("1,2,8-15").is_ok());
+    assert!(IO::check_cpuset("").is_err());
+    assert!(IO::check_cpuset("3-1").is_err());
+    assert!(IO::check_cpuset("0-3,").is_err());
+    assert!(IO::check_cpuset("+1").is_err());
+    assert!(IO::check_cpuset("a-b").is_err());
+}
+
+#[test]
+fn test_permission_fixup_allowed() {
+    let args = |args: &[&str]| -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    };
+    assert!(Container::permission_fixup_allowed(&args(&["app", "-l"])));
+    assert!(! Container::permission_fixup_allowed(
+        &args(&["app", "%no-perm-fix", "-l"])
+    ));
+}
+
+#[test]
+fn test_check_timezone() {
+    assert!(check_timezone("UTC").is_ok());
+    assert!(check_timezone("local").is_ok());
+    assert!(check_timezone("Europe/Berlin").is_ok());
+    assert!(check_timezone("America/Argentina/Buenos_Aires").is_ok());
+    assert!(check_timezone("Etc/GMT+1").is_ok());
+    assert!(check_timezone("").is_err());
+    assert!(check_timezone("/etc/localtime").is_err());
+    assert!(check_timezone("Europe/../Berlin").is_err());
+    assert!(check_timezone("Europe/Ber lin").is_err());
+}
+
+#[test]
+fn test_kill_instance_with() {
+    // the main process is signalled via podman kill
+    let runner = MockRunner::new().respond(0, "", "");
+    kill_instance_with(&runner, "cid", 15, None, User::ROOT).unwrap();
+    assert!(runner.calls()[0].ends_with("kill --signal 15 cid"));
+
+    // the tagged process whose parent is untagged is signalled,
+    // grep and ps fail on processes which exited in between
+    let runner = MockRunner::new()
+        .respond(0, "HPID\n100\n200\n201\n202\n", "")
+        .respond(2, "/proc/200/environ\n/proc/201/environ\n", "No such file")
+        .respond(0, "  201   200\n  200   150\n", "")
+        .respond(0, "", "");
+    kill_instance_with(&runner, "cid", 1, Some("4711"), User::ROOT).unwrap();
+    let calls = runner.calls();
+    assert!(calls[0].ends_with("top cid hpid"));
+    assert!(calls[1].contains("FLAKE_EXEC_ID=4711 /proc/100/environ"));
+    assert!(calls[2].ends_with("ps -o pid=,ppid= -p 200,201"));
+    assert!(calls[3].ends_with("kill -1 200"));
+
+    // nothing is signalled if the exec'd process is gone
+    let runner = MockRunner::new()
+        .respond(0, "HPID\n100\n", "")
+        .respond(1, "", "");
+    kill_instance_with(&runner, "cid", 1, Some("4711"), User::ROOT).unwrap();
+    assert_eq!(runner.calls().len(), 2);
+}